@@ -7,6 +7,10 @@ interface ProgressListener {
     /**
      * Called when extraction progress updates.
      *
+     * The first update of an extraction arrives while the manifest is still being
+     * read, before any partition starts: its [currentFile] is
+     * [NativeLib.PARSING_MANIFEST] and everything else is 0.
+     *
      * @param currentFile Name of the file currently being processed
      * @param progress Progress percentage (0-100)
      * @param bytesProcessed Number of bytes processed so far
@@ -15,16 +19,268 @@ interface ProgressListener {
     fun onProgress(currentFile: String, progress: Int, bytesProcessed: Long, totalBytes: Long)
 }
 
+/**
+ * Progress listener that also receives throughput and estimated time remaining.
+ *
+ * Pass it anywhere a [ProgressListener] is accepted; the native side then calls
+ * [onProgressWithThroughput] instead of [onProgress].
+ */
+interface ThroughputProgressListener : ProgressListener {
+    /**
+     * Called when extraction progress updates.
+     *
+     * @param currentFile Name of the file currently being processed
+     * @param progress Progress percentage (0-100)
+     * @param bytesProcessed Number of bytes processed so far
+     * @param totalBytes Total bytes to process
+     * @param bytesPerSecond Throughput averaged over the last few seconds
+     * @param etaSeconds Estimated seconds remaining, or -1 while unknown
+     */
+    fun onProgressWithThroughput(
+        currentFile: String,
+        progress: Int,
+        bytesProcessed: Long,
+        totalBytes: Long,
+        bytesPerSecond: Long,
+        etaSeconds: Long
+    )
+
+    override fun onProgress(currentFile: String, progress: Int, bytesProcessed: Long, totalBytes: Long) {
+        onProgressWithThroughput(currentFile, progress, bytesProcessed, totalBytes, 0, -1)
+    }
+}
+
+/**
+ * Supplies the output of each partition for [NativeLib.extractPayloadToFds].
+ */
+fun interface OutputFdProvider {
+    /**
+     * Called on the extracting thread right before [partitionName] is written.
+     *
+     * Typically creates a document with `DocumentFile.createFile` and opens it with
+     * `contentResolver.openFileDescriptor(uri, "rw")`. Keep the `ParcelFileDescriptor`
+     * open until the extraction returns; the native side works on a duplicate.
+     *
+     * @param partitionName Name of the partition, e.g. "boot"
+     * @return A raw file descriptor opened for reading and writing, or -1 to abort
+     */
+    fun openOutput(partitionName: String): Int
+}
+
+/**
+ * Receives each partition of [NativeLib.extractPayloadStreaming] as soon as its
+ * image is complete.
+ */
+fun interface PartitionListener {
+    /**
+     * Called once per completed partition. With "max_threads" > 1 this runs on a
+     * native worker thread, in completion order; post to the main thread before
+     * touching UI.
+     *
+     * @param name Name of the partition, e.g. "boot"
+     * @param size Size of the written image in bytes
+     * @param path Path of the written image
+     */
+    fun onPartitionComplete(name: String, size: Long, path: String)
+}
+
+/**
+ * Values of the "code" field of native error responses.
+ *
+ * Mirrors `PayloadError::code` in payload.rs; new codes may be added, so keep a
+ * fallback branch when matching on them.
+ */
+object NativeErrorCode {
+    const val FILE_NOT_FOUND = "FILE_NOT_FOUND"
+    const val PERMISSION_DENIED = "PERMISSION_DENIED"
+    const val IO_ERROR = "IO_ERROR"
+    const val INVALID_MAGIC = "INVALID_MAGIC"
+    const val UNSUPPORTED_VERSION = "UNSUPPORTED_VERSION"
+    const val PROTOBUF_DECODE = "PROTOBUF_DECODE"
+    const val MANIFEST_TOO_LARGE = "MANIFEST_TOO_LARGE"
+    const val FILE_TOO_SMALL = "FILE_TOO_SMALL"
+    const val EMPTY_PATH = "EMPTY_PATH"
+
+    /** The payload ends inside the manifest, e.g. an incomplete download */
+    const val TRUNCATED_MANIFEST = "TRUNCATED_MANIFEST"
+
+    const val UNEXPECTED_EOF = "UNEXPECTED_EOF"
+    const val CANCELLED = "CANCELLED"
+    const val TIMEOUT = "TIMEOUT"
+    const val OPERATION_FAILED = "OPERATION_FAILED"
+    const val INVALID_INPUT = "INVALID_INPUT"
+    const val HASH_MISMATCH = "HASH_MISMATCH"
+    const val INSUFFICIENT_SPACE = "INSUFFICIENT_SPACE"
+    const val OUTPUT_EXISTS = "OUTPUT_EXISTS"
+
+    /** An argument couldn't be read on the native side */
+    const val JNI_ERROR = "JNI_ERROR"
+}
+
+/**
+ * Values of the "code_number" field of native error responses: the codes of
+ * [NativeErrorCode] as stable integers, for mapping errors onto an app's own
+ * types (e.g. a sealed class) in a `when` without comparing strings.
+ *
+ * Mirrors `ERROR_CODE_NUMBERS` in payload.rs. A number is never changed or
+ * reused; [UNKNOWN] is sent for a code added after this table.
+ */
+object NativeErrorNumber {
+    const val UNKNOWN = 0
+    const val FILE_NOT_FOUND = 1
+    const val PERMISSION_DENIED = 2
+    const val IO_ERROR = 3
+    const val INVALID_MAGIC = 4
+    const val UNSUPPORTED_VERSION = 5
+    const val PROTOBUF_DECODE = 6
+    const val MANIFEST_TOO_LARGE = 7
+    const val FILE_TOO_SMALL = 8
+    const val EMPTY_PATH = 9
+    const val TRUNCATED_MANIFEST = 10
+    const val UNEXPECTED_EOF = 11
+    const val CANCELLED = 12
+    const val TIMEOUT = 13
+    const val OPERATION_FAILED = 14
+    const val INVALID_INPUT = 15
+    const val HASH_MISMATCH = 16
+    const val INSUFFICIENT_SPACE = 17
+    const val OUTPUT_EXISTS = 18
+    const val JNI_ERROR = 19
+}
+
+/**
+ * A partition in a payload.bin, as returned by [NativeLib.inspectPartitions].
+ *
+ * Constructed from native code; the constructor signature must stay in sync
+ * with `PARTITION_INFO_CTOR` in jni_bindings.rs.
+ *
+ * @property name Name of the partition (e.g. "system", "boot")
+ * @property size Size of the partition image in bytes
+ * @property operationsCount Number of install operations
+ * @property sizeHuman Size in human-readable form, e.g. "3.00 GB"
+ * @property hash SHA-256 of the new image (hex), or null if absent
+ * @property oldSize Size of the source partition, or -1 for full payloads
+ * @property oldHash SHA-256 of the source image (hex), or null for full payloads
+ * @property totalBlocks Number of blocks written
+ * @property compressedSize Bytes of operation data in the payload
+ */
+class PartitionInfo(
+    val name: String,
+    val size: Long,
+    val operationsCount: Int,
+    val sizeHuman: String,
+    val hash: String?,
+    val oldSize: Long,
+    val oldHash: String?,
+    val totalBlocks: Long,
+    val compressedSize: Long
+) {
+    /** `compressedSize / size`, or null for empty partitions */
+    val compressionRatio: Double?
+        get() = if (size > 0) compressedSize.toDouble() / size else null
+
+    override fun toString(): String = "PartitionInfo(name=$name, size=$sizeHuman)"
+}
+
+/**
+ * Fixed header of a payload.bin, part of [PayloadInfo].
+ *
+ * Constructed from native code; see `PAYLOAD_HEADER_CTOR` in jni_bindings.rs.
+ *
+ * @property version Payload format version (1 or 2)
+ * @property manifestSize Size of the manifest in bytes
+ * @property metadataSignatureSize Size of the metadata signature (0 for version 1)
+ * @property dataOffset Offset of the data blob section
+ */
+class PayloadHeader(
+    val version: Long,
+    val manifestSize: Long,
+    val metadataSignatureSize: Int,
+    val dataOffset: Long
+)
+
+/**
+ * Values from the payload_properties.txt next to a payload, part of [PayloadInfo].
+ *
+ * Constructed from native code; see `PAYLOAD_PROPERTIES_CTOR` in jni_bindings.rs.
+ *
+ * @property fileHash FILE_HASH, or null if absent
+ * @property fileSize FILE_SIZE, or -1 if absent
+ * @property metadataHash METADATA_HASH, or null if absent
+ * @property metadataSize METADATA_SIZE, or -1 if absent
+ */
+class PayloadProperties(
+    val fileHash: String?,
+    val fileSize: Long,
+    val metadataHash: String?,
+    val metadataSize: Long
+)
+
+/**
+ * Inspection result of a payload.bin, as returned by [NativeLib.inspectPayloadInfo].
+ *
+ * The typed counterpart of the [NativeLib.inspectPayload] JSON: sizes are exact
+ * `Long`s rather than JSON numbers, which lose precision above 2^53 in
+ * double-based parsers. Constructed from native code; see `PAYLOAD_INFO_CTOR`
+ * in jni_bindings.rs.
+ *
+ * @property header The payload header
+ * @property blockSize Block size of the payload
+ * @property partialUpdate Whether the payload updates only some partitions
+ * @property isIncremental Whether the payload needs a source build
+ * @property updateType "full", "incremental" or "partial"
+ * @property securityPatchLevel Security patch level, or null if absent
+ * @property maxTimestamp Build timestamp (seconds since epoch), or -1 if absent
+ * @property minorVersion Minor version of the payload format, or -1 if absent
+ * @property buildFingerprint Fingerprint of the target build, or null if unavailable
+ * @property totalSize Total size of all partitions in bytes
+ * @property totalSizeHuman Total size in human-readable form
+ * @property filePath Path that was inspected
+ * @property properties payload_properties.txt values, or null if not found
+ * @property metadataVerified Whether the header and manifest match METADATA_SIZE and
+ *   METADATA_HASH, or null if those are unavailable
+ */
+class PayloadInfo(
+    val header: PayloadHeader,
+    val blockSize: Int,
+    val partialUpdate: Boolean,
+    val isIncremental: Boolean,
+    val updateType: String,
+    val securityPatchLevel: String?,
+    val maxTimestamp: Long,
+    val minorVersion: Int,
+    val buildFingerprint: String?,
+    partitions: Array<PartitionInfo>,
+    val totalSize: Long,
+    val totalSizeHuman: String,
+    val filePath: String,
+    val properties: PayloadProperties?,
+    val metadataVerified: Boolean?
+) {
+    /** Partitions sorted by name */
+    val partitions: List<PartitionInfo> = partitions.asList()
+
+    override fun toString(): String =
+        "PayloadInfo(path=$filePath, partitions=${partitions.size}, totalSize=$totalSizeHuman)"
+}
+
 /**
  * Native Library interface for PayloadPack.
  *
  * This object provides Kotlin bindings to the Rust native library.
  * All native functions are implemented in Rust via JNI.
  *
- * @see src/main/rust/src/lib.rs for the Rust implementation
+ * @see src/main/rust/src/jni_bindings.rs for the Rust implementation
  */
 object NativeLib {
 
+    /**
+     * `currentFile` reported to a [ProgressListener] while the payload manifest is
+     * being parsed, before the first partition starts. Show it as e.g.
+     * "Reading payload…"; it is never a partition name.
+     */
+    const val PARSING_MANIFEST = "<manifest>"
+
     /**
      * Flag to track if the native library has been loaded.
      */
@@ -77,14 +333,83 @@ object NativeLib {
     @JvmStatic
     external fun processMessage(input: String): String?
 
+    /**
+     * List what this build of the native library can extract.
+     *
+     * Meant for warning before an extraction fails: [inspectPayload] already
+     * cross-references a payload's "operation_types" with this list in
+     * "unsupported_operation_types".
+     *
+     * Example response:
+     * ```json
+     * {
+     *   "version": "0.1.0",
+     *   "operations": ["REPLACE", "REPLACE_BZ", "ZERO", "DISCARD", "REPLACE_XZ", "ZSTD",
+     *                  "REPLACE_BROTLI"],
+     *   "source_operations": ["SOURCE_COPY", "SOURCE_BSDIFF", "BROTLI_BSDIFF"],
+     *   "unsupported_operations": ["MOVE", "BSDIFF", "PUFFDIFF", "ZUCCHINI",
+     *                              "LZ4DIFF_BSDIFF", "LZ4DIFF_PUFFDIFF"],
+     *   "compressions": ["bzip2", "xz", "zstd", "brotli"]
+     * }
+     * ```
+     * "source_operations" are the delta operations of incremental payloads, applied
+     * with [applyIncremental] or the `source_dir` option of [extractPayloadWithOptions].
+     *
+     * @return JSON string as above, or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun getCapabilities(): String?
+
     /**
      * Inspect a payload.bin file and extract partition information.
      *
      * This function parses the payload header and manifest to extract:
      * - Payload version
      * - Block size
-     * - List of partitions with names and sizes
-     * - Total size of all partitions
+     * - Kind of update: "update_type" is "full" (flashable on its own), "incremental"
+     *   (needs the exact source build; "is_incremental" is true) or "partial"
+     * - List of partitions with names, sizes, hashes, block counts and compression
+     *   ("compression_ratio" is the payload data size over the image size, null if empty)
+     *   ("old_size"/"old_hash" are only set for incremental payloads)
+     * - Total size of all partitions, plus "partition_count" and "total_operations"
+     *   (operations over all partitions), e.g. to size progress tracking up front
+     * - Operation counts by type ("operation_types", e.g. `{"REPLACE_XZ": 812,
+     *   "ZERO": 3}`) per partition and over the whole payload. Types this build
+     *   doesn't know show up as e.g. "UNKNOWN(12)". "unsupported_operation_types"
+     *   lists the payload's types this build can't apply (see [getCapabilities]),
+     *   which explains failed extractions; warn before extracting if it isn't empty.
+     * - Build identification: "max_timestamp" (build time, seconds since epoch),
+     *   "minor_version", "security_patch_level", per-partition "version" and
+     *   "build_fingerprint" (from META-INF/com/android/metadata of the OTA package,
+     *   null when unavailable)
+     * - Anti-rollback: "max_timestamp_utc" (the build time as a UTC date) and
+     *   "ota_downgrade" (true for downgrade packages, null without OTA metadata).
+     *   A device refuses the payload when "max_timestamp" is older than its
+     *   `Build.TIME / 1000`, unless "ota_downgrade" is true; nothing is checked
+     *   when "max_timestamp" is null.
+     * - Dynamic partition groups and their size limits ("dynamic_partitions", null
+     *   for payloads without dynamic partitions)
+     * - APEX modules updated by the payload ("apex_packages", empty if none)
+     * - Per-partition dm-verity metadata ("verity": hash tree algorithm, hex salt and
+     *   extents, FEC presence and roots; null when the partition has none)
+     * - "properties": FILE_HASH, FILE_SIZE, METADATA_HASH and METADATA_SIZE from
+     *   payload_properties.txt, plus any other keys in "extra" (e.g.
+     *   `{"SWITCH_SLOT_ON_REBOOT": "0"}`); null if no properties file was found
+     * - "properties_source": the payload_properties.txt that was read (null if none)
+     * - "metadata_verified": whether the header and manifest match METADATA_SIZE and
+     *   METADATA_HASH from payload_properties.txt (null if those are not available).
+     *   A cheap integrity check; use [verifyPayloadIntegrity] to hash the whole file.
+     * - "is_signed": whether the header declares a metadata signature
+     *   ("metadata_signature_size" > 0). Unsigned payloads (test builds, repacks)
+     *   inspect and extract normally; [verifyMetadataSignature] reports an error for them.
+     * - "signatures": the metadata and payload signatures with their size and the
+     *   algorithm inferred from it (e.g. "RSA-2048/SHA-256", "ECDSA-P256/SHA-256"),
+     *   empty for unsigned payloads. Not verified against any key. The payload
+     *   signature is left out for DEFLATE-compressed zips and remote URLs, where
+     *   reading the end of the data would mean decompressing or downloading it all.
+     * - "signatures_offset"/"signatures_size": where the payload signature blob sits,
+     *   relative to "data_offset" of the header (null if unsigned). Extraction rejects
+     *   payloads whose operation data overlaps it.
      *
      * Memory-efficient: Only reads header and manifest, not the entire file.
      *
@@ -94,25 +419,348 @@ object NativeLib {
      * Example success response:
      * ```json
      * {
-     *   "header": { "version": 2, "manifest_size": 1234567, "metadata_signature_size": 256 },
+     *   "header": { "version": 2, "manifest_size": 1234567, "metadata_signature_size": 256, "data_offset": 1234847 },
      *   "block_size": 4096,
+     *   "partial_update": false,
+     *   "is_incremental": false,
+     *   "update_type": "full",
+     *   "security_patch_level": "2024-01-05",
+     *   "max_timestamp": 1704067200,
+     *   "max_timestamp_utc": "2024-01-01T00:00:00Z",
+     *   "ota_downgrade": false,
+     *   "minor_version": 0,
+     *   "build_fingerprint": "google/oriole/oriole:14/UQ1A.240105.004/11206848:user/release-keys",
      *   "partitions": [
-     *     { "name": "system", "size": 2147483648, "size_human": "2.00 GB", "operations_count": 4521 },
-     *     { "name": "vendor", "size": 536870912, "size_human": "512.00 MB", "operations_count": 1234 }
+     *     { "name": "system", "size": 2147483648, "size_human": "2.00 GB", "operations_count": 4521,
+     *       "operation_types": { "REPLACE_XZ": 4390, "ZERO": 131 },
+     *       "hash": "9f86d0...", "old_size": null, "old_hash": null, "total_blocks": 524288,
+     *       "compressed_size": 858993459, "compression_ratio": 0.4,
+     *       "verity": { "hash_tree_algorithm": "sha256", "hash_tree_salt": "5f3a...",
+     *         "hash_tree_data_extent": { "start_block": 0, "num_blocks": 520192 },
+     *         "hash_tree_extent": { "start_block": 520192, "num_blocks": 4104 },
+     *         "has_fec": true, "fec_extent": { "start_block": 524296, "num_blocks": 4112 },
+     *         "fec_roots": 2 } },
+     *     { "name": "vendor", "size": 536870912, "size_human": "512.00 MB", "operations_count": 1234,
+     *       "operation_types": { "REPLACE_XZ": 1234 },
+     *       "hash": "2c26b4...", "old_size": null, "old_hash": null, "total_blocks": 131072,
+     *       "compressed_size": 268435456, "compression_ratio": 0.5, "verity": null }
      *   ],
+     *   "partition_count": 2,
+     *   "total_operations": 5755,
      *   "total_size": 3221225472,
-     *   "total_size_human": "3.00 GB"
+     *   "total_size_human": "3.00 GB",
+     *   "operation_types": { "REPLACE_XZ": 5624, "ZERO": 131 },
+     *   "unsupported_operation_types": [],
+     *   "properties_source": "/sdcard/PayloadPack/payload_properties.txt",
+     *   "metadata_verified": true,
+     *   "dynamic_partitions": {
+     *     "groups": [
+     *       { "name": "qti_dynamic_partitions", "max_size": 9122611200,
+     *         "partitions": ["system", "vendor"] }
+     *     ],
+     *     "snapshot_enabled": true,
+     *     "vabc_enabled": true
+     *   },
+     *   "apex_packages": [
+     *     { "name": "com.android.tzdata", "version": 340090000, "is_compressed": true,
+     *       "decompressed_size": 1048576 }
+     *   ],
+     *   "is_signed": true,
+     *   "signatures": [
+     *     { "kind": "metadata", "version": null, "signature_size": 256, "key_bits": 2048,
+     *       "algorithm": "RSA-2048/SHA-256" },
+     *     { "kind": "payload", "version": null, "signature_size": 256, "key_bits": 2048,
+     *       "algorithm": "RSA-2048/SHA-256" }
+     *   ],
+     *   "signatures_offset": 3006477312,
+     *   "signatures_size": 267
      * }
      * ```
      *
      * Example error response:
      * ```json
-     * { "error": "Payload inspection error: Invalid magic bytes" }
+     * { "error": "Invalid magic bytes: expected 'CrAU' ...", "code": "INVALID_MAGIC",
+     *   "code_number": 4 }
      * ```
+     * When the file is a ZIP archive (usually the whole OTA package picked by mistake)
+     * or gzip file, the INVALID_MAGIC message says so and what to do instead, e.g. use
+     * [inspectPayloadZip]; it is suitable to show to the user.
+     *
+     * Every error response of this library has a "code" from [NativeErrorCode] and
+     * the same code as an integer from [NativeErrorNumber] in "code_number";
+     * branch on either rather than on the message, which is meant for logs.
+
+     * Every size and offset field in the JSON of this library ("size", "total_size",
+     * "data_offset", "bytes_processed", ...) comes with a string copy suffixed
+     * "_str", e.g. `"total_size": 3221225472, "total_size_str": "3221225472"`.
+     * Read the string copy when parsing with doubles (JavaScript, Gson into
+     * `Double`), which are exact only up to 2^53 — or use [inspectPayloadInfo].
      */
     @JvmStatic
     external fun inspectPayload(path: String): String?
 
+    /**
+     * Inspect a payload.bin file like [inspectPayload], giving up after [timeoutMs].
+     *
+     * Use it for payloads on network or FUSE-backed storage, where a hung read
+     * would otherwise block the calling thread indefinitely.
+     *
+     * @param path Absolute path to the payload.bin file
+     * @param timeoutMs Time limit in milliseconds; 0 or less waits indefinitely
+     * @return JSON string with the same shape as [inspectPayload], or JSON with an
+     *         "error" field; code [NativeErrorCode.TIMEOUT] if the time ran out
+     */
+    @JvmStatic
+    external fun inspectPayloadWithTimeout(path: String, timeoutMs: Long): String?
+
+    /**
+     * Inspect a payload.bin file like [inspectPayload], with a different limit on the
+     * manifest size than the default 100 MiB.
+     *
+     * Raise it for payloads whose manifest legitimately exceeds the default (very
+     * many partitions or APEX entries); lower it to cap the memory an untrusted
+     * payload can make the inspection allocate.
+     *
+     * @param path Absolute path to the payload.bin file
+     * @param maxManifestSize Largest manifest accepted in bytes; 0 or less uses the default
+     * @return JSON string with the same shape as [inspectPayload], or JSON with an
+     *         "error" field; code [NativeErrorCode.MANIFEST_TOO_LARGE] if the manifest
+     *         is larger than [maxManifestSize]
+     */
+    @JvmStatic
+    external fun inspectPayloadWithManifestLimit(path: String, maxManifestSize: Long): String?
+
+    /**
+     * Inspect several payload.bin files in one call, e.g. to compare the OTAs in a
+     * directory side by side.
+     *
+     * @param paths Paths to the payload.bin files
+     * @return JSON array with one element per path, in the same order:
+     * ```json
+     * [
+     *   { "path": "/sdcard/a/payload.bin", "success": true, "inspection": { ... } },
+     *   { "path": "/sdcard/b/payload.bin", "success": false,
+     *     "error": "File not found: ...", "code": "FILE_NOT_FOUND" }
+     * ]
+     * ```
+     * where "inspection" is what [inspectPayload] returns for that file. A file that
+     * can't be inspected doesn't affect the others.
+     */
+    @JvmStatic
+    external fun inspectPayloads(paths: Array<String>): String?
+
+    /**
+     * List the partition names in a payload.bin file.
+     *
+     * Much cheaper than [inspectPayload]: only the manifest is decoded, without
+     * reading payload_properties.txt or computing sizes. Use it to populate a
+     * partition picker. Names are returned in manifest order.
+     *
+     * @param path Path to the payload.bin file
+     * @return JSON string `{"partitions": ["boot", "system", ...]}`, or JSON with
+     *         "error" field on failure
+     */
+    @JvmStatic
+    external fun listPartitions(path: String): String?
+
+    /**
+     * Estimate what extracting a payload.bin will cost, before starting it.
+     *
+     * Only the manifest is read. Byte counts are exact; the time is the image
+     * bytes to write divided by [bytesPerSecond], so it is a rough figure for
+     * showing e.g. "~4 minutes" in a confirmation dialog. The per-partition
+     * breakdown is in manifest order.
+     *
+     * @param path Path to the payload.bin file
+     * @param bytesPerSecond Assumed write throughput; 0 uses the default of 40 MiB/s
+     * @return JSON string with "compressed_bytes", "uncompressed_bytes",
+     *         "estimated_seconds", "bytes_per_second" and "partitions" (each with
+     *         "name" and the same three figures), or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun estimateExtraction(path: String, bytesPerSecond: Long): String?
+
+    /**
+     * Locate each partition's operation data within a payload.bin.
+     *
+     * For external tools that read partition blobs straight from the file. Offsets
+     * are absolute file offsets. "span" runs from a partition's first blob to the
+     * end of its last one; "compressed_size" is the sum of its blobs, smaller than
+     * the span only when other partitions' data is interleaved. Partitions without
+     * data (only ZERO/DISCARD operations) have a null "offset".
+     *
+     * @param path Path to the payload.bin file
+     * @return JSON string `{"data_offset": ..., "block_size": 4096, "layout": [{"name":
+     *         "boot", "offset": ..., "span": ..., "compressed_size": ...,
+     *         "operations_count": ...}, ...]}` in manifest order, or JSON with
+     *         "error" field on failure
+     */
+    @JvmStatic
+    external fun inspectPayloadLayout(path: String): String?
+
+    /**
+     * Read the fixed header of a payload.bin and the offsets derived from it, e.g.
+     * to script tools that need the exact position of the partition data.
+     *
+     * Only the header is read. "data_offset" is header + manifest + metadata
+     * signature, where operation data begins; "header_bytes" is the raw header in
+     * hex, and "manifest_offset", "metadata_signature_offset", "file_size" and
+     * "data_size" (bytes from "data_offset" to the end of the file) complete it.
+     *
+     * @param path Path to the payload.bin file
+     * @return JSON string `{"version": 2, "manifest_size": ..., "metadata_signature_size":
+     *         ..., "data_offset": ..., "header_bytes": "43724155...", ...}`, or JSON
+     *         with "error" field on failure
+     */
+    @JvmStatic
+    external fun readPayloadHeader(path: String): String?
+
+    /**
+     * Compare the partitions of two payloads, e.g. to write release notes between
+     * two builds of a ROM. Only the manifests are read, so this is quick even for
+     * multi-GB payloads.
+     *
+     * "added" and "removed" list the partitions only in [pathB] or only in [pathA],
+     * with the same fields as the partitions of [inspectPayload]. "partitions" has
+     * one entry per partition in both: "size_a", "size_b", "size_delta" (negative if
+     * it shrank), "hash_a", "hash_b" and "hash_changed" (null when either manifest
+     * has no hash), and "changed" when the size or hash differs. "changed_count",
+     * "total_size_a", "total_size_b" and "total_size_delta" sum it up; these sizes
+     * and deltas have "_str" copies too. Each list is sorted by partition name.
+     *
+     * @param pathA Path to the older payload.bin
+     * @param pathB Path to the newer payload.bin
+     * @return JSON string with the comparison, or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun diffPayloads(pathA: String, pathB: String): String?
+
+    /**
+     * Write the raw protobuf manifest of a payload.bin to a file.
+     *
+     * Useful for diffing manifests between builds. The bytes are copied as they
+     * are, so even a manifest that fails to decode can be dumped.
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outPath File to write the manifest to
+     * @param decoded Also write the decoded manifest as text to `outPath + ".txt"`
+     * @return JSON string `{"path": ..., "size": ..., "decoded_path": ...}`
+     *         ("decoded_path" is null unless [decoded]), or JSON with "error"
+     *         field on failure
+     */
+    @JvmStatic
+    external fun dumpManifest(payloadPath: String, outPath: String, decoded: Boolean): String?
+
+    /**
+     * Inspect a payload.bin file and return its partitions as typed objects.
+     *
+     * Carries the same per-partition data as [inspectPayload] without the JSON
+     * round trip, which matters for payloads with many partitions.
+     *
+     * @param path Path to the payload.bin file
+     * @return Partitions sorted by name, like the "partitions" list of [inspectPayload]
+     * @throws java.io.IOException if the payload cannot be read or parsed
+     */
+    @JvmStatic
+    @Throws(java.io.IOException::class)
+    external fun inspectPartitions(path: String): Array<PartitionInfo>
+
+    /**
+     * Inspect a payload.bin file and return the result as typed objects.
+     *
+     * Carries the header, build information, partitions and payload_properties.txt
+     * values of [inspectPayload] without any JSON, keeping 64-bit sizes and offsets
+     * exact.
+     *
+     * @param path Path to the payload.bin file
+     * @return The inspection result
+     * @throws java.io.IOException if the payload cannot be read or parsed
+     */
+    @JvmStatic
+    @Throws(java.io.IOException::class)
+    external fun inspectPayloadInfo(path: String): PayloadInfo
+
+    /**
+     * Summarize a payload.bin as human-readable text for display.
+     *
+     * Formats the key facts of [inspectPayload]: Android release (from the build
+     * fingerprint), partition count, total size, kind of update and security patch
+     * level. Facts that aren't known for the payload are left out.
+     *
+     * @param path Path to the payload.bin file
+     * @param multiline One fact per line instead of a single comma-separated line
+     * @return e.g. "Android 14, 28 partitions, 6.20 GB total, full update, security
+     *         patch 2024-03-05"
+     * @throws java.io.IOException if the payload cannot be read or parsed
+     */
+    @JvmStatic
+    @Throws(java.io.IOException::class)
+    external fun inspectPayloadSummary(path: String, multiline: Boolean): String
+
+    /**
+     * Convert a raw partition image to the Android sparse format.
+     *
+     * Zero-filled blocks are left out of the file (DONT_CARE chunks), so the result
+     * is smaller and can be flashed directly with `fastboot flash`.
+     *
+     * @param rawImagePath Path of the raw image, e.g. an extracted system.img
+     * @param sparseOutputPath Where to write the sparse image (must differ from the input)
+     * @return JSON string `{"path", "raw_size", "sparse_size", "block_size",
+     *         "total_blocks", "total_chunks", "data_blocks"}`, or JSON with "error"
+     *         field on failure
+     */
+    @JvmStatic
+    external fun convertToSparse(rawImagePath: String, sparseOutputPath: String): String?
+
+    /**
+     * Assemble a super image from extracted dynamic partition images.
+     *
+     * Lays out the partitions of the payload's dynamic partition groups with LP
+     * metadata, as `lpmake` would, taking each image from `<imagesDir>/<name>.img`.
+     * Partitions get one extent each, aligned to 1 MiB, and keep their names as in
+     * the manifest (no slot suffix). The image is sized to hold every group at its
+     * maximum size; unused space is left as holes in the file.
+     *
+     * @param payloadPath Path to the payload.bin the images were extracted from
+     * @param imagesDir Directory holding the extracted images
+     * @param outPath Where to write super.img (overwritten)
+     * @return JSON string `{"path", "size", "metadata_max_size", "metadata_slot_count",
+     *         "partitions": [{"name", "group", "offset", "size"}]}`, or JSON with "error"
+     *         field on failure (e.g. "INVALID_INPUT" when a group's partitions exceed its
+     *         maximum size, "FILE_NOT_FOUND" when an image is missing)
+     */
+    @JvmStatic
+    external fun buildSuperImage(payloadPath: String, imagesDir: String, outPath: String): String?
+
+    /**
+     * Build a full payload.bin from partition images, e.g. after modifying an extracted image.
+     *
+     * Each image becomes one partition, split into 2 MiB operations: REPLACE with
+     * `compression` "none", or REPLACE_XZ where it makes the data smaller with "xz"
+     * (much slower). Blocks of zeros are stored as ZERO operations. Images must be a
+     * whole number of 4096-byte blocks.
+     *
+     * The payload is UNSIGNED: it has no metadata or payload signature, so stock
+     * recovery and update_engine on a locked device will reject it. It can be
+     * inspected and extracted again, and flashed with tools that don't check
+     * signatures.
+     *
+     * @param names Partition names, in manifest order
+     * @param imagePaths Image of each partition, same length as [names]
+     * @param outPath Where to write the payload (overwritten)
+     * @param compression "none" or "xz"
+     * @return JSON with "path", "size", "manifest_size", "signed" (always false) and
+     *         "partitions" (name, size, data_size, operations_count, hash), or JSON
+     *         with "error" field on failure
+     */
+    @JvmStatic
+    external fun createPayload(
+        names: Array<String>,
+        imagePaths: Array<String>,
+        outPath: String,
+        compression: String
+    ): String?
+
     /**
      * Extract partition images from a payload.bin file.
      *
@@ -121,7 +769,8 @@ object NativeLib {
      *
      * @param payloadPath Path to the payload.bin file
      * @param outputDir Directory where .img files will be written (created if doesn't exist)
-     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @param progressListener Callback for progress updates (can be null for no progress).
+     *        Pass a [ThroughputProgressListener] to also receive throughput and ETA.
      * @return JSON string with extraction result
      *
      * Example success response:
@@ -129,17 +778,33 @@ object NativeLib {
      * {
      *   "status": "success",
      *   "extracted": [
-     *     {"name": "system", "size": 2147483648, "path": "/data/PayloadPack/project/system.img"},
-     *     {"name": "vendor", "size": 536870912, "path": "/data/PayloadPack/project/vendor.img"}
-     *   ]
+     *     {"name": "system", "size": 2147483648, "path": "/data/PayloadPack/project/system.img",
+     *      "verified": true, "expected_hash": "9f86d081...", "actual_hash": "9f86d081..."},
+     *     {"name": "vendor", "size": 536870912, "path": "/data/PayloadPack/project/vendor.img",
+     *      "verified": false, "expected_hash": "2c26b46b...", "actual_hash": "fcde2b2e..."}
+     *   ],
+     *   "skipped": [],
+     *   "failed": [],
+     *   "warnings": [],
+     *   "empty_partitions": [],
+     *   "excluded": [],
+     *   "linked": []
      * }
      * ```
      *
+     * "warnings" lists operations of a type this build doesn't know, whose data was
+     * written unprocessed, e.g. `{"partition": "odm", "operation_index": 12,
+     * "operation_type": "UNKNOWN(15)"}`. Treat the named images as unreliable.
+     * "empty_partitions" names partitions of size 0 (placeholders or removed
+     * partitions); no image file is created for them. "excluded" names the partitions
+     * left out by the `exclude` option of [extractPayloadWithOptions].
+     *
      * Example error response:
      * ```json
      * {
      *   "status": "error",
-     *   "message": "Failed to write partition: Permission denied"
+     *   "message": "Permission denied: Failed to write partition",
+     *   "code": "PERMISSION_DENIED"
      * }
      * ```
      */
@@ -149,4 +814,538 @@ object NativeLib {
         outputDir: String,
         progressListener: ProgressListener?
     ): String?
+
+    /**
+     * Apply an incremental OTA payload to the images of the build it updates from.
+     *
+     * [sourceDir] must hold the source build's partition images named
+     * `<partition>.img` (e.g. dumped from the device, or extracted from the source
+     * build's full OTA). SOURCE_COPY, SOURCE_BSDIFF and BROTLI_BSDIFF operations
+     * are applied to them; payloads using other diff operations (PUFFDIFF, ZUCCHINI,
+     * LZ4DIFF_*) fail before anything is written, with code
+     * [NativeErrorCode.OPERATION_FAILED]. Each new image is checked against the
+     * manifest hash: "verified": false usually means a source image is from a
+     * different build. Can be cancelled with [cancelCurrentExtraction].
+     *
+     * @param payloadPath Path to the incremental payload.bin
+     * @param sourceDir Directory with the source build's `<partition>.img` files
+     * @param outputDir Directory where the new images will be written
+     * @param progressListener Callback for progress updates (can be null)
+     * @return JSON string with the same shape as [extractPayload]; code
+     *         [NativeErrorCode.FILE_NOT_FOUND] if [sourceDir] doesn't exist
+     */
+    @JvmStatic
+    external fun applyIncremental(
+        payloadPath: String,
+        sourceDir: String,
+        outputDir: String,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Extract partition images from a payload.bin file with extra options.
+     *
+     * Behaves like [extractPayload]; [optionsJson] is a JSON object whose fields
+     * are all optional:
+     * ```json
+     * {
+     *   "max_threads": 4,
+     *   "partitions": ["boot", "vendor_boot"],
+     *   "exclude": ["userdata", "vendor_*"],
+     *   "verify_operations": true,
+     *   "source_dir": "/sdcard/PayloadPack/stock",
+     *   "progress_interval_ms": 100,
+     *   "progress_min_bytes": 0,
+     *   "dry_run": false,
+     *   "use_mmap": false,
+     *   "output_sparse": false,
+     *   "force": false,
+     *   "on_existing": "overwrite",
+     *   "filename_template": "{name}.img",
+     *   "decompress_threads": 1,
+     *   "create_empty": false,
+     *   "write_buffer_size": 1048576,
+     *   "raw": false,
+     *   "block_size": 4096,
+     *   "progress_unit": "output",
+     *   "timeout_ms": 0,
+     *   "continue_on_error": false,
+     *   "drop_page_cache": false,
+     *   "link_duplicates": false
+     * }
+     * ```
+     * `exclude` leaves out partitions by name or pattern, e.g. to skip large
+     * partitions like userdata from a full OTA: `*` matches any run of characters
+     * and `?` a single one. It applies after `partitions`; excluded partitions are
+     * listed in "excluded" of the result and don't count towards progress.
+     * `filename_template` names the output images: `{name}` is replaced with the
+     * partition name and `{index}` with its position in the manifest, e.g.
+     * "{name}_a.img" for slot-suffixed files. It must contain `{name}` and may not
+     * contain path separators.
+     * `decompress_threads` decompresses up to that many consecutive REPLACE_XZ
+     * operations of a partition in parallel, holding each blob and its output
+     * (at most the size of its destination) in memory. On a single core, 2 or 4
+     * threads were about 7% slower than 1, and multi-core devices haven't been
+     * measured yet, so it defaults to 1 (streaming).
+     * `create_empty` writes a 0-byte image for each partition of size 0, for
+     * workflows that need a placeholder file, instead of only listing it in
+     * "empty_partitions".
+     * `write_buffer_size` sets the buffer in front of each output image in bytes
+     * (default 1 MiB); small operations are then written in fewer, larger writes.
+     * Unbuffered, an image of 4 KiB operations took about 25% longer; 64 KiB, 1 MiB
+     * and 4 MiB were within a few percent of each other.
+     * `raw` is for forensics and debugging: instead of images, each output file holds
+     * the partition's operation blobs exactly as stored in the payload (still
+     * compressed), back to back, and a `<file>.ops.json` sidecar gives each
+     * operation's "operation_type", "offset" in the file, "payload_offset", "length"
+     * and extents. Such files are not flashable; consider a "filename_template" like
+     * "{name}.raw". Cannot be combined with `output_sparse`.
+     * `block_size` overrides the block size from the manifest when placing
+     * operation data, to recover images from payloads whose manifest gets it wrong.
+     * It must be a power of two between 512 and 65536; leave it out to use the
+     * manifest's value.
+     * `progress_unit` picks what "bytes_processed" and "total_bytes" of progress
+     * updates count: "output" (default) is bytes of image written, "compressed" is
+     * bytes of payload data read. A partition of mostly zeros fills up in a flash
+     * under "output", so "compressed" gives a percentage that tracks the time left
+     * more closely.
+     * `timeout_ms` bounds the whole extraction, for payloads on network or FUSE
+     * storage where a read can hang: once it elapses the extraction is cancelled,
+     * images it didn't finish are deleted and the error code is
+     * [NativeErrorCode.TIMEOUT]. 0 (the default) waits indefinitely.
+     * `continue_on_error` keeps going when a partition fails (an unsupported
+     * operation, corrupt data, an I/O error) so the partitions that do work are still
+     * extracted. The result then has status "partial" and a "failed" array of
+     * `{"name", "error", "code", "code_number"}` entries; partial images of failed
+     * partitions are deleted. Cancellation still stops the whole extraction.
+     * `drop_page_cache` writes each finished image back to storage and tells the
+     * kernel to evict it from the page cache. Without it, extracting several GB
+     * fills RAM with cached image data and pushes other apps out of memory; with it,
+     * each partition costs an extra sync, so leave it off for small payloads.
+     * `link_duplicates` saves space when several partitions have identical images:
+     * once everything is extracted, each duplicate is replaced with a hard link to
+     * the first image with the same hash. "linked" of the result lists them as
+     * `{"name", "same_as", "hard_linked", "error"}`; on storage without hard links
+     * (e.g. FAT-formatted SD cards) "hard_linked" is false and the copy is kept.
+     * `on_existing` protects images already in [outputDir]: "overwrite" (default)
+     * replaces them, "skip" leaves them and lists the partition under "skipped", and
+     * "error" fails before anything is written.
+     * Progress is checkpointed in the output directory: calling this again after
+     * an interrupted extraction skips partitions that were already completed, once
+     * their images pass a size and SHA-256 check. They are reported with
+     * `"resumed": true`. Set `force` to extract everything again.
+     * `output_sparse` writes Android sparse images that can be flashed with
+     * `fastboot flash`; hashes are still checked against the raw image first.
+     * `use_mmap` memory-maps the payload to avoid a seek and read per operation.
+     * It is off by default: in a benchmark of 4 KiB and 16 MiB operations it was
+     * about 3% slower than regular reads, not faster.
+     * Multi-GB mappings may not fit in a 32-bit process; extraction then silently
+     * falls back to regular reads. Ignored for payloads inside an OTA zip.
+     * With `dry_run` set, no file is created: the result has status "dry_run" and
+     * each entry of "extracted" gives the would-be image size plus `supported` and
+     * `unsupported_reason`, so unsupported operations can be flagged up front.
+     * Progress callbacks are throttled to at most one per `progress_interval_ms`
+     * (default 100 ms), plus one every `progress_min_bytes` when non-zero. The final
+     * 100% callback always fires.
+     * With `max_threads` > 1, independent partitions are extracted concurrently
+     * (default 1, sequential). Progress is aggregated across workers, and the
+     * first failure stops the rest. Can be cancelled with [cancelCurrentExtraction].
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outputDir Directory where .img files will be written (created if doesn't exist)
+     * @param optionsJson Extraction options as JSON, e.g. `{"max_threads": 4}`
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with the same shape as [extractPayload]
+     */
+    @JvmStatic
+    external fun extractPayloadWithOptions(
+        payloadPath: String,
+        outputDir: String,
+        optionsJson: String,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Extract partition images like [extractPayloadWithOptions], reporting each
+     * partition to [partitionListener] as soon as its image is written.
+     *
+     * Lets a result list fill in while the extraction runs instead of all at once
+     * when it returns. Partitions resumed from an interrupted extraction are
+     * reported right at the start. Can be cancelled with [cancelCurrentExtraction];
+     * partitions reported before a failure or cancellation are complete.
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outputDir Directory where .img files will be written (created if doesn't exist)
+     * @param optionsJson Extraction options, as for [extractPayloadWithOptions]
+     * @param partitionListener Called with each completed partition
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with the same shape as [extractPayload], listing every
+     *         partition again
+     */
+    @JvmStatic
+    external fun extractPayloadStreaming(
+        payloadPath: String,
+        outputDir: String,
+        optionsJson: String,
+        partitionListener: PartitionListener,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Extract a subset of partitions from a payload.bin file.
+     *
+     * Behaves like [extractPayload] but only writes the named partitions, and the
+     * progress percentage is based on their combined size. Can be cancelled with
+     * [cancelCurrentExtraction].
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outputDir Directory where .img files will be written (created if doesn't exist)
+     * @param partitionNames Partitions to extract, e.g. `arrayOf("boot", "vendor_boot", "dtbo")`
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with extraction result. Requested names that are not in the
+     *         payload are listed in "skipped":
+     * ```json
+     * {
+     *   "status": "success",
+     *   "extracted": [ {"name": "boot", "size": 100663296, "path": "...", "verified": true, ...} ],
+     *   "skipped": [ {"name": "vendor_bot", "reason": "not found in payload"} ]
+     * }
+     * ```
+     * If none of [partitionNames] are in the payload, an error response is returned
+     * whose message lists the available partitions in sorted order.
+     */
+    @JvmStatic
+    external fun extractPayloadSelective(
+        payloadPath: String,
+        outputDir: String,
+        partitionNames: Array<String>,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Extract a single partition image from a payload.bin file.
+     *
+     * Only the operations of the requested partition are processed, so extracting
+     * e.g. boot.img is far faster than a full [extractPayload].
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outputDir Directory where `<partitionName>.img` will be written
+     * @param partitionName Name of the partition to extract (e.g. "boot", "init_boot")
+     * @return JSON string with the same shape as [extractPayload], with a single
+     *         entry in "extracted". If the partition doesn't exist, the error
+     *         message lists the available partitions.
+     */
+    @JvmStatic
+    external fun extractSinglePartition(
+        payloadPath: String,
+        outputDir: String,
+        partitionName: String
+    ): String?
+
+    /**
+     * Extract a single small partition into memory, without writing anything to storage.
+     *
+     * Meant for reading e.g. the boot image header or kernel version. The image hash
+     * is checked against the manifest like [extractSinglePartition], but a mismatch
+     * is only logged.
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param partitionName Name of the partition to extract (e.g. "boot", "init_boot")
+     * @param maxSize Largest image accepted in bytes, or 0 for the default of 128 MiB.
+     *        Larger partitions are refused rather than risking an OutOfMemoryError.
+     * @return The partition image
+     * @throws java.io.IOException if the payload cannot be read, the partition doesn't
+     *         exist or is larger than [maxSize]
+     */
+    @JvmStatic
+    @Throws(java.io.IOException::class)
+    external fun extractPartitionToBytes(payloadPath: String, partitionName: String, maxSize: Long): ByteArray
+
+    /**
+     * Read the first bytes of a partition into memory.
+     *
+     * Only the operations writing into the first [maxBytes] are decompressed, so
+     * checking e.g. the filesystem magic of system or the header of vendor_boot
+     * doesn't cost a full extraction. No hash is checked.
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param partitionName Name of the partition to read (e.g. "system")
+     * @param maxBytes Number of bytes wanted from the start of the image, at most 128 MiB
+     * @return The first [maxBytes] of the image, fewer if the image is smaller
+     * @throws java.io.IOException if the payload cannot be read, the partition doesn't
+     *         exist or [maxBytes] is larger than 128 MiB
+     */
+    @JvmStatic
+    @Throws(java.io.IOException::class)
+    external fun extractPartitionPrefix(payloadPath: String, partitionName: String, maxBytes: Long): ByteArray
+
+    /**
+     * Verify that a payload's header and manifest are signed by the given key.
+     *
+     * Uses the metadata signature embedded in the payload (RSA PKCS#1 v1.5 over
+     * SHA-256, as produced by AOSP's signing tools). Run this before flashing to
+     * make sure the payload is authentic.
+     *
+     * @param path Path to the payload.bin file
+     * @param publicKeyPem RSA public key in PEM form ("BEGIN PUBLIC KEY" or
+     *        "BEGIN RSA PUBLIC KEY")
+     * @return `{"valid": true}` / `{"valid": false}`, or JSON with "error" field
+     *         if the payload is unsigned or the key can't be parsed
+     */
+    @JvmStatic
+    external fun verifyMetadataSignature(path: String, publicKeyPem: String): String?
+
+    /**
+     * Verify a whole payload.bin against the payload_properties.txt next to it.
+     *
+     * Streams the entire file through SHA-256 and compares it with FILE_HASH, and
+     * its length with FILE_SIZE. Catches truncated or corrupted downloads before
+     * extraction. Takes a while for multi-GB payloads; progress is reported through
+     * [progressListener] with the payload file name as the current file, and
+     * [cancelCurrentExtraction] stops it after the current 1 MiB chunk.
+     *
+     * Example response:
+     * ```json
+     * { "valid": false, "algorithm": "sha256", "size_matches": false, "hash_matches": false,
+     *   "expected_size": 2147483648, "actual_size": 1073741824,
+     *   "expected_hash": "n4bQgYhMfWWaL+qgxVrQFaO/TxsrC4Is0V1sFbDwCgg=",
+     *   "actual_hash": "LPJNul+wow4m6DsqxbninhsWHlwfp0JecwQzYpOLmCQ=" }
+     * ```
+     * A check is null when the corresponding property is missing.
+     *
+     * @param path Path to the payload.bin file
+     * @param progressListener Callback for hashing progress (can be null)
+     * @return JSON string with the result, or JSON with "error" field if the file
+     *         is unreadable, there is no payload_properties.txt, or the check was
+     *         cancelled (code [NativeErrorCode.CANCELLED])
+     */
+    @JvmStatic
+    external fun verifyPayloadIntegrity(path: String, progressListener: ProgressListener?): String?
+
+    /**
+     * Verify a whole payload.bin like [verifyPayloadIntegrity], with a choice of
+     * checksum.
+     *
+     * "crc32" is the quick check: several times faster than SHA-256, it reliably
+     * detects truncated or damaged copies but offers no protection against deliberate
+     * tampering. payload_properties.txt only carries a SHA-256, so for CRC32 pass the
+     * checksum to compare with, e.g. one recorded when the file was downloaded; the
+     * length is still checked against FILE_SIZE. "sha256" behaves like
+     * [verifyPayloadIntegrity] unless [expectedChecksum] replaces FILE_HASH.
+     *
+     * The result has the same shape as [verifyPayloadIntegrity], with "algorithm"
+     * telling which checksum "actual_hash" is (base64 SHA-256 or 8 hex digits of CRC32).
+     *
+     * @param path Path to the payload.bin file
+     * @param algorithm "sha256" or "crc32"
+     * @param expectedChecksum Checksum to compare with, or null to use FILE_HASH
+     *        (SHA-256 only)
+     * @param progressListener Callback for hashing progress (can be null)
+     * @return JSON string with the result, or JSON with "error" field if the algorithm
+     *         is unknown, the file is unreadable, there is nothing to check against, or
+     *         the check was cancelled (code [NativeErrorCode.CANCELLED])
+     */
+    @JvmStatic
+    external fun verifyPayloadIntegrityWithAlgorithm(
+        path: String,
+        algorithm: String,
+        expectedChecksum: String?,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Inspect a payload.bin hosted on an HTTP(S) server without downloading it.
+     *
+     * Only the header and manifest are fetched, using HTTP range requests. If the
+     * server doesn't support ranges, the download is stopped as soon as the
+     * manifest has been read. Performs network I/O: call it off the main thread.
+     *
+     * @param url http:// or https:// URL of the payload.bin
+     * @return JSON string with the same shape as [inspectPayload] ("properties" is
+     *         null), or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun inspectPayloadUrl(url: String): String?
+
+    /**
+     * Inspect a payload.bin opened through the Storage Access Framework.
+     *
+     * Use with `contentResolver.openFileDescriptor(uri, "r")`. The descriptor is
+     * duplicated natively, so the caller keeps ownership and must still close the
+     * `ParcelFileDescriptor` afterwards.
+     *
+     * @param fd Raw file descriptor of the payload (`ParcelFileDescriptor.fd`)
+     * @return JSON string with the same shape as [inspectPayload] (without
+     *         "properties"), or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun inspectPayloadFd(fd: Int): String?
+
+    /**
+     * Inspect a payload.bin that is already in memory.
+     *
+     * Only the header and manifest are needed, so the start of a download is
+     * enough; a complete payload works as well.
+     *
+     * @param data Payload bytes, starting at the "CrAU" magic
+     * @return JSON string with the same shape as [inspectPayload] (without
+     *         "properties"), or JSON with "error" field on failure
+     */
+    @JvmStatic
+    external fun inspectPayloadBytes(data: ByteArray): String?
+
+    /**
+     * Extract partition images from a payload.bin opened through the Storage
+     * Access Framework, without copying it into app-private storage first.
+     *
+     * The descriptor is duplicated natively; close the `ParcelFileDescriptor`
+     * after this returns. Can be cancelled with [cancelCurrentExtraction].
+     *
+     * @param fd Raw file descriptor of the payload (`ParcelFileDescriptor.fd`)
+     * @param outputDir Directory where .img files will be written (created if doesn't exist)
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with the same shape as [extractPayload]
+     */
+    @JvmStatic
+    external fun extractPayloadFd(
+        fd: Int,
+        outputDir: String,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Extract partition images into file descriptors supplied per partition.
+     *
+     * For writing through the Storage Access Framework when there is no single
+     * writable output directory: [outputProvider] is asked for a descriptor for each
+     * partition just before it is written. Partitions are extracted one at a time,
+     * and "output_sparse" and "timeout_ms" are not available. Can be cancelled
+     * with [cancelCurrentExtraction].
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param optionsJson Options as for [extractPayloadWithOptions], e.g.
+     *        `{"partitions": ["boot", "vendor_boot"]}`
+     * @param outputProvider Opens the output of each partition
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with the same shape as [extractPayload]; each "path" is
+     *         "fd:<n>" for the descriptor the image was written to
+     */
+    @JvmStatic
+    external fun extractPayloadToFds(
+        payloadPath: String,
+        optionsJson: String,
+        outputProvider: OutputFdProvider,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Inspect a payload.bin that was taken out of its OTA package, reading
+     * payload_properties.txt from where it is now.
+     *
+     * @param path Path to the payload.bin file
+     * @param propertiesPath The payload_properties.txt to use, or the original OTA
+     *        package zip, whose payload_properties.txt entry is read. If it can't be
+     *        read, the payload_properties.txt next to [path] is used as in [inspectPayload].
+     * @return JSON string with the same shape as [inspectPayload]; "properties_source"
+     *         is the file the properties came from, or "<zip>!payload_properties.txt"
+     */
+    @JvmStatic
+    external fun inspectPayloadWithProperties(path: String, propertiesPath: String): String?
+
+    /**
+     * Inspect the payload.bin inside an OTA package zip without unzipping it.
+     *
+     * payload.bin is read in place (directly by offset for the usual STORE entries),
+     * and payload_properties.txt is read from the same zip.
+     *
+     * @param zipPath Path to the OTA package (.zip)
+     * @return JSON string with the same shape as [inspectPayload], or JSON with
+     *         "error" field on failure (e.g. no payload.bin in the zip)
+     */
+    @JvmStatic
+    external fun inspectPayloadZip(zipPath: String): String?
+
+    /**
+     * Extract partition images from the payload.bin inside an OTA package zip.
+     *
+     * Behaves like [extractPayload] but skips unzipping the package first, halving
+     * the storage needed. DEFLATE-compressed payload entries are supported but
+     * slower, since they are decompressed on the fly. Can be cancelled with
+     * [cancelCurrentExtraction].
+     *
+     * @param zipPath Path to the OTA package (.zip)
+     * @param outputDir Directory where .img files will be written (created if doesn't exist)
+     * @param progressListener Callback for progress updates (can be null for no progress)
+     * @return JSON string with the same shape as [extractPayload]
+     */
+    @JvmStatic
+    external fun extractPayloadZip(
+        zipPath: String,
+        outputDir: String,
+        progressListener: ProgressListener?
+    ): String?
+
+    /**
+     * Cancel the extraction currently running in [extractPayload] or [extractPayloadSelective].
+     *
+     * Also stops a running [verifyPayloadIntegrity], which returns a CANCELLED error
+     * response as well.
+     *
+     * The native extractor stops at the next operation boundary and deletes the
+     * partially written image. [extractPayload] then returns an error response
+     * with code [NativeErrorCode.CANCELLED].
+     *
+     * @return true if an extraction was running and has been asked to stop
+     */
+    @JvmStatic
+    external fun cancelCurrentExtraction(): Boolean
+
+    /**
+     * Start extracting a payload.bin on a native background thread.
+     *
+     * Returns immediately with a handle instead of blocking and calling back a
+     * listener: poll it with [pollProgress], stop it with [cancelExtraction], and
+     * free it with [releaseExtraction] once the result has been read. Several
+     * extractions can run at once, each with its own handle.
+     *
+     * @param payloadPath Path to the payload.bin file
+     * @param outputDir Directory where .img files will be written
+     * @param optionsJson Same options as [extractPayloadWithOptions], e.g. "{}"
+     * @return A handle (> 0)
+     * @throws java.io.IOException if the options are invalid or the thread can't be started
+     */
+    @JvmStatic
+    external fun beginExtraction(payloadPath: String, outputDir: String, optionsJson: String): Long
+
+    /**
+     * Get the progress of an extraction started with [beginExtraction].
+     *
+     * @param handle Handle returned by [beginExtraction]
+     * @return JSON string with "state" ("running" or "finished"), "current_file",
+     *         "percent", "bytes_processed", "total_bytes", "bytes_per_second",
+     *         "eta_seconds" (null while unknown), "cancelled" and "result". Once
+     *         finished, "result" is the object [extractPayload] would have returned.
+     *         JSON with "error" field for unknown or released handles.
+     */
+    @JvmStatic
+    external fun pollProgress(handle: Long): String?
+
+    /**
+     * Ask the extraction behind [handle] to stop at the next operation boundary.
+     *
+     * [pollProgress] then reports it as finished with an error result.
+     *
+     * @return false if the handle is unknown
+     */
+    @JvmStatic
+    external fun cancelExtraction(handle: Long): Boolean
+
+    /**
+     * Free a handle returned by [beginExtraction], cancelling the extraction if it
+     * is still running. The handle can't be used afterwards.
+     *
+     * @return false if the handle is unknown or already released
+     */
+    @JvmStatic
+    external fun releaseExtraction(handle: Long): Boolean
 }
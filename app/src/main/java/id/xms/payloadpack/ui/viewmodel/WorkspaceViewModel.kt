@@ -217,7 +217,11 @@ class WorkspaceViewModel(private val projectPath: String) : ViewModel() {
                         viewModelScope.launch(Dispatchers.Main) {
                             _uiState.value = _uiState.value.copy(
                                 extractionProgress = progress / 100f,
-                                currentFile = currentFile,
+                                currentFile = if (currentFile == NativeLib.PARSING_MANIFEST) {
+                                    "Reading manifest..."
+                                } else {
+                                    currentFile
+                                },
                                 bytesProcessed = bytesProcessed,
                                 totalBytes = totalBytes
                             )
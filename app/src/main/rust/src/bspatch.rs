@@ -0,0 +1,257 @@
+//! bspatch for SOURCE_BSDIFF and BROTLI_BSDIFF operations
+//!
+//! Applies patches in the two formats update_engine emits:
+//!
+//! - `BSDIFF40`: the classic bsdiff format, all three streams bzip2-compressed.
+//! - `BSDF2`: the 3 bytes after the magic give the compression of each
+//!   stream (0 none, 1 bzip2, 2 brotli). BROTLI_BSDIFF always uses it.
+//!
+//! After the 32-byte header (magic, control length, diff length, new size)
+//! come the control stream, a list of (diff length, extra length, old offset
+//! adjustment) triples; the diff stream, added bytewise to the old data; and
+//! the extra stream, copied as is. Numbers are 64-bit little-endian
+//! sign-magnitude.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use std::io::{self, Read};
+
+/// Compression of one stream of a patch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    None,
+    Bzip2,
+    Brotli,
+}
+
+/// Apply `patch` to `old`.
+///
+/// # Arguments
+/// * `old` - The source data (the operation's source extents)
+/// * `patch` - The operation's data blob
+/// * `max_size` - Largest result accepted, so a corrupt header can't ask for
+///   an absurd allocation; the operation's destination extents bound it
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The new data
+/// * `Err(io::Error)` - `InvalidData` for a malformed patch
+pub(crate) fn apply(old: &[u8], patch: &[u8], max_size: u64) -> io::Result<Vec<u8>> {
+    let mut header = [[0u8; 8]; 4];
+    let mut reader = patch;
+    for field in &mut header {
+        reader
+            .read_exact(field)
+            .map_err(|_| corrupt(format!("patch is only {} bytes", patch.len())))?;
+    }
+    let [magic, ctrl_len, diff_len, new_size] = header;
+
+    let compression = if &magic == b"BSDIFF40" {
+        [Compression::Bzip2; 3]
+    } else if &magic[..5] == b"BSDF2" {
+        [compression(magic[5])?, compression(magic[6])?, compression(magic[7])?]
+    } else {
+        return Err(corrupt(format!("unknown patch magic {:02X?}", magic)));
+    };
+
+    let body = reader;
+    let (ctrl_len, diff_len, new_size) = (offtin(&ctrl_len), offtin(&diff_len), offtin(&new_size));
+    let ctrl_end = usize::try_from(ctrl_len).ok().filter(|&end| end <= body.len());
+    let diff_end = ctrl_end.and_then(|start| {
+        usize::try_from(diff_len)
+            .ok()
+            .and_then(|len| start.checked_add(len))
+            .filter(|&end| end <= body.len())
+    });
+    let (Some(ctrl_end), Some(diff_end)) = (ctrl_end, diff_end) else {
+        return Err(corrupt(format!(
+            "stream lengths {} and {} exceed the {} byte patch",
+            ctrl_len,
+            diff_len,
+            body.len()
+        )));
+    };
+    let new_size = match u64::try_from(new_size) {
+        Ok(size) if size <= max_size => size as usize,
+        _ => {
+            return Err(corrupt(format!(
+                "patch produces {} bytes, more than the {} expected",
+                new_size, max_size
+            )))
+        }
+    };
+
+    let mut ctrl = decoder(compression[0], &body[..ctrl_end]);
+    let mut diff = decoder(compression[1], &body[ctrl_end..diff_end]);
+    let mut extra = decoder(compression[2], &body[diff_end..]);
+
+    let mut new = vec![0u8; new_size];
+    let mut new_pos = 0usize;
+    let mut old_pos = 0i64;
+    while new_pos < new_size {
+        let mut triple = [[0u8; 8]; 3];
+        for field in &mut triple {
+            ctrl.read_exact(field)?;
+        }
+        let [diff_len, extra_len, seek] = triple.map(|field| offtin(&field));
+
+        // Diff bytes are added to the old data at the same position
+        let diff_end = stream_end(new_pos, diff_len, new_size)?;
+        diff.read_exact(&mut new[new_pos..diff_end])?;
+        for (i, byte) in new[new_pos..diff_end].iter_mut().enumerate() {
+            let old_byte = old_pos
+                .checked_add(i as i64)
+                .and_then(|idx| usize::try_from(idx).ok())
+                .and_then(|idx| old.get(idx));
+            if let Some(old_byte) = old_byte {
+                *byte = byte.wrapping_add(*old_byte);
+            }
+        }
+        new_pos = diff_end;
+
+        let extra_end = stream_end(new_pos, extra_len, new_size)?;
+        extra.read_exact(&mut new[new_pos..extra_end])?;
+        new_pos = extra_end;
+
+        old_pos = old_pos
+            .checked_add(diff_len)
+            .and_then(|pos| pos.checked_add(seek))
+            .ok_or_else(|| corrupt("old data offset overflows".to_string()))?;
+    }
+
+    Ok(new)
+}
+
+/// Compression named by a byte of a BSDF2 header
+fn compression(byte: u8) -> io::Result<Compression> {
+    match byte {
+        0 => Ok(Compression::None),
+        1 => Ok(Compression::Bzip2),
+        2 => Ok(Compression::Brotli),
+        other => Err(corrupt(format!("unknown stream compression {}", other))),
+    }
+}
+
+/// Decompressing reader over one stream of a patch
+fn decoder(compression: Compression, data: &[u8]) -> Box<dyn Read + '_> {
+    match compression {
+        Compression::None => Box::new(data),
+        Compression::Bzip2 => Box::new(bzip2::read::BzDecoder::new(data)),
+        Compression::Brotli => Box::new(brotli::Decompressor::new(data, 64 * 1024)),
+    }
+}
+
+/// Decode a 64-bit little-endian sign-magnitude number
+fn offtin(bytes: &[u8; 8]) -> i64 {
+    let value = u64::from_le_bytes(*bytes);
+    let magnitude = (value & (u64::MAX >> 1)) as i64;
+    if value >> 63 == 1 {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+/// End of a run of `len` bytes starting at `pos`, which must fit in `size`
+fn stream_end(pos: usize, len: i64, size: usize) -> io::Result<usize> {
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| pos.checked_add(len))
+        .filter(|&end| end <= size)
+        .ok_or_else(|| corrupt(format!("control entry of {} bytes at {} overruns {} bytes", len, pos, size)))
+}
+
+fn corrupt(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("corrupt bsdiff patch: {}", message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn offtout(value: i64) -> [u8; 8] {
+        let magnitude = value.unsigned_abs();
+        (if value < 0 { magnitude | 1 << 63 } else { magnitude }).to_le_bytes()
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Compression::Brotli => {
+                let mut output = Vec::new();
+                let mut encoder = brotli::CompressorWriter::new(&mut output, 4096, 9, 22);
+                encoder.write_all(data).unwrap();
+                drop(encoder);
+                output
+            }
+        }
+    }
+
+    /// Build a patch from control triples and the raw diff and extra streams
+    fn make_patch(magic: &[u8; 8], streams: [Compression; 3], ctrl: &[(i64, i64, i64)], diff: &[u8], extra: &[u8], new_size: i64) -> Vec<u8> {
+        let ctrl: Vec<u8> = ctrl
+            .iter()
+            .flat_map(|&(x, y, z)| [offtout(x), offtout(y), offtout(z)].concat())
+            .collect();
+        let (ctrl, diff, extra) = (
+            compress(streams[0], &ctrl),
+            compress(streams[1], diff),
+            compress(streams[2], extra),
+        );
+
+        let mut patch = magic.to_vec();
+        patch.extend_from_slice(&offtout(ctrl.len() as i64));
+        patch.extend_from_slice(&offtout(diff.len() as i64));
+        patch.extend_from_slice(&offtout(new_size));
+        patch.extend([ctrl, diff, extra].concat());
+        patch
+    }
+
+    #[test]
+    fn test_apply_patch_formats() {
+        let old = b"hello old world, unchanged tail".to_vec();
+        // new: "hellO" (diff over old[0..5]), "NEW" (extra), then old[9..] again
+        let mut expected = b"hellO".to_vec();
+        expected.extend_from_slice(b"NEW");
+        expected.extend_from_slice(&old[9..]);
+
+        let mut diff = vec![0u8; 4];
+        diff.push(b'O'.wrapping_sub(b'o'));
+        diff.extend(vec![0u8; old.len() - 9]);
+        let ctrl = [(5, 3, 4), (old.len() as i64 - 9, 0, 0)];
+
+        use Compression::*;
+        for (magic, streams) in [
+            (*b"BSDIFF40", [Bzip2; 3]),
+            (*b"BSDF2\x01\x01\x01", [Bzip2; 3]),
+            (*b"BSDF2\x02\x02\x02", [Brotli; 3]),
+            (*b"BSDF2\x00\x02\x01", [None, Brotli, Bzip2]),
+        ] {
+            let patch = make_patch(&magic, streams, &ctrl, &diff, b"NEW", expected.len() as i64);
+            assert_eq!(apply(&old, &patch, 4096).unwrap(), expected, "{:?}", streams);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_patches() {
+        let old = vec![1u8; 16];
+        let patch = make_patch(b"BSDIFF40", [Compression::Bzip2; 3], &[(16, 0, 0)], &[0u8; 16], b"", 16);
+        assert_eq!(apply(&old, &patch, 16).unwrap(), old);
+
+        let invalid = |result: io::Result<Vec<u8>>| result.unwrap_err().kind() == io::ErrorKind::InvalidData;
+        // Larger than the destination allows
+        assert!(invalid(apply(&old, &patch, 15)));
+        // Unknown magic and compression
+        assert!(invalid(apply(&old, &[b"NOTAPTCH".as_slice(), &patch[8..]].concat(), 16)));
+        assert!(invalid(apply(&old, &[b"BSDF2\x01\x01\x07".as_slice(), &patch[8..]].concat(), 16)));
+        // Truncated header, and a control entry past the new size
+        assert!(apply(&old, &patch[..20], 16).is_err());
+        let overrun = make_patch(b"BSDF2\0\0\0", [Compression::None; 3], &[(32, 0, 0)], &[0u8; 32], b"", 16);
+        assert!(invalid(apply(&old, &overrun, 16)));
+    }
+}
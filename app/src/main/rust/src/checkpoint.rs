@@ -0,0 +1,170 @@
+//! Extraction Checkpoint Module
+//!
+//! Lets an interrupted extraction resume where it stopped. After each
+//! partition is written, its result is recorded in a small JSON file in the
+//! output directory. A later extraction of the same payload into the same
+//! directory skips partitions recorded there, once their image has been
+//! re-validated by size and SHA-256.
+//!
+//! The checkpoint is tied to the payload by the SHA-256 of its manifest, so
+//! extracting a different payload into the directory starts from scratch.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use crate::payload::{self, ExtractedPartition};
+
+/// Name of the checkpoint file in the output directory
+pub(crate) const CHECKPOINT_FILE: &str = ".payloadpack-checkpoint.json";
+
+/// Partitions completed so far by extractions into one output directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Checkpoint {
+    /// Hex SHA-256 of the encoded manifest of the payload
+    manifest_digest: String,
+    /// Whether images were written in sparse format
+    output_sparse: bool,
+    /// Whether raw operation blobs were written instead of images
+    #[serde(default)]
+    raw: bool,
+    completed: Vec<CompletedPartition>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// A partition image known to be complete
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CompletedPartition {
+    result: ExtractedPartition,
+    /// Size and hex SHA-256 of the image file as written (these differ from
+    /// the partition's for sparse output)
+    file_size: u64,
+    file_sha256: String,
+}
+
+impl Checkpoint {
+    /// Load the checkpoint of `output_path` if it belongs to this payload and
+    /// output format, or start a new one.
+    ///
+    /// With `force`, any existing checkpoint is discarded.
+    pub(crate) fn load(output_path: &Path, manifest_digest: &str, output_sparse: bool, raw: bool, force: bool) -> Self {
+        let path = output_path.join(CHECKPOINT_FILE);
+        let fresh = Checkpoint {
+            manifest_digest: manifest_digest.to_string(),
+            output_sparse,
+            raw,
+            completed: Vec::new(),
+            path: path.clone(),
+        };
+
+        if force {
+            log::info!("Forced extraction, ignoring any checkpoint");
+            return fresh;
+        }
+
+        let loaded: Checkpoint = match std::fs::read(&path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(checkpoint) => checkpoint,
+                Err(e) => {
+                    log::warn!("Ignoring unreadable checkpoint {}: {}", path.display(), e);
+                    return fresh;
+                }
+            },
+            Err(_) => return fresh,
+        };
+
+        if loaded.manifest_digest != manifest_digest || loaded.output_sparse != output_sparse || loaded.raw != raw {
+            log::info!("Checkpoint is for another payload or output format, ignoring it");
+            return fresh;
+        }
+
+        log::info!("Loaded checkpoint with {} completed partitions", loaded.completed.len());
+        Checkpoint { path, ..loaded }
+    }
+
+    /// The recorded result for `name`, if its image is still intact.
+    ///
+    /// The image is re-hashed, so a corrupted or half-overwritten file is
+    /// extracted again rather than trusted.
+    pub(crate) fn resume(&self, name: &str) -> Option<ExtractedPartition> {
+        let completed = self.completed.iter().find(|entry| entry.result.name == name)?;
+        let image_path = Path::new(&completed.result.path);
+
+        let size = std::fs::metadata(image_path).map(|m| m.len()).ok()?;
+        if size != completed.file_size {
+            log::warn!("Checkpointed {} changed size, extracting it again", name);
+            return None;
+        }
+
+        let mut buffer = vec![0u8; 1024 * 1024];
+        let hash = File::open(image_path)
+            .ok()
+            .and_then(|mut file| payload::hash_region(&mut file, 0, size, &mut buffer).ok())
+            .map(|hash| payload::to_hex(&hash));
+        if hash.as_deref() != Some(completed.file_sha256.as_str()) {
+            log::warn!("Checkpointed {} failed re-validation, extracting it again", name);
+            return None;
+        }
+
+        log::info!("✓ {} already extracted and intact, skipping", name);
+        Some(ExtractedPartition {
+            resumed: true,
+            ..completed.result.clone()
+        })
+    }
+
+    /// Record a freshly extracted partition and save the checkpoint.
+    ///
+    /// Failing to save only costs the ability to resume, so it is logged
+    /// rather than returned.
+    pub(crate) fn record(&mut self, extracted: &ExtractedPartition) {
+        let image_path = Path::new(&extracted.path);
+        let file_size = match std::fs::metadata(image_path) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                log::warn!("Cannot checkpoint {}: {}", extracted.name, e);
+                return;
+            }
+        };
+
+        // A raw image is exactly what `actual_hash` describes
+        let file_sha256 = if self.output_sparse {
+            let mut buffer = vec![0u8; 1024 * 1024];
+            match File::open(image_path)
+                .and_then(|mut file| payload::hash_region(&mut file, 0, file_size, &mut buffer))
+            {
+                Ok(hash) => payload::to_hex(&hash),
+                Err(e) => {
+                    log::warn!("Cannot checkpoint {}: {}", extracted.name, e);
+                    return;
+                }
+            }
+        } else {
+            extracted.actual_hash.clone()
+        };
+
+        self.completed.retain(|entry| entry.result.name != extracted.name);
+        self.completed.push(CompletedPartition {
+            result: extracted.clone(),
+            file_size,
+            file_sha256,
+        });
+
+        if let Err(e) = self.save() {
+            log::warn!("Failed to save checkpoint {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// Write the checkpoint atomically, so a crash never leaves it truncated
+    fn save(&self) -> std::io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(std::io::Error::other)?;
+        let mut temp_name = self.path.as_os_str().to_owned();
+        temp_name.push(".tmp");
+
+        std::fs::write(&temp_name, data)?;
+        std::fs::rename(&temp_name, &self.path)
+    }
+}
@@ -0,0 +1,263 @@
+//! Extraction Handle Module
+//!
+//! Runs extractions on a background thread and tracks them by an opaque
+//! handle, so the app can start an extraction, poll its progress and cancel
+//! it without passing a listener object into native code.
+//!
+//! Each handle maps to the shared state of one extraction: its cancellation
+//! token, the latest progress update and, once done, the result JSON. The
+//! registry is a map guarded by a Mutex; entries stay until released.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::payload::{ExtractOptions, PayloadError, ProgressUpdate};
+
+/// Live extractions by handle
+static HANDLES: Mutex<BTreeMap<i64, Arc<ExtractionState>>> = Mutex::new(BTreeMap::new());
+
+/// Next handle to give out; 0 is never used so it can mean "no handle"
+static NEXT_HANDLE: AtomicI64 = AtomicI64::new(1);
+
+/// State shared between an extraction thread and the pollers of its handle
+struct ExtractionState {
+    cancel: Arc<AtomicBool>,
+    progress: Mutex<ProgressSnapshot>,
+    /// Result JSON, set when the extraction thread is done
+    result: Mutex<Option<String>>,
+}
+
+/// Latest progress of an extraction, as reported by `pollProgress`
+#[derive(Debug, Clone, Default, Serialize)]
+pub(crate) struct ProgressSnapshot {
+    /// "running" or "finished"
+    pub state: &'static str,
+    pub current_file: String,
+    pub percent: i32,
+    pub bytes_processed: u64,
+    pub total_bytes: u64,
+    pub bytes_per_second: u64,
+    pub eta_seconds: Option<u64>,
+    /// Whether cancellation was requested
+    pub cancelled: bool,
+    /// The extraction result (same shape as `extractPayload`) once finished
+    pub result: Option<serde_json::Value>,
+}
+
+/// Start `run` on a new thread and return the handle tracking it.
+///
+/// `run` gets the options with a fresh cancellation token and a callback
+/// that records progress for `poll`; it returns the result JSON.
+///
+/// In builds that unwind (debug builds and tests), a panic in `run` finishes
+/// the handle with an OPERATION_FAILED error result instead of leaving it
+/// "running" forever. Release builds set `panic = "abort"`, so there a panic
+/// still takes down the process: `run` must not panic.
+pub(crate) fn begin<F>(mut options: ExtractOptions, run: F) -> Result<i64, PayloadError>
+where
+    F: FnOnce(&ExtractOptions, &mut (dyn FnMut(&ProgressUpdate) + Send)) -> String + Send + 'static,
+{
+    let cancel = Arc::new(AtomicBool::new(false));
+    options.cancel = Some(cancel.clone());
+
+    let state = Arc::new(ExtractionState {
+        cancel,
+        progress: Mutex::new(ProgressSnapshot {
+            state: "running",
+            ..Default::default()
+        }),
+        result: Mutex::new(None),
+    });
+
+    let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+    HANDLES
+        .lock()
+        .map_err(|_| PayloadError::OperationFailed("Handle registry poisoned".to_string()))?
+        .insert(handle, state.clone());
+
+    let spawned = std::thread::Builder::new()
+        .name(format!("extraction-{}", handle))
+        .spawn(move || {
+            let mut on_progress = |update: &ProgressUpdate| {
+                if let Ok(mut progress) = state.progress.lock() {
+                    progress.current_file = update.current_file.to_string();
+                    progress.percent = update.percent;
+                    progress.bytes_processed = update.bytes_processed;
+                    progress.total_bytes = update.total_bytes;
+                    progress.bytes_per_second = update.bytes_per_second;
+                    progress.eta_seconds = update.eta_seconds;
+                }
+            };
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| run(&options, &mut on_progress)))
+                .unwrap_or_else(|panic| {
+                    let reason = panic
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "unknown panic".to_string());
+                    log::error!("Extraction {} panicked: {}", handle, reason);
+                    panic_result_json(&reason)
+                });
+
+            log::info!("Extraction {} finished", handle);
+            if let Ok(mut slot) = state.result.lock() {
+                *slot = Some(result);
+            }
+        });
+
+    if let Err(e) = spawned {
+        release(handle);
+        return Err(PayloadError::OperationFailed(format!(
+            "Failed to start extraction thread: {}",
+            e
+        )));
+    }
+
+    log::info!("Started extraction {}", handle);
+    Ok(handle)
+}
+
+/// `{"status": "error", ...}` result of an extraction whose thread panicked,
+/// in the same shape as a failed `extractPayload`
+fn panic_result_json(reason: &str) -> String {
+    let error = PayloadError::OperationFailed(format!("Extraction thread panicked: {}", reason));
+    serde_json::json!({
+        "status": "error",
+        "message": error.to_string(),
+        "code": error.code(),
+        "code_number": error.code_number(),
+    })
+    .to_string()
+}
+
+/// Look up the state of `handle`
+fn state(handle: i64) -> Option<Arc<ExtractionState>> {
+    HANDLES.lock().ok()?.get(&handle).cloned()
+}
+
+/// Progress of the extraction behind `handle`, `None` for unknown handles
+pub(crate) fn poll(handle: i64) -> Option<ProgressSnapshot> {
+    let state = state(handle)?;
+    let mut snapshot = state.progress.lock().ok()?.clone();
+    snapshot.cancelled = state.cancel.load(Ordering::Relaxed);
+
+    if let Some(result) = state.result.lock().ok()?.as_deref() {
+        snapshot.state = "finished";
+        snapshot.result = Some(
+            serde_json::from_str(result).unwrap_or_else(|_| serde_json::Value::String(result.to_string())),
+        );
+    }
+    Some(snapshot)
+}
+
+/// Ask the extraction behind `handle` to stop.
+///
+/// # Returns
+/// `false` if the handle is unknown
+pub(crate) fn cancel(handle: i64) -> bool {
+    match state(handle) {
+        Some(state) => {
+            state.cancel.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Forget `handle`. A still running extraction is cancelled first; its
+/// thread exits at the next operation boundary.
+///
+/// # Returns
+/// `false` if the handle is unknown
+pub(crate) fn release(handle: i64) -> bool {
+    let removed = HANDLES.lock().ok().and_then(|mut handles| handles.remove(&handle));
+    match removed {
+        Some(state) => {
+            state.cancel.store(true, Ordering::Relaxed);
+            true
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn wait_until_finished(handle: i64) -> ProgressSnapshot {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            let snapshot = poll(handle).unwrap();
+            if snapshot.state == "finished" || Instant::now() > deadline {
+                return snapshot;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn test_poll_reports_progress_and_result() {
+        let handle = begin(ExtractOptions::default(), |_, on_progress| {
+            on_progress(&ProgressUpdate {
+                current_file: "boot",
+                percent: 100,
+                bytes_processed: 4096,
+                total_bytes: 4096,
+                bytes_per_second: 0,
+                eta_seconds: Some(0),
+            });
+            r#"{"status":"success"}"#.to_string()
+        })
+        .unwrap();
+
+        let snapshot = wait_until_finished(handle);
+        assert_eq!(snapshot.state, "finished");
+        assert_eq!((snapshot.current_file.as_str(), snapshot.percent), ("boot", 100));
+        assert_eq!(snapshot.result.unwrap()["status"], "success");
+        assert!(!snapshot.cancelled);
+
+        assert!(release(handle));
+        assert!(poll(handle).is_none());
+        assert!(!release(handle));
+    }
+
+    #[test]
+    fn test_cancel_reaches_running_extraction() {
+        let handle = begin(ExtractOptions::default(), |options, _| {
+            let cancel = options.cancel.clone().unwrap();
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !cancel.load(Ordering::Relaxed) && Instant::now() < deadline {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            r#"{"status":"error","message":"Operation cancelled"}"#.to_string()
+        })
+        .unwrap();
+
+        assert_eq!(poll(handle).unwrap().state, "running");
+        assert!(cancel(handle));
+
+        let snapshot = wait_until_finished(handle);
+        assert!(snapshot.cancelled);
+        assert_eq!(snapshot.result.unwrap()["status"], "error");
+        assert!(release(handle));
+        assert!(!cancel(handle));
+    }
+
+    #[test]
+    fn test_panicking_extraction_finishes_with_error() {
+        let handle = begin(ExtractOptions::default(), |_, _| panic!("boom")).unwrap();
+
+        let snapshot = wait_until_finished(handle);
+        assert_eq!(snapshot.state, "finished");
+        let result = snapshot.result.unwrap();
+        assert_eq!(result["status"], "error");
+        assert_eq!(result["code"], "OPERATION_FAILED");
+        assert_eq!(result["message"], "Operation failed: Extraction thread panicked: boom");
+        assert!(release(handle));
+    }
+}
@@ -0,0 +1,3264 @@
+//! JNI Bindings
+//!
+//! Exposes the library to Kotlin/Java through the Java Native Interface:
+//! one `Java_id_xms_payloadpack_native_NativeLib_*` function per external
+//! in NativeLib.kt. Arguments come in as Java objects, results go back as
+//! JSON strings (or typed objects where noted).
+//!
+//! Built with the `jni` feature, which is on by default.
+//!
+//! IMPORTANT: These functions are called from the JVM and must NEVER panic.
+
+use jni::objects::{JByteArray, JClass, JObject, JObjectArray, JString};
+use jni::sys::{jboolean, jbyteArray, jint, jlong, jobject, jobjectArray, jstring, JNI_FALSE, JNI_TRUE};
+use jni::JNIEnv;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::{handles, payload, remote, repack, signature, sparse, super_image};
+
+/// Progress callback handed to the payload extractor
+type ProgressCallback = Box<dyn Fn(&payload::ProgressUpdate) + Send>;
+
+/// Completed partition callback handed to the payload extractor
+type PartitionCallback = Box<dyn Fn(&payload::ExtractedPartition) + Send>;
+
+/// Listener variant that also receives throughput and ETA
+const THROUGHPUT_LISTENER_CLASS: &str = "id/xms/payloadpack/native/ThroughputProgressListener";
+
+/// Kotlin class mirroring `payload::PartitionInfo`
+const PARTITION_INFO_CLASS: &str = "id/xms/payloadpack/native/PartitionInfo";
+
+/// Constructor of `PARTITION_INFO_CLASS`: (name, size, operationsCount,
+/// sizeHuman, hash, oldSize, oldHash, totalBlocks, compressedSize)
+const PARTITION_INFO_CTOR: &str =
+    "(Ljava/lang/String;JILjava/lang/String;Ljava/lang/String;JLjava/lang/String;JJ)V";
+
+/// Kotlin classes making up the typed result of `inspectPayloadInfo`
+const PAYLOAD_INFO_CLASS: &str = "id/xms/payloadpack/native/PayloadInfo";
+const PAYLOAD_HEADER_CLASS: &str = "id/xms/payloadpack/native/PayloadHeader";
+const PAYLOAD_PROPERTIES_CLASS: &str = "id/xms/payloadpack/native/PayloadProperties";
+
+/// Constructor of `PAYLOAD_HEADER_CLASS`: (version, manifestSize,
+/// metadataSignatureSize, dataOffset)
+const PAYLOAD_HEADER_CTOR: &str = "(JJIJ)V";
+
+/// Constructor of `PAYLOAD_PROPERTIES_CLASS`: (fileHash, fileSize,
+/// metadataHash, metadataSize)
+const PAYLOAD_PROPERTIES_CTOR: &str = "(Ljava/lang/String;JLjava/lang/String;J)V";
+
+/// Constructor of `PAYLOAD_INFO_CLASS`: (header, blockSize, partialUpdate,
+/// isIncremental, updateType, securityPatchLevel, maxTimestamp, minorVersion,
+/// buildFingerprint, partitions, totalSize, totalSizeHuman, filePath,
+/// properties, metadataVerified)
+const PAYLOAD_INFO_CTOR: &str = "(Lid/xms/payloadpack/native/PayloadHeader;IZZLjava/lang/String;\
+Ljava/lang/String;JILjava/lang/String;[Lid/xms/payloadpack/native/PartitionInfo;JLjava/lang/String;\
+Ljava/lang/String;Lid/xms/payloadpack/native/PayloadProperties;Ljava/lang/Boolean;)V";
+
+/// Cancellation token of the extraction currently running via `extractPayload`
+/// or `extractPayloadSelective`
+static ACTIVE_CANCEL_TOKEN: Mutex<Option<Arc<AtomicBool>>> = Mutex::new(None);
+
+/// Initialize the Android logger for debugging
+/// This should be called once when the library is loaded
+fn init_logger() {
+    #[cfg(target_os = "android")]
+    android_logger::init_once(
+        android_logger::Config::default()
+            .with_max_level(log::LevelFilter::Debug)
+            .with_tag("PayloadPack"),
+    );
+}
+
+/// Read a Java `String[]` into a Vec
+fn get_string_array(env: &mut JNIEnv, array: &JObjectArray) -> Result<Vec<String>, String> {
+    if array.is_null() {
+        return Ok(Vec::new());
+    }
+
+    let length = env.get_array_length(array).map_err(|e| {
+        log::error!("Failed to get array length: {:?}", e);
+        "Failed to read string array".to_string()
+    })?;
+
+    let mut values = Vec::with_capacity(length as usize);
+    for i in 0..length {
+        let element = env.get_object_array_element(array, i).map_err(|e| {
+            log::error!("Failed to get array element {}: {:?}", i, e);
+            "Failed to read string array".to_string()
+        })?;
+        let value: String = env
+            .get_string(&JString::from(element))
+            .map_err(|e| {
+                log::error!("Failed to get array string {}: {:?}", i, e);
+                "Failed to read string array".to_string()
+            })?
+            .into();
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+/// Build a progress callback that forwards to `ProgressListener.onProgress`, or
+/// to `ThroughputProgressListener.onProgressWithThroughput` when the listener
+/// implements it
+///
+/// Returns `Ok(None)` when the listener is null.
+fn make_progress_callback(
+    env: &mut JNIEnv,
+    progress_listener: &JObject,
+) -> Result<Option<ProgressCallback>, String> {
+    if progress_listener.is_null() {
+        return Ok(None);
+    }
+
+    // Convert jobject to GlobalRef to keep it alive across calls
+    let listener_global = env.new_global_ref(progress_listener).map_err(|e| {
+        log::error!("Failed to create global ref for listener: {:?}", e);
+        "Failed to create global ref for listener".to_string()
+    })?;
+
+    // Get JavaVM to attach thread for callbacks
+    let jvm = env.get_java_vm().map_err(|e| {
+        log::error!("Failed to get JavaVM: {:?}", e);
+        "Failed to get JavaVM".to_string()
+    })?;
+
+    // Older listeners only know the four-argument onProgress
+    let with_throughput = env
+        .is_instance_of(progress_listener, THROUGHPUT_LISTENER_CLASS)
+        .unwrap_or_else(|e| {
+            log::warn!("Could not check listener type: {:?}", e);
+            let _ = env.exception_clear();
+            false
+        });
+
+    Ok(Some(Box::new(move |update: &payload::ProgressUpdate| {
+        // Attach current thread to JVM (safe to call multiple times)
+        let mut env = match jvm.attach_current_thread() {
+            Ok(env) => env,
+            Err(e) => {
+                log::error!("Failed to attach thread: {:?}", e);
+                return;
+            }
+        };
+
+        // Create Java string for current file
+        let j_current_file = match env.new_string(update.current_file) {
+            Ok(s) => s,
+            Err(e) => {
+                log::error!("Failed to create string: {:?}", e);
+                return;
+            }
+        };
+
+        let mut args = vec![
+            jni::objects::JValue::Object(&j_current_file),
+            jni::objects::JValue::Int(update.percent),
+            jni::objects::JValue::Long(update.bytes_processed as i64),
+            jni::objects::JValue::Long(update.total_bytes as i64),
+        ];
+
+        // Call onProgress method, or the throughput variant (ETA -1 = unknown)
+        let result = if with_throughput {
+            args.push(jni::objects::JValue::Long(update.bytes_per_second as i64));
+            args.push(jni::objects::JValue::Long(
+                update.eta_seconds.map_or(-1, |eta| eta as i64),
+            ));
+            env.call_method(
+                listener_global.as_obj(),
+                "onProgressWithThroughput",
+                "(Ljava/lang/String;IJJJJ)V",
+                &args,
+            )
+        } else {
+            env.call_method(
+                listener_global.as_obj(),
+                "onProgress",
+                "(Ljava/lang/String;IJJ)V",
+                &args,
+            )
+        };
+
+        if let Err(e) = result {
+            log::error!("Failed to call onProgress: {:?}", e);
+        }
+    })))
+}
+
+/// Build a callback that forwards each completed partition to
+/// `PartitionListener.onPartitionComplete`
+///
+/// Returns `Ok(None)` when the listener is null.
+fn make_partition_callback(
+    env: &mut JNIEnv,
+    partition_listener: &JObject,
+) -> Result<Option<PartitionCallback>, String> {
+    if partition_listener.is_null() {
+        return Ok(None);
+    }
+
+    let listener_global = env.new_global_ref(partition_listener).map_err(|e| {
+        log::error!("Failed to create global ref for partition listener: {:?}", e);
+        "Failed to create global ref for partition listener".to_string()
+    })?;
+
+    let jvm = env.get_java_vm().map_err(|e| {
+        log::error!("Failed to get JavaVM: {:?}", e);
+        "Failed to get JavaVM".to_string()
+    })?;
+
+    Ok(Some(Box::new(move |done: &payload::ExtractedPartition| {
+        // Partitions complete on worker threads with `max_threads` > 1
+        let mut env = match jvm.attach_current_thread() {
+            Ok(env) => env,
+            Err(e) => {
+                log::error!("Failed to attach thread: {:?}", e);
+                return;
+            }
+        };
+
+        let (j_name, j_path) = match (env.new_string(&done.name), env.new_string(&done.path)) {
+            (Ok(name), Ok(path)) => (name, path),
+            _ => {
+                log::error!("Failed to create strings for partition {}", done.name);
+                return;
+            }
+        };
+
+        let result = env.call_method(
+            listener_global.as_obj(),
+            "onPartitionComplete",
+            "(Ljava/lang/String;JLjava/lang/String;)V",
+            &[
+                jni::objects::JValue::Object(&j_name),
+                jni::objects::JValue::Long(done.size as i64),
+                jni::objects::JValue::Object(&j_path),
+            ],
+        );
+
+        if let Err(e) = result {
+            log::error!("Failed to call onPartitionComplete: {:?}", e);
+        }
+    })))
+}
+
+/// Run an extraction with a fresh cancellation token published for
+/// `cancelCurrentExtraction()`
+fn with_cancel_token<T>(
+    mut options: payload::ExtractOptions,
+    extract: impl FnOnce(&payload::ExtractOptions) -> T,
+) -> T {
+    let cancel_token = Arc::new(AtomicBool::new(false));
+    if let Ok(mut active) = ACTIVE_CANCEL_TOKEN.lock() {
+        *active = Some(cancel_token.clone());
+    }
+    options.cancel = Some(cancel_token.clone());
+
+    let result = extract(&options);
+
+    // Clear the token, unless a newer extraction has already replaced it
+    if let Ok(mut active) = ACTIVE_CANCEL_TOKEN.lock() {
+        if active.as_ref().is_some_and(|t| Arc::ptr_eq(t, &cancel_token)) {
+            *active = None;
+        }
+    }
+
+    result
+}
+
+/// Turn a file descriptor handed over from Java into a `File`.
+///
+/// The descriptor stays owned by the caller (typically a ParcelFileDescriptor),
+/// so it is duplicated rather than adopted: the returned `File` closes only the
+/// duplicate, and the Java side remains responsible for closing the original.
+fn file_from_fd(fd: jint) -> Result<std::fs::File, String> {
+    use std::os::fd::BorrowedFd;
+
+    if fd < 0 {
+        return Err(format!("Invalid file descriptor: {}", fd));
+    }
+
+    // SAFETY: the caller guarantees `fd` is open for the duration of this call
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned = borrowed
+        .try_clone_to_owned()
+        .map_err(|e| format!("Failed to duplicate file descriptor {}: {}", fd, e))?;
+
+    Ok(std::fs::File::from(owned))
+}
+
+/// Convert a Rust string into a Java string, returning null on failure
+fn to_jstring(env: &mut JNIEnv, value: &str) -> jstring {
+    match env.new_string(value) {
+        Ok(output) => output.into_raw(),
+        Err(e) => {
+            log::error!("Failed to create result string: {:?}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Convert extracted data into a Java byte array, throwing
+/// `java.io.IOException` and returning null if `data` is an error
+fn to_jbytearray(env: &mut JNIEnv, data: Result<Vec<u8>, payload::PayloadError>, what: &str) -> jbyteArray {
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => {
+            log::error!("{} failed: {}", what, e);
+            let _ = env.throw_new("java/io/IOException", e.to_string());
+            return std::ptr::null_mut();
+        }
+    };
+
+    match env.byte_array_from_slice(&data) {
+        Ok(array) => array.into_raw(),
+        Err(e) => {
+            log::error!("Failed to build byte array: {:?}", e);
+            // A pending Java exception (e.g. OutOfMemoryError) is left for
+            // the caller to see
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new("java/io/IOException", "Failed to build byte array");
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// `code` of errors raised on the JNI side, e.g. an argument that can't be read
+const JNI_ERROR: &str = "JNI_ERROR";
+
+/// `{"error": ..., "code": ..., "code_number": ...}` response of a failed
+/// call. `code` is the `PayloadError::code` of the failure, or `JNI_ERROR`;
+/// `code_number` its `payload::error_code_number`.
+fn error_json(message: &str, code: &str) -> String {
+    serde_json::json!({
+        "error": message,
+        "code": code,
+        "code_number": payload::error_code_number(code),
+    })
+    .to_string()
+}
+
+/// `{"status": "error", "message": ..., "code": ..., "code_number": ...}`
+/// response of a failed extraction, see `error_json`
+fn extraction_error_json(message: &str, code: &str) -> String {
+    serde_json::json!({
+        "status": "error",
+        "message": message,
+        "code": code,
+        "code_number": payload::error_code_number(code),
+    })
+    .to_string()
+}
+
+/// JNI Function: Returns a "Hello from Rust!" greeting
+///
+/// This is a proof-of-concept function demonstrating JNI integration.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String helloFromRust();
+/// ```
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_helloFromRust<'local>(
+    env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jstring {
+    // Initialize logger on first call
+    init_logger();
+    
+    log::debug!("helloFromRust called");
+
+    let greeting = "Hello from Rust! 🦀";
+
+    match env.new_string(greeting) {
+        Ok(output) => output.into_raw(),
+        Err(e) => {
+            log::error!("Failed to create Java string: {:?}", e);
+            // Return null on error
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// JNI Function: Process input string and return modified version
+///
+/// This demonstrates passing data between Kotlin and Rust.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String processMessage(String input);
+/// ```
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_processMessage<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    input: JString<'local>,
+) -> jstring {
+    log::debug!("processMessage called");
+
+    // Extract the input string from JNI
+    let input_str: String = match env.get_string(&input) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get input string: {:?}", e);
+            return std::ptr::null_mut();
+        }
+    };
+
+    // Process the message (example: reverse and convert to uppercase)
+    let processed = format!(
+        "Rust processed: {} (length: {}, reversed: {})",
+        input_str,
+        input_str.len(),
+        input_str.chars().rev().collect::<String>()
+    );
+
+    match env.new_string(&processed) {
+        Ok(output) => output.into_raw(),
+        Err(e) => {
+            log::error!("Failed to create output string: {:?}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// JNI Function: List the operation types and compression formats this
+/// build supports
+///
+/// # JNI Signature
+/// ```java
+/// public static native String getCapabilities();
+/// ```
+///
+/// # Returns
+/// * JSON string, e.g.
+///   `{"version": "0.1.0", "operations": ["REPLACE", ...], "source_operations":
+///   ["SOURCE_COPY", ...], "unsupported_operations": ["MOVE", ...],
+///   "compressions": ["bzip2", "xz", "zstd", "brotli"]}`
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_getCapabilities<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("getCapabilities called");
+
+    let result = payload::capabilities_json().unwrap_or_else(|e| error_json(&e.to_string(), e.code()));
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin file
+///
+/// Parses the payload header and manifest to extract partition information.
+/// Memory-efficient: only reads header and manifest, not the entire file.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayload(String path);
+/// ```
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+///
+/// # Returns
+/// * JSON string with payload information on success
+/// * JSON object with "error" field on failure, e.g.
+///   `{"error": "Invalid magic bytes: ...", "code": "INVALID_MAGIC"}`
+///
+/// Every error response in this library carries such a "code": the
+/// `PayloadError::code` of the failure (FILE_NOT_FOUND, INVALID_MAGIC,
+/// UNSUPPORTED_VERSION, MANIFEST_TOO_LARGE, TRUNCATED_MANIFEST, CANCELLED,
+/// ...), or JNI_ERROR when the arguments couldn't be read. Next to it,
+/// "code_number" gives the same code as a stable integer (see
+/// `payload::error_code_number`), for mapping onto Kotlin types.
+///
+/// Likewise every size and offset field in this library's JSON ("size",
+/// "total_size", "data_offset", ...) has a string copy suffixed "_str" (see
+/// `payload::to_json`), exact for consumers that parse numbers as doubles.
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayload<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayload called");
+
+    // Extract the path string from JNI
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let error_json = error_json("Failed to get path string", JNI_ERROR);
+            return match env.new_string(&error_json) {
+                Ok(s) => s.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            };
+        }
+    };
+
+    log::info!("Inspecting payload: {}", path_str);
+
+    // Call the payload inspection function
+    let result = match payload::inspect_payload_json(&path_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    match env.new_string(&result) {
+        Ok(output) => output.into_raw(),
+        Err(e) => {
+            log::error!("Failed to create result string: {:?}", e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// JNI Function: Inspect a payload.bin, giving up after a timeout
+///
+/// For payloads on network or FUSE-backed storage, where a hung read would
+/// otherwise block the call indefinitely. A read that hangs keeps its native
+/// thread until it returns; the call itself returns on time. A `timeoutMs`
+/// of 0 or less waits indefinitely, like `inspectPayload`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadWithTimeout(String path, long timeoutMs);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload`
+/// * JSON object with "error" field on failure, with code "TIMEOUT" if
+///   `timeoutMs` elapsed
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadWithTimeout<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    timeout_ms: jlong,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadWithTimeout called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let error_json = error_json("Failed to get path string", JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Inspecting payload: {} (timeout {} ms)", path_str, timeout_ms);
+
+    let result = if timeout_ms > 0 {
+        payload::inspect_payload_with_timeout_json(&path_str, Duration::from_millis(timeout_ms as u64))
+    } else {
+        payload::inspect_payload_json(&path_str)
+    };
+    let result = match result {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin with a custom manifest size limit
+///
+/// Raises the 100 MiB limit for payloads with unusually large manifests, or
+/// lowers it to bound what a crafted payload can make the inspection
+/// allocate. A `maxManifestSize` of 0 or less uses the default.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadWithManifestLimit(String path, long maxManifestSize);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload`
+/// * JSON object with "error" field on failure, with code
+///   "MANIFEST_TOO_LARGE" if the manifest exceeds the limit
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadWithManifestLimit<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    max_manifest_size: jlong,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadWithManifestLimit called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let error_json = error_json("Failed to get path string", JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let max_manifest_size = if max_manifest_size > 0 {
+        max_manifest_size as u64
+    } else {
+        payload::DEFAULT_MAX_MANIFEST_SIZE
+    };
+    log::info!("Inspecting payload: {} (manifest limit {} bytes)", path_str, max_manifest_size);
+
+    let result = match payload::inspect_payload_with_manifest_limit_json(&path_str, max_manifest_size) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect several payload.bin files at once
+///
+/// Saves a round-trip per file when e.g. comparing all OTAs in a directory.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloads(String[] paths);
+/// ```
+///
+/// # Arguments
+/// * `paths` - Paths to the payload.bin files
+///
+/// # Returns
+/// * JSON array with one element per path, in order, e.g.
+/// ```json
+/// [
+///   {"path": "/sdcard/a/payload.bin", "success": true, "inspection": {...}},
+///   {"path": "/sdcard/b/payload.bin", "success": false, "error": "File not found: ...", "code": "FILE_NOT_FOUND", "code_number": 1}
+/// ]
+/// ```
+/// where "inspection" has the shape of `inspectPayload`'s result
+/// * JSON object with "error" field if the paths couldn't be read
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloads<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    paths: JObjectArray<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloads called");
+
+    let paths = match get_string_array(&mut env, &paths) {
+        Ok(paths) => paths,
+        Err(message) => {
+            let error_json = error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Inspecting {} payloads", paths.len());
+
+    let result = match payload::inspect_payloads_json(&paths) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Batch inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// Build a Java `PartitionInfo` object from a partition
+fn new_partition_info<'local>(
+    env: &mut JNIEnv<'local>,
+    class: &JClass<'local>,
+    partition: &payload::PartitionInfo,
+) -> jni::errors::Result<JObject<'local>> {
+    let name = JObject::from(env.new_string(&partition.name)?);
+    let size_human = JObject::from(env.new_string(&partition.size_human)?);
+    let hash = match &partition.hash {
+        Some(hash) => JObject::from(env.new_string(hash)?),
+        None => JObject::null(),
+    };
+    let old_hash = match &partition.old_hash {
+        Some(hash) => JObject::from(env.new_string(hash)?),
+        None => JObject::null(),
+    };
+
+    env.new_object(
+        class,
+        PARTITION_INFO_CTOR,
+        &[
+            jni::objects::JValue::Object(&name),
+            jni::objects::JValue::Long(partition.size as i64),
+            jni::objects::JValue::Int(partition.operations_count as i32),
+            jni::objects::JValue::Object(&size_human),
+            jni::objects::JValue::Object(&hash),
+            jni::objects::JValue::Long(partition.old_size.map_or(-1, |size| size as i64)),
+            jni::objects::JValue::Object(&old_hash),
+            jni::objects::JValue::Long(partition.total_blocks as i64),
+            jni::objects::JValue::Long(partition.compressed_size as i64),
+        ],
+    )
+}
+
+/// Build a Java `PartitionInfo[]` from a list of partitions
+fn new_partition_info_array<'local>(
+    env: &mut JNIEnv<'local>,
+    partitions: &[payload::PartitionInfo],
+) -> jni::errors::Result<JObjectArray<'local>> {
+    let class = env.find_class(PARTITION_INFO_CLASS)?;
+    let array = env.new_object_array(partitions.len() as i32, &class, JObject::null())?;
+
+    for (i, partition) in partitions.iter().enumerate() {
+        let element = new_partition_info(env, &class, partition)?;
+        env.set_object_array_element(&array, i as i32, &element)?;
+        // Free the local reference early, partition lists can be long
+        env.delete_local_ref(element)?;
+    }
+
+    Ok(array)
+}
+
+/// JNI Function: Inspect a payload.bin and return typed partition objects
+///
+/// Same data as the "partitions" list of `inspectPayload`, but handed to Kotlin
+/// as `PartitionInfo` objects so no JSON has to be built or parsed.
+///
+/// # JNI Signature
+/// ```java
+/// public static native PartitionInfo[] inspectPartitions(String path);
+/// ```
+///
+/// # Returns
+/// * `PartitionInfo[]` sorted by name on success
+/// * Throws `java.io.IOException` with the error message on failure (the
+///   return value is then null)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPartitions<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jobjectArray {
+    init_logger();
+    log::info!("inspectPartitions called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let _ = env.throw_new("java/io/IOException", "Failed to get path string");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let inspection = match payload::inspect_payload(&path_str) {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            let _ = env.throw_new("java/io/IOException", e.to_string());
+            return std::ptr::null_mut();
+        }
+    };
+
+    match new_partition_info_array(&mut env, &inspection.partitions) {
+        Ok(array) => array.into_raw(),
+        Err(e) => {
+            log::error!("Failed to build PartitionInfo array: {:?}", e);
+            // A pending Java exception (e.g. NoClassDefFoundError) is left
+            // for the caller to see
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new("java/io/IOException", "Failed to build partition list");
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// A Java string, or null for `None`
+fn new_optional_string<'local>(env: &mut JNIEnv<'local>, value: Option<&str>) -> jni::errors::Result<JObject<'local>> {
+    match value {
+        Some(value) => Ok(JObject::from(env.new_string(value)?)),
+        None => Ok(JObject::null()),
+    }
+}
+
+/// Values passed to the `PayloadInfo`, `PayloadHeader` and
+/// `PayloadProperties` constructors, in the Java types they take. Absent
+/// numbers are -1, see the Kotlin classes.
+#[derive(Debug, PartialEq)]
+struct PayloadInfoFields<'a> {
+    version: i64,
+    manifest_size: i64,
+    metadata_signature_size: i32,
+    data_offset: i64,
+    block_size: i32,
+    partial_update: bool,
+    is_incremental: bool,
+    update_type: &'static str,
+    security_patch_level: Option<&'a str>,
+    max_timestamp: i64,
+    minor_version: i32,
+    build_fingerprint: Option<&'a str>,
+    total_size: i64,
+    total_size_human: &'a str,
+    file_path: &'a str,
+    /// (fileHash, fileSize, metadataHash, metadataSize), or None without
+    /// payload_properties.txt
+    properties: Option<(Option<&'a str>, i64, Option<&'a str>, i64)>,
+    metadata_verified: Option<bool>,
+}
+
+fn payload_info_fields(inspection: &payload::PayloadInspection) -> PayloadInfoFields<'_> {
+    let header = &inspection.header;
+    PayloadInfoFields {
+        version: header.version as i64,
+        manifest_size: header.manifest_size as i64,
+        metadata_signature_size: header.metadata_signature_size as i32,
+        data_offset: header.data_offset as i64,
+        block_size: inspection.block_size as i32,
+        partial_update: inspection.partial_update,
+        is_incremental: inspection.is_incremental,
+        update_type: match inspection.update_type {
+            payload::UpdateType::Full => "full",
+            payload::UpdateType::Incremental => "incremental",
+            payload::UpdateType::Partial => "partial",
+        },
+        security_patch_level: inspection.security_patch_level.as_deref(),
+        max_timestamp: inspection.max_timestamp.unwrap_or(-1),
+        minor_version: inspection.minor_version.map_or(-1, |version| version as i32),
+        build_fingerprint: inspection.build_fingerprint.as_deref(),
+        total_size: inspection.total_size as i64,
+        total_size_human: &inspection.total_size_human,
+        file_path: &inspection.file_path,
+        properties: inspection.properties.as_ref().map(|props| {
+            (
+                props.file_hash.as_deref(),
+                props.file_size.map_or(-1, |size| size as i64),
+                props.metadata_hash.as_deref(),
+                props.metadata_size.map_or(-1, |size| size as i64),
+            )
+        }),
+        metadata_verified: inspection.metadata_verified,
+    }
+}
+
+/// Build a Java `PayloadInfo` object from an inspection result
+fn new_payload_info<'local>(
+    env: &mut JNIEnv<'local>,
+    inspection: &payload::PayloadInspection,
+) -> jni::errors::Result<JObject<'local>> {
+    use jni::objects::JValue;
+
+    let fields = payload_info_fields(inspection);
+    let header = env.new_object(
+        PAYLOAD_HEADER_CLASS,
+        PAYLOAD_HEADER_CTOR,
+        &[
+            JValue::Long(fields.version),
+            JValue::Long(fields.manifest_size),
+            JValue::Int(fields.metadata_signature_size),
+            JValue::Long(fields.data_offset),
+        ],
+    )?;
+
+    let properties = match fields.properties {
+        Some((file_hash, file_size, metadata_hash, metadata_size)) => {
+            let file_hash = new_optional_string(env, file_hash)?;
+            let metadata_hash = new_optional_string(env, metadata_hash)?;
+            env.new_object(
+                PAYLOAD_PROPERTIES_CLASS,
+                PAYLOAD_PROPERTIES_CTOR,
+                &[
+                    JValue::Object(&file_hash),
+                    JValue::Long(file_size),
+                    JValue::Object(&metadata_hash),
+                    JValue::Long(metadata_size),
+                ],
+            )?
+        }
+        None => JObject::null(),
+    };
+
+    let metadata_verified = match fields.metadata_verified {
+        Some(verified) => env
+            .call_static_method(
+                "java/lang/Boolean",
+                "valueOf",
+                "(Z)Ljava/lang/Boolean;",
+                &[JValue::Bool(verified as u8)],
+            )?
+            .l()?,
+        None => JObject::null(),
+    };
+
+    let update_type = JObject::from(env.new_string(fields.update_type)?);
+    let security_patch_level = new_optional_string(env, fields.security_patch_level)?;
+    let build_fingerprint = new_optional_string(env, fields.build_fingerprint)?;
+    let partitions = JObject::from(new_partition_info_array(env, &inspection.partitions)?);
+    let total_size_human = JObject::from(env.new_string(fields.total_size_human)?);
+    let file_path = JObject::from(env.new_string(fields.file_path)?);
+
+    env.new_object(
+        PAYLOAD_INFO_CLASS,
+        PAYLOAD_INFO_CTOR,
+        &[
+            JValue::Object(&header),
+            JValue::Int(fields.block_size),
+            JValue::Bool(fields.partial_update as u8),
+            JValue::Bool(fields.is_incremental as u8),
+            JValue::Object(&update_type),
+            JValue::Object(&security_patch_level),
+            JValue::Long(fields.max_timestamp),
+            JValue::Int(fields.minor_version),
+            JValue::Object(&build_fingerprint),
+            JValue::Object(&partitions),
+            JValue::Long(fields.total_size),
+            JValue::Object(&total_size_human),
+            JValue::Object(&file_path),
+            JValue::Object(&properties),
+            JValue::Object(&metadata_verified),
+        ],
+    )
+}
+
+/// JNI Function: Inspect a payload.bin and return a typed result
+///
+/// Same data as `inspectPayload` (without dynamic partitions and APEX
+/// packages), handed to Kotlin as a `PayloadInfo` object so 64-bit sizes
+/// keep their exact value instead of passing through a JSON number.
+///
+/// # JNI Signature
+/// ```java
+/// public static native PayloadInfo inspectPayloadInfo(String path);
+/// ```
+///
+/// # Returns
+/// * `PayloadInfo` with partitions sorted by name on success
+/// * Throws `java.io.IOException` with the error message on failure (the
+///   return value is then null)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadInfo<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jobject {
+    init_logger();
+    log::info!("inspectPayloadInfo called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let _ = env.throw_new("java/io/IOException", "Failed to get path string");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let inspection = match payload::inspect_payload(&path_str) {
+        Ok(inspection) => inspection,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            let _ = env.throw_new("java/io/IOException", e.to_string());
+            return std::ptr::null_mut();
+        }
+    };
+
+    match new_payload_info(&mut env, &inspection) {
+        Ok(info) => info.into_raw(),
+        Err(e) => {
+            log::error!("Failed to build PayloadInfo: {:?}", e);
+            // A pending Java exception (e.g. NoClassDefFoundError) is left
+            // for the caller to see
+            if !env.exception_check().unwrap_or(true) {
+                let _ = env.throw_new("java/io/IOException", "Failed to build payload info");
+            }
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// JNI Function: Summarize a payload.bin in human-readable text
+///
+/// The key facts of `inspectPayload` (Android release, partition count, total
+/// size, kind of update, security patch level) formatted for display, so apps
+/// don't have to build the text from the JSON.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadSummary(String path, boolean multiline);
+/// ```
+///
+/// # Returns
+/// * e.g. "Android 14, 28 partitions, 6.20 GB total, full update, security
+///   patch 2024-03-05", or one fact per line with `multiline`
+/// * Throws `java.io.IOException` with the error message on failure (the
+///   return value is then null)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadSummary<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    multiline: jboolean,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadSummary called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            let _ = env.throw_new("java/io/IOException", "Failed to get path string");
+            return std::ptr::null_mut();
+        }
+    };
+
+    let summary = match payload::inspect_payload(&path_str) {
+        Ok(inspection) if multiline != JNI_FALSE => inspection.summary_lines().join("\n"),
+        Ok(inspection) => inspection.summary(),
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            let _ = env.throw_new("java/io/IOException", e.to_string());
+            return std::ptr::null_mut();
+        }
+    };
+
+    to_jstring(&mut env, &summary)
+}
+
+/// JNI Function: List partition names in a payload.bin
+///
+/// Lightweight alternative to `inspectPayload` for populating a partition
+/// picker: only the manifest is decoded, in manifest order.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String listPartitions(String path);
+/// ```
+///
+/// # Returns
+/// * `{"partitions": ["boot", "system", ...]}` on success
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_listPartitions<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("listPartitions called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get path string", JNI_ERROR));
+        }
+    };
+
+    let result = match payload::list_partition_names_json(&path_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Listing partitions failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Estimate how long extracting a payload.bin will take
+///
+/// Only the manifest is read, so this is quick enough to call before asking
+/// the user to confirm an extraction.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String estimateExtraction(String path, long bytesPerSecond);
+/// ```
+///
+/// # Arguments
+/// * `bytes_per_second` - Assumed write throughput; 0 or less uses the
+///   default of 40 MiB/s
+///
+/// # Returns
+/// * JSON string on success:
+/// ```json
+/// {
+///   "compressed_bytes": 1500000000,
+///   "uncompressed_bytes": 4000000000,
+///   "estimated_seconds": 95.4,
+///   "bytes_per_second": 41943040,
+///   "partitions": [
+///     { "name": "system", "compressed_bytes": 900000000,
+///       "uncompressed_bytes": 2500000000, "estimated_seconds": 59.6 }
+///   ]
+/// }
+/// ```
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_estimateExtraction<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    bytes_per_second: jlong,
+) -> jstring {
+    init_logger();
+    log::info!("estimateExtraction called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get path string", JNI_ERROR));
+        }
+    };
+
+    let throughput = (bytes_per_second > 0).then_some(bytes_per_second as u64);
+    let result = match payload::estimate_extraction_json(&path_str, throughput) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Estimating extraction failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Locate each partition's data within a payload.bin
+///
+/// For external flashing pipelines that read the operation blobs directly.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadLayout(String path);
+/// ```
+///
+/// # Returns
+/// * JSON string on success:
+/// ```json
+/// {
+///   "data_offset": 1234871,
+///   "block_size": 4096,
+///   "layout": [
+///     { "name": "boot", "offset": 1234871, "span": 41943040,
+///       "compressed_size": 41943040, "operations_count": 20 }
+///   ]
+/// }
+/// ```
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadLayout<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadLayout called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get path string", JNI_ERROR));
+        }
+    };
+
+    let result = match payload::inspect_payload_layout_json(&path_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Computing payload layout failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Read the fixed header of a payload.bin and its section offsets
+///
+/// Only the header is read, not the manifest. `data_offset` (header +
+/// manifest + metadata signature) is where operation data begins; manifest
+/// data offsets are relative to it.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String readPayloadHeader(String path);
+/// ```
+///
+/// # Returns
+/// * JSON string on success:
+/// ```json
+/// {
+///   "version": 2,
+///   "manifest_size": 98765,
+///   "metadata_signature_size": 267,
+///   "data_offset": 99056,
+///   "header_bytes": "43724155000000000000000200000000000181cd0000010b",
+///   "manifest_offset": 24,
+///   "metadata_signature_offset": 98789,
+///   "file_size": 2147581952,
+///   "data_size": 2147482896
+/// }
+/// ```
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_readPayloadHeader<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("readPayloadHeader called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get path string", JNI_ERROR));
+        }
+    };
+
+    let result = match payload::read_payload_header_json(&path_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Reading payload header failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Compare the partitions of two payload.bin files
+///
+/// For release notes between two builds: partitions added and removed, and
+/// for the ones in both, the size change and whether the image hash changed.
+/// Only the manifests are read.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String diffPayloads(String pathA, String pathB);
+/// ```
+///
+/// # Returns
+/// * JSON string on success:
+/// ```json
+/// {
+///   "path_a": "/sdcard/PayloadPack/old/payload.bin",
+///   "path_b": "/sdcard/PayloadPack/new/payload.bin",
+///   "added": [{"name": "vendor_dlkm", "size": 4096, ...}],
+///   "removed": [],
+///   "partitions": [
+///     {"name": "system", "size_a": 16384, "size_b": 12288, "size_delta": -4096,
+///      "hash_a": "0202...", "hash_b": "0404...", "hash_changed": true, "changed": true}
+///   ],
+///   "changed_count": 1,
+///   "total_size_a": 32768,
+///   "total_size_b": 28672,
+///   "total_size_delta": -4096
+/// }
+/// ```
+/// "added" and "removed" entries have the shape of `inspectPayload` partitions.
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_diffPayloads<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path_a: JString<'local>,
+    path_b: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("diffPayloads called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&path_a, "first payload path"), (&path_b, "second payload path")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match payload::diff_payloads_json(&args[0], &args[1]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload diff failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Write the raw manifest of a payload.bin to a file
+///
+/// For ROM developers who want to diff manifests between builds. With
+/// `decoded`, a readable text form is also written to `outPath` + ".txt".
+///
+/// # JNI Signature
+/// ```java
+/// public static native String dumpManifest(String payloadPath, String outPath, boolean decoded);
+/// ```
+///
+/// # Returns
+/// * `{"path": ..., "size": ..., "decoded_path": ...}` on success
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_dumpManifest<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    out_path: JString<'local>,
+    decoded: jboolean,
+) -> jstring {
+    init_logger();
+    log::info!("dumpManifest called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&payload_path, "payload path"), (&out_path, "output path")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match payload::dump_manifest_json(&args[0], &args[1], decoded != JNI_FALSE) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Dumping manifest failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin with properties from elsewhere
+///
+/// For a payload.bin taken out of its OTA package: `propertiesPath` is the
+/// payload_properties.txt to use, or the OTA package zip holding it. Falls
+/// back to the properties next to the payload if it can't be read.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadWithProperties(String path, String propertiesPath);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload`; "properties_source"
+///   tells which properties were used
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadWithProperties<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    properties_path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadWithProperties called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&path, "path"), (&properties_path, "properties path")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match payload::inspect_payload_with_properties_json(&args[0], &args[1]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect the payload.bin inside an OTA package zip
+///
+/// Reads payload.bin in place from the zip, so the package doesn't have to be
+/// unzipped first. payload_properties.txt is taken from the same zip.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadZip(String zipPath);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload`
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadZip<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    zip_path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadZip called");
+
+    let zip_path_str: String = match env.get_string(&zip_path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get zip path: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get zip path", JNI_ERROR));
+        }
+    };
+
+    let result = match payload::inspect_payload_zip_json(&zip_path_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin hosted on an HTTP(S) server
+///
+/// Fetches only the header and manifest with range requests, so a large OTA
+/// can be previewed before downloading it. Servers without range support
+/// also work: the download is aborted once the manifest has been read.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadUrl(String url);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload` ("properties" is null)
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadUrl<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    url: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadUrl called");
+
+    let url_str: String = match env.get_string(&url) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get URL string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get URL string", JNI_ERROR));
+        }
+    };
+
+    let result = match remote::inspect_payload_url_json(&url_str) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Remote payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin given as a file descriptor
+///
+/// For scoped storage, where the app only has a content:// URI and opens it
+/// with `ContentResolver.openFileDescriptor`. The descriptor is duplicated,
+/// so the caller still owns (and must close) the ParcelFileDescriptor.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadFd(int fd);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload` (no "properties")
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadFd<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    fd: jint,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadFd called with fd {}", fd);
+
+    let result = match file_from_fd(fd) {
+        Ok(file) => match payload::inspect_payload_file_json(file, &format!("fd:{}", fd)) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload inspection failed: {}", e);
+                error_json(&e.to_string(), e.code())
+            }
+        },
+        Err(e) => {
+            log::error!("{}", e);
+            error_json(&e, "INVALID_INPUT")
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Inspect a payload.bin already in memory
+///
+/// For payload bytes the app fetched itself, e.g. the first few hundred KiB
+/// of a download. Only the header and manifest need to be present.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String inspectPayloadBytes(byte[] data);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `inspectPayload` (no "properties")
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_inspectPayloadBytes<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    data: JByteArray<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("inspectPayloadBytes called");
+
+    let bytes = match env.convert_byte_array(&data) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            log::error!("Failed to get payload bytes: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get payload bytes", JNI_ERROR));
+        }
+    };
+
+    let result = match payload::inspect_payload_bytes_json(&bytes) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Verify the metadata signature of a payload.bin
+///
+/// Checks that the header and manifest were signed with the given RSA key,
+/// i.e. that the payload is authentic and its manifest untampered.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String verifyMetadataSignature(String path, String publicKeyPem);
+/// ```
+///
+/// # Returns
+/// * `{"valid": true}` or `{"valid": false}` when the payload is signed
+/// * JSON object with "error" field for unsigned payloads, invalid keys or
+///   unreadable files
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_verifyMetadataSignature<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    public_key_pem: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("verifyMetadataSignature called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&path, "path"), (&public_key_pem, "public key")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match signature::verify_metadata_signature_json(&args[0], &args[1]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Signature verification failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Verify a whole payload.bin against payload_properties.txt
+///
+/// Hashes the entire file and compares it with FILE_HASH, and its length with
+/// FILE_SIZE, to catch truncated or corrupted downloads before extraction.
+/// Hashing a multi-GB payload takes a while, so progress is reported, and it
+/// can be cancelled with `cancelCurrentExtraction` like an extraction.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String verifyPayloadIntegrity(String path, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * `{"valid": true, "size_matches": true, "hash_matches": true, ...}`
+/// * JSON object with "error" field if the file is unreadable, there is no
+///   payload_properties.txt to check against, or the check was cancelled
+///   (code CANCELLED)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_verifyPayloadIntegrity<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("verifyPayloadIntegrity called");
+
+    let path_str: String = match env.get_string(&path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get path string: {:?}", e);
+            return to_jstring(&mut env, &error_json("Failed to get path string", JNI_ERROR));
+        }
+    };
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::verify_payload_integrity_json(&path_str, options.cancel.clone(), progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Integrity check failed: {}", e);
+                error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Verify a whole payload.bin with a chosen checksum
+///
+/// Like `verifyPayloadIntegrity`, with `algorithm` "sha256" or "crc32". CRC32
+/// is several times faster and enough to catch accidental corruption, but
+/// not tampering. The checksum is compared with `expectedChecksum` if it is
+/// not null (base64 SHA-256 or hex CRC32), else with FILE_HASH for SHA-256;
+/// the length is checked against FILE_SIZE either way.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String verifyPayloadIntegrityWithAlgorithm(String path, String algorithm, String expectedChecksum, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * `{"valid": true, "algorithm": "crc32", "size_matches": true, "hash_matches": true, ...}`
+/// * JSON object with "error" field if the algorithm is unknown, the file is
+///   unreadable, there is nothing to check against, or the check was
+///   cancelled (code CANCELLED)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_verifyPayloadIntegrityWithAlgorithm<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    path: JString<'local>,
+    algorithm: JString<'local>,
+    expected_checksum: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("verifyPayloadIntegrityWithAlgorithm called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&path, "path"), (&algorithm, "algorithm")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+    let expected_checksum: Option<String> = if expected_checksum.is_null() {
+        None
+    } else {
+        match env.get_string(&expected_checksum) {
+            Ok(s) => Some(s.into()),
+            Err(e) => {
+                log::error!("Failed to get expected checksum: {:?}", e);
+                return to_jstring(&mut env, &error_json("Failed to get expected checksum", JNI_ERROR));
+            }
+        }
+    };
+
+    let Some(algorithm) = payload::IntegrityAlgorithm::from_name(&args[1]) else {
+        let message = format!("Unknown checksum algorithm '{}', expected sha256 or crc32", args[1]);
+        return to_jstring(&mut env, &error_json(&message, "INVALID_INPUT"));
+    };
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::verify_payload_integrity_with_json(
+            &args[0],
+            algorithm,
+            expected_checksum.as_deref(),
+            options.cancel.clone(),
+            progress_callback,
+        ) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Integrity check failed: {}", e);
+                error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Convert a raw image to an Android sparse image
+///
+/// Zero-filled blocks are stored as DONT_CARE chunks, so the result is
+/// smaller and can be flashed with `fastboot flash`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String convertToSparse(String rawImagePath, String sparseOutputPath);
+/// ```
+///
+/// # Returns
+/// * `{"path": ..., "raw_size": ..., "sparse_size": ..., "total_chunks": ...}` on success
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_convertToSparse<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    raw_image_path: JString<'local>,
+    sparse_output_path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("convertToSparse called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&raw_image_path, "raw image path"), (&sparse_output_path, "sparse output path")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match sparse::convert_to_sparse_json(&args[0], &args[1]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Sparse conversion failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Assemble a super image from extracted partition images
+///
+/// Lays the partitions of the payload's dynamic partition groups out in a
+/// super image with LP metadata, taking each from `<imagesDir>/<name>.img`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String buildSuperImage(String payloadPath, String imagesDir, String outPath);
+/// ```
+///
+/// # Returns
+/// * `{"path": ..., "size": ..., "metadata_max_size": ..., "metadata_slot_count": ...,
+///   "partitions": [{"name", "group", "offset", "size"}, ...]}` on success
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_buildSuperImage<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    images_dir: JString<'local>,
+    out_path: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("buildSuperImage called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [(&payload_path, "payload path"), (&images_dir, "images directory"), (&out_path, "output path")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let result = match super_image::build_super_image_json(&args[0], &args[1], &args[2]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Building super image failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Build a payload.bin from partition images
+///
+/// Writes a full payload with one partition per image, using REPLACE
+/// operations ("none") or REPLACE_XZ where it saves space ("xz"). The payload
+/// is unsigned, see the `repack` module.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String createPayload(String[] names, String[] imagePaths, String outPath, String compression);
+/// ```
+///
+/// # Arguments
+/// * `names` - Partition names, in manifest order
+/// * `imagePaths` - Image of each partition, same length as `names`
+/// * `compression` - "none" or "xz"
+///
+/// # Returns
+/// * `{"path": ..., "size": ..., "manifest_size": ..., "signed": false,
+///   "partitions": [{"name", "size", "data_size", "operations_count", "hash"}, ...]}`
+///   on success
+/// * JSON object with "error" field on failure
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_createPayload<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    names: JObjectArray<'local>,
+    image_paths: JObjectArray<'local>,
+    out_path: JString<'local>,
+    compression: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("createPayload called");
+
+    let (names, image_paths) = match (get_string_array(&mut env, &names), get_string_array(&mut env, &image_paths)) {
+        (Ok(names), Ok(image_paths)) => (names, image_paths),
+        (Err(message), _) | (_, Err(message)) => {
+            let error_json = error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&out_path, "output path"), (&compression, "compression")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    if names.len() != image_paths.len() {
+        let message = format!("{} names but {} image paths", names.len(), image_paths.len());
+        let error_json = error_json(&message, "INVALID_INPUT");
+        return to_jstring(&mut env, &error_json);
+    }
+    let images: Vec<(String, String)> = names.into_iter().zip(image_paths).collect();
+    log::info!("Repacking {} partitions -> {}", images.len(), args[0]);
+
+    let result = match repack::create_payload_json(&images, &args[0], &args[1]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Repacking payload failed: {}", e);
+            error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partition images from payload.bin
+///
+/// Extracts all partitions from a payload.bin file to the specified output directory.
+/// Uses streaming I/O to handle large files without OOM.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayload(String payloadPath, String outputDir, ProgressListener listener);
+/// ```
+///
+/// # Arguments
+/// * `payloadPath` - Path to the payload.bin file
+/// * `outputDir` - Directory where .img files will be written
+/// * `progressListener` - Optional callback for progress updates
+///
+/// # Returns
+/// * JSON string with status and result
+///
+/// Success response:
+/// ```json
+/// {
+///   "status": "success",
+///   "extracted": [
+///     {"name": "system", "size": 2147483648, "path": "/data/PayloadPack/project/system.img",
+///      "verified": true, "expected_hash": "9f86d081...", "actual_hash": "9f86d081..."},
+///     {"name": "vendor", "size": 536870912, "path": "/data/PayloadPack/project/vendor.img",
+///      "verified": false, "expected_hash": "2c26b46b...", "actual_hash": "fcde2b2e..."}
+///   ],
+///   "skipped": [],
+///   "failed": [],
+///   "warnings": [],
+///   "empty_partitions": [],
+///   "excluded": [],
+///   "linked": []
+/// }
+/// ```
+///
+/// `warnings` lists operations of unknown type whose data was written as-is,
+/// e.g. `{"partition": "odm", "operation_index": 12, "operation_type": "UNKNOWN(15)"}`;
+/// the affected images may be invalid.
+/// `empty_partitions` names partitions of size 0 (e.g. placeholders), for
+/// which no image is written. `excluded` names the partitions left out by the
+/// `exclude` option of `extractPayloadWithOptions`.
+///
+/// Error response:
+/// ```json
+/// {
+///   "status": "error",
+///   "message": "Permission denied: Failed to write partition",
+///   "code": "PERMISSION_DENIED"
+/// }
+/// ```
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayload<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayload called");
+
+    // Extract path strings from JNI
+    let payload_path_str: String = match env.get_string(&payload_path) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get payload path: {:?}", e);
+            let error_json = extraction_error_json("Failed to get payload path", JNI_ERROR);
+            return match env.new_string(&error_json) {
+                Ok(s) => s.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            };
+        }
+    };
+
+    let output_dir_str: String = match env.get_string(&output_dir) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get output dir: {:?}", e);
+            let error_json = extraction_error_json("Failed to get output directory", JNI_ERROR);
+            return match env.new_string(&error_json) {
+                Ok(s) => s.into_raw(),
+                Err(_) => std::ptr::null_mut(),
+            };
+        }
+    };
+
+    log::info!("Extracting payload: {} -> {}", payload_path_str, output_dir_str);
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    // Call the extraction function with progress callback
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::extract_payload_json(
+            &payload_path_str,
+            &output_dir_str,
+            options,
+            progress_callback,
+        ) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Apply an incremental payload to the source build's images
+///
+/// Rebuilds the target build's partition images from `<sourceDir>/<name>.img`
+/// and the payload's SOURCE_COPY, SOURCE_BSDIFF and BROTLI_BSDIFF operations
+/// (see `payload::apply_incremental`). Payloads with other diff operations
+/// fail up front with code OPERATION_FAILED; a missing `sourceDir` with
+/// FILE_NOT_FOUND. Can be cancelled with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String applyIncremental(String payloadPath, String sourceDir, String outputDir, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`; "verified" is false
+///   for images whose source image didn't match the payload
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_applyIncremental<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    source_dir: JString<'local>,
+    output_dir: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("applyIncremental called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [
+        (&payload_path, "payload path"),
+        (&source_dir, "source directory"),
+        (&output_dir, "output directory"),
+    ] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    log::info!("Applying incremental payload: {} + {} -> {}", args[0], args[1], args[2]);
+
+    if let Err(e) = payload::check_source_dir(&args[1]) {
+        let error_json = extraction_error_json(&e.to_string(), e.code());
+        return to_jstring(&mut env, &error_json);
+    }
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    // Same as payload::apply_incremental, with a cancellation token
+    let options = payload::ExtractOptions {
+        source_dir: Some(args[1].clone()),
+        ..Default::default()
+    };
+    let result = with_cancel_token(options, |options| {
+        match payload::extract_payload_json(&args[0], &args[2], options, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Incremental update failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partition images from payload.bin with extra options
+///
+/// Like `extractPayload`, with the extraction options given as a JSON object.
+/// All fields are optional:
+/// ```json
+/// {
+///   "max_threads": 4,
+///   "partitions": ["boot", "vendor_boot"],
+///   "exclude": ["userdata", "vendor_*"],
+///   "verify_operations": true,
+///   "source_dir": "/sdcard/PayloadPack/stock",
+///   "progress_interval_ms": 100,
+///   "progress_min_bytes": 0,
+///   "dry_run": false,
+///   "use_mmap": false,
+///   "output_sparse": false,
+///   "force": false,
+///   "on_existing": "overwrite",
+///   "filename_template": "{name}.img",
+///   "decompress_threads": 1,
+///   "create_empty": false,
+///   "write_buffer_size": 1048576,
+///   "raw": false,
+///   "block_size": 4096,
+///   "progress_unit": "output",
+///   "timeout_ms": 0,
+///   "continue_on_error": false,
+///   "drop_page_cache": false,
+///   "link_duplicates": false
+/// }
+/// ```
+/// `exclude` leaves out partitions by name or pattern (`*` matches any run of
+/// characters, `?` one character), applied after `partitions`; they are
+/// listed in "excluded" and don't count towards progress.
+/// `filename_template` names the images: `{name}` is the partition name and
+/// `{index}` its position in the manifest (e.g. "{name}_a.img"). It must
+/// contain `{name}` and give a plain file name inside the output directory.
+/// `decompress_threads` decodes that many consecutive REPLACE_XZ operations
+/// of a partition in parallel, holding their data in memory meanwhile.
+/// `create_empty` writes an empty image for each partition of size 0 instead
+/// of only listing it in "empty_partitions".
+/// `write_buffer_size` is the write buffer in front of each image, in bytes
+/// (default 1 MiB; 0 disables buffering).
+/// `raw` writes each partition's operation blobs as stored in the payload,
+/// without decompressing them, plus a `<file>.ops.json` sidecar listing each
+/// operation's type, offsets and length. Not available with `output_sparse`.
+/// `block_size` replaces the manifest's block size for the extent math (a
+/// power of two from 512 to 65536; omit it to use the manifest's).
+/// `progress_unit` is what progress counts: "output" (default) for image
+/// bytes written, or "compressed" for payload data read, which moves more
+/// evenly when partitions compress differently.
+/// `timeout_ms` gives up after that many milliseconds (0, the default, waits
+/// indefinitely): the extraction is cancelled, images it didn't complete are
+/// removed and the error code is "TIMEOUT".
+/// `continue_on_error` moves on to the next partition when one fails: the
+/// status becomes "partial" and "failed" lists each failed partition as
+/// `{"name": "vendor", "error": "...", "code": "IO_ERROR", "code_number": 3}`,
+/// its partial image removed.
+/// `drop_page_cache` syncs each completed image and drops it from the page
+/// cache (posix_fadvise DONTNEED), to relieve memory pressure during large
+/// extractions.
+/// `link_duplicates` replaces each image identical to an earlier one with a
+/// hard link to it once extraction is done; "linked" lists them as
+/// `{"name": "vbmeta_b", "same_as": "vbmeta_a", "hard_linked": true, "error": null}`
+/// (`hard_linked` false and an "error" where the filesystem has no hard links
+/// and the copy is kept).
+/// `on_existing` decides what happens to images already in the output
+/// directory: "overwrite" (default), "skip" (listed in "skipped") or "error"
+/// (fails before writing anything).
+/// Partitions completed by an earlier, interrupted extraction into the same
+/// directory are re-validated and skipped (reported with `"resumed": true`)
+/// unless `force` is set.
+/// `output_sparse` writes Android sparse images instead of raw ones.
+/// `use_mmap` reads operation data from a memory mapping of the payload
+/// instead of seek+read calls; it falls back to regular reads if mapping fails.
+/// With `dry_run`, nothing is written: the result has status "dry_run" and
+/// lists each partition's output size with `supported`/`unsupported_reason`.
+/// Progress callbacks are throttled to one per `progress_interval_ms`
+/// (default 100), or every `progress_min_bytes` when set; the final update
+/// always fires.
+/// With `max_threads` > 1, partitions are extracted concurrently; progress is
+/// aggregated across workers and the first failure stops the others.
+/// Can be cancelled with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadWithOptions(String payloadPath, String outputDir, String optionsJson, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadWithOptions<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    options_json: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadWithOptions called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [
+        (&payload_path, "payload path"),
+        (&output_dir, "output directory"),
+        (&options_json, "options"),
+    ] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let options: payload::ExtractOptions = match serde_json::from_str(&args[2]) {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("Invalid extraction options: {}", e);
+            let error_json = extraction_error_json(&format!("Invalid options: {}", e), "INVALID_INPUT");
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Extracting payload: {} -> {} with {:?}", args[0], args[1], options);
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(options, |options| {
+        match payload::extract_payload_json(&args[0], &args[1], options, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partition images, reporting each one as it completes
+///
+/// Same as `extractPayloadWithOptions`, plus `partitionListener.onPartitionComplete(name, size, path)`
+/// as soon as each image is written (and up front for partitions resumed from
+/// an interrupted extraction), so a list of results can fill in while the
+/// extraction runs. With `max_threads` > 1 it is called from worker threads,
+/// in completion order. Can be cancelled with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadStreaming(String payloadPath, String outputDir, String optionsJson, PartitionListener partitionListener, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadStreaming<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    options_json: JString<'local>,
+    partition_listener: JObject<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadStreaming called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [
+        (&payload_path, "payload path"),
+        (&output_dir, "output directory"),
+        (&options_json, "options"),
+    ] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let options: payload::ExtractOptions = match serde_json::from_str(&args[2]) {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("Invalid extraction options: {}", e);
+            let error_json = extraction_error_json(&format!("Invalid options: {}", e), "INVALID_INPUT");
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Extracting payload: {} -> {} with {:?}", args[0], args[1], options);
+
+    let callbacks = make_partition_callback(&mut env, &partition_listener)
+        .and_then(|partition| Ok((partition, make_progress_callback(&mut env, &progress_listener)?)));
+    let (partition_callback, progress_callback) = match callbacks {
+        Ok(callbacks) => callbacks,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+    let on_partition = move |done: &payload::ExtractedPartition| {
+        if let Some(callback) = &partition_callback {
+            callback(done);
+        }
+    };
+
+    let result = with_cancel_token(options, |options| {
+        match payload::extract_payload_streaming_json(&args[0], &args[1], options, progress_callback, on_partition) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract a subset of partitions from payload.bin
+///
+/// Like `extractPayload`, but only the named partitions are written and the
+/// progress percentage is based on their combined size. Names that are not in
+/// the payload are reported in the "skipped" array; if none of them are, the
+/// extraction fails with an error listing the available partitions. Can be
+/// cancelled with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadSelective(String payloadPath, String outputDir, String[] partitionNames, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with status and result, e.g.
+/// ```json
+/// {
+///   "status": "success",
+///   "extracted": [{"name": "boot", "size": 100663296, "path": "/data/PayloadPack/project/boot.img", ...}],
+///   "skipped": [{"name": "vendor_bot", "reason": "not found in payload"}]
+/// }
+/// ```
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadSelective<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    partition_names: JObjectArray<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadSelective called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&payload_path, "payload path"), (&output_dir, "output directory")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    let names = match get_string_array(&mut env, &partition_names) {
+        Ok(names) => names,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Extracting {:?} from {} -> {}", names, args[0], args[1]);
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::extract_payload_selective_json(
+            &args[0],
+            &args[1],
+            &names,
+            options,
+            progress_callback,
+        ) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Selective extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partition images from the payload.bin inside an OTA zip
+///
+/// Same as `extractPayload`, but reads payload.bin in place from the package,
+/// halving the storage needed on-device. Cancellable with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadZip(String zipPath, String outputDir, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadZip<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    zip_path: JString<'local>,
+    output_dir: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadZip called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&zip_path, "zip path"), (&output_dir, "output directory")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    log::info!("Extracting payload from zip: {} -> {}", args[0], args[1]);
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::extract_payload_zip_json(&args[0], &args[1], options, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction from zip failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partition images from a payload.bin file descriptor
+///
+/// Same as `extractPayload` for payloads opened through the Storage Access
+/// Framework. The descriptor is duplicated, so the caller keeps ownership of
+/// the original and must close it. Cancellable with `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadFd(int fd, String outputDir, ProgressListener listener);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadFd<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    fd: jint,
+    output_dir: JString<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadFd called with fd {}", fd);
+
+    let output_dir_str: String = match env.get_string(&output_dir) {
+        Ok(s) => s.into(),
+        Err(e) => {
+            log::error!("Failed to get output dir: {:?}", e);
+            return to_jstring(&mut env, &extraction_error_json("Failed to get output directory", JNI_ERROR));
+        }
+    };
+
+    let file = match file_from_fd(fd) {
+        Ok(file) => file,
+        Err(e) => {
+            log::error!("{}", e);
+            let error_json = extraction_error_json(&e, "INVALID_INPUT");
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    log::info!("Extracting payload from fd {} -> {}", fd, output_dir_str);
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(payload::ExtractOptions::default(), |options| {
+        match payload::extract_payload_file_json(file, &output_dir_str, options, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract partitions into file descriptors supplied per partition
+///
+/// For Storage Access Framework destinations, where the app can create a
+/// document per image but has no writable directory path. Before writing each
+/// partition, `provider.openOutput(name)` is called on this thread and must
+/// return a file descriptor opened for reading and writing ("rw"), or -1 to
+/// abort. The descriptor is duplicated, so the app keeps ownership and closes
+/// its ParcelFileDescriptors after this call returns. Cancellable with
+/// `cancelCurrentExtraction`.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractPayloadToFds(String payloadPath, String optionsJson, OutputFdProvider provider, ProgressListener listener);
+/// ```
+///
+/// # Arguments
+/// * `optionsJson` - Same options as `extractPayloadWithOptions`; partitions
+///   are always written one at a time and "output_sparse" and "timeout_ms"
+///   are rejected
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`, with "path" set to
+///   "fd:<n>" for each image
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPayloadToFds<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    options_json: JString<'local>,
+    provider: JObject<'local>,
+    progress_listener: JObject<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractPayloadToFds called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&payload_path, "payload path"), (&options_json, "options")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    if provider.is_null() {
+        return to_jstring(&mut env, &extraction_error_json("No output provider given", JNI_ERROR));
+    }
+
+    let options: payload::ExtractOptions = match serde_json::from_str(&args[1]) {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("Invalid extraction options: {}", e);
+            let error_json = extraction_error_json(&format!("Invalid options: {}", e), "INVALID_INPUT");
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let progress_callback = match make_progress_callback(&mut env, &progress_listener) {
+        Ok(callback) => callback,
+        Err(message) => {
+            let error_json = extraction_error_json(&message, JNI_ERROR);
+            return to_jstring(&mut env, &error_json);
+        }
+    };
+
+    let result = with_cancel_token(options, |options| {
+        let open_output = |name: &str| open_provided_output(&mut env, &provider, name);
+        match payload::extract_payload_to_files_json(&args[0], options, open_output, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    to_jstring(&mut env, &result)
+}
+
+/// Ask the Kotlin `OutputFdProvider` for the descriptor of a partition's image
+fn open_provided_output(
+    env: &mut JNIEnv,
+    provider: &JObject,
+    name: &str,
+) -> Result<(std::fs::File, String), payload::PayloadError> {
+    let j_name = env
+        .new_string(name)
+        .map_err(|e| payload::PayloadError::Io(format!("Failed to create string: {:?}", e)))?;
+    let fd = env
+        .call_method(
+            provider,
+            "openOutput",
+            "(Ljava/lang/String;)I",
+            &[jni::objects::JValue::Object(&j_name)],
+        )
+        .and_then(|value| value.i());
+    let _ = env.delete_local_ref(j_name);
+
+    let fd = match fd {
+        Ok(fd) => fd,
+        Err(e) => {
+            // Don't leave the exception pending for the rest of the extraction
+            let _ = env.exception_clear();
+            log::error!("openOutput failed for {}: {:?}", name, e);
+            return Err(payload::PayloadError::Io(format!("Could not open output for {}", name)));
+        }
+    };
+
+    log::info!("Writing {} to fd {}", name, fd);
+    let file = file_from_fd(fd).map_err(payload::PayloadError::Io)?;
+    Ok((file, format!("fd:{}", fd)))
+}
+
+/// JNI Function: Extract a single partition image from payload.bin
+///
+/// Only processes the operations of the named partition, which is much faster
+/// than a full extraction when only e.g. boot.img is needed.
+///
+/// # JNI Signature
+/// ```java
+/// public static native String extractSinglePartition(String payloadPath, String outputDir, String partitionName);
+/// ```
+///
+/// # Returns
+/// * JSON string with the same shape as `extractPayload`, with one entry in "extracted"
+/// * `{"status":"error","message":"..."}` on failure; an unknown partition name
+///   lists the available partitions in the message
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractSinglePartition<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    partition_name: JString<'local>,
+) -> jstring {
+    init_logger();
+    log::info!("extractSinglePartition called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [
+        (&payload_path, "payload path"),
+        (&output_dir, "output directory"),
+        (&partition_name, "partition name"),
+    ] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let error_json = extraction_error_json(&format!("Failed to get {}", what), JNI_ERROR);
+                return to_jstring(&mut env, &error_json);
+            }
+        }
+    }
+
+    log::info!("Extracting partition {} from {} -> {}", args[2], args[0], args[1]);
+
+    let result = match payload::extract_partition_json(&args[0], &args[1], &args[2]) {
+        Ok(json) => json,
+        Err(e) => {
+            log::error!("Partition extraction failed: {}", e);
+            extraction_error_json(&e.to_string(), e.code())
+        }
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Extract a single small partition into a byte array
+///
+/// Nothing is written to storage, which suits reading e.g. the boot image
+/// header. Partitions larger than `maxSize` are refused instead of risking an
+/// OutOfMemoryError.
+///
+/// # JNI Signature
+/// ```java
+/// public static native byte[] extractPartitionToBytes(String payloadPath, String partitionName, long maxSize);
+/// ```
+///
+/// # Arguments
+/// * `maxSize` - Largest image accepted in bytes; 0 or less for the default
+///   (`payload::DEFAULT_IN_MEMORY_LIMIT`, 128 MiB)
+///
+/// # Returns
+/// * The partition image on success
+/// * Throws `java.io.IOException` with the error message on failure (the
+///   return value is then null)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPartitionToBytes<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    partition_name: JString<'local>,
+    max_size: jlong,
+) -> jbyteArray {
+    init_logger();
+    log::info!("extractPartitionToBytes called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&payload_path, "payload path"), (&partition_name, "partition name")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let _ = env.throw_new("java/io/IOException", format!("Failed to get {}", what));
+                return std::ptr::null_mut();
+            }
+        }
+    }
+
+    let max_size = (max_size > 0).then_some(max_size as u64);
+    let image = payload::extract_partition_to_vec(&args[0], &args[1], max_size);
+    to_jbytearray(&mut env, image, "In-memory partition extraction")
+}
+
+/// JNI Function: Read the first bytes of a partition into a byte array
+///
+/// Only the operations writing into the first `maxBytes` are decompressed,
+/// which makes reading magic numbers and version headers of large
+/// partitions (e.g. system) cheap.
+///
+/// # JNI Signature
+/// ```java
+/// public static native byte[] extractPartitionPrefix(String payloadPath, String partitionName, long maxBytes);
+/// ```
+///
+/// # Returns
+/// * The first `maxBytes` of the partition image, fewer if the image is smaller
+/// * Throws `java.io.IOException` with the error message on failure (the
+///   return value is then null)
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_extractPartitionPrefix<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    partition_name: JString<'local>,
+    max_bytes: jlong,
+) -> jbyteArray {
+    init_logger();
+    log::info!("extractPartitionPrefix called");
+
+    let mut args = Vec::with_capacity(2);
+    for (value, what) in [(&payload_path, "payload path"), (&partition_name, "partition name")] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let _ = env.throw_new("java/io/IOException", format!("Failed to get {}", what));
+                return std::ptr::null_mut();
+            }
+        }
+    }
+
+    let prefix = payload::extract_partition_prefix(&args[0], &args[1], max_bytes.max(0) as u64);
+    to_jbytearray(&mut env, prefix, "Partition prefix extraction")
+}
+
+/// JNI Function: Cancel the extraction started by `extractPayload`
+///
+/// Flips the cancellation token of the running extraction. The extractor
+/// stops at the next operation boundary, removes the partially written
+/// image, and `extractPayload` returns an error response with code
+/// "CANCELLED".
+///
+/// # JNI Signature
+/// ```java
+/// public static native boolean cancelCurrentExtraction();
+/// ```
+///
+/// # Returns
+/// * `true` if an extraction was running and has been asked to stop
+/// * `false` if no extraction is in progress
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_cancelCurrentExtraction<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+) -> jboolean {
+    log::info!("cancelCurrentExtraction called");
+
+    let active = match ACTIVE_CANCEL_TOKEN.lock() {
+        Ok(active) => active,
+        Err(e) => {
+            log::error!("Cancel token lock poisoned: {:?}", e);
+            return JNI_FALSE;
+        }
+    };
+
+    match active.as_ref() {
+        Some(token) => {
+            token.store(true, Ordering::Relaxed);
+            JNI_TRUE
+        }
+        None => {
+            log::debug!("No extraction in progress");
+            JNI_FALSE
+        }
+    }
+}
+
+/// JNI Function: Start extracting a payload.bin in the background
+///
+/// Returns at once with a handle; the extraction runs on a native thread.
+/// Use `pollProgress` to follow it, `cancelExtraction` to stop it and
+/// `releaseExtraction` to free the handle once the result has been read.
+/// Unlike `extractPayload`, no listener object is called back.
+///
+/// # JNI Signature
+/// ```java
+/// public static native long beginExtraction(String payloadPath, String outputDir, String optionsJson);
+/// ```
+///
+/// # Arguments
+/// * `optionsJson` - Same options as `extractPayloadWithOptions`
+///
+/// # Returns
+/// * A handle (> 0) on success
+/// * 0 with a pending `java.io.IOException` if the arguments are invalid or
+///   the thread can't be started
+///
+/// # Safety
+/// This function is called from the JVM and must not panic.
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_beginExtraction<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    payload_path: JString<'local>,
+    output_dir: JString<'local>,
+    options_json: JString<'local>,
+) -> jlong {
+    init_logger();
+    log::info!("beginExtraction called");
+
+    let mut args = Vec::with_capacity(3);
+    for (value, what) in [
+        (&payload_path, "payload path"),
+        (&output_dir, "output directory"),
+        (&options_json, "options"),
+    ] {
+        match env.get_string(value) {
+            Ok(s) => args.push(String::from(s)),
+            Err(e) => {
+                log::error!("Failed to get {}: {:?}", what, e);
+                let _ = env.throw_new("java/io/IOException", format!("Failed to get {}", what));
+                return 0;
+            }
+        }
+    }
+
+    let options: payload::ExtractOptions = match serde_json::from_str(&args[2]) {
+        Ok(options) => options,
+        Err(e) => {
+            log::error!("Invalid extraction options: {}", e);
+            let _ = env.throw_new("java/io/IOException", format!("Invalid options: {}", e));
+            return 0;
+        }
+    };
+
+    log::info!("Extracting payload in background: {} -> {} with {:?}", args[0], args[1], options);
+
+    let (payload_path, output_dir) = (args[0].clone(), args[1].clone());
+    let started = handles::begin(options, move |options, on_progress| {
+        let progress_callback = Some(|update: &payload::ProgressUpdate| on_progress(update));
+        match payload::extract_payload_json(&payload_path, &output_dir, options, progress_callback) {
+            Ok(json) => json,
+            Err(e) => {
+                log::error!("Payload extraction failed: {}", e);
+                extraction_error_json(&e.to_string(), e.code())
+            }
+        }
+    });
+
+    match started {
+        Ok(handle) => handle,
+        Err(e) => {
+            log::error!("Failed to start extraction: {}", e);
+            let _ = env.throw_new("java/io/IOException", e.to_string());
+            0
+        }
+    }
+}
+
+/// JNI Function: Get the progress of an extraction started by `beginExtraction`
+///
+/// # JNI Signature
+/// ```java
+/// public static native String pollProgress(long handle);
+/// ```
+///
+/// # Returns
+/// * JSON string, e.g.
+/// ```json
+/// {
+///   "state": "running",
+///   "current_file": "system",
+///   "percent": 42,
+///   "bytes_processed": 1800000000,
+///   "total_bytes": 4294967296,
+///   "bytes_per_second": 52428800,
+///   "eta_seconds": 48,
+///   "cancelled": false,
+///   "result": null
+/// }
+/// ```
+///   Once "state" is "finished", "result" holds the same object
+///   `extractPayload` returns.
+/// * JSON object with "error" field for unknown or released handles
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_pollProgress<'local>(
+    mut env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jstring {
+    let result = match handles::poll(handle) {
+        Some(snapshot) => payload::to_json(&snapshot, false).unwrap_or_else(|e| error_json(&e.to_string(), e.code())),
+        None => error_json(&format!("Unknown extraction handle {}", handle), "INVALID_INPUT"),
+    };
+
+    to_jstring(&mut env, &result)
+}
+
+/// JNI Function: Cancel an extraction started by `beginExtraction`
+///
+/// The extraction stops at the next operation boundary; `pollProgress` then
+/// reports it as finished with a "cancelled" error result.
+///
+/// # JNI Signature
+/// ```java
+/// public static native boolean cancelExtraction(long handle);
+/// ```
+///
+/// # Returns
+/// * `false` if the handle is unknown
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_cancelExtraction<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    log::info!("cancelExtraction called for {}", handle);
+
+    if handles::cancel(handle) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// JNI Function: Free a handle returned by `beginExtraction`
+///
+/// A still running extraction is cancelled. The handle is invalid afterwards.
+///
+/// # JNI Signature
+/// ```java
+/// public static native boolean releaseExtraction(long handle);
+/// ```
+///
+/// # Returns
+/// * `false` if the handle is unknown or already released
+#[unsafe(no_mangle)]
+pub extern "system" fn Java_id_xms_payloadpack_native_NativeLib_releaseExtraction<'local>(
+    _env: JNIEnv<'local>,
+    _class: JClass<'local>,
+    handle: jlong,
+) -> jboolean {
+    log::info!("releaseExtraction called for {}", handle);
+
+    if handles::release(handle) {
+        JNI_TRUE
+    } else {
+        JNI_FALSE
+    }
+}
+
+/// JNI Function: Library initialization
+/// Called when System.loadLibrary() is executed
+#[unsafe(no_mangle)]
+pub extern "system" fn JNI_OnLoad(
+    vm: jni::JavaVM,
+    _reserved: *mut std::ffi::c_void,
+) -> jni::sys::jint {
+    init_logger();
+    log::info!("PayloadPack native library loaded successfully");
+    
+    // Verify we can get an environment
+    match vm.get_env() {
+        Ok(_) => log::debug!("JNI environment verified"),
+        Err(e) => log::error!("Failed to get JNI environment: {:?}", e),
+    }
+
+    jni::sys::JNI_VERSION_1_6
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_from_fd_leaves_original_open() {
+        use std::io::{Read, Seek, SeekFrom};
+        use std::os::fd::AsRawFd;
+
+        let mut original = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut original, b"payload").unwrap();
+
+        drop(file_from_fd(original.as_raw_fd()).unwrap());
+
+        // The original descriptor must still be usable after the duplicate is closed
+        let mut contents = String::new();
+        original.seek(SeekFrom::Start(0)).unwrap();
+        original.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "payload");
+
+        assert!(file_from_fd(-1).is_err());
+    }
+
+    #[test]
+    fn test_payload_info_fields_match_inspection_json() {
+        use crate::proto::{DeltaArchiveManifest, PartitionInfo, PartitionUpdate};
+        use prost::Message;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("payload.bin");
+        let write_payload = |manifest: DeltaArchiveManifest| {
+            let manifest = manifest.encode_to_vec();
+            let mut data = b"CrAU".to_vec();
+            data.extend_from_slice(&2u64.to_be_bytes());
+            data.extend_from_slice(&(manifest.len() as u64).to_be_bytes());
+            data.extend_from_slice(&0u32.to_be_bytes());
+            data.extend_from_slice(&manifest);
+            std::fs::write(&path, data).unwrap();
+        };
+        let system = PartitionUpdate {
+            partition_name: "system".to_string(),
+            new_partition_info: Some(PartitionInfo { size: Some(8192), hash: None }),
+            ..Default::default()
+        };
+
+        write_payload(DeltaArchiveManifest {
+            block_size: Some(4096),
+            minor_version: Some(0),
+            max_timestamp: Some(1_700_000_000),
+            security_patch_level: Some("2024-01-05".to_string()),
+            partitions: vec![system.clone()],
+            ..Default::default()
+        });
+        std::fs::write(dir.path().join("payload_properties.txt"), "FILE_SIZE=1234\nMETADATA_HASH=abc=\n").unwrap();
+
+        let inspection = payload::inspect_payload(path.to_str().unwrap()).unwrap();
+        let json = serde_json::to_value(&inspection).unwrap();
+        let fields = payload_info_fields(&inspection);
+        assert_eq!(json["header"]["version"], fields.version);
+        assert_eq!(json["header"]["manifest_size"], fields.manifest_size);
+        assert_eq!(json["header"]["metadata_signature_size"], fields.metadata_signature_size);
+        assert_eq!(json["header"]["data_offset"], fields.data_offset);
+        assert_eq!(json["block_size"], fields.block_size);
+        assert_eq!(json["partial_update"], fields.partial_update);
+        assert_eq!(json["is_incremental"], fields.is_incremental);
+        assert_eq!(json["update_type"], fields.update_type);
+        assert_eq!(json["security_patch_level"], "2024-01-05");
+        assert_eq!(fields.security_patch_level, Some("2024-01-05"));
+        assert_eq!(json["max_timestamp"], fields.max_timestamp);
+        assert_eq!(json["minor_version"], fields.minor_version);
+        assert_eq!(json["build_fingerprint"], serde_json::Value::Null);
+        assert_eq!(fields.build_fingerprint, None);
+        assert_eq!(json["total_size"], fields.total_size);
+        assert_eq!(json["total_size_human"], fields.total_size_human);
+        assert_eq!(json["file_path"], fields.file_path);
+        assert_eq!(json["metadata_verified"], serde_json::Value::Null);
+        assert_eq!(fields.metadata_verified, None);
+        assert_eq!(json["properties"]["file_size"], 1234);
+        assert_eq!(json["properties"]["metadata_hash"], "abc=");
+        assert_eq!(fields.properties, Some((None, 1234, Some("abc="), -1)));
+
+        // Absent values are null in the JSON and -1 or null in the typed result
+        write_payload(DeltaArchiveManifest {
+            block_size: Some(4096),
+            partitions: vec![system],
+            ..Default::default()
+        });
+        std::fs::remove_file(dir.path().join("payload_properties.txt")).unwrap();
+
+        let inspection = payload::inspect_payload(path.to_str().unwrap()).unwrap();
+        let json = serde_json::to_value(&inspection).unwrap();
+        let fields = payload_info_fields(&inspection);
+        assert_eq!(json["update_type"], fields.update_type);
+        assert_eq!(json["max_timestamp"], serde_json::Value::Null);
+        assert_eq!(fields.max_timestamp, -1);
+        assert_eq!(json["minor_version"], serde_json::Value::Null);
+        assert_eq!(fields.minor_version, -1);
+        assert_eq!(json["properties"], serde_json::Value::Null);
+        assert_eq!(fields.properties, None);
+    }
+}
@@ -0,0 +1,282 @@
+//! OTA Package Zip Module
+//!
+//! Android OTA packages are zips holding payload.bin and payload_properties.txt.
+//! This module exposes the payload.bin entry as a seekable stream so it can be
+//! inspected and extracted in place, without unzipping the package first.
+//!
+//! - STORE entries (the norm for OTA packages) are read directly from the zip
+//!   by offset, so random access is as cheap as for a plain payload.bin.
+//! - DEFLATE entries are decompressed on the fly through a `DeflateDecoder`.
+//!   Forward seeks skip through the stream; backward seeks restart
+//!   decompression from the entry start.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use flate2::read::DeflateDecoder;
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use zip::{CompressionMethod, ZipArchive};
+
+use crate::payload::PayloadError;
+
+/// Name of the payload entry inside an OTA package
+pub(crate) const PAYLOAD_ENTRY: &str = "payload.bin";
+
+/// Name of the properties entry inside an OTA package
+pub(crate) const PROPERTIES_ENTRY: &str = "payload_properties.txt";
+
+/// OTA package metadata (`key=value` lines, including the build fingerprints)
+pub(crate) const METADATA_ENTRY: &str = "META-INF/com/android/metadata";
+
+/// Seekable stream of a payload, wherever it lives
+pub(crate) trait PayloadStream: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> PayloadStream for T {}
+
+/// Open the payload.bin entry of an OTA package.
+///
+/// # Arguments
+/// * `file` - The opened OTA package zip
+///
+/// # Returns
+/// * `Ok((stream, size))` - The uncompressed payload stream and its size
+/// * `Err(PayloadError)` - If the zip is unreadable, has no payload.bin, or
+///   uses a compression method other than STORE or DEFLATE
+pub(crate) fn open_payload_entry(file: File) -> Result<(Box<dyn PayloadStream>, u64), PayloadError> {
+    let data_file = file
+        .try_clone()
+        .map_err(|e| PayloadError::Io(format!("Failed to duplicate zip handle: {}", e)))?;
+
+    let mut archive = ZipArchive::new(file)
+        .map_err(|e| PayloadError::InvalidInput(format!("Not a valid zip file: {}", e)))?;
+    let entry = archive.by_name(PAYLOAD_ENTRY).map_err(|_| {
+        PayloadError::InvalidInput(format!("No {} found in zip", PAYLOAD_ENTRY))
+    })?;
+
+    let data_start = entry.data_start();
+    let compressed_size = entry.compressed_size();
+    let size = entry.size();
+    let method = entry.compression();
+
+    log::info!(
+        "Found {} in zip: {:?}, {} bytes at offset {}",
+        PAYLOAD_ENTRY,
+        method,
+        size,
+        data_start
+    );
+
+    let stream: Box<dyn PayloadStream> = match method {
+        CompressionMethod::Stored => Box::new(RangeReader::new(data_file, data_start, size)),
+        CompressionMethod::Deflated => {
+            log::warn!("{} is DEFLATE-compressed, decompressing on the fly", PAYLOAD_ENTRY);
+            Box::new(InflateReader::new(data_file, data_start, compressed_size, size)?)
+        }
+        other => {
+            return Err(PayloadError::InvalidInput(format!(
+                "Unsupported compression for {}: {:?}",
+                PAYLOAD_ENTRY, other
+            )))
+        }
+    };
+
+    Ok((stream, size))
+}
+
+/// Whether the payload.bin entry of an OTA package is stored uncompressed,
+/// i.e. whether seeking within it is as cheap as in a plain file
+pub(crate) fn payload_entry_is_stored(file: File) -> bool {
+    ZipArchive::new(file)
+        .ok()
+        .and_then(|mut archive| archive.by_name(PAYLOAD_ENTRY).ok().map(|entry| entry.compression()))
+        == Some(CompressionMethod::Stored)
+}
+
+/// Read a small text entry (e.g. payload_properties.txt) from an OTA package.
+///
+/// Returns `None` if the entry is missing or unreadable.
+pub(crate) fn read_text_entry(file: File, name: &str) -> Option<String> {
+    let mut archive = ZipArchive::new(file).ok()?;
+    let mut entry = archive.by_name(name).ok()?;
+
+    let mut text = String::new();
+    match entry.read_to_string(&mut text) {
+        Ok(_) => Some(text),
+        Err(e) => {
+            log::warn!("Could not read {} from zip: {:?}", name, e);
+            None
+        }
+    }
+}
+
+/// Whether `file` starts with a zip local file header. Rewinds `file`
+/// afterwards.
+pub(crate) fn is_zip(file: &mut File) -> bool {
+    let mut magic = [0u8; 4];
+    let is_zip = file.read_exact(&mut magic).is_ok() && magic == *b"PK\x03\x04";
+    file.seek(SeekFrom::Start(0)).is_ok() && is_zip
+}
+
+/// How an entry of a zip is named in results, e.g.
+/// "/sdcard/ota.zip!payload_properties.txt"
+pub(crate) fn entry_label(zip_path: &str, name: &str) -> String {
+    format!("{}!{}", zip_path, name)
+}
+
+/// Build fingerprint of the target build (`post-build=`) from the OTA
+/// package metadata
+pub(crate) fn post_build_fingerprint(metadata: &str) -> Option<String> {
+    metadata.lines().find_map(|line| {
+        let (key, value) = line.split_once('=')?;
+        (key.trim() == "post-build" && !value.trim().is_empty()).then(|| value.trim().to_string())
+    })
+}
+
+/// Whether the OTA package metadata marks a downgrade package
+/// (`ota-downgrade=yes`), which devices accept from an older build only
+/// because the data partition is wiped along with it
+pub(crate) fn is_downgrade(metadata: &str) -> bool {
+    metadata.lines().any(|line| {
+        line.split_once('=')
+            .is_some_and(|(key, value)| key.trim() == "ota-downgrade" && value.trim() == "yes")
+    })
+}
+
+/// Window of `len` bytes starting at `start` in an underlying stream
+struct RangeReader<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    pos: u64,
+    /// Whether `inner` is positioned at `start + pos`
+    synced: bool,
+}
+
+impl<R: Read + Seek> RangeReader<R> {
+    fn new(inner: R, start: u64, len: u64) -> Self {
+        Self {
+            inner,
+            start,
+            len,
+            pos: 0,
+            synced: false,
+        }
+    }
+}
+
+impl<R: Read + Seek> Read for RangeReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pos >= self.len {
+            return Ok(0);
+        }
+        if !self.synced {
+            self.inner.seek(SeekFrom::Start(self.start + self.pos))?;
+            self.synced = true;
+        }
+
+        let want = (self.len - self.pos).min(buf.len() as u64) as usize;
+        let n = self.inner.read(&mut buf[..want])?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for RangeReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = resolve_seek(pos, self.pos, self.len)?;
+        if target != self.pos {
+            self.pos = target;
+            self.synced = false;
+        }
+        Ok(self.pos)
+    }
+}
+
+/// Seekable view of a DEFLATE-compressed entry.
+///
+/// Seeks are resolved lazily on the next read: forward by decompressing and
+/// discarding, backward by restarting from the beginning of the entry.
+struct InflateReader {
+    source: File,
+    start: u64,
+    compressed_len: u64,
+    /// Uncompressed length of the entry, as recorded in the zip
+    len: u64,
+    decoder: DeflateDecoder<BufReader<RangeReader<File>>>,
+    /// Uncompressed position of `decoder`
+    decoded: u64,
+    /// Position requested by the caller
+    pos: u64,
+}
+
+impl InflateReader {
+    fn new(source: File, start: u64, compressed_len: u64, len: u64) -> Result<Self, PayloadError> {
+        let decoder = Self::decoder(&source, start, compressed_len)?;
+        Ok(Self {
+            source,
+            start,
+            compressed_len,
+            len,
+            decoder,
+            decoded: 0,
+            pos: 0,
+        })
+    }
+
+    fn decoder(
+        source: &File,
+        start: u64,
+        compressed_len: u64,
+    ) -> std::io::Result<DeflateDecoder<BufReader<RangeReader<File>>>> {
+        let range = RangeReader::new(source.try_clone()?, start, compressed_len);
+        Ok(DeflateDecoder::new(BufReader::new(range)))
+    }
+
+    /// Bring the decoder to the requested position
+    fn catch_up(&mut self) -> std::io::Result<()> {
+        if self.pos < self.decoded {
+            log::debug!("Backward seek in DEFLATE payload, restarting decompression");
+            self.decoder = Self::decoder(&self.source, self.start, self.compressed_len)?;
+            self.decoded = 0;
+        }
+
+        if self.pos > self.decoded {
+            let skip = self.pos - self.decoded;
+            let skipped = std::io::copy(&mut (&mut self.decoder).take(skip), &mut std::io::sink())?;
+            self.decoded += skipped;
+            if skipped < skip {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Read for InflateReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.catch_up()?;
+        let n = self.decoder.read(buf)?;
+        self.decoded += n as u64;
+        self.pos = self.decoded;
+        Ok(n)
+    }
+}
+
+impl Seek for InflateReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = resolve_seek(pos, self.pos, self.len)?;
+        Ok(self.pos)
+    }
+}
+
+/// Resolve a `SeekFrom` against the current position and stream length
+pub(crate) fn resolve_seek(pos: SeekFrom, current: u64, len: u64) -> std::io::Result<u64> {
+    let target = match pos {
+        SeekFrom::Start(offset) => Some(offset),
+        SeekFrom::Current(delta) => current.checked_add_signed(delta),
+        SeekFrom::End(delta) => len.checked_add_signed(delta),
+    };
+    target.ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start of stream")
+    })
+}
@@ -11,24 +11,70 @@
 //! - Offset 20: Metadata Signature Size (u32, Big Endian)
 //! - Offset 24: Manifest data begins
 //!
+//! Version 1 headers lack the metadata signature size field, so the manifest
+//! begins at offset 20 and there is no metadata signature.
+//!
 //! IMPORTANT: This module is called from JNI and must NEVER panic.
 //! All errors must be returned as Result::Err, never via unwrap/expect.
 
 use prost::Message;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
 // Use the proto module with generated protobuf code
-use crate::proto::DeltaArchiveManifest;
+use crate::bspatch;
+use crate::checkpoint::Checkpoint;
+use crate::ota_zip::{self, PayloadStream};
+use crate::signature::{self, SignatureInfo};
+use crate::sparse;
+use crate::proto::install_operation::Type as OperationType;
+use crate::proto::{DeltaArchiveManifest, Extent, InstallOperation, PartitionUpdate};
 
 /// Magic bytes for payload.bin files
 const PAYLOAD_MAGIC: &[u8; 4] = b"CrAU";
 
 /// Header size in bytes (for version 2)
-const HEADER_SIZE: u64 = 24;
+pub(crate) const HEADER_SIZE: u64 = 24;
+
+/// Header size in bytes for version 1, which has no metadata signature size
+const HEADER_SIZE_V1: u64 = 20;
+
+/// Largest manifest accepted unless another limit is given (100 MiB), see
+/// `inspect_payload_with_manifest_limit`; real manifests are well under 1 MiB
+pub const DEFAULT_MAX_MANIFEST_SIZE: u64 = 100 * 1024 * 1024;
+
+/// Most partitions accepted in a manifest; real payloads have under a hundred
+const MAX_PARTITIONS: usize = 1024;
+
+/// Most operations accepted over all partitions. A full OTA of 16 GiB of
+/// images in 2 MiB operations has about 8k; incremental ones a few 100k.
+const MAX_OPERATIONS: usize = 4 * 1024 * 1024;
+
+/// Most source or destination extents accepted for one operation
+const MAX_EXTENTS_PER_OPERATION: usize = 64 * 1024;
+
+#[cfg(test)]
+thread_local! {
+    /// Number of payload files opened on this thread, to check that the
+    /// header and manifest are only read once
+    static PAYLOAD_OPENS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Chunk size used when streaming operation data to the output image
+const STREAM_CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Largest data blob accepted for a single operation. update_engine splits
+/// images into operations of a few MiB, so anything near this is corrupt.
+const MAX_OPERATION_DATA_LENGTH: u64 = 1024 * 1024 * 1024;
 
 /// Error types for payload parsing
 #[derive(Error, Debug)]
@@ -42,17 +88,17 @@ pub enum PayloadError {
     #[error("IO error reading file: {0}")]
     Io(String),
 
-    #[error("Invalid magic bytes: expected 'CrAU' (0x43724155), got '{0}' (0x{1:08X})")]
+    #[error("Invalid magic bytes: expected 'CrAU' (0x43724155), got '{0}' (0x{1:08X}){hint}", hint = wrong_file_hint(*.1))]
     InvalidMagic(String, u32),
 
-    #[error("Unsupported payload version: {0}. Only Version 2 is supported.")]
+    #[error("Unsupported payload version: {0}. Only Versions 1 and 2 are supported.")]
     UnsupportedVersion(u64),
 
     #[error("Protobuf decode error: {0}")]
     ProtobufDecode(String),
 
-    #[error("Manifest too large: {0} bytes (max 100MB). File may be corrupted.")]
-    ManifestTooLarge(u64),
+    #[error("Manifest too large: {0} bytes (max {1} bytes). File may be corrupted.")]
+    ManifestTooLarge(u64, u64),
 
     #[error("File too small ({0} bytes) to be a valid payload. Minimum size is {1} bytes.")]
     FileTooSmall(u64, u64),
@@ -60,8 +106,38 @@ pub enum PayloadError {
     #[error("Path is empty")]
     EmptyPath,
 
+    #[error("Manifest truncated: the header declares {0} bytes but only {1} are present. The download is probably incomplete.")]
+    TruncatedManifest(u64, u64),
+
     #[error("Unexpected end of file while reading {0}")]
     UnexpectedEof(String),
+
+    #[error("Operation failed: {0}")]
+    OperationFailed(String),
+
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
+
+    #[error("Data hash mismatch in operation {1} of partition {0}. The payload is corrupt or incomplete.")]
+    OperationHashMismatch(String, usize),
+
+    /// Partition, operation index, what was being done (e.g. "reading") and the cause
+    #[error("I/O error {2} operation {1} of {0}: {3}")]
+    OperationIo(String, usize, String, String),
+
+    #[error("Insufficient space: extraction needs {} but only {} is available", format_size(*.0), format_size(*.1))]
+    InsufficientSpace(u64, u64),
+
+    #[error("Output file already exists: {0}")]
+    OutputExists(String),
+
+    /// Stopped through the cancellation token
+    #[error("Operation cancelled")]
+    Cancelled,
+
+    /// Ran out of the time it was given
+    #[error("Operation timed out")]
+    Timeout,
 }
 
 // Custom From implementations for better error messages
@@ -78,21 +154,170 @@ impl From<std::io::Error> for PayloadError {
     }
 }
 
+/// Advice for files commonly passed instead of a payload.bin, recognised by
+/// their first four bytes; empty for anything else
+fn wrong_file_hint(magic: u32) -> &'static str {
+    match magic.to_be_bytes() {
+        // Local file header, or the end record of an empty archive
+        [b'P', b'K', 3, 4] | [b'P', b'K', 5, 6] => {
+            ". This looks like a ZIP archive, such as a full OTA package; extract payload.bin \
+             from it first or use inspectPayloadZip/extractPayloadZip"
+        }
+        [0x1F, 0x8B, ..] => ". This looks like a gzip file; decompress it first",
+        _ => "",
+    }
+}
+
+impl From<serde_json::Error> for PayloadError {
+    fn from(e: serde_json::Error) -> Self {
+        PayloadError::OperationFailed(format!("JSON serialization error: {}", e))
+    }
+}
+
+impl PayloadError {
+    /// Stable, machine-readable name of the error kind, sent alongside the
+    /// message in JSON error responses so callers don't have to match text
+    pub fn code(&self) -> &'static str {
+        match self {
+            PayloadError::FileNotFound(_) => "FILE_NOT_FOUND",
+            PayloadError::PermissionDenied(_) => "PERMISSION_DENIED",
+            PayloadError::Io(_) | PayloadError::OperationIo(..) => "IO_ERROR",
+            PayloadError::InvalidMagic(..) => "INVALID_MAGIC",
+            PayloadError::UnsupportedVersion(_) => "UNSUPPORTED_VERSION",
+            PayloadError::ProtobufDecode(_) => "PROTOBUF_DECODE",
+            PayloadError::ManifestTooLarge(..) => "MANIFEST_TOO_LARGE",
+            PayloadError::FileTooSmall(..) => "FILE_TOO_SMALL",
+            PayloadError::EmptyPath => "EMPTY_PATH",
+            PayloadError::TruncatedManifest(..) => "TRUNCATED_MANIFEST",
+            PayloadError::UnexpectedEof(_) => "UNEXPECTED_EOF",
+            PayloadError::Cancelled => "CANCELLED",
+            PayloadError::Timeout => "TIMEOUT",
+            PayloadError::OperationFailed(_) => "OPERATION_FAILED",
+            PayloadError::InvalidInput(_) => "INVALID_INPUT",
+            PayloadError::OperationHashMismatch(..) => "HASH_MISMATCH",
+            PayloadError::InsufficientSpace(..) => "INSUFFICIENT_SPACE",
+            PayloadError::OutputExists(_) => "OUTPUT_EXISTS",
+        }
+    }
+
+    /// Stable number of `code`, looked up in `ERROR_CODE_NUMBERS`
+    pub fn code_number(&self) -> i32 {
+        error_code_number(self.code())
+    }
+}
+
+/// Numbers of the error codes, sent as "code_number" next to "code" so
+/// callers can map errors onto their own types (e.g. a Kotlin sealed class)
+/// without comparing strings. A number is never changed or reused; new codes
+/// get the next free one. JNI_ERROR is raised by the JNI bindings; every
+/// `PayloadError::code` must have an entry.
+const ERROR_CODE_NUMBERS: [(&str, i32); 19] = [
+    ("FILE_NOT_FOUND", 1),
+    ("PERMISSION_DENIED", 2),
+    ("IO_ERROR", 3),
+    ("INVALID_MAGIC", 4),
+    ("UNSUPPORTED_VERSION", 5),
+    ("PROTOBUF_DECODE", 6),
+    ("MANIFEST_TOO_LARGE", 7),
+    ("FILE_TOO_SMALL", 8),
+    ("EMPTY_PATH", 9),
+    ("TRUNCATED_MANIFEST", 10),
+    ("UNEXPECTED_EOF", 11),
+    ("CANCELLED", 12),
+    ("TIMEOUT", 13),
+    ("OPERATION_FAILED", 14),
+    ("INVALID_INPUT", 15),
+    ("HASH_MISMATCH", 16),
+    ("INSUFFICIENT_SPACE", 17),
+    ("OUTPUT_EXISTS", 18),
+    ("JNI_ERROR", 19),
+];
+
+/// Stable number of an error code (see `PayloadError::code`), or 0 for a
+/// code without one
+pub fn error_code_number(code: &str) -> i32 {
+    ERROR_CODE_NUMBERS
+        .iter()
+        .find(|(name, _)| *name == code)
+        .map_or(0, |(_, number)| *number)
+}
+
+/// Map an I/O error of operation `op_idx` of `partition_name` during `step`
+/// ("seeking to", "reading", ...), for use with `map_err`. Running out of data
+/// is reported as `UnexpectedEof`, anything else as `OperationIo`.
+fn operation_io_error<'a>(
+    partition_name: &'a str,
+    op_idx: usize,
+    step: &'a str,
+) -> impl FnOnce(std::io::Error) -> PayloadError + 'a {
+    move |e| match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => {
+            PayloadError::UnexpectedEof(format!("operation {} of {}", op_idx, partition_name))
+        }
+        _ => PayloadError::OperationIo(partition_name.to_string(), op_idx, step.to_string(), e.to_string()),
+    }
+}
+
 impl From<prost::DecodeError> for PayloadError {
     fn from(e: prost::DecodeError) -> Self {
         PayloadError::ProtobufDecode(e.to_string())
     }
 }
 
-/// Payload header information
+/// Payload header information, with the offsets derived from it
 #[derive(Debug, Clone, Serialize)]
-pub struct PayloadHeader {
-    /// Payload format version (should be 2)
+pub struct ParsedHeader {
+    /// Payload format version (1 or 2)
     pub version: u64,
     /// Size of the manifest in bytes
     pub manifest_size: u64,
     /// Size of the metadata signature (v2+)
     pub metadata_signature_size: u32,
+    /// Offset of the data blob section: header + manifest + metadata signature
+    pub data_offset: u64,
+}
+
+impl ParsedHeader {
+    /// Size of the fixed header for this payload version, i.e. the offset at
+    /// which the manifest begins
+    pub fn header_size(&self) -> u64 {
+        header_size_for(self.version)
+    }
+
+    /// Size of the header plus manifest, i.e. the data covered by the
+    /// metadata signature and the offset at which that signature begins
+    pub fn metadata_size(&self) -> u64 {
+        self.header_size() + self.manifest_size
+    }
+}
+
+/// The fixed header of a payload and where its sections begin, see
+/// `read_payload_header`
+#[derive(Debug, Clone, Serialize)]
+pub struct PayloadHeaderInfo {
+    #[serde(flatten)]
+    pub header: ParsedHeader,
+    /// The fixed header as stored in the file, in hex (starts with
+    /// "43724155", "CrAU")
+    pub header_bytes: String,
+    /// Size of the fixed header, i.e. the offset of the manifest
+    pub manifest_offset: u64,
+    /// Offset of the metadata signature: header plus manifest
+    pub metadata_signature_offset: u64,
+    /// Size of the payload file
+    pub file_size: u64,
+    /// Bytes from `data_offset` to the end of the file: operation data and
+    /// the payload signature
+    pub data_size: u64,
+}
+
+/// Size of the fixed header of a payload of `version`
+fn header_size_for(version: u64) -> u64 {
+    if version == 1 {
+        HEADER_SIZE_V1
+    } else {
+        HEADER_SIZE
+    }
 }
 
 /// Information about a single partition
@@ -104,8 +329,65 @@ pub struct PartitionInfo {
     pub size: u64,
     /// Number of operations to apply
     pub operations_count: usize,
+    /// Number of operations of each type, e.g. {"REPLACE_XZ": 812, "ZERO": 3}
+    pub operation_types: BTreeMap<String, usize>,
     /// Size of the partition in human-readable format
     pub size_human: String,
+    /// SHA-256 of the new partition image (hex), if present in the manifest
+    pub hash: Option<String>,
+    /// Size of the source partition (delta payloads only)
+    pub old_size: Option<u64>,
+    /// SHA-256 of the source partition image (hex, delta payloads only)
+    pub old_hash: Option<String>,
+    /// Total number of blocks written, summed over all dst_extents
+    pub total_blocks: u64,
+    /// Bytes of operation data in the payload (sum of data_length)
+    pub compressed_size: u64,
+    /// `compressed_size / size`, e.g. 0.4 when the payload carries 40% of the
+    /// image size. `None` for empty partitions.
+    pub compression_ratio: Option<f64>,
+    /// Version string of the new partition (usually its build timestamp)
+    pub version: Option<String>,
+    /// dm-verity hash tree and FEC parameters (None if the manifest has none)
+    pub verity: Option<VerityInfo>,
+}
+
+/// dm-verity metadata of a partition: where update_engine writes the hash
+/// tree and FEC data, and how the hash tree is computed
+#[derive(Debug, Clone, Serialize)]
+pub struct VerityInfo {
+    /// Hash algorithm of the hash tree (e.g. "sha256")
+    pub hash_tree_algorithm: Option<String>,
+    /// Salt of the hash tree (hex)
+    pub hash_tree_salt: Option<String>,
+    /// Blocks covered by the hash tree
+    pub hash_tree_data_extent: Option<BlockRange>,
+    /// Blocks the hash tree is written to
+    pub hash_tree_extent: Option<BlockRange>,
+    /// Whether FEC (forward error correction) data is generated
+    pub has_fec: bool,
+    /// Blocks the FEC data is written to
+    pub fec_extent: Option<BlockRange>,
+    /// Number of FEC roots
+    pub fec_roots: Option<u32>,
+}
+
+/// A run of blocks in a partition image
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BlockRange {
+    /// First block of the run
+    pub start_block: u64,
+    /// Number of blocks in the run
+    pub num_blocks: u64,
+}
+
+impl From<&Extent> for BlockRange {
+    fn from(extent: &Extent) -> Self {
+        BlockRange {
+            start_block: extent.start_block.unwrap_or(0),
+            num_blocks: extent.num_blocks.unwrap_or(0),
+        }
+    }
 }
 
 /// Properties from payload_properties.txt
@@ -119,33 +401,186 @@ pub struct PayloadProperties {
     pub metadata_hash: Option<String>,
     /// METADATA_SIZE
     pub metadata_size: Option<u64>,
+    /// Any other `KEY=value` lines, e.g. SWITCH_SLOT_ON_REBOOT or
+    /// RUNNING_IN_RECOVERY, with key and value trimmed
+    pub extra: HashMap<String, String>,
 }
 
 /// Complete payload inspection result
 #[derive(Debug, Clone, Serialize)]
 pub struct PayloadInspection {
     /// Header information
-    pub header: PayloadHeader,
+    pub header: ParsedHeader,
     /// Block size used (usually 4096)
     pub block_size: u32,
     /// Whether this is a partial update
     pub partial_update: bool,
+    /// Whether the payload patches a source build (old partition info or
+    /// SOURCE_* operations) instead of carrying complete images
+    pub is_incremental: bool,
+    /// Kind of update, for display
+    pub update_type: UpdateType,
     /// Security patch level (if available)
     pub security_patch_level: Option<String>,
-    /// List of partitions in the payload
+    /// Build timestamp of the target build (seconds since the Unix epoch);
+    /// devices refuse payloads older than their current build
+    pub max_timestamp: Option<i64>,
+    /// `max_timestamp` as a UTC date, e.g. "2024-01-01T00:00:00Z"
+    pub max_timestamp_utc: Option<String>,
+    /// Whether the OTA package metadata marks this as a downgrade package
+    /// (`ota-downgrade=yes`), which devices accept despite `max_timestamp`
+    /// because the data partition is wiped. None when the metadata is not
+    /// available.
+    pub ota_downgrade: Option<bool>,
+    /// Minor version of the payload format (0 for full payloads)
+    pub minor_version: Option<u32>,
+    /// Fingerprint of the target build, from the OTA package metadata
+    /// (META-INF/com/android/metadata) when it is available
+    pub build_fingerprint: Option<String>,
+    /// List of partitions in the payload, sorted by name
     pub partitions: Vec<PartitionInfo>,
+    /// Number of partitions, i.e. `partitions.len()`
+    pub partition_count: usize,
+    /// Number of operations over all partitions
+    pub total_operations: usize,
     /// Total size of all partitions
     pub total_size: u64,
     /// Total size in human-readable format
     pub total_size_human: String,
+    /// Number of operations of each type over all partitions
+    pub operation_types: BTreeMap<String, usize>,
+    /// Keys of `operation_types` this build can't apply (see `capabilities`),
+    /// e.g. ["PUFFDIFF"]. Partitions using them fail to extract.
+    pub unsupported_operation_types: Vec<String>,
     /// Path that was inspected
     pub file_path: String,
     /// Properties from payload_properties.txt (if found)
     pub properties: Option<PayloadProperties>,
+    /// Where `properties` was read from: the path of payload_properties.txt,
+    /// or `<zip>!payload_properties.txt` for the entry of an OTA package
+    pub properties_source: Option<String>,
+    /// Whether the header and manifest match METADATA_SIZE and METADATA_HASH
+    /// from payload_properties.txt (None if either is missing)
+    pub metadata_verified: Option<bool>,
+    /// Super partition layout (None for payloads without dynamic partitions)
+    pub dynamic_partitions: Option<DynamicPartitionsInfo>,
+    /// APEX modules updated by this payload (empty if the manifest has none)
+    pub apex_packages: Vec<ApexPackageInfo>,
+    /// Whether the header declares a metadata signature
+    /// (`metadata_signature_size` > 0). Unsigned payloads, e.g. test builds
+    /// and repacked ones, inspect and extract like signed ones.
+    pub is_signed: bool,
+    /// Metadata and payload signatures, unverified (empty if unsigned)
+    pub signatures: Vec<SignatureInfo>,
+    /// Offset of the payload signature blob, relative to `header.data_offset`
+    /// (None if the payload is unsigned). Operation data never extends into it.
+    pub signatures_offset: Option<u64>,
+    /// Size of the payload signature blob in bytes
+    pub signatures_size: Option<u64>,
+}
+
+/// dm-verity metadata of `partition`, None if it has no hash tree or FEC
+fn verity_info(partition: &PartitionUpdate) -> Option<VerityInfo> {
+    let has_hash_tree = partition.hash_tree_extent.is_some()
+        || partition.hash_tree_data_extent.is_some()
+        || partition.hash_tree_algorithm.is_some();
+    let has_fec = partition.fec_extent.is_some() || partition.fec_data_extent.is_some();
+    if !has_hash_tree && !has_fec {
+        return None;
+    }
+
+    Some(VerityInfo {
+        hash_tree_algorithm: partition.hash_tree_algorithm.clone(),
+        hash_tree_salt: partition.hash_tree_salt.as_deref().map(to_hex),
+        hash_tree_data_extent: partition.hash_tree_data_extent.as_ref().map(BlockRange::from),
+        hash_tree_extent: partition.hash_tree_extent.as_ref().map(BlockRange::from),
+        has_fec,
+        fec_extent: partition.fec_extent.as_ref().map(BlockRange::from),
+        fec_roots: partition.fec_roots,
+    })
+}
+
+/// Kind of OTA a payload contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpdateType {
+    /// Complete images of every partition; flashable on its own
+    Full,
+    /// Deltas against one exact source build
+    Incremental,
+    /// Complete images of only some partitions
+    Partial,
+}
+
+/// An APEX module bundled in the payload, from the manifest's apex_info
+#[derive(Debug, Clone, Serialize)]
+pub struct ApexPackageInfo {
+    /// Package name (e.g. "com.android.tzdata")
+    pub name: Option<String>,
+    /// Version code of the APEX
+    pub version: Option<i64>,
+    /// Whether the APEX is shipped compressed (.capex)
+    pub is_compressed: bool,
+    /// Size once decompressed, in bytes (compressed APEXes only)
+    pub decompressed_size: Option<i64>,
+}
+
+/// Dynamic partition metadata from the manifest: how the partitions inside
+/// the super partition are grouped
+#[derive(Debug, Clone, Serialize)]
+pub struct DynamicPartitionsInfo {
+    /// Partition groups, in manifest order
+    pub groups: Vec<DynamicPartitionGroupInfo>,
+    /// Whether Virtual A/B snapshots are enabled
+    pub snapshot_enabled: bool,
+    /// Whether Virtual A/B compression is enabled
+    pub vabc_enabled: bool,
+}
+
+/// A group of dynamic partitions sharing a size limit
+#[derive(Debug, Clone, Serialize)]
+pub struct DynamicPartitionGroupInfo {
+    /// Group name (e.g. "qti_dynamic_partitions")
+    pub name: String,
+    /// Maximum total size of the group's partitions in bytes
+    pub max_size: Option<u64>,
+    /// Names of the partitions in this group
+    pub partitions: Vec<String>,
+}
+
+/// Summarize the manifest's dynamic_partition_metadata, if present
+fn dynamic_partitions_info(manifest: &DeltaArchiveManifest) -> Option<DynamicPartitionsInfo> {
+    let metadata = manifest.dynamic_partition_metadata.as_ref()?;
+
+    let groups: Vec<DynamicPartitionGroupInfo> = metadata
+        .groups
+        .iter()
+        .map(|group| DynamicPartitionGroupInfo {
+            name: group.name.clone(),
+            max_size: group.size,
+            partitions: group.partition_names.clone(),
+        })
+        .collect();
+
+    log::info!("Dynamic partitions: {} groups", groups.len());
+    for group in &groups {
+        log::info!(
+            "  Group {}: max {}, partitions [{}]",
+            group.name,
+            group.max_size.map_or("unknown".to_string(), format_size),
+            group.partitions.join(", ")
+        );
+    }
+
+    Some(DynamicPartitionsInfo {
+        groups,
+        snapshot_enabled: metadata.snapshot_enabled.unwrap_or(false),
+        vabc_enabled: metadata.vabc_enabled.unwrap_or(false),
+    })
 }
 
 /// Format bytes into human-readable string
-fn format_size(bytes: u64) -> String {
+pub(crate) fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
     const GB: u64 = MB * 1024;
@@ -161,6 +596,34 @@ fn format_size(bytes: u64) -> String {
     }
 }
 
+/// Format seconds since the Unix epoch as an ISO 8601 UTC date, e.g.
+/// "2024-01-01T00:00:00Z"
+pub(crate) fn format_utc_timestamp(timestamp: i64) -> String {
+    let days = timestamp.div_euclid(86_400);
+    let seconds = timestamp.rem_euclid(86_400);
+
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
 /// Inspect a payload.bin file and extract partition information.
 ///
 /// This function reads only the header and manifest, making it memory-efficient
@@ -176,15 +639,423 @@ fn format_size(bytes: u64) -> String {
 /// # Safety
 /// This function NEVER panics. All errors are returned via Result.
 pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
+    inspect_payload_with_properties(path, None)
+}
+
+/// Inspect a payload.bin file, giving up after `timeout`.
+///
+/// The inspection runs on a thread of its own. If a read hangs (e.g. on a
+/// network or FUSE filesystem), the thread is left behind and its result is
+/// dropped once it finishes.
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information
+/// * `Err(PayloadError::Timeout)` - If `timeout` elapsed
+/// * `Err(PayloadError)` - If parsing fails
+pub fn inspect_payload_with_timeout(path: &str, timeout: Duration) -> Result<PayloadInspection, PayloadError> {
+    let path = path.to_string();
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("timed-inspection".to_string())
+        .spawn(move || {
+            let _ = tx.send(inspect_payload(&path));
+        })
+        .map_err(|e| PayloadError::Io(format!("Failed to start inspection thread: {}", e)))?;
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result,
+        Err(RecvTimeoutError::Timeout) => {
+            log::error!("Inspection timed out after {:?}", timeout);
+            Err(PayloadError::Timeout)
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(PayloadError::OperationFailed(
+            "Inspection thread stopped unexpectedly".to_string(),
+        )),
+    }
+}
+
+/// Inspect a payload with a timeout and return the result as a JSON string,
+/// see `inspect_payload_with_timeout`
+pub fn inspect_payload_with_timeout_json(path: &str, timeout: Duration) -> Result<String, PayloadError> {
+    inspect_payload_with_timeout(path, timeout).and_then(|inspection| to_json(&inspection, true))
+}
+
+/// Inspect a payload with a custom manifest size limit and return the result
+/// as a JSON string, see `inspect_payload_with_manifest_limit`
+pub fn inspect_payload_with_manifest_limit_json(path: &str, max_manifest_size: u64) -> Result<String, PayloadError> {
+    inspect_payload_with_manifest_limit(path, max_manifest_size).and_then(|inspection| to_json(&inspection, true))
+}
+
+/// Read the fixed header of a payload and the offsets derived from it.
+///
+/// Operation data offsets in the manifest are relative to `data_offset`,
+/// header + manifest + metadata signature, so adding it gives the position of
+/// a blob in the file. The manifest is not read.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+///
+/// # Returns
+/// * `Ok(PayloadHeaderInfo)` - Header fields, raw header bytes and section offsets
+/// * `Err(PayloadError)` - Bad magic, unsupported version or a short header
+pub fn read_payload_header(path: &str) -> Result<PayloadHeaderInfo, PayloadError> {
+    log::info!("Reading payload header: {}", path);
+
+    let (mut file, file_size) = open_payload_file(path)?;
+    let header = parse_header(&mut file, file_size)?;
+
+    let mut header_bytes = vec![0u8; header.header_size() as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut header_bytes)?;
+
+    log::info!("Data offset: {} of {} bytes", header.data_offset, file_size);
+
+    Ok(PayloadHeaderInfo {
+        header_bytes: to_hex(&header_bytes),
+        manifest_offset: header.header_size(),
+        metadata_signature_offset: header.metadata_size(),
+        file_size,
+        data_size: file_size.saturating_sub(header.data_offset),
+        header,
+    })
+}
+
+/// Read the payload header and return it as JSON, see `read_payload_header`
+pub fn read_payload_header_json(path: &str) -> Result<String, PayloadError> {
+    read_payload_header(path).and_then(|header| to_json(&header, true))
+}
+
+/// Inspect a payload.bin file, taking payload_properties.txt from
+/// `properties_path` if given.
+///
+/// Meant for payloads that were taken out of their OTA package, where the
+/// properties are no longer next to payload.bin. `properties_path` may be
+/// the properties file itself or the OTA package zip holding it. If it
+/// can't be read, the properties next to the payload are used as usual.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+/// * `properties_path` - payload_properties.txt or OTA package zip
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information, with
+///   `properties_source` telling which properties were used
+/// * `Err(PayloadError)` - If parsing fails
+pub fn inspect_payload_with_properties(
+    path: &str,
+    properties_path: Option<&str>,
+) -> Result<PayloadInspection, PayloadError> {
+    inspect_path(path, properties_path, DEFAULT_MAX_MANIFEST_SIZE)
+}
+
+/// Inspect a payload.bin file, accepting manifests of up to
+/// `max_manifest_size` bytes instead of `DEFAULT_MAX_MANIFEST_SIZE`.
+///
+/// A larger limit admits payloads with unusually many partitions or APEX
+/// entries; a smaller one bounds the memory a crafted payload can make the
+/// inspection allocate.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+/// * `max_manifest_size` - Largest manifest accepted, in bytes
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information
+/// * `Err(PayloadError::ManifestTooLarge)` - If the manifest exceeds the limit
+/// * `Err(PayloadError)` - If parsing fails
+pub fn inspect_payload_with_manifest_limit(
+    path: &str,
+    max_manifest_size: u64,
+) -> Result<PayloadInspection, PayloadError> {
+    inspect_path(path, None, max_manifest_size)
+}
+
+/// Inspect the payload.bin at `path`, see `inspect_payload_with_properties`
+fn inspect_path(
+    path: &str,
+    properties_path: Option<&str>,
+    max_manifest_size: u64,
+) -> Result<PayloadInspection, PayloadError> {
+    log::info!("=== PAYLOAD INSPECTION START ===");
+    log::info!("Path: {}", path);
+
+    let (mut file, file_size) = open_payload_file(path)?;
+    let metadata = read_metadata_bytes(&mut file, file_size, max_manifest_size)?;
+    let mut inspection = inspect_metadata(&metadata, path, &mut file, file_size, true, max_manifest_size)?;
+
+    // =========================================================================
+    // STEP 8: Try to read payload_properties.txt if it exists
+    // =========================================================================
+    let properties = properties_path
+        .and_then(|properties_path| read_properties_file(Path::new(properties_path)))
+        .or_else(|| {
+            let adjacent = adjacent_properties_path(path)?;
+            read_properties_file(&adjacent)
+        });
+    attach_properties(&mut inspection, properties, &metadata);
+    attach_ota_metadata(&mut inspection, read_ota_metadata(path).as_deref());
+    Ok(inspection)
+}
+
+/// Inspect several payload.bin files, e.g. every OTA in a directory.
+///
+/// Each payload is inspected on its own with `inspect_payload`, so one bad
+/// file doesn't keep the others from being reported.
+///
+/// # Arguments
+/// * `paths` - Paths to the payload.bin files
+///
+/// # Returns
+/// * One result per path, in the order of `paths`
+pub fn inspect_payloads(paths: &[String]) -> Vec<Result<PayloadInspection, PayloadError>> {
+    log::info!("Inspecting {} payloads", paths.len());
+    paths.iter().map(|path| inspect_payload(path)).collect()
+}
+
+/// Inspect a payload held in memory.
+///
+/// Only the header and manifest are needed, so `data` may be just the start
+/// of a payload (e.g. fetched over the network); a complete payload.bin
+/// works too. There is no file to look next to, so `properties` and
+/// `build_fingerprint` are not filled in and `file_path` is empty.
+///
+/// # Arguments
+/// * `data` - Payload bytes, starting at the "CrAU" magic
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information
+/// * `Err(PayloadError)` - If `data` is not a payload or is cut short
+pub fn inspect_payload_bytes(data: &[u8]) -> Result<PayloadInspection, PayloadError> {
+    log::info!("=== PAYLOAD INSPECTION START (memory) ===");
+    log::info!("{} bytes", data.len());
+
+    inspect_metadata(
+        data,
+        "",
+        &mut std::io::Cursor::new(data),
+        data.len() as u64,
+        true,
+        DEFAULT_MAX_MANIFEST_SIZE,
+    )
+}
+
+/// Parse the header and manifest at the start of `data` into an inspection
+/// labelled `label`, with the signatures read from `payload` (see
+/// `signature::read_signatures`). Every local inspect entry point ends up here.
+fn inspect_metadata<R: Read + Seek>(
+    data: &[u8],
+    label: &str,
+    payload: &mut R,
+    payload_size: u64,
+    payload_signature: bool,
+    max_manifest_size: u64,
+) -> Result<PayloadInspection, PayloadError> {
+    let mut cursor = std::io::Cursor::new(data);
+    let (header, manifest) = read_header_and_manifest_with_limit(&mut cursor, data.len() as u64, max_manifest_size)?;
+    let signatures = signature::read_signatures(payload, payload_size, &header, &manifest, payload_signature);
+
+    let mut inspection = build_inspection(label, header, &manifest)?;
+    inspection.signatures = signatures;
+    Ok(inspection)
+}
+
+/// Add payload_properties.txt and where it came from to an inspection,
+/// checking the header and manifest (`metadata`) against its METADATA_SIZE
+/// and METADATA_HASH
+fn attach_properties(
+    inspection: &mut PayloadInspection,
+    properties: Option<(PayloadProperties, String)>,
+    metadata: &[u8],
+) {
+    if let Some((props, source)) = properties {
+        log::info!("✓ Found and parsed payload_properties.txt ({})", source);
+        inspection.metadata_verified = verify_metadata_hash(metadata, &props);
+        inspection.properties = Some(props);
+        inspection.properties_source = Some(source);
+    }
+}
+
+/// Compare the first METADATA_SIZE bytes of the payload (header and
+/// manifest) with METADATA_HASH.
+///
+/// This only needs the metadata, so it is cheap enough to run on every
+/// inspection, unlike hashing the whole file against FILE_HASH.
+///
+/// # Returns
+/// * `Some(true)` / `Some(false)` - Whether size and hash match
+/// * `None` - If METADATA_SIZE or METADATA_HASH is missing
+fn verify_metadata_hash(metadata: &[u8], properties: &PayloadProperties) -> Option<bool> {
+    use base64::Engine;
+
+    let expected_hash = properties.metadata_hash.as_deref()?;
+    let expected_size = properties.metadata_size?;
+
+    // METADATA_SIZE covers exactly the header and manifest
+    if expected_size != metadata.len() as u64 {
+        log::warn!(
+            "METADATA_SIZE is {}, but header and manifest are {} bytes",
+            expected_size,
+            metadata.len()
+        );
+        return Some(false);
+    }
+
+    let actual_hash = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(metadata));
+    let matches = actual_hash == expected_hash;
+    if matches {
+        log::info!("✓ Metadata matches METADATA_HASH");
+    } else {
+        log::warn!("✗ Metadata hash {} does not match METADATA_HASH {}", actual_hash, expected_hash);
+    }
+    Some(matches)
+}
+
+/// Read the header and manifest from the start of a payload into memory.
+///
+/// Only the fixed header is parsed, to know how much to read; anything wrong
+/// with it is left for `read_header_and_manifest` to report. The
+/// result is never longer than `payload_size` or than a manifest of
+/// `max_manifest_size` bytes plus header.
+fn read_metadata_bytes<R: Read>(
+    reader: &mut R,
+    payload_size: u64,
+    max_manifest_size: u64,
+) -> Result<Vec<u8>, PayloadError> {
+    let mut data = Vec::new();
+    reader.by_ref().take(HEADER_SIZE.min(payload_size)).read_to_end(&mut data)?;
+
+    let wanted = match parse_header_with_limit(&mut &data[..], payload_size, max_manifest_size) {
+        Ok(header) => header.metadata_size().min(payload_size),
+        // All there is of a manifest within the limit, so the error reports
+        // how much of it is present
+        Err(PayloadError::TruncatedManifest(..)) => payload_size,
+        Err(_) => return Ok(data),
+    };
+    let remaining = wanted.saturating_sub(data.len() as u64);
+    reader.take(remaining).read_to_end(&mut data)?;
+
+    Ok(data)
+}
+
+/// Inspect the payload.bin inside an OTA package zip without unzipping it.
+///
+/// payload_properties.txt is read from the same zip.
+///
+/// # Arguments
+/// * `zip_path` - Path to the OTA package (.zip)
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information
+/// * `Err(PayloadError)` - Error if the zip has no usable payload.bin
+pub fn inspect_payload_zip(zip_path: &str) -> Result<PayloadInspection, PayloadError> {
+    log::info!("=== PAYLOAD INSPECTION START (zip) ===");
+    log::info!("Path: {}", zip_path);
+
+    let (zip_file, _) = open_payload_file(zip_path)?;
+    let read_entry = |name| {
+        zip_file
+            .try_clone()
+            .ok()
+            .and_then(|file| ota_zip::read_text_entry(file, name))
+    };
+    let properties = read_entry(ota_zip::PROPERTIES_ENTRY).map(|text| {
+        (
+            parse_properties(text.as_bytes()),
+            ota_zip::entry_label(zip_path, ota_zip::PROPERTIES_ENTRY),
+        )
+    });
+    let ota_metadata = read_entry(ota_zip::METADATA_ENTRY);
+
+    // Seeking to the payload signature would inflate all of a DEFLATE entry
+    let stored = zip_file.try_clone().is_ok_and(ota_zip::payload_entry_is_stored);
+    let (mut stream, payload_size) = ota_zip::open_payload_entry(zip_file)?;
+    let metadata = read_metadata_bytes(&mut stream, payload_size, DEFAULT_MAX_MANIFEST_SIZE)?;
+
+    let mut inspection = inspect_metadata(
+        &metadata,
+        zip_path,
+        &mut stream,
+        payload_size,
+        stored,
+        DEFAULT_MAX_MANIFEST_SIZE,
+    )?;
+    attach_properties(&mut inspection, properties, &metadata);
+    attach_ota_metadata(&mut inspection, ota_metadata.as_deref());
+    Ok(inspection)
+}
+
+/// List partition names in manifest order.
+///
+/// Only decodes the header and manifest; unlike `inspect_payload` it skips
+/// payload_properties.txt, size aggregation and sorting, which keeps it fast
+/// enough to populate a partition picker.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+///
+/// # Returns
+/// * `Ok(Vec<String>)` - Partition names as they appear in the manifest
+/// * `Err(PayloadError)` - Error if the header or manifest cannot be read
+pub fn list_partition_names(path: &str) -> Result<Vec<String>, PayloadError> {
+    log::info!("Listing partitions: {}", path);
+
+    let (mut file, file_size) = open_payload_file(path)?;
+    let (_, manifest) = read_header_and_manifest(&mut file, file_size)?;
+
+    Ok(manifest
+        .partitions
+        .into_iter()
+        .map(|partition| partition.partition_name)
+        .collect())
+}
+
+/// Inspect a payload from an already opened file, e.g. one obtained from a
+/// file descriptor for a content:// URI.
+///
+/// There is no directory to look in, so payload_properties.txt is not read.
+///
+/// # Arguments
+/// * `file` - The opened payload.bin; it is consumed and closed when done
+/// * `label` - Shown as `file_path` in the result (e.g. the URI)
+pub fn inspect_payload_file(file: File, label: &str) -> Result<PayloadInspection, PayloadError> {
+    log::info!("=== PAYLOAD INSPECTION START (file) ===");
+    log::info!("Source: {}", label);
+
+    let file_size = opened_file_size(&file)?;
+    let mut file = file;
+    let metadata = read_metadata_bytes(&mut file, file_size, DEFAULT_MAX_MANIFEST_SIZE)?;
+
+    inspect_metadata(&metadata, label, &mut file, file_size, true, DEFAULT_MAX_MANIFEST_SIZE)
+}
+
+/// Size of an opened payload file, rejecting anything that isn't a regular file
+fn opened_file_size(file: &File) -> Result<u64, PayloadError> {
+    let metadata = file
+        .metadata()
+        .map_err(|e| PayloadError::Io(format!("Cannot stat payload file: {}", e)))?;
+
+    // Pipes and sockets can't be seeked, which extraction relies on
+    if !metadata.is_file() {
+        log::error!("Payload descriptor is not a regular file");
+        return Err(PayloadError::InvalidInput(
+            "Payload must be a regular, seekable file".to_string(),
+        ));
+    }
+
+    // Apparent size, like `open_payload_file`
+    Ok(metadata.len())
+}
+
+/// Open a payload file after validating the path.
+///
+/// Returns the file and its size in bytes.
+pub(crate) fn open_payload_file(path: &str) -> Result<(File, u64), PayloadError> {
     // Validate path is not empty
     if path.is_empty() {
         log::error!("Empty path provided");
         return Err(PayloadError::EmptyPath);
     }
 
-    log::info!("=== PAYLOAD INSPECTION START ===");
-    log::info!("Path: {}", path);
-
     // Check if file exists before trying to open
     let path_obj = Path::new(path);
     if !path_obj.exists() {
@@ -204,7 +1075,7 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
     }
 
     // Open the file
-    let mut file = match File::open(path) {
+    let file = match File::open(path) {
         Ok(f) => {
             log::debug!("File opened successfully");
             f
@@ -215,7 +1086,9 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
         }
     };
 
-    // Get file size
+    // Get file size. This is the apparent size (st_size), not the space
+    // allocated: a payload stored sparse or on f2fs with holes reads back
+    // zeros for its holes, so every offset is checked against this size
     let file_size = match file.metadata() {
         Ok(m) => m.len(),
         Err(e) => {
@@ -226,26 +1099,172 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
 
     log::info!("File size: {} bytes ({})", file_size, format_size(file_size));
 
-    if file_size < HEADER_SIZE {
-        log::error!(
-            "File too small: {} bytes, need at least {} bytes",
-            file_size,
-            HEADER_SIZE
-        );
-        return Err(PayloadError::FileTooSmall(file_size, HEADER_SIZE));
+    #[cfg(test)]
+    PAYLOAD_OPENS.with(|opens| opens.set(opens.get() + 1));
+
+    Ok((file, file_size))
+}
+
+/// Smallest block size accepted in a manifest
+pub const MIN_BLOCK_SIZE: u32 = 512;
+
+/// Largest block size accepted in a manifest
+pub const MAX_BLOCK_SIZE: u32 = 65536;
+
+/// The manifest's block size (4096 when absent), checked for plausibility.
+///
+/// Every extent is converted to bytes with it, so a corrupt value such as 0
+/// must be rejected before any offset is computed.
+///
+/// # Returns
+/// * `Ok(u32)` - A power of two between `MIN_BLOCK_SIZE` and `MAX_BLOCK_SIZE`
+/// * `Err(PayloadError::InvalidInput)` - For any other value
+pub fn validated_block_size(manifest: &DeltaArchiveManifest) -> Result<u32, PayloadError> {
+    let block_size = manifest.block_size.unwrap_or(4096);
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        log::error!("Implausible block size in manifest: {}", block_size);
+        return Err(PayloadError::InvalidInput(format!(
+            "Block size {} is not a power of two between {} and {}",
+            block_size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        )));
     }
+    Ok(block_size)
+}
 
-    // =========================================================================
-    // STEP 1: Read and verify Magic Bytes (Offset 0, 4 bytes)
-    // Expected: "CrAU" = 0x43 0x72 0x41 0x55
-    // =========================================================================
-    let mut magic = [0u8; 4];
-    if let Err(e) = file.read_exact(&mut magic) {
-        log::error!("Failed to read magic bytes: {:?}", e);
-        return Err(PayloadError::from(e));
+/// The block size given to override the manifest's, checked like
+/// `validated_block_size`. The manifest's own value isn't checked, so
+/// payloads with a bogus one can still be extracted.
+fn overridden_block_size(manifest: &DeltaArchiveManifest, block_size: u32) -> Result<u32, PayloadError> {
+    if !block_size.is_power_of_two() || !(MIN_BLOCK_SIZE..=MAX_BLOCK_SIZE).contains(&block_size) {
+        log::error!("Invalid block size override: {}", block_size);
+        return Err(PayloadError::InvalidInput(format!(
+            "Block size override {} is not a power of two between {} and {}",
+            block_size, MIN_BLOCK_SIZE, MAX_BLOCK_SIZE
+        )));
     }
 
-    log::info!(
+    let manifest_block_size = manifest.block_size.unwrap_or(4096);
+    if block_size != manifest_block_size {
+        log::warn!(
+            "Overriding manifest block size {} with {}",
+            manifest_block_size,
+            block_size
+        );
+    }
+    Ok(block_size)
+}
+
+/// Reject manifests that list a partition name more than once.
+///
+/// Both entries would be written to the same image, the second silently
+/// replacing the first, so such a payload is treated as malformed.
+fn check_unique_partition_names(manifest: &DeltaArchiveManifest) -> Result<(), PayloadError> {
+    let mut seen = std::collections::HashSet::new();
+    for partition in &manifest.partitions {
+        if !seen.insert(partition.partition_name.as_str()) {
+            log::error!("Duplicate partition in manifest: {}", partition.partition_name);
+            return Err(PayloadError::InvalidInput(format!(
+                "Partition '{}' appears more than once in the manifest",
+                partition.partition_name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Reject manifests with more partitions, operations or extents than any
+/// real payload has.
+///
+/// Everything after decoding loops over these lists and sizes allocations by
+/// them, so a crafted manifest within `DEFAULT_MAX_MANIFEST_SIZE` could otherwise make
+/// the extractor do absurd amounts of work.
+fn check_manifest_limits(manifest: &DeltaArchiveManifest) -> Result<(), PayloadError> {
+    if manifest.partitions.len() > MAX_PARTITIONS {
+        log::error!("Manifest lists {} partitions", manifest.partitions.len());
+        return Err(PayloadError::InvalidInput(format!(
+            "Manifest lists {} partitions (max {})",
+            manifest.partitions.len(),
+            MAX_PARTITIONS
+        )));
+    }
+
+    let mut total_operations: usize = 0;
+    for partition in &manifest.partitions {
+        total_operations += partition.operations.len();
+        if total_operations > MAX_OPERATIONS {
+            log::error!("Manifest has more than {} operations", MAX_OPERATIONS);
+            return Err(PayloadError::InvalidInput(format!(
+                "Manifest has more than {} operations (partition {} has {})",
+                MAX_OPERATIONS,
+                partition.partition_name,
+                partition.operations.len()
+            )));
+        }
+
+        for (op_idx, operation) in partition.operations.iter().enumerate() {
+            let extents = operation.src_extents.len().max(operation.dst_extents.len());
+            if extents > MAX_EXTENTS_PER_OPERATION {
+                log::error!(
+                    "Operation {} of {} has {} extents",
+                    op_idx,
+                    partition.partition_name,
+                    extents
+                );
+                return Err(PayloadError::InvalidInput(format!(
+                    "Operation {} of partition {} has {} extents (max {})",
+                    op_idx, partition.partition_name, extents, MAX_EXTENTS_PER_OPERATION
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Read and validate the fixed payload header and compute the offsets of the
+/// sections that follow it.
+///
+/// The reader must be positioned at the start of the payload and is left at
+/// the start of the manifest.
+///
+/// # Arguments
+/// * `reader` - Payload data, positioned at its start
+/// * `file_size` - Total size of the payload, to reject truncated headers
+///
+/// # Returns
+/// * `Ok(ParsedHeader)` - Version, section sizes and the data blob offset
+/// * `Err(PayloadError)` - Bad magic, unsupported version or a short header
+pub(crate) fn parse_header<R: Read>(reader: &mut R, file_size: u64) -> Result<ParsedHeader, PayloadError> {
+    parse_header_with_limit(reader, file_size, DEFAULT_MAX_MANIFEST_SIZE)
+}
+
+/// Same as `parse_header`, rejecting manifests larger than
+/// `max_manifest_size` bytes with `ManifestTooLarge`
+fn parse_header_with_limit<R: Read>(
+    reader: &mut R,
+    file_size: u64,
+    max_manifest_size: u64,
+) -> Result<ParsedHeader, PayloadError> {
+    if file_size < HEADER_SIZE_V1 {
+        log::error!(
+            "File too small: {} bytes, need at least {} bytes",
+            file_size,
+            HEADER_SIZE_V1
+        );
+        return Err(PayloadError::FileTooSmall(file_size, HEADER_SIZE_V1));
+    }
+
+    // =========================================================================
+    // STEP 1: Read and verify Magic Bytes (Offset 0, 4 bytes)
+    // Expected: "CrAU" = 0x43 0x72 0x41 0x55
+    // =========================================================================
+    let mut magic = [0u8; 4];
+    if let Err(e) = reader.read_exact(&mut magic) {
+        log::error!("Failed to read magic bytes: {:?}", e);
+        return Err(PayloadError::from(e));
+    }
+
+    log::info!(
         "Magic bytes: {:02X} {:02X} {:02X} {:02X} ('{}')",
         magic[0],
         magic[1],
@@ -269,10 +1288,10 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
 
     // =========================================================================
     // STEP 2: Read Version (Offset 4, 8 bytes, u64 Big Endian)
-    // Expected: 2 (Android 10+ uses Version 2)
+    // Expected: 2 (Android 10+ uses Version 2), 1 for legacy payloads
     // =========================================================================
     let mut version_bytes = [0u8; 8];
-    if let Err(e) = file.read_exact(&mut version_bytes) {
+    if let Err(e) = reader.read_exact(&mut version_bytes) {
         log::error!("Failed to read version bytes: {:?}", e);
         return Err(PayloadError::from(e));
     }
@@ -293,21 +1312,30 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
     );
     log::info!("Version (BE): {}", version);
 
-    if version != 2 {
+    if version != 1 && version != 2 {
         log::error!(
-            "Unsupported version: {}. Only Version 2 is supported.",
+            "Unsupported version: {}. Only Versions 1 and 2 are supported.",
             version
         );
         return Err(PayloadError::UnsupportedVersion(version));
     }
 
-    log::info!("✓ Version verified: 2");
+    if version == 2 && file_size < HEADER_SIZE {
+        log::error!(
+            "File too small: {} bytes, need at least {} bytes",
+            file_size,
+            HEADER_SIZE
+        );
+        return Err(PayloadError::FileTooSmall(file_size, HEADER_SIZE));
+    }
+
+    log::info!("✓ Version verified: {}", version);
 
     // =========================================================================
     // STEP 3: Read Manifest Size (Offset 12, 8 bytes, u64 Big Endian)
     // =========================================================================
     let mut manifest_size_bytes = [0u8; 8];
-    if let Err(e) = file.read_exact(&mut manifest_size_bytes) {
+    if let Err(e) = reader.read_exact(&mut manifest_size_bytes) {
         log::error!("Failed to read manifest size bytes: {:?}", e);
         return Err(PayloadError::from(e));
     }
@@ -327,54 +1355,101 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
     );
     log::info!("Manifest size (BE): {} bytes ({})", manifest_size, format_size(manifest_size));
 
-    // Sanity check: manifest shouldn't be larger than 100MB
-    const MAX_MANIFEST_SIZE: u64 = 100 * 1024 * 1024;
-    if manifest_size > MAX_MANIFEST_SIZE {
+    // Sanity check: manifest shouldn't be larger than the limit (100MB by default)
+    if manifest_size > max_manifest_size {
         log::error!(
             "Manifest too large: {} bytes (max {} bytes)",
             manifest_size,
-            MAX_MANIFEST_SIZE
+            max_manifest_size
         );
-        return Err(PayloadError::ManifestTooLarge(manifest_size));
+        return Err(PayloadError::ManifestTooLarge(manifest_size, max_manifest_size));
+    }
+
+    // A manifest reaching past the end of the file is never read in full, and
+    // its size must not be trusted for allocations either
+    let present = file_size.saturating_sub(header_size_for(version));
+    if manifest_size > present {
+        log::error!("Manifest truncated: {} of {} bytes present", present, manifest_size);
+        return Err(PayloadError::TruncatedManifest(manifest_size, present));
     }
 
     // =========================================================================
     // STEP 4: Read Metadata Signature Size (Offset 20, 4 bytes, u32 Big Endian)
+    // Version 1 headers end before this field and carry no metadata signature
     // =========================================================================
-    let mut metadata_sig_size_bytes = [0u8; 4];
-    if let Err(e) = file.read_exact(&mut metadata_sig_size_bytes) {
-        log::error!("Failed to read metadata signature size: {:?}", e);
-        return Err(PayloadError::from(e));
-    }
+    let metadata_signature_size = if version == 1 {
+        log::info!("Version 1 header: no metadata signature size field");
+        0
+    } else {
+        let mut metadata_sig_size_bytes = [0u8; 4];
+        if let Err(e) = reader.read_exact(&mut metadata_sig_size_bytes) {
+            log::error!("Failed to read metadata signature size: {:?}", e);
+            return Err(PayloadError::from(e));
+        }
 
-    let metadata_signature_size = u32::from_be_bytes(metadata_sig_size_bytes);
+        let metadata_signature_size = u32::from_be_bytes(metadata_sig_size_bytes);
 
-    log::info!(
-        "Metadata signature size bytes: {:02X} {:02X} {:02X} {:02X}",
-        metadata_sig_size_bytes[0],
-        metadata_sig_size_bytes[1],
-        metadata_sig_size_bytes[2],
-        metadata_sig_size_bytes[3]
-    );
-    log::info!("Metadata signature size (BE): {} bytes", metadata_signature_size);
+        log::info!(
+            "Metadata signature size bytes: {:02X} {:02X} {:02X} {:02X}",
+            metadata_sig_size_bytes[0],
+            metadata_sig_size_bytes[1],
+            metadata_sig_size_bytes[2],
+            metadata_sig_size_bytes[3]
+        );
+        log::info!("Metadata signature size (BE): {} bytes", metadata_signature_size);
+        metadata_signature_size
+    };
+
+    let data_offset = checked_offset(header_size_for(version), manifest_size, "Data blob offset")
+        .and_then(|end| checked_offset(end, metadata_signature_size as u64, "Data blob offset"))?;
+    log::info!("Data blob offset: {}", data_offset);
+
+    Ok(ParsedHeader {
+        version,
+        manifest_size,
+        metadata_signature_size,
+        data_offset,
+    })
+}
+
+/// Read and validate the payload header, then read and decode the manifest.
+///
+/// The reader must be positioned at the start of the payload.
+pub(crate) fn read_header_and_manifest<R: Read + Seek>(
+    reader: &mut R,
+    file_size: u64,
+) -> Result<(ParsedHeader, DeltaArchiveManifest), PayloadError> {
+    read_header_and_manifest_with_limit(reader, file_size, DEFAULT_MAX_MANIFEST_SIZE)
+}
+
+/// Same as `read_header_and_manifest`, accepting manifests of up to
+/// `max_manifest_size` bytes
+fn read_header_and_manifest_with_limit<R: Read + Seek>(
+    reader: &mut R,
+    file_size: u64,
+    max_manifest_size: u64,
+) -> Result<(ParsedHeader, DeltaArchiveManifest), PayloadError> {
+    let header = parse_header_with_limit(reader, file_size, max_manifest_size)?;
+    let manifest_size = header.manifest_size;
 
     // =========================================================================
-    // STEP 5: Read Manifest Data (Offset 24, manifest_size bytes)
+    // STEP 5: Read Manifest Data (Offset 24 or 20 for v1, manifest_size bytes)
     // =========================================================================
-    // Current position should be at offset 24 (HEADER_SIZE)
-    let current_pos = match file.stream_position() {
+    // Current position should be at the end of the fixed header
+    let header_size = header.header_size();
+    let current_pos = match reader.stream_position() {
         Ok(pos) => pos,
         Err(e) => {
             log::error!("Failed to get stream position: {:?}", e);
             return Err(PayloadError::from(e));
         }
     };
-    log::info!("Current file position: {} (should be {})", current_pos, HEADER_SIZE);
+    log::info!("Current file position: {} (should be {})", current_pos, header_size);
 
     // Ensure we're at the right position
-    if current_pos != HEADER_SIZE {
-        log::warn!("Position mismatch, seeking to {}", HEADER_SIZE);
-        if let Err(e) = file.seek(SeekFrom::Start(HEADER_SIZE)) {
+    if current_pos != header_size {
+        log::warn!("Position mismatch, seeking to {}", header_size);
+        if let Err(e) = reader.seek(SeekFrom::Start(header_size)) {
             log::error!("Failed to seek to manifest: {:?}", e);
             return Err(PayloadError::from(e));
         }
@@ -382,11 +1457,7 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
 
     // Read manifest data
     log::info!("Reading {} bytes of manifest data...", manifest_size);
-    let mut manifest_data = vec![0u8; manifest_size as usize];
-    if let Err(e) = file.read_exact(&mut manifest_data) {
-        log::error!("Failed to read manifest data: {:?}", e);
-        return Err(PayloadError::from(e));
-    }
+    let manifest_data = read_manifest_data(reader, manifest_size)?;
 
     log::info!(
         "✓ Read {} bytes of manifest data",
@@ -394,40 +1465,191 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
     );
 
     // Log first few bytes of manifest for debugging
-    if manifest_data.len() >= 16 {
-        log::debug!(
-            "Manifest first 16 bytes: {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X} {:02X}",
-            manifest_data[0], manifest_data[1], manifest_data[2], manifest_data[3],
-            manifest_data[4], manifest_data[5], manifest_data[6], manifest_data[7],
-            manifest_data[8], manifest_data[9], manifest_data[10], manifest_data[11],
-            manifest_data[12], manifest_data[13], manifest_data[14], manifest_data[15]
-        );
-    }
+    log::debug!("Manifest first bytes: {}", hex_prefix(&manifest_data, 16));
 
     // =========================================================================
     // STEP 6: Parse Protobuf Manifest
     // =========================================================================
     log::info!("Parsing protobuf manifest...");
-    let manifest = match DeltaArchiveManifest::decode(&manifest_data[..]) {
-        Ok(m) => {
-            log::info!("✓ Manifest parsed successfully");
-            m
-        }
-        Err(e) => {
-            log::error!("Failed to decode protobuf manifest: {:?}", e);
-            return Err(PayloadError::from(e));
-        }
-    };
+    let manifest = decode_manifest(&manifest_data, header_size)?;
+    log::info!("✓ Manifest parsed successfully");
 
     log::info!("Partition count: {}", manifest.partitions.len());
     log::info!("Block size: {:?}", manifest.block_size);
     log::info!("Partial update: {:?}", manifest.partial_update);
 
+    check_manifest_limits(&manifest)?;
+
+    Ok((header, manifest))
+}
+
+/// Decode `manifest_data`, read from `manifest_offset` of the payload.
+///
+/// prost only says what went wrong, so on failure the top-level field that
+/// doesn't decode is located and the error gives its position, in the
+/// manifest and in the file, along with the first bytes of the manifest.
+fn decode_manifest(manifest_data: &[u8], manifest_offset: u64) -> Result<DeltaArchiveManifest, PayloadError> {
+    DeltaArchiveManifest::decode(manifest_data).map_err(|e| {
+        let (position, field) = locate_decode_failure(manifest_data);
+        let message = format!(
+            "{} in {} at byte {} of the manifest (file offset {}); manifest starts with {}",
+            e,
+            field,
+            position,
+            manifest_offset.saturating_add(position as u64),
+            hex_prefix(manifest_data, 16)
+        );
+        log::error!("Failed to decode protobuf manifest: {}", message);
+        PayloadError::ProtobufDecode(message)
+    })
+}
+
+/// Find the first top-level field of a manifest that fails to decode.
+///
+/// Each field record is a valid message on its own, so the fields are
+/// decoded one at a time until one fails.
+///
+/// # Returns
+/// The position of the failing field's key in `data` and a description of
+/// the field, e.g. "partitions[3]" or "field 9"
+fn locate_decode_failure(data: &[u8]) -> (usize, String) {
+    let mut position = 0;
+    let mut partition_index = 0;
+
+    while position < data.len() {
+        let mut rest = &data[position..];
+        let Ok(key) = prost::encoding::decode_varint(&mut rest) else {
+            return (position, "an unreadable field key".to_string());
+        };
+        let field = key >> 3;
+        // Bytes of the value following the key, per wire type
+        let value_len = match key & 7 {
+            0 => prost::encoding::decode_varint(&mut rest).ok().map(|_| 0),
+            1 => Some(8),
+            2 => prost::encoding::decode_varint(&mut rest)
+                .ok()
+                .and_then(|len| usize::try_from(len).ok()),
+            5 => Some(4),
+            _ => None,
+        };
+        let end = value_len
+            .and_then(|len| (data.len() - rest.len()).checked_add(len))
+            .filter(|&end| end <= data.len());
+
+        let name = match field {
+            13 => format!("partitions[{}]", partition_index),
+            15 => "dynamic_partition_metadata".to_string(),
+            17 => "apex_info".to_string(),
+            other => format!("field {}", other),
+        };
+        match end {
+            Some(end) if field != 0 && DeltaArchiveManifest::decode(&data[position..end]).is_ok() => position = end,
+            _ => return (position, name),
+        }
+        if field == 13 {
+            partition_index += 1;
+        }
+    }
+
+    // Every field decodes on its own; blame the end of the data
+    (data.len(), "the end of the manifest".to_string())
+}
+
+/// First `count` bytes of `data` in hex, separated by spaces
+fn hex_prefix(data: &[u8], count: usize) -> String {
+    let prefix: Vec<String> = data.iter().take(count).map(|byte| format!("{:02X}", byte)).collect();
+    prefix.join(" ")
+}
+
+/// Read the `manifest_size` bytes of manifest following the header.
+///
+/// A payload that ends early (typically an incomplete download) fails with
+/// `TruncatedManifest`, giving how many bytes were there.
+fn read_manifest_data<R: Read>(reader: &mut R, manifest_size: u64) -> Result<Vec<u8>, PayloadError> {
+    // Not preallocated: the size comes from the header of a possibly corrupt file
+    let mut manifest_data = Vec::new();
+    if let Err(e) = reader.take(manifest_size).read_to_end(&mut manifest_data) {
+        log::error!("Failed to read manifest data: {:?}", e);
+        return Err(PayloadError::from(e));
+    }
+
+    let available = manifest_data.len() as u64;
+    if available < manifest_size {
+        log::error!("Manifest truncated: {} of {} bytes present", available, manifest_size);
+        return Err(PayloadError::TruncatedManifest(manifest_size, available));
+    }
+
+    Ok(manifest_data)
+}
+
+/// Suffix of the string copy added next to each size and offset in JSON
+/// output, e.g. "total_size_str" next to "total_size"
+const EXACT_NUMBER_SUFFIX: &str = "_str";
+
+/// Whether a JSON field named `key` holds a size or offset in bytes, also
+/// per compared payload or as their difference ("size_a", "total_size_delta")
+fn is_size_field(key: &str) -> bool {
+    let key = ["_a", "_b", "_delta"]
+        .iter()
+        .find_map(|side| key.strip_suffix(side))
+        .unwrap_or(key);
+    ["size", "offset", "bytes"]
+        .iter()
+        .any(|unit| key == *unit || key.ends_with(&format!("_{}", unit)))
+}
+
+/// Add a string copy (`<key>_str`) of every size and offset field, at any
+/// depth.
+///
+/// Many JSON consumers (JavaScript, Gson mapping to double, ...) parse
+/// numbers as doubles, which are only exact up to 2^53. The string copy
+/// always carries the exact value; the number is kept for compatibility.
+fn add_exact_sizes(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            let exact: Vec<(String, serde_json::Value)> = fields
+                .iter()
+                .filter(|(key, value)| is_size_field(key) && (value.is_u64() || value.is_i64()))
+                .map(|(key, value)| (format!("{}{}", key, EXACT_NUMBER_SUFFIX), value.to_string().into()))
+                .collect();
+            fields.values_mut().for_each(add_exact_sizes);
+            fields.extend(exact);
+        }
+        serde_json::Value::Array(items) => items.iter_mut().for_each(add_exact_sizes),
+        _ => {}
+    }
+}
+
+/// Serialize `value` to the JSON returned over JNI, with `add_exact_sizes`
+/// applied
+pub(crate) fn to_json<T: Serialize>(value: &T, pretty: bool) -> Result<String, PayloadError> {
+    let mut json = serde_json::to_value(value)?;
+    add_exact_sizes(&mut json);
+    let json = if pretty {
+        serde_json::to_string_pretty(&json)?
+    } else {
+        serde_json::to_string(&json)?
+    };
+    Ok(json)
+}
+
+/// Build the inspection result from a decoded header and manifest
+pub(crate) fn build_inspection(
+    path: &str,
+    header: ParsedHeader,
+    manifest: &DeltaArchiveManifest,
+) -> Result<PayloadInspection, PayloadError> {
+    let block_size = validated_block_size(manifest)?;
+    check_unique_partition_names(manifest)?;
+    let is_signed = header.metadata_signature_size > 0;
+
     // =========================================================================
     // STEP 7: Extract Partition Information
     // =========================================================================
     let mut partitions = Vec::new();
     let mut total_size: u64 = 0;
+    let mut total_operations = 0;
+    let mut operation_types = BTreeMap::new();
 
     for partition in &manifest.partitions {
         let size = partition
@@ -436,7 +1658,8 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
             .and_then(|info| info.size)
             .unwrap_or(0);
 
-        total_size += size;
+        total_size = checked_offset(total_size, size, "Total partition size")?;
+        total_operations += partition.operations.len();
 
         log::debug!(
             "  Partition: {} - {} ({} ops)",
@@ -445,48 +1668,105 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
             partition.operations.len()
         );
 
+        let new_info = partition.new_partition_info.as_ref();
+        let old_info = partition.old_partition_info.as_ref();
+        let total_blocks = partition
+            .operations
+            .iter()
+            .flat_map(|op| op.dst_extents.iter())
+            .map(|extent| extent.num_blocks.unwrap_or(0))
+            .try_fold(0, |sum, blocks| checked_offset(sum, blocks, "Partition block count"))?;
+        let compressed_size = partition
+            .operations
+            .iter()
+            .filter_map(|op| op.data_length)
+            .try_fold(0, |sum, length| checked_offset(sum, length, "Compressed partition size"))?;
+        let compression_ratio = (size > 0).then(|| compressed_size as f64 / size as f64);
+
+        let partition_operation_types = operation_type_counts(&partition.operations);
+        for (name, count) in &partition_operation_types {
+            *operation_types.entry(name.clone()).or_insert(0) += count;
+        }
+
         partitions.push(PartitionInfo {
             name: partition.partition_name.clone(),
             size,
             operations_count: partition.operations.len(),
+            operation_types: partition_operation_types,
             size_human: format_size(size),
+            hash: new_info.and_then(|info| info.hash.as_deref()).map(to_hex),
+            old_size: old_info.and_then(|info| info.size),
+            old_hash: old_info.and_then(|info| info.hash.as_deref()).map(to_hex),
+            total_blocks,
+            compressed_size,
+            compression_ratio,
+            version: partition.version.clone(),
+            verity: verity_info(partition),
         });
     }
 
     // Sort partitions by name for consistent output
     partitions.sort_by(|a, b| a.name.cmp(&b.name));
 
-    let header = PayloadHeader {
-        version,
-        manifest_size,
-        metadata_signature_size,
-    };
-
-    // =========================================================================
-    // STEP 8: Try to read payload_properties.txt if it exists
-    // =========================================================================
-    let properties = parse_payload_properties(path);
-    if properties.is_some() {
-        log::info!("✓ Found and parsed payload_properties.txt");
-    }
-
     log::info!("=== PAYLOAD INSPECTION COMPLETE ===");
     log::info!(
-        "Result: {} partitions, {}",
+        "Result: {} partitions, {} operations, {}",
         partitions.len(),
+        total_operations,
         format_size(total_size)
     );
 
+    // An incremental payload needs its source build even if it is also
+    // partial, so that takes precedence
+    let is_incremental = manifest.partitions.iter().any(is_incremental_partition);
+    let partial_update = manifest.partial_update.unwrap_or(false);
+    let update_type = if is_incremental {
+        UpdateType::Incremental
+    } else if partial_update {
+        UpdateType::Partial
+    } else {
+        UpdateType::Full
+    };
+    log::info!("Update type: {:?}", update_type);
+
     Ok(PayloadInspection {
         header,
-        block_size: manifest.block_size.unwrap_or(4096),
-        partial_update: manifest.partial_update.unwrap_or(false),
-        security_patch_level: manifest.security_patch_level,
+        block_size,
+        partial_update,
+        is_incremental,
+        update_type,
+        security_patch_level: manifest.security_patch_level.clone(),
+        max_timestamp: manifest.max_timestamp,
+        max_timestamp_utc: manifest.max_timestamp.map(format_utc_timestamp),
+        ota_downgrade: None,
+        minor_version: manifest.minor_version,
+        build_fingerprint: None,
+        partition_count: partitions.len(),
         partitions,
+        total_operations,
         total_size,
         total_size_human: format_size(total_size),
+        unsupported_operation_types: unsupported_operation_types(&operation_types),
+        operation_types,
         file_path: path.to_string(),
-        properties,
+        properties: None,
+        properties_source: None,
+        metadata_verified: None,
+        dynamic_partitions: dynamic_partitions_info(manifest),
+        apex_packages: manifest
+            .apex_info
+            .iter()
+            .map(|apex| ApexPackageInfo {
+                name: apex.package_name.clone(),
+                version: apex.version,
+                is_compressed: apex.is_compressed.unwrap_or(false),
+                decompressed_size: apex.decompressed_size,
+            })
+            .collect(),
+        is_signed,
+        signatures: Vec::new(),
+        signatures_offset: manifest.signatures_offset,
+        signatures_size: manifest.signatures_size,
     })
 }
 
@@ -500,14 +1780,14 @@ pub fn inspect_payload(path: &str) -> Result<PayloadInspection, PayloadError> {
 ///
 /// # Returns
 /// * `Ok(String)` - JSON string with payload information
-/// * `Err(String)` - Error message if parsing fails
-pub fn inspect_payload_json(path: &str) -> Result<String, String> {
+/// * `Err(PayloadError)` - Error if parsing fails
+pub fn inspect_payload_json(path: &str) -> Result<String, PayloadError> {
     log::info!("inspect_payload_json called with path: {}", path);
 
     match inspect_payload(path) {
         Ok(inspection) => {
             log::debug!("Inspection successful, serializing to JSON");
-            match serde_json::to_string_pretty(&inspection) {
+            match to_json(&inspection, true) {
                 Ok(json) => {
                     log::debug!(
                         "JSON serialization successful, {} bytes",
@@ -517,359 +1797,8494 @@ pub fn inspect_payload_json(path: &str) -> Result<String, String> {
                 }
                 Err(e) => {
                     log::error!("JSON serialization failed: {:?}", e);
-                    Err(format!("JSON serialization error: {}", e))
+                    Err(e)
                 }
             }
         }
         Err(e) => {
             log::error!("Payload inspection failed: {}", e);
-            Err(e.to_string())
+            Err(e)
         }
     }
 }
 
-/// Parse payload_properties.txt from the same directory as the payload.
+impl PayloadInspection {
+    /// The key facts of the payload as short phrases, e.g. "Android 14",
+    /// "28 partitions", "6.20 GB total", "full update",
+    /// "security patch 2024-03-05". Facts that are unknown are left out.
+    pub fn summary_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if let Some(release) = self.build_fingerprint.as_deref().and_then(android_release) {
+            lines.push(format!("Android {}", release));
+        }
+        lines.push(match self.partitions.len() {
+            1 => "1 partition".to_string(),
+            count => format!("{} partitions", count),
+        });
+        lines.push(format!("{} total", self.total_size_human));
+        lines.push(
+            match self.update_type {
+                UpdateType::Full => "full update",
+                UpdateType::Incremental => "incremental update",
+                UpdateType::Partial => "partial update",
+            }
+            .to_string(),
+        );
+        if let Some(level) = &self.security_patch_level {
+            lines.push(format!("security patch {}", level));
+        }
+        lines
+    }
+
+    /// Whether a device running a build from `device_build_timestamp`
+    /// (seconds since the Unix epoch, `ro.build.date.utc`) refuses this
+    /// payload as a rollback: its `max_timestamp` is older and it isn't a
+    /// downgrade package.
+    ///
+    /// # Returns
+    /// * `Some(blocked)` - The outcome of the check update_engine makes
+    /// * `None` - If the manifest has no `max_timestamp`, so nothing is checked
+    pub fn rollback_blocked(&self, device_build_timestamp: i64) -> Option<bool> {
+        let max_timestamp = self.max_timestamp?;
+        Some(max_timestamp < device_build_timestamp && self.ota_downgrade != Some(true))
+    }
+
+    /// One-line summary for display, e.g. "Android 14, 28 partitions,
+    /// 6.20 GB total, full update, security patch 2024-03-05"
+    pub fn summary(&self) -> String {
+        self.summary_lines().join(", ")
+    }
+}
+
+/// Android release of a build fingerprint
+/// (`brand/product/device:release/id/incremental:type/tags`)
+fn android_release(fingerprint: &str) -> Option<&str> {
+    let release = fingerprint.split(':').nth(1)?.split('/').next()?;
+    (!release.is_empty()).then_some(release)
+}
+
+/// Inspect a payload and return a one-line human-readable summary, see
+/// `PayloadInspection::summary`
+pub fn inspect_payload_summary(path: &str) -> Result<String, PayloadError> {
+    log::info!("inspect_payload_summary called with path: {}", path);
+
+    let summary = inspect_payload(path)?.summary();
+    log::info!("Summary: {}", summary);
+    Ok(summary)
+}
+
+/// Inspect a payload.bin with properties from `properties_path` and return
+/// JSON.
 ///
-/// Format:
-/// ```text
-/// FILE_HASH=abc123
-/// FILE_SIZE=123456789
-/// METADATA_HASH=def456
-/// METADATA_SIZE=12345
-/// ```
-fn parse_payload_properties(payload_path: &str) -> Option<PayloadProperties> {
-    use std::io::BufRead;
+/// Same output as `inspect_payload_json`; see
+/// `inspect_payload_with_properties`.
+pub fn inspect_payload_with_properties_json(path: &str, properties_path: &str) -> Result<String, PayloadError> {
+    log::info!(
+        "inspect_payload_with_properties_json called with path: {}, properties: {}",
+        path,
+        properties_path
+    );
 
-    // Get directory of payload.bin
-    let path = Path::new(payload_path);
-    let parent = path.parent()?;
-    let properties_path = parent.join("payload_properties.txt");
+    match inspect_payload_with_properties(path, Some(properties_path)) {
+        Ok(inspection) => to_json(&inspection, true),
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            Err(e)
+        }
+    }
+}
 
-    log::debug!("Looking for properties at: {:?}", properties_path);
+/// Inspect an already opened payload.bin and return JSON.
+///
+/// Same output as `inspect_payload_json`, without "properties".
+pub fn inspect_payload_file_json(file: File, label: &str) -> Result<String, PayloadError> {
+    log::info!("inspect_payload_file_json called for {}", label);
 
-    if !properties_path.exists() {
-        log::debug!("payload_properties.txt not found");
-        return None;
+    match inspect_payload_file(file, label) {
+        Ok(inspection) => to_json(&inspection, true),
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            Err(e)
+        }
     }
+}
 
-    let file = match File::open(&properties_path) {
-        Ok(f) => f,
+/// Inspect a payload held in memory and return JSON.
+///
+/// Same output as `inspect_payload_file_json`.
+pub fn inspect_payload_bytes_json(data: &[u8]) -> Result<String, PayloadError> {
+    log::info!("inspect_payload_bytes_json called with {} bytes", data.len());
+
+    match inspect_payload_bytes(data) {
+        Ok(inspection) => to_json(&inspection, true),
         Err(e) => {
-            log::warn!("Could not open payload_properties.txt: {:?}", e);
-            return None;
+            log::error!("Payload inspection failed: {}", e);
+            Err(e)
         }
-    };
+    }
+}
 
-    let reader = std::io::BufReader::new(file);
-    let mut props = PayloadProperties::default();
+/// Inspect the payload.bin inside an OTA package zip and return JSON.
+///
+/// Same output as `inspect_payload_json`.
+pub fn inspect_payload_zip_json(zip_path: &str) -> Result<String, PayloadError> {
+    log::info!("inspect_payload_zip_json called with path: {}", zip_path);
 
-    for line in reader.lines().flatten() {
-        if let Some((key, value)) = line.split_once('=') {
-            match key.trim() {
-                "FILE_HASH" => props.file_hash = Some(value.trim().to_string()),
-                "FILE_SIZE" => props.file_size = value.trim().parse().ok(),
-                "METADATA_HASH" => props.metadata_hash = Some(value.trim().to_string()),
-                "METADATA_SIZE" => props.metadata_size = value.trim().parse().ok(),
-                _ => {}
-            }
+    match inspect_payload_zip(zip_path) {
+        Ok(inspection) => to_json(&inspection, true),
+        Err(e) => {
+            log::error!("Payload inspection failed: {}", e);
+            Err(e)
         }
     }
+}
 
-    log::debug!("Parsed properties: file_size={:?}, metadata_size={:?}", 
-                props.file_size, props.metadata_size);
+/// Outcome for one payload of `inspect_payloads_json`
+#[derive(Debug, Serialize)]
+struct BatchInspection<'a> {
+    path: &'a str,
+    success: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inspection: Option<PayloadInspection>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code_number: Option<i32>,
+}
+
+/// Inspect several payload.bin files and return a JSON array.
+///
+/// Each element has the "path" and whether it could be inspected
+/// ("success"), then either the `inspect_payload_json` output under
+/// "inspection" or the failure's "error", "code" and "code_number".
+pub fn inspect_payloads_json(paths: &[String]) -> Result<String, PayloadError> {
+    log::info!("inspect_payloads_json called with {} paths", paths.len());
 
-    Some(props)
+    let results: Vec<BatchInspection> = paths
+        .iter()
+        .zip(inspect_payloads(paths))
+        .map(|(path, result)| match result {
+            Ok(inspection) => BatchInspection {
+                path,
+                success: true,
+                inspection: Some(inspection),
+                error: None,
+                code: None,
+                code_number: None,
+            },
+            Err(e) => {
+                log::error!("Payload inspection of {} failed: {}", path, e);
+                BatchInspection {
+                    path,
+                    success: false,
+                    inspection: None,
+                    error: Some(e.to_string()),
+                    code: Some(e.code()),
+                    code_number: Some(e.code_number()),
+                }
+            }
+        })
+        .collect();
+    to_json(&results, true)
 }
 
-/// Result of extracting a single partition
+/// Comparison of a partition present in both payloads of `diff_payloads`
 #[derive(Debug, Clone, Serialize)]
-pub struct ExtractedPartition {
+pub struct PartitionDiff {
     pub name: String,
-    pub size: u64,
-    pub path: String,
+    pub size_a: u64,
+    pub size_b: u64,
+    /// `size_b - size_a`, negative if the partition shrank
+    pub size_delta: i64,
+    /// SHA-256 of the image in each payload (hex), if in its manifest
+    pub hash_a: Option<String>,
+    pub hash_b: Option<String>,
+    /// Whether the image hashes differ (None if either one is missing)
+    pub hash_changed: Option<bool>,
+    /// Whether the size or hash differs. A partition without hashes whose
+    /// size is the same counts as unchanged.
+    pub changed: bool,
 }
 
-/// Result of payload extraction
+/// Differences between two payloads, as reported by `diff_payloads`
 #[derive(Debug, Clone, Serialize)]
-pub struct ExtractionResult {
-    pub status: String,
-    pub extracted: Vec<ExtractedPartition>,
+pub struct PayloadDiff {
+    pub path_a: String,
+    pub path_b: String,
+    /// Partitions only in payload b, sorted by name
+    pub added: Vec<PartitionInfo>,
+    /// Partitions only in payload a, sorted by name
+    pub removed: Vec<PartitionInfo>,
+    /// Partitions in both payloads, sorted by name
+    pub partitions: Vec<PartitionDiff>,
+    /// Number of `partitions` that changed
+    pub changed_count: usize,
+    pub total_size_a: u64,
+    pub total_size_b: u64,
+    /// `total_size_b - total_size_a`
+    pub total_size_delta: i64,
 }
 
-/// Extract all partitions from a payload.bin file
+/// Difference `b - a` of two sizes, saturating at the bounds of i64
+fn size_delta(a: u64, b: u64) -> i64 {
+    if b >= a {
+        i64::try_from(b - a).unwrap_or(i64::MAX)
+    } else {
+        i64::try_from(a - b).map_or(i64::MIN, |delta| -delta)
+    }
+}
+
+/// Compare the partitions of two payloads, e.g. two builds of a ROM.
 ///
-/// This function uses streaming I/O to handle large files efficiently.
-/// Each partition is extracted to a separate .img file.
+/// Only the headers and manifests are read: sizes and hashes come from the
+/// manifests, so partitions are compared without extracting them.
 ///
 /// # Arguments
-/// * `payload_path` - Path to the payload.bin file
-/// * `output_dir` - Directory where .img files will be written
-/// * `progress_callback` - Optional callback for progress updates (file, progress%, bytes_processed, total_bytes)
+/// * `path_a` - Path to the first (older) payload.bin
+/// * `path_b` - Path to the second (newer) payload.bin
 ///
 /// # Returns
-/// * `Ok(ExtractionResult)` - List of extracted partitions
-/// * `Err(PayloadError)` - If extraction fails
-pub fn extract_payload<F>(payload_path: &str, output_dir: &str, mut progress_callback: Option<F>) -> Result<ExtractionResult, PayloadError>
-where
-    F: FnMut(&str, i32, i64, i64) + Send,
-{
-    use std::io::{BufWriter, Write};
+/// * `Ok(PayloadDiff)` - Added, removed and common partitions
+/// * `Err(PayloadError)` - If either payload can't be inspected
+pub fn diff_payloads(path_a: &str, path_b: &str) -> Result<PayloadDiff, PayloadError> {
+    log::info!("=== PAYLOAD DIFF ===");
+    log::info!("A: {}", path_a);
+    log::info!("B: {}", path_b);
 
-    log::info!("=== PAYLOAD EXTRACTION START ===");
-    log::info!("Payload: {}", payload_path);
-    log::info!("Output: {}", output_dir);
+    let a = inspect_payload(path_a)?;
+    let b = inspect_payload(path_b)?;
 
-    // First, inspect the payload to get partition info
-    let inspection = inspect_payload(payload_path)?;
+    let removed: Vec<PartitionInfo> = a
+        .partitions
+        .iter()
+        .filter(|old| !b.partitions.iter().any(|new| new.name == old.name))
+        .cloned()
+        .collect();
+    let mut added = Vec::new();
+    let mut partitions = Vec::new();
+    for new in &b.partitions {
+        let Some(old) = a.partitions.iter().find(|old| old.name == new.name) else {
+            added.push(new.clone());
+            continue;
+        };
 
-    // Create output directory if it doesn't exist
-    let output_path = Path::new(output_dir);
-    if !output_path.exists() {
-        log::info!("Creating output directory: {}", output_dir);
-        std::fs::create_dir_all(output_path).map_err(|e| {
-            PayloadError::Io(format!("Failed to create output directory: {}", e))
-        })?;
+        let hash_changed = match (&old.hash, &new.hash) {
+            (Some(hash_a), Some(hash_b)) => Some(hash_a != hash_b),
+            _ => None,
+        };
+        partitions.push(PartitionDiff {
+            name: new.name.clone(),
+            size_a: old.size,
+            size_b: new.size,
+            size_delta: size_delta(old.size, new.size),
+            hash_a: old.hash.clone(),
+            hash_b: new.hash.clone(),
+            hash_changed,
+            changed: old.size != new.size || hash_changed == Some(true),
+        });
     }
+    let changed_count = partitions.iter().filter(|partition| partition.changed).count();
 
-    // Open payload file
-    let mut payload_file = File::open(payload_path)?;
+    log::info!(
+        "Added {}, removed {}, changed {} of {} common partitions",
+        added.len(),
+        removed.len(),
+        changed_count,
+        partitions.len()
+    );
 
-    // Skip to data blobs section
-    // Data starts after: header (24) + manifest + metadata_signature
-    let data_offset = HEADER_SIZE +
-                      inspection.header.manifest_size +
-                      inspection.header.metadata_signature_size as u64;
+    Ok(PayloadDiff {
+        path_a: path_a.to_string(),
+        path_b: path_b.to_string(),
+        added,
+        removed,
+        partitions,
+        changed_count,
+        total_size_a: a.total_size,
+        total_size_b: b.total_size,
+        total_size_delta: size_delta(a.total_size, b.total_size),
+    })
+}
 
-    log::info!("Data blob starts at offset: {}", data_offset);
-    payload_file.seek(SeekFrom::Start(data_offset))?;
+/// Throughput assumed by `estimate_extraction` when none is given: bytes of
+/// image written per second, a middle-of-the-road figure for phone storage
+/// with XZ-compressed payloads
+pub const DEFAULT_ESTIMATE_THROUGHPUT: u64 = 40 * 1024 * 1024;
 
-    // Re-parse manifest to get operations
-    payload_file.seek(SeekFrom::Start(HEADER_SIZE))?;
-    let mut manifest_data = vec![0u8; inspection.header.manifest_size as usize];
-    payload_file.read_exact(&mut manifest_data)?;
-    let manifest = DeltaArchiveManifest::decode(&manifest_data[..])?;
+/// Expected cost of extracting one partition
+#[derive(Debug, Clone, Serialize)]
+pub struct PartitionEstimate {
+    pub name: String,
+    /// Bytes of operation data read from the payload
+    pub compressed_bytes: u64,
+    /// Bytes of image written
+    pub uncompressed_bytes: u64,
+    pub estimated_seconds: f64,
+}
 
-    // Seek back to data section
-    payload_file.seek(SeekFrom::Start(data_offset))?;
+/// Expected cost of extracting a whole payload, computed from the manifest
+/// alone before anything is written
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractionEstimate {
+    pub compressed_bytes: u64,
+    pub uncompressed_bytes: u64,
+    pub estimated_seconds: f64,
+    /// Throughput the estimate assumes, in bytes written per second
+    pub bytes_per_second: u64,
+    /// Per partition, in manifest order
+    pub partitions: Vec<PartitionEstimate>,
+}
+
+/// Estimate how much data extracting a payload reads and writes, and
+/// roughly how long it takes.
+///
+/// The time is the image bytes written divided by `bytes_per_second`, which
+/// makes it only as good as that assumption; the byte counts are exact.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+/// * `bytes_per_second` - Assumed write throughput, or `None` for
+///   `DEFAULT_ESTIMATE_THROUGHPUT`
+///
+/// # Returns
+/// * `Ok(ExtractionEstimate)` - Totals and a per-partition breakdown
+/// * `Err(PayloadError)` - If the header or manifest cannot be read
+pub fn estimate_extraction(path: &str, bytes_per_second: Option<u64>) -> Result<ExtractionEstimate, PayloadError> {
+    log::info!("Estimating extraction: {}", path);
+
+    let bytes_per_second = bytes_per_second.unwrap_or(DEFAULT_ESTIMATE_THROUGHPUT);
+    if bytes_per_second == 0 {
+        return Err(PayloadError::InvalidInput(
+            "Throughput must be greater than zero".to_string(),
+        ));
+    }
+
+    let (mut file, file_size) = open_payload_file(path)?;
+    let (_, manifest) = read_header_and_manifest(&mut file, file_size)?;
+    let seconds = |bytes: u64| bytes as f64 / bytes_per_second as f64;
+
+    let partitions = manifest
+        .partitions
+        .iter()
+        .map(|partition| {
+            let compressed_bytes = partition
+                .operations
+                .iter()
+                .filter_map(|op| op.data_length)
+                .try_fold(0, |sum, length| checked_offset(sum, length, "Compressed partition size"))?;
+            let uncompressed_bytes = partition
+                .new_partition_info
+                .as_ref()
+                .and_then(|info| info.size)
+                .unwrap_or(0);
+
+            Ok(PartitionEstimate {
+                name: partition.partition_name.clone(),
+                compressed_bytes,
+                uncompressed_bytes,
+                estimated_seconds: seconds(uncompressed_bytes),
+            })
+        })
+        .collect::<Result<Vec<_>, PayloadError>>()?;
+
+    let compressed_bytes = partitions
+        .iter()
+        .try_fold(0, |sum, p| checked_offset(sum, p.compressed_bytes, "Compressed payload size"))?;
+    let uncompressed_bytes = partitions
+        .iter()
+        .try_fold(0, |sum, p| checked_offset(sum, p.uncompressed_bytes, "Total partition size"))?;
+    log::info!(
+        "Estimate: read {}, write {}, ~{:.0}s at {}/s",
+        format_size(compressed_bytes),
+        format_size(uncompressed_bytes),
+        seconds(uncompressed_bytes),
+        format_size(bytes_per_second)
+    );
+
+    Ok(ExtractionEstimate {
+        compressed_bytes,
+        uncompressed_bytes,
+        estimated_seconds: seconds(uncompressed_bytes),
+        bytes_per_second,
+        partitions,
+    })
+}
+
+/// Where the operation data of one partition lies in the payload
+#[derive(Debug, Clone, Serialize)]
+pub struct PartitionLayout {
+    pub name: String,
+    /// Absolute offset of the partition's first data blob in the payload,
+    /// `None` if no operation carries data (e.g. only ZERO operations)
+    pub offset: Option<u64>,
+    /// Bytes from `offset` to the end of the partition's last data blob
+    pub span: u64,
+    /// Bytes of operation data (sum of data_length); less than `span` if
+    /// blobs of other partitions are interleaved
+    pub compressed_size: u64,
+    pub operations_count: usize,
+}
+
+/// Location of each partition's data in a payload, for tools that read the
+/// blobs directly
+#[derive(Debug, Clone, Serialize)]
+pub struct PayloadLayout {
+    /// Absolute offset of the data blob section
+    pub data_offset: u64,
+    pub block_size: u32,
+    /// Per partition, in manifest order
+    pub layout: Vec<PartitionLayout>,
+}
+
+/// Compute where each partition's operation data lives in a payload.bin.
+///
+/// Operation `data_offset`s are relative to the data blob section; the
+/// offsets returned here are absolute, so a blob can be read straight from
+/// the file at `offset` without parsing the header again.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+///
+/// # Returns
+/// * `Ok(PayloadLayout)` - Data section offset and per-partition layout
+/// * `Err(PayloadError)` - If the header or manifest cannot be read
+pub fn inspect_payload_layout(path: &str) -> Result<PayloadLayout, PayloadError> {
+    log::info!("Computing payload layout: {}", path);
+
+    let (mut file, file_size) = open_payload_file(path)?;
+    let (header, manifest) = read_header_and_manifest(&mut file, file_size)?;
+    let block_size = validated_block_size(&manifest)?;
+
+    let layout = manifest
+        .partitions
+        .iter()
+        .map(|partition| {
+            let blobs: Vec<(u64, u64)> = partition
+                .operations
+                .iter()
+                .filter_map(|op| Some((op.data_offset?, op.data_length?)))
+                .filter(|&(_, length)| length > 0)
+                .collect();
+            let start = blobs.iter().map(|&(offset, _)| offset).min();
+            let end = blobs
+                .iter()
+                .map(|&(offset, length)| checked_offset(offset, length, "Operation blob end"))
+                .collect::<Result<Vec<_>, _>>()?
+                .into_iter()
+                .max();
+            let offset = start
+                .map(|start| checked_offset(header.data_offset, start, "Partition data offset"))
+                .transpose()?;
+
+            Ok(PartitionLayout {
+                name: partition.partition_name.clone(),
+                offset,
+                span: end.zip(start).map_or(0, |(end, start)| end - start),
+                compressed_size: blobs
+                    .iter()
+                    .try_fold(0, |sum, &(_, length)| checked_offset(sum, length, "Compressed partition size"))?,
+                operations_count: partition.operations.len(),
+            })
+        })
+        .collect::<Result<_, PayloadError>>()?;
+
+    Ok(PayloadLayout {
+        data_offset: header.data_offset,
+        block_size,
+        layout,
+    })
+}
+
+/// Files written by `dump_manifest`
+#[derive(Debug, Clone, Serialize)]
+pub struct ManifestDump {
+    /// Raw protobuf manifest
+    pub path: String,
+    pub size: u64,
+    /// Decoded text form of the manifest, if requested
+    pub decoded_path: Option<String>,
+}
+
+/// Write the raw protobuf manifest of a payload to a file, e.g. to diff the
+/// manifests of two builds.
+///
+/// The raw bytes are copied as they are, so a manifest that fails to decode
+/// can still be dumped. With `decoded`, the decoded manifest is also written
+/// as text next to it, at `out_path` + ".txt".
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `out_path` - File to write the manifest bytes to
+/// * `decoded` - Also write the decoded text form
+///
+/// # Returns
+/// * `Ok(ManifestDump)` - Paths and size of what was written
+/// * `Err(PayloadError)` - If the header is invalid, or reading, decoding or
+///   writing fails
+pub fn dump_manifest(payload_path: &str, out_path: &str, decoded: bool) -> Result<ManifestDump, PayloadError> {
+    log::info!("Dumping manifest of {} to {}", payload_path, out_path);
+
+    let (mut file, file_size) = open_payload_file(payload_path)?;
+    let header = parse_header(&mut file, file_size)?;
+
+    file.seek(SeekFrom::Start(header.header_size()))?;
+    let manifest_data = read_manifest_data(&mut file, header.manifest_size)?;
+
+    std::fs::write(out_path, &manifest_data)
+        .map_err(|e| PayloadError::Io(format!("Cannot write {}: {}", out_path, e)))?;
+    log::info!("✓ Wrote {} bytes of manifest", manifest_data.len());
+
+    let decoded_path = if decoded {
+        let manifest = decode_manifest(&manifest_data, header.header_size())?;
+        let decoded_path = format!("{}.txt", out_path);
+        std::fs::write(&decoded_path, format!("{:#?}\n", manifest))
+            .map_err(|e| PayloadError::Io(format!("Cannot write {}: {}", decoded_path, e)))?;
+        log::info!("✓ Wrote decoded manifest to {}", decoded_path);
+        Some(decoded_path)
+    } else {
+        None
+    };
+
+    Ok(ManifestDump {
+        path: out_path.to_string(),
+        size: header.manifest_size,
+        decoded_path,
+    })
+}
+
+/// Result of checking a payload file against payload_properties.txt
+#[derive(Debug, Clone, Serialize)]
+pub struct IntegrityResult {
+    /// Whether every check that could be made passed
+    pub valid: bool,
+    /// Checksum `actual_hash` was computed with
+    pub algorithm: IntegrityAlgorithm,
+    /// File length equals FILE_SIZE (None if FILE_SIZE is missing)
+    pub size_matches: Option<bool>,
+    /// Checksum of the file equals `expected_hash` (None if there is none)
+    pub hash_matches: Option<bool>,
+    pub expected_size: Option<u64>,
+    pub actual_size: u64,
+    /// FILE_HASH for SHA-256, or the checksum given by the caller
+    pub expected_hash: Option<String>,
+    /// Base64 SHA-256, as written in payload_properties.txt, or hex CRC32
+    pub actual_hash: String,
+}
+
+/// Checksum `verify_payload_integrity_with` streams the payload through
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IntegrityAlgorithm {
+    /// SHA-256, the algorithm of FILE_HASH in payload_properties.txt
+    #[default]
+    Sha256,
+    /// CRC32, several times faster than SHA-256. Catches accidental
+    /// corruption such as truncated or damaged downloads, but not deliberate
+    /// tampering. payload_properties.txt has no CRC32, so only FILE_SIZE and
+    /// a checksum given by the caller are checked.
+    Crc32,
+}
+
+impl IntegrityAlgorithm {
+    /// Name as used in JSON, e.g. "crc32"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Some(IntegrityAlgorithm::Sha256),
+            "crc32" => Some(IntegrityAlgorithm::Crc32),
+            _ => None,
+        }
+    }
+}
+
+/// Running checksum of an integrity check
+enum IntegrityHasher {
+    Sha256(Sha256),
+    Crc32(crc32fast::Hasher),
+}
+
+impl IntegrityHasher {
+    fn new(algorithm: IntegrityAlgorithm) -> Self {
+        match algorithm {
+            IntegrityAlgorithm::Sha256 => IntegrityHasher::Sha256(Sha256::new()),
+            IntegrityAlgorithm::Crc32 => IntegrityHasher::Crc32(crc32fast::Hasher::new()),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            IntegrityHasher::Sha256(hasher) => hasher.update(data),
+            IntegrityHasher::Crc32(hasher) => hasher.update(data),
+        }
+    }
+
+    /// Base64 for SHA-256, like FILE_HASH; 8 lowercase hex digits for CRC32
+    fn finish(self) -> String {
+        use base64::Engine;
+
+        match self {
+            IntegrityHasher::Sha256(hasher) => base64::engine::general_purpose::STANDARD.encode(hasher.finalize()),
+            IntegrityHasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+        }
+    }
+}
+
+/// Check a whole payload file against FILE_SIZE and FILE_HASH from the
+/// payload_properties.txt next to it.
+///
+/// The entire file is streamed through SHA-256, which takes a while for
+/// multi-GB payloads; `progress_callback` reports the bytes hashed so far.
+/// Like an extraction, hashing stops at the next chunk once `cancel` is set.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+/// * `cancel` - Optional cancellation token, see `ExtractOptions::cancel`
+/// * `progress_callback` - Optional callback for hashing progress
+///
+/// # Returns
+/// * `Ok(IntegrityResult)` - Outcome of each check
+/// * `Err(PayloadError)` - If the payload can't be read, there is no
+///   payload_properties.txt with FILE_SIZE or FILE_HASH to check against, or
+///   the check was cancelled
+pub fn verify_payload_integrity<F>(
+    path: &str,
+    cancel: Option<Arc<AtomicBool>>,
+    progress_callback: Option<F>,
+) -> Result<IntegrityResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate),
+{
+    verify_payload_integrity_with(path, IntegrityAlgorithm::Sha256, None, cancel, progress_callback)
+}
+
+/// Check a whole payload file with the checksum `algorithm`.
+///
+/// Like `verify_payload_integrity`, which this is with SHA-256. The file
+/// length is checked against FILE_SIZE either way; the checksum against
+/// `expected_hash` if given, else against FILE_HASH for SHA-256. CRC32 is
+/// the quick check for accidental corruption, e.g. comparing a copied
+/// payload with a checksum recorded earlier.
+///
+/// # Arguments
+/// * `path` - Path to the payload.bin file
+/// * `algorithm` - Checksum to compute
+/// * `expected_hash` - Checksum to compare with (base64 SHA-256 or hex CRC32)
+/// * `cancel` - Optional cancellation token, see `ExtractOptions::cancel`
+/// * `progress_callback` - Optional callback for hashing progress
+///
+/// # Returns
+/// * `Ok(IntegrityResult)` - Outcome of each check and the computed checksum
+/// * `Err(PayloadError)` - If the payload can't be read, there is nothing to
+///   check against, or the check was cancelled
+pub fn verify_payload_integrity_with<F>(
+    path: &str,
+    algorithm: IntegrityAlgorithm,
+    expected_hash: Option<&str>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress_callback: Option<F>,
+) -> Result<IntegrityResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate),
+{
+    log::info!("=== PAYLOAD INTEGRITY CHECK ({:?}) ===", algorithm);
+    log::info!("Path: {}", path);
+
+    let (mut file, actual_size) = open_payload_file(path)?;
+
+    let properties = parse_payload_properties(path).unwrap_or_default();
+    let expected_hash = match (expected_hash, algorithm) {
+        (Some(expected), _) => Some(expected.trim().to_string()),
+        (None, IntegrityAlgorithm::Sha256) => properties.file_hash.clone(),
+        (None, IntegrityAlgorithm::Crc32) => None,
+    };
+    if properties.file_size.is_none() && expected_hash.is_none() {
+        return Err(PayloadError::InvalidInput(match algorithm {
+            IntegrityAlgorithm::Sha256 => "No FILE_SIZE or FILE_HASH in payload_properties.txt to verify against",
+            IntegrityAlgorithm::Crc32 => "No FILE_SIZE in payload_properties.txt or expected CRC32 to verify against",
+        }
+        .to_string()));
+    }
+
+    let size_matches = properties.file_size.map(|size| size == actual_size);
+    log::info!(
+        "Size: {} bytes, expected {:?} -> {:?}",
+        actual_size,
+        properties.file_size,
+        size_matches
+    );
+
+    // Stream the whole file through the checksum
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "payload.bin".to_string());
+    let mut progress = ProgressTracker::new(progress_callback, actual_size)
+        .throttled(Duration::from_millis(DEFAULT_PROGRESS_INTERVAL_MS), 0);
+    progress.partition_started(&file_name);
+
+    let mut hasher = IntegrityHasher::new(algorithm);
+    let mut buffer = vec![0u8; STREAM_CHUNK_SIZE];
+    loop {
+        if cancel.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+            log::warn!("Integrity check cancelled");
+            return Err(PayloadError::Cancelled);
+        }
+
+        let n = match file.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(PayloadError::from(e)),
+        };
+        hasher.update(&buffer[..n]);
+        progress.advance(&file_name, n as u64);
+    }
+    let actual_hash = hasher.finish();
+
+    let hash_matches = expected_hash.as_deref().map(|expected| match algorithm {
+        IntegrityAlgorithm::Sha256 => expected == actual_hash,
+        IntegrityAlgorithm::Crc32 => expected.trim_start_matches("0x").eq_ignore_ascii_case(&actual_hash),
+    });
+    log::info!(
+        "{:?}: {}, expected {:?} -> {:?}",
+        algorithm,
+        actual_hash,
+        expected_hash,
+        hash_matches
+    );
+
+    let valid = size_matches != Some(false) && hash_matches != Some(false);
+    if valid {
+        log::info!("✓ Payload matches payload_properties.txt");
+    } else {
+        log::error!("✗ Payload does not match payload_properties.txt");
+    }
+
+    Ok(IntegrityResult {
+        valid,
+        algorithm,
+        size_matches,
+        hash_matches,
+        expected_size: properties.file_size,
+        actual_size,
+        expected_hash,
+        actual_hash,
+    })
+}
+
+/// Check payload integrity and return JSON result
+pub fn verify_payload_integrity_json<F>(
+    path: &str,
+    cancel: Option<Arc<AtomicBool>>,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate),
+{
+    log::info!("verify_payload_integrity_json called with path: {}", path);
+
+    match verify_payload_integrity(path, cancel, progress_callback) {
+        Ok(result) => to_json(&result, false),
+        Err(e) => {
+            log::error!("Integrity check failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Check payload integrity with the checksum `algorithm` and return JSON
+/// result, see `verify_payload_integrity_with`
+pub fn verify_payload_integrity_with_json<F>(
+    path: &str,
+    algorithm: IntegrityAlgorithm,
+    expected_hash: Option<&str>,
+    cancel: Option<Arc<AtomicBool>>,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate),
+{
+    verify_payload_integrity_with(path, algorithm, expected_hash, cancel, progress_callback)
+        .and_then(|result| to_json(&result, false))
+}
+
+/// Parse payload_properties.txt from the same directory as the payload.
+///
+/// Format:
+/// ```text
+/// FILE_HASH=abc123
+/// FILE_SIZE=123456789
+/// METADATA_HASH=def456
+/// METADATA_SIZE=12345
+/// SWITCH_SLOT_ON_REBOOT=0
+/// ```
+fn parse_payload_properties(payload_path: &str) -> Option<PayloadProperties> {
+    let (properties, _) = read_properties_file(&adjacent_properties_path(payload_path)?)?;
+    Some(properties)
+}
+
+/// payload_properties.txt in the same directory as the payload
+fn adjacent_properties_path(payload_path: &str) -> Option<PathBuf> {
+    Some(Path::new(payload_path).parent()?.join(ota_zip::PROPERTIES_ENTRY))
+}
+
+/// Read payload properties from `path`, which is either a
+/// payload_properties.txt or an OTA package zip holding one.
+///
+/// # Returns
+/// * `Some((properties, source))` - The properties and where they were read
+///   from, see `PayloadInspection::properties_source`
+/// * `None` - If `path` doesn't exist or has no properties
+fn read_properties_file(path: &Path) -> Option<(PayloadProperties, String)> {
+    log::debug!("Looking for properties at: {:?}", path);
+
+    if !path.exists() {
+        log::debug!("{:?} not found", path);
+        return None;
+    }
+
+    let mut file = match File::open(path) {
+        Ok(f) => f,
+        Err(e) => {
+            log::warn!("Could not open {:?}: {:?}", path, e);
+            return None;
+        }
+    };
+
+    if ota_zip::is_zip(&mut file) {
+        let text = ota_zip::read_text_entry(file, ota_zip::PROPERTIES_ENTRY)?;
+        let source = ota_zip::entry_label(&path.to_string_lossy(), ota_zip::PROPERTIES_ENTRY);
+        return Some((parse_properties(text.as_bytes()), source));
+    }
+
+    Some((
+        parse_properties(std::io::BufReader::new(file)),
+        path.to_string_lossy().into_owned(),
+    ))
+}
+
+/// Read the OTA metadata of an unpacked OTA package, i.e.
+/// `META-INF/com/android/metadata` next to the payload
+fn read_ota_metadata(payload_path: &str) -> Option<String> {
+    let metadata_path = Path::new(payload_path).parent()?.join(ota_zip::METADATA_ENTRY);
+    std::fs::read_to_string(&metadata_path).ok()
+}
+
+/// Add the target build fingerprint and downgrade flag from the OTA package
+/// `metadata`, if there is any, to an inspection
+fn attach_ota_metadata(inspection: &mut PayloadInspection, metadata: Option<&str>) {
+    let Some(metadata) = metadata else {
+        return;
+    };
+    inspection.build_fingerprint = ota_zip::post_build_fingerprint(metadata);
+    inspection.ota_downgrade = Some(ota_zip::is_downgrade(metadata));
+    log::debug!(
+        "Build fingerprint: {:?}, downgrade: {:?}",
+        inspection.build_fingerprint,
+        inspection.ota_downgrade
+    );
+}
+
+/// Parse the `KEY=value` lines of payload_properties.txt
+fn parse_properties<R: std::io::BufRead>(reader: R) -> PayloadProperties {
+    let mut props = PayloadProperties::default();
+
+    for line in reader.lines().map_while(Result::ok) {
+        if let Some((key, value)) = line.split_once('=') {
+            match key.trim() {
+                "FILE_HASH" => props.file_hash = Some(value.trim().to_string()),
+                "FILE_SIZE" => props.file_size = value.trim().parse().ok(),
+                "METADATA_HASH" => props.metadata_hash = Some(value.trim().to_string()),
+                "METADATA_SIZE" => props.metadata_size = value.trim().parse().ok(),
+                "" => {}
+                other => {
+                    props.extra.insert(other.to_string(), value.trim().to_string());
+                }
+            }
+        }
+    }
+
+    log::debug!("Parsed properties: file_size={:?}, metadata_size={:?}", 
+                props.file_size, props.metadata_size);
+
+    props
+}
+
+/// Options controlling how a payload is extracted.
+///
+/// Can be deserialized from JSON (e.g. `{"max_threads": 4}`); missing fields
+/// take their default values.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExtractOptions {
+    /// Directory holding the source build's partition images (`<name>.img`).
+    /// Required for incremental payloads that contain SOURCE_COPY,
+    /// SOURCE_BSDIFF or BROTLI_BSDIFF operations.
+    pub source_dir: Option<String>,
+    /// Cancellation token; set it to `true` to abort the extraction.
+    /// Checked between operations and after each partition. In parallel mode
+    /// the extractor also sets it when a worker fails, to stop the others.
+    #[serde(skip)]
+    pub cancel: Option<Arc<AtomicBool>>,
+    /// Only extract these partitions (all partitions when `None`)
+    pub partitions: Option<Vec<String>>,
+    /// Leave out partitions matching any of these names or patterns (`*`
+    /// matches any run of characters, `?` a single one), e.g. "userdata" or
+    /// "vendor_*". Applied after `partitions`; excluded partitions are listed
+    /// in `excluded` of the result and don't count towards progress.
+    pub exclude: Vec<String>,
+    /// Check each operation's blob against its data_sha256_hash before
+    /// decompressing it. Catches corrupt or partially downloaded payloads at
+    /// the failing operation, at the cost of reading every blob twice.
+    pub verify_operations: bool,
+    /// Number of partitions extracted concurrently, each worker reading the
+    /// payload through its own handle. 0 or 1 extracts sequentially.
+    pub max_threads: usize,
+    /// Minimum time between two progress callbacks; intermediate updates
+    /// are coalesced. The final update of an extraction is never dropped.
+    pub progress_interval_ms: u64,
+    /// Also report progress once this many bytes were processed since the
+    /// last callback, even within `progress_interval_ms` (0 disables this)
+    pub progress_min_bytes: u64,
+    /// Only plan the extraction: report each partition's output size and
+    /// whether its operations are supported, without creating any file
+    pub dry_run: bool,
+    /// Memory-map the payload and read operation data from the mapping
+    /// instead of seeking and reading the file, saving a seek and a read
+    /// syscall per operation. Off by default, as it measured no faster: a
+    /// 256 MiB image (release build, single-core x86-64 VM, best of 5) took
+    /// 0.75 s mapped vs 0.73 s read from 65,536 4 KiB operations, and 0.69 s
+    /// vs 0.67 s from 16 16 MiB ones. Not yet profiled on devices. A
+    /// multi-GB mapping may not fit the address space of a 32-bit process;
+    /// the extractor then falls back to regular reads. Not available for
+    /// payloads read from inside an OTA zip.
+    pub use_mmap: bool,
+    /// Write Android sparse images (flashable with fastboot) instead of raw
+    /// ones. Each image is verified in raw form, then converted; `size` in
+    /// the result stays the raw image size.
+    pub output_sparse: bool,
+    /// Extract every partition again, ignoring the checkpoint left in the
+    /// output directory by an earlier, interrupted extraction
+    pub force: bool,
+    /// What to do when the output image already exists in the output
+    /// directory (and isn't a checkpointed image being resumed)
+    pub on_existing: OnExisting,
+    /// File name of each output image. `{name}` is replaced with the
+    /// partition name and `{index}` with its position in the manifest,
+    /// e.g. "{name}_a.img" or "{index}_{name}.img"
+    pub filename_template: String,
+    /// Number of REPLACE_XZ operations of one partition decompressed
+    /// concurrently. The bundled liblzma has no multithreaded decoder, so
+    /// consecutive operations are read ahead and decoded on separate
+    /// threads, holding up to this many blobs and their output in memory.
+    /// Each output is capped at the size of its destination extents.
+    /// Measured on a single-core x86-64 VM (release build, 64 MiB image of
+    /// 64 1 MiB REPLACE_XZ operations, best of 5): 2.57 s with 1 thread,
+    /// 2.74 s with 2 or 4. No multi-core numbers show a gain yet, so the
+    /// default is 1; 0 or 1 streams each operation as usual.
+    pub decompress_threads: usize,
+    /// Write an empty image for partitions of size 0 (e.g. placeholders),
+    /// for workflows that expect one file per partition. By default they are
+    /// only listed in `empty_partitions` of the result.
+    pub create_empty: bool,
+    /// Capacity of the buffer in front of each output image. Small
+    /// operations are coalesced into writes of up to this size; 0 writes
+    /// each chunk straight through.
+    pub write_buffer_size: usize,
+    /// Write each partition's operation blobs as stored in the payload,
+    /// without decompressing or applying them, followed back to back in the
+    /// output file. A sidecar `<file>.ops.json` gives each operation's type,
+    /// offsets and length. For inspecting the payload data, not flashing.
+    pub raw: bool,
+    /// Block size used for the extent math instead of the manifest's, for
+    /// payloads whose manifest value is wrong. Must be a power of two between
+    /// `MIN_BLOCK_SIZE` and `MAX_BLOCK_SIZE`.
+    pub block_size: Option<u32>,
+    /// What `bytes_processed` and `total_bytes` of progress updates count
+    pub progress_unit: ProgressUnit,
+    /// Give up on the extraction after this many milliseconds, e.g. when a
+    /// read from a network or FUSE filesystem hangs (0 waits indefinitely).
+    /// Images that weren't completed in time are removed. Rejected by
+    /// `extract_payload_to_files`, whose outputs can't be removed.
+    pub timeout_ms: u64,
+    /// Carry on with the next partition when one fails instead of aborting
+    /// the extraction. Failed partitions are listed in `failed` of the
+    /// result, their partial images removed, and the status is "partial".
+    /// Cancellation still stops everything.
+    pub continue_on_error: bool,
+    /// Once a partition's image is complete, write it back to storage and
+    /// ask the kernel to drop it from the page cache
+    /// (`posix_fadvise(POSIX_FADV_DONTNEED)`). Multi-GB images otherwise
+    /// fill the cache with data nobody reads again, evicting the pages of
+    /// other apps. Costs a sync per partition.
+    pub drop_page_cache: bool,
+    /// Once all partitions are extracted, replace each image identical to an
+    /// earlier one (same size and SHA-256, e.g. slot pairs) with a hard link
+    /// to it. Linked partitions are listed in `linked` of the result; where
+    /// the filesystem has no hard links the duplicate stays a copy.
+    pub link_duplicates: bool,
+}
+
+/// Default `ExtractOptions::write_buffer_size`.
+///
+/// Extracting a 256 MiB image (release build, single-core x86-64 VM, best of
+/// 5) took, unbuffered / 64 KiB / 1 MiB / 4 MiB:
+/// - from 65,536 4 KiB operations: 1.01 s / 0.81 s / 0.79 s / 0.80 s
+/// - from 16 16 MiB operations: 0.71 s / 0.69 s / 0.67 s / 0.70 s
+///
+/// Buffering pays off for small operations; past 64 KiB the differences are
+/// within noise, and 1 MiB was fastest in both cases.
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Default `filename_template`: `<partition>.img`
+pub const DEFAULT_FILENAME_TEMPLATE: &str = "{name}.img";
+
+/// Handling of output images that already exist
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnExisting {
+    /// Replace the existing image
+    #[default]
+    Overwrite,
+    /// Leave the existing image alone and report the partition as skipped
+    Skip,
+    /// Fail before writing anything
+    Error,
+}
+
+/// What the progress of an extraction is measured in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProgressUnit {
+    /// Bytes of image written, out of the partition sizes
+    #[default]
+    Output,
+    /// Bytes of operation data read from the payload, out of the payload
+    /// data of the partitions. Follows the reading and decompressing work,
+    /// so it advances more evenly across partitions that compress differently.
+    Compressed,
+}
+
+impl ProgressUnit {
+    /// Progress made by applying `operation`
+    fn operation_bytes(self, operation: &InstallOperation, block_size: u64) -> u64 {
+        match self {
+            ProgressUnit::Output => extents_length(&operation.dst_extents, block_size),
+            ProgressUnit::Compressed => operation.data_length.unwrap_or(0),
+        }
+    }
+
+    /// Progress made by extracting all of `partition`
+    fn partition_bytes(self, partition: &PartitionUpdate) -> u64 {
+        match self {
+            ProgressUnit::Output => partition
+                .new_partition_info
+                .as_ref()
+                .and_then(|info| info.size)
+                .unwrap_or(0),
+            ProgressUnit::Compressed => partition
+                .operations
+                .iter()
+                .filter_map(|op| op.data_length)
+                .fold(0, u64::saturating_add),
+        }
+    }
+
+    /// Progress made by extracting all of `partitions`
+    fn total_bytes<'a>(self, partitions: impl IntoIterator<Item = &'a PartitionUpdate>) -> Result<u64, PayloadError> {
+        match self {
+            ProgressUnit::Output => total_partition_size(partitions),
+            ProgressUnit::Compressed => partitions
+                .into_iter()
+                .map(|partition| self.partition_bytes(partition))
+                .try_fold(0, |total, bytes| checked_offset(total, bytes, "Total payload data size")),
+        }
+    }
+}
+
+/// Default `ExtractOptions::progress_interval_ms`
+const DEFAULT_PROGRESS_INTERVAL_MS: u64 = 100;
+
+impl Default for ExtractOptions {
+    fn default() -> Self {
+        Self {
+            source_dir: None,
+            cancel: None,
+            partitions: None,
+            exclude: Vec::new(),
+            verify_operations: false,
+            max_threads: 1,
+            progress_interval_ms: DEFAULT_PROGRESS_INTERVAL_MS,
+            progress_min_bytes: 0,
+            dry_run: false,
+            use_mmap: false,
+            output_sparse: false,
+            force: false,
+            on_existing: OnExisting::Overwrite,
+            filename_template: DEFAULT_FILENAME_TEMPLATE.to_string(),
+            decompress_threads: 1,
+            create_empty: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            raw: false,
+            block_size: None,
+            progress_unit: ProgressUnit::Output,
+            timeout_ms: 0,
+            continue_on_error: false,
+            drop_page_cache: false,
+            link_duplicates: false,
+        }
+    }
+}
+
+impl ExtractOptions {
+    /// Whether the caller has requested cancellation
+    fn is_cancelled(&self) -> bool {
+        self.cancel
+            .as_ref()
+            .is_some_and(|flag| flag.load(Ordering::Relaxed))
+    }
+}
+
+/// Result of extracting a single partition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExtractedPartition {
+    pub name: String,
+    pub size: u64,
+    pub path: String,
+    /// Whether the image SHA-256 matches new_partition_info.hash
+    pub verified: bool,
+    /// Hex SHA-256 from the manifest (None if the manifest has no hash)
+    pub expected_hash: Option<String>,
+    /// Hex SHA-256 of the written image (empty for a dry run)
+    pub actual_hash: String,
+    /// Whether every operation of the partition can be applied. Always true
+    /// for extracted partitions; a dry run reports unsupported ones as false.
+    pub supported: bool,
+    /// Why the partition can't be extracted, when `supported` is false
+    pub unsupported_reason: Option<String>,
+    /// Whether the image was left over from an interrupted extraction and
+    /// re-validated instead of being extracted again
+    #[serde(default)]
+    pub resumed: bool,
+    /// Operations whose data was written unprocessed, so the image may be
+    /// invalid
+    #[serde(default)]
+    pub warnings: Vec<OperationWarning>,
+}
+
+/// An operation of unknown or unhandled type whose data was copied to the
+/// image as-is
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct OperationWarning {
+    pub partition: String,
+    pub operation_index: usize,
+    /// e.g. "PUFFDIFF", or "UNKNOWN(42)" for a type this build doesn't know
+    pub operation_type: String,
+}
+
+/// A requested partition that was not extracted
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedPartition {
+    pub name: String,
+    pub reason: String,
+}
+
+/// A partition that failed to extract under `ExtractOptions::continue_on_error`
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedPartition {
+    pub name: String,
+    pub error: String,
+    /// Error code, as returned by `PayloadError::code`
+    pub code: String,
+    /// Number of `code`, as returned by `PayloadError::code_number`
+    pub code_number: i32,
+}
+
+/// An image found identical to an earlier one, see
+/// `ExtractOptions::link_duplicates`
+#[derive(Debug, Clone, Serialize)]
+pub struct LinkedPartition {
+    pub name: String,
+    /// Partition whose image it is identical to
+    pub same_as: String,
+    /// Whether the image is now a hard link to that of `same_as`. If not,
+    /// e.g. on filesystems without hard links, it was left a separate copy.
+    pub hard_linked: bool,
+    /// Why linking failed, when `hard_linked` is false
+    pub error: Option<String>,
+}
+
+/// Result of payload extraction
+#[derive(Debug, Clone, Serialize)]
+pub struct ExtractionResult {
+    pub status: String,
+    pub extracted: Vec<ExtractedPartition>,
+    pub skipped: Vec<SkippedPartition>,
+    /// Partitions that failed, with `ExtractOptions::continue_on_error`
+    pub failed: Vec<FailedPartition>,
+    /// Warnings of all extracted partitions, for images that may be invalid
+    pub warnings: Vec<OperationWarning>,
+    /// Selected partitions of size 0, for which no image was written (see
+    /// `ExtractOptions::create_empty`)
+    pub empty_partitions: Vec<String>,
+    /// Partitions left out by `ExtractOptions::exclude`
+    pub excluded: Vec<String>,
+    /// Duplicate images, with `ExtractOptions::link_duplicates`
+    pub linked: Vec<LinkedPartition>,
+}
+
+impl ExtractionResult {
+    fn new(status: &str, extracted: Vec<ExtractedPartition>, skipped: Vec<SkippedPartition>) -> Self {
+        let warnings = extracted
+            .iter()
+            .flat_map(|partition| partition.warnings.iter().cloned())
+            .collect();
+        ExtractionResult {
+            status: status.to_string(),
+            extracted,
+            skipped,
+            failed: Vec::new(),
+            warnings,
+            empty_partitions: Vec::new(),
+            excluded: Vec::new(),
+            linked: Vec::new(),
+        }
+    }
+
+    fn with_linked(self, linked: Vec<LinkedPartition>) -> Self {
+        ExtractionResult { linked, ..self }
+    }
+
+    fn with_excluded(self, excluded: Vec<String>) -> Self {
+        ExtractionResult { excluded, ..self }
+    }
+
+    fn with_empty_partitions(self, empty_partitions: Vec<String>) -> Self {
+        ExtractionResult {
+            empty_partitions,
+            ..self
+        }
+    }
+
+    /// The status becomes "partial" if any partition failed
+    fn with_failed(self, failed: Vec<FailedPartition>) -> Self {
+        let status = if failed.is_empty() { self.status } else { "partial".to_string() };
+        ExtractionResult { status, failed, ..self }
+    }
+}
+
+/// Payload opened for extraction: file handle, decoded manifest and data layout
+struct OpenedPayload {
+    file: Box<dyn PayloadStream>,
+    origin: PayloadOrigin,
+    manifest: DeltaArchiveManifest,
+    /// Absolute offset of the data blob section
+    data_offset: u64,
+    block_size: u64,
+    /// Total size of the payload in bytes
+    size: u64,
+}
+
+/// Where an opened payload was read from, so it can be opened again
+#[derive(Clone)]
+enum PayloadOrigin {
+    /// A payload.bin on disk
+    Path(String),
+    /// The payload.bin entry of an OTA package zip
+    Zip(String),
+    /// An already opened file (e.g. from a file descriptor) that can't be
+    /// reopened by path; read with positioned reads instead
+    File(Arc<File>),
+    /// A payload mapped into memory, shared by all readers
+    Mapped(Arc<memmap2::Mmap>),
+}
+
+impl PayloadOrigin {
+    /// Open an independent stream on the payload, e.g. for a worker thread
+    fn reopen(&self) -> Result<Box<dyn PayloadStream>, PayloadError> {
+        Ok(match self {
+            PayloadOrigin::Path(path) => Box::new(open_payload_file(path)?.0),
+            PayloadOrigin::Zip(path) => ota_zip::open_payload_entry(open_payload_file(path)?.0)?.0,
+            PayloadOrigin::File(file) => Box::new(PositionedReader::new(file.clone())),
+            PayloadOrigin::Mapped(map) => Box::new(std::io::Cursor::new(SharedMap(map.clone()))),
+        })
+    }
+
+    /// Map the payload into memory, for `ExtractOptions::use_mmap`.
+    ///
+    /// Returns `None` if the payload can't be mapped (inside a zip, or the
+    /// mapping failed, e.g. for lack of address space on 32-bit devices).
+    fn map(&self) -> Option<PayloadOrigin> {
+        let file = match self {
+            PayloadOrigin::Path(path) => Arc::new(open_payload_file(path).ok()?.0),
+            PayloadOrigin::File(file) => file.clone(),
+            PayloadOrigin::Mapped(_) => return Some(self.clone()),
+            PayloadOrigin::Zip(_) => {
+                log::warn!("Memory mapping is not supported for payloads inside a zip");
+                return None;
+            }
+        };
+
+        // SAFETY: the payload is only read. If another process truncates the
+        // file while it is mapped, reads of the lost pages fault; callers opt
+        // into this with `use_mmap`.
+        match unsafe { memmap2::Mmap::map(file.as_ref()) } {
+            Ok(map) => {
+                log::info!("Payload mapped into memory ({})", format_size(map.len() as u64));
+                Some(PayloadOrigin::Mapped(Arc::new(map)))
+            }
+            Err(e) => {
+                log::warn!("Failed to map payload, using regular reads: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// Shared memory map usable as the backing buffer of a `Cursor`, so seeks
+/// are plain offset updates and reads copy straight out of the mapping
+struct SharedMap(Arc<memmap2::Mmap>);
+
+impl AsRef<[u8]> for SharedMap {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Reader over a shared file using positioned reads (`pread`), so several
+/// readers can seek independently without sharing a file offset
+struct PositionedReader {
+    file: Arc<File>,
+    pos: u64,
+}
+
+impl PositionedReader {
+    fn new(file: Arc<File>) -> Self {
+        Self { file, pos: 0 }
+    }
+}
+
+impl Read for PositionedReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use std::os::unix::fs::FileExt;
+
+        let n = self.file.read_at(buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for PositionedReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        self.pos = target.ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "seek before start of file")
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Open a payload, decode its manifest and locate the data blob section.
+///
+/// The file is opened and the manifest decoded exactly once.
+/// `block_size` overrides the manifest's block size, see `ExtractOptions`.
+fn open_for_extraction(payload_path: &str, block_size: Option<u32>) -> Result<OpenedPayload, PayloadError> {
+    let (payload_file, file_size) = open_payload_file(payload_path)?;
+    open_stream(
+        Box::new(payload_file),
+        file_size,
+        PayloadOrigin::Path(payload_path.to_string()),
+        block_size,
+    )
+}
+
+/// Same as `open_for_extraction`, for the payload.bin inside an OTA package zip
+fn open_zip_for_extraction(zip_path: &str, block_size: Option<u32>) -> Result<OpenedPayload, PayloadError> {
+    let (zip_file, _) = open_payload_file(zip_path)?;
+    let (stream, payload_size) = ota_zip::open_payload_entry(zip_file)?;
+    open_stream(stream, payload_size, PayloadOrigin::Zip(zip_path.to_string()), block_size)
+}
+
+/// Decode the manifest of a payload stream and locate its data blob section
+fn open_stream(
+    mut payload_file: Box<dyn PayloadStream>,
+    file_size: u64,
+    origin: PayloadOrigin,
+    block_size_override: Option<u32>,
+) -> Result<OpenedPayload, PayloadError> {
+    let (header, manifest) = read_header_and_manifest(&mut payload_file, file_size)?;
+
+    // Data starts after: header (24, or 20 for v1) + manifest + metadata_signature
+    let data_offset = header.data_offset;
+
+    log::info!("Data blob starts at offset: {}", data_offset);
+
+    if manifest.partial_update.unwrap_or(false) {
+        log::info!("Payload is a partial update");
+    }
+
+    let block_size = match block_size_override {
+        Some(block_size) => overridden_block_size(&manifest, block_size)?,
+        None => validated_block_size(&manifest)?,
+    } as u64;
+    check_unique_partition_names(&manifest)?;
+
+    Ok(OpenedPayload {
+        file: payload_file,
+        origin,
+        manifest,
+        data_offset,
+        block_size,
+        size: file_size,
+    })
+}
+
+/// Output image path of each partition in `selected`, keyed by name.
+///
+/// Partition names come from the (untrusted) manifest and the template from
+/// the caller, so every resulting file name is checked to be a plain name
+/// that stays inside `output_path`.
+fn image_paths(
+    manifest_partitions: &[PartitionUpdate],
+    selected: &[&PartitionUpdate],
+    output_path: &Path,
+    template: &str,
+) -> Result<HashMap<String, PathBuf>, PayloadError> {
+    if !template.contains("{name}") {
+        return Err(PayloadError::InvalidInput(format!(
+            "Filename template '{}' must contain {{name}}",
+            template
+        )));
+    }
+
+    let mut paths = HashMap::with_capacity(selected.len());
+    for partition in selected {
+        let name = &partition.partition_name;
+        if !is_plain_file_name(name) {
+            log::error!("Refusing unsafe partition name: {:?}", name);
+            return Err(PayloadError::InvalidInput(format!("Unsafe partition name '{}'", name)));
+        }
+
+        let index = manifest_partitions
+            .iter()
+            .position(|p| p.partition_name == *name)
+            .unwrap_or(0);
+        let file_name = template
+            .replace("{name}", name)
+            .replace("{index}", &index.to_string());
+        if !is_plain_file_name(&file_name) || file_name.contains(['{', '}']) {
+            log::error!("Filename template produced an unsafe name: {:?}", file_name);
+            return Err(PayloadError::InvalidInput(format!(
+                "Filename template '{}' gives invalid file name '{}'",
+                template, file_name
+            )));
+        }
+
+        paths.insert(name.clone(), output_path.join(file_name));
+    }
+
+    Ok(paths)
+}
+
+/// Whether `name` is a single file name: not empty, no path separators and
+/// not `.` or `..`
+fn is_plain_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+/// The path from `image_paths` for a partition
+fn image_path<'a>(paths: &'a HashMap<String, PathBuf>, name: &str) -> Result<&'a Path, PayloadError> {
+    paths
+        .get(name)
+        .map(PathBuf::as_path)
+        .ok_or_else(|| PayloadError::InvalidInput(format!("No output path for partition {}", name)))
+}
+
+/// Create the output directory if it doesn't exist and check that files can
+/// be created in it
+fn ensure_output_dir(output_dir: &str) -> Result<&Path, PayloadError> {
+    let output_path = Path::new(output_dir);
+    if !output_path.exists() {
+        log::info!("Creating output directory: {}", output_dir);
+        std::fs::create_dir_all(output_path).map_err(|e| {
+            PayloadError::Io(format!("Failed to create output directory: {}", e))
+        })?;
+    }
+
+    // A read-only mount or missing permission would otherwise only show up
+    // when the first image is created
+    let probe = output_path.join(format!(".payloadpack-write-test-{}", std::process::id()));
+    match std::fs::OpenOptions::new().write(true).create(true).truncate(true).open(&probe) {
+        Ok(file) => {
+            drop(file);
+            let _ = std::fs::remove_file(&probe);
+        }
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem) => {
+            log::error!("Output directory {} is not writable: {}", output_dir, e);
+            return Err(PayloadError::PermissionDenied(format!(
+                "Output directory {} is not writable: {}",
+                output_dir, e
+            )));
+        }
+        Err(e) => {
+            log::error!("Failed to create a file in output directory {}: {}", output_dir, e);
+            return Err(PayloadError::Io(format!(
+                "Failed to create a file in output directory {}: {}",
+                output_dir, e
+            )));
+        }
+    }
+
+    Ok(output_path)
+}
+
+/// Make sure the output filesystem can hold the images of `partitions`.
+///
+/// If the free space can't be determined the check is skipped with a warning.
+fn check_free_space<'a>(
+    output_path: &Path,
+    partitions: impl IntoIterator<Item = &'a PartitionUpdate>,
+) -> Result<(), PayloadError> {
+    let needed = total_partition_size(partitions)?;
+
+    let available = match available_space(output_path) {
+        Ok(available) => available,
+        Err(e) => {
+            log::warn!("Could not determine free space of {}: {}", output_path.display(), e);
+            return Ok(());
+        }
+    };
+
+    log::info!(
+        "Space needed: {}, available: {}",
+        format_size(needed),
+        format_size(available)
+    );
+
+    if needed > available {
+        log::error!("Not enough free space in {}", output_path.display());
+        return Err(PayloadError::InsufficientSpace(needed, available));
+    }
+
+    Ok(())
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+fn available_space(path: &Path) -> std::io::Result<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let c_path = std::ffi::CString::new(path.as_os_str().as_bytes())
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e))?;
+
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out-pointer
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+/// Write `file` back to storage and drop its pages from the page cache, see
+/// `ExtractOptions::drop_page_cache`.
+///
+/// Only a hint to the kernel: failures are logged, never returned. Where
+/// `posix_fadvise` isn't available the file is only synced.
+fn drop_page_cache(file: &File, label: &str) {
+    // DONTNEED leaves dirty pages in the cache, so write them back first
+    if let Err(e) = file.sync_data() {
+        log::warn!("  Failed to sync {}: {}", label, e);
+        return;
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        // SAFETY: the descriptor stays open for the duration of the call
+        let ret = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+        if ret != 0 {
+            log::warn!(
+                "  Failed to drop {} from the page cache: {}",
+                label,
+                std::io::Error::from_raw_os_error(ret)
+            );
+            return;
+        }
+    }
+
+    log::debug!("  Dropped {} from the page cache", label);
+}
+
+/// Extract all partitions from a payload.bin file
+///
+/// This function uses streaming I/O to handle large files efficiently.
+/// Each partition is extracted to a separate .img file.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `output_dir` - Directory where .img files will be written
+/// * `options` - Extraction options (source images for incremental payloads, ...)
+/// * `progress_callback` - Optional callback for progress updates (file, progress%, bytes_processed, total_bytes)
+///
+/// # Returns
+/// * `Ok(ExtractionResult)` - List of extracted partitions
+/// * `Err(PayloadError)` - If extraction fails, or if the payload contains
+///   operations that cannot be applied (e.g. PUFFDIFF, or SOURCE_COPY
+///   without `options.source_dir`)
+/// * `Err(PayloadError::Timeout)` - If `options.timeout_ms`
+///   elapsed, see `extract_with_timeout`
+pub fn extract_payload<F>(
+    payload_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    mut progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    if options.timeout_ms > 0 {
+        let path = payload_path.to_string();
+        let block_size = options.block_size;
+        let open = move || open_for_extraction(&path, block_size);
+        return extract_with_timeout(open, payload_path, output_dir, options, progress_callback, |_: &ExtractedPartition| {});
+    }
+
+    log::info!("=== PAYLOAD EXTRACTION START ===");
+    log::info!("Payload: {}", payload_path);
+    log::info!("Output: {}", output_dir);
+
+    report_parsing_manifest(&mut progress_callback);
+    let payload = open_for_extraction(payload_path, options.block_size)?;
+    extract_opened(payload, output_dir, options, progress_callback, |_: &ExtractedPartition| {})
+}
+
+/// Apply an incremental payload to the source build's images.
+///
+/// Rebuilds every partition of the target build from the images in
+/// `source_dir` (`<partition>.img`, e.g. dumped from the device or extracted
+/// from the source build's full OTA). SOURCE_COPY, SOURCE_BSDIFF and
+/// BROTLI_BSDIFF operations are applied to them; payloads using other diff
+/// operations (PUFFDIFF, ZUCCHINI, LZ4DIFF_*) are rejected before anything is
+/// written. Full-image operations are extracted as usual, so a full payload
+/// works too. Each image is checked against the manifest hash; a mismatch
+/// usually means the source images are from a different build.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `source_dir` - Directory holding the source build's partition images
+/// * `output_dir` - Directory where the new images will be written
+/// * `progress_callback` - Optional callback for progress updates
+///
+/// # Returns
+/// * `Ok(ExtractionResult)` - The rebuilt partitions, as for `extract_payload`
+/// * `Err(PayloadError::FileNotFound)` - If `source_dir` is not a directory
+/// * `Err(PayloadError)` - If extraction fails
+pub fn apply_incremental<F>(
+    payload_path: &str,
+    source_dir: &str,
+    output_dir: &str,
+    progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    log::info!("=== INCREMENTAL UPDATE ===");
+    log::info!("Source images: {}", source_dir);
+    check_source_dir(source_dir)?;
+
+    let options = ExtractOptions {
+        source_dir: Some(source_dir.to_string()),
+        ..Default::default()
+    };
+    extract_payload(payload_path, output_dir, &options, progress_callback)
+}
+
+/// Check that the source image directory of an incremental update exists
+pub(crate) fn check_source_dir(source_dir: &str) -> Result<(), PayloadError> {
+    if Path::new(source_dir).is_dir() {
+        return Ok(());
+    }
+
+    log::error!("Source image directory not found: {}", source_dir);
+    Err(PayloadError::FileNotFound(format!(
+        "Source image directory does not exist: {}",
+        source_dir
+    )))
+}
+
+/// How long a timed-out extraction gets to stop at an operation boundary and
+/// remove its partial image itself before its unfinished images are removed
+/// from under it
+const TIMEOUT_GRACE: Duration = Duration::from_secs(2);
+
+/// Message from the thread running an extraction for `extract_with_timeout`
+enum TimedExtraction {
+    /// Images the extraction will create that don't exist yet
+    Planned(Vec<String>),
+    /// An owned `ProgressUpdate`
+    Progress {
+        current_file: String,
+        percent: i32,
+        bytes_processed: u64,
+        total_bytes: u64,
+        bytes_per_second: u64,
+        eta_seconds: Option<u64>,
+    },
+    /// A partition's image is complete
+    Completed(ExtractedPartition),
+    Done(Result<ExtractionResult, PayloadError>),
+}
+
+/// Extraction of the payload returned by `open` bounded by
+/// `options.timeout_ms`, for `extract_payload_streaming` and the other
+/// extraction entry points writing to a directory. `source` names the
+/// payload in the log.
+///
+/// The payload is opened and extracted on a thread of its own; progress and completed
+/// partitions are relayed to the callbacks on the calling thread. When the time is up, the
+/// extraction is cancelled (through `options.cancel` if given) and gets
+/// `TIMEOUT_GRACE` to stop. If it is stuck in a read, the images it created
+/// and didn't complete are removed here instead, and the thread is left
+/// behind until the read returns.
+fn extract_with_timeout<P, F, C>(
+    open: P,
+    source: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    mut progress_callback: Option<F>,
+    mut on_partition: C,
+) -> Result<ExtractionResult, PayloadError>
+where
+    P: FnOnce() -> Result<OpenedPayload, PayloadError> + Send + 'static,
+    F: FnMut(&ProgressUpdate) + Send,
+    C: FnMut(&ExtractedPartition) + Send,
+{
+    let timeout = Duration::from_millis(options.timeout_ms);
+    log::info!("=== PAYLOAD EXTRACTION START (timeout {:?}) ===", timeout);
+    log::info!("Payload: {}", source);
+    log::info!("Output: {}", output_dir);
+    let deadline = Instant::now() + timeout;
+
+    let cancel = options.cancel.clone().unwrap_or_default();
+    let options = ExtractOptions {
+        cancel: Some(Arc::clone(&cancel)),
+        timeout_ms: 0,
+        ..options.clone()
+    };
+    let output_dir = output_dir.to_string();
+    let (tx, rx) = mpsc::channel();
+
+    std::thread::Builder::new()
+        .name("timed-extraction".to_string())
+        .spawn(move || {
+            // Not through extract_payload_streaming: its instantiation would
+            // recurse through this function at compile time
+            let (progress_tx, completed_tx) = (tx.clone(), tx.clone());
+            let mut progress_callback = Some(move |update: &ProgressUpdate| {
+                let _ = progress_tx.send(TimedExtraction::Progress {
+                    current_file: update.current_file.to_string(),
+                    percent: update.percent,
+                    bytes_processed: update.bytes_processed,
+                    total_bytes: update.total_bytes,
+                    bytes_per_second: update.bytes_per_second,
+                    eta_seconds: update.eta_seconds,
+                });
+            });
+            report_parsing_manifest(&mut progress_callback);
+            let result = open().and_then(|payload| {
+                // Plan first: should this thread hang, the caller knows which
+                // images it may have left half-written
+                if !options.dry_run {
+                    let new_images = new_image_paths(&payload, &output_dir, &options)?;
+                    let _ = tx.send(TimedExtraction::Planned(new_images));
+                }
+                extract_opened(payload, &output_dir, &options, progress_callback, |partition: &ExtractedPartition| {
+                    let _ = completed_tx.send(TimedExtraction::Completed(partition.clone()));
+                })
+            });
+            let _ = tx.send(TimedExtraction::Done(result));
+        })
+        .map_err(|e| PayloadError::Io(format!("Failed to start extraction thread: {}", e)))?;
+
+    // Relay messages until the extraction is done or `until` passes
+    let mut unfinished: Vec<String> = Vec::new();
+    let mut wait_until = |until: Instant| -> Option<Result<ExtractionResult, PayloadError>> {
+        loop {
+            let message = match rx.recv_timeout(until.saturating_duration_since(Instant::now())) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => return None,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Some(Err(PayloadError::OperationFailed(
+                        "Extraction thread stopped unexpectedly".to_string(),
+                    )))
+                }
+            };
+
+            match message {
+                TimedExtraction::Planned(images) => unfinished = images,
+                TimedExtraction::Progress {
+                    current_file,
+                    percent,
+                    bytes_processed,
+                    total_bytes,
+                    bytes_per_second,
+                    eta_seconds,
+                } => {
+                    if let Some(callback) = progress_callback.as_mut() {
+                        callback(&ProgressUpdate {
+                            current_file: &current_file,
+                            percent,
+                            bytes_processed,
+                            total_bytes,
+                            bytes_per_second,
+                            eta_seconds,
+                        });
+                    }
+                }
+                TimedExtraction::Completed(partition) => {
+                    unfinished.retain(|image| *image != partition.path);
+                    on_partition(&partition);
+                }
+                TimedExtraction::Done(result) => return Some(result),
+            }
+        }
+    };
+
+    if let Some(result) = wait_until(deadline) {
+        return result;
+    }
+
+    log::error!("Extraction timed out after {:?}, cancelling", timeout);
+    cancel.store(true, Ordering::SeqCst);
+
+    match wait_until(Instant::now() + TIMEOUT_GRACE) {
+        // Finished during the grace period after all
+        Some(Ok(result)) => return Ok(result),
+        Some(Err(e)) => log::info!("Extraction stopped: {}", e),
+        None => log::warn!("Extraction thread is blocked, removing its unfinished images"),
+    }
+
+    for image in unfinished.iter().filter(|image| Path::new(image).exists()) {
+        match std::fs::remove_file(image) {
+            Ok(()) => log::info!("  Removed unfinished image {}", image),
+            Err(e) => log::warn!("  Failed to remove unfinished image {}: {:?}", image, e),
+        }
+    }
+
+    Err(PayloadError::Timeout)
+}
+
+/// Images an extraction of `payload` into `output_dir` would write that
+/// don't exist yet, in the form `ExtractedPartition::path` reports them
+fn new_image_paths(payload: &OpenedPayload, output_dir: &str, options: &ExtractOptions) -> Result<Vec<String>, PayloadError> {
+    let (selected, _) = select_partitions(&payload.manifest.partitions, options.partitions.as_deref())?;
+    let (selected, _) = exclude_partitions(selected, &options.exclude)?;
+    // Crafted extents are rejected here, before they are used to size images
+    check_operation_bounds(selected.iter().copied(), &payload.manifest, payload)?;
+    let (selected, _) = split_empty_partitions(selected, payload.block_size, options)?;
+    let image_paths = image_paths(&payload.manifest.partitions, &selected, Path::new(output_dir), &options.filename_template)?;
+
+    Ok(image_paths
+        .into_values()
+        .filter(|path| !path.exists())
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
+}
+
+/// Extract all partitions from a payload.bin file, reporting each one as
+/// soon as its image is complete
+///
+/// Same as `extract_payload`, for callers that want to show finished
+/// partitions while the rest are still being extracted. `on_partition` is
+/// called once per partition written, in completion order (which is not
+/// manifest order with `max_threads` > 1), and once up front for each
+/// partition resumed from an interrupted extraction. The returned result
+/// still lists all of them.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `output_dir` - Directory where .img files will be written
+/// * `options` - Extraction options (source images for incremental payloads, ...)
+/// * `progress_callback` - Optional callback for progress updates
+/// * `on_partition` - Called with each completed partition
+///
+/// # Returns
+/// * `Ok(ExtractionResult)` - List of extracted partitions
+/// * `Err(PayloadError)` - If extraction fails; partitions reported before
+///   the failure are complete
+pub fn extract_payload_streaming<F, C>(
+    payload_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    mut progress_callback: Option<F>,
+    on_partition: C,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    C: FnMut(&ExtractedPartition) + Send,
+{
+    if options.timeout_ms > 0 {
+        let path = payload_path.to_string();
+        let block_size = options.block_size;
+        let open = move || open_for_extraction(&path, block_size);
+        return extract_with_timeout(open, payload_path, output_dir, options, progress_callback, on_partition);
+    }
+
+    log::info!("=== PAYLOAD EXTRACTION START (streaming) ===");
+    log::info!("Payload: {}", payload_path);
+    log::info!("Output: {}", output_dir);
+
+    report_parsing_manifest(&mut progress_callback);
+    let payload = open_for_extraction(payload_path, options.block_size)?;
+    extract_opened(payload, output_dir, options, progress_callback, on_partition)
+}
+
+/// Extract all partitions from an already opened payload.bin
+///
+/// Same as `extract_payload`, for payloads only reachable through a file
+/// descriptor (Storage Access Framework / content:// URIs).
+///
+/// # Arguments
+/// * `file` - The opened payload.bin; it is consumed and closed when done
+/// * `output_dir` - Directory where .img files will be written
+/// * `options` - Extraction options (source images, cancellation, ...)
+/// * `progress_callback` - Optional callback for progress updates (file, progress%, bytes_processed, total_bytes)
+pub fn extract_payload_file<F>(
+    file: File,
+    output_dir: &str,
+    options: &ExtractOptions,
+    mut progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    let block_size = options.block_size;
+    let open = move || {
+        let file_size = opened_file_size(&file)?;
+        let file = Arc::new(file);
+        open_stream(
+            Box::new(PositionedReader::new(file.clone())),
+            file_size,
+            PayloadOrigin::File(file),
+            block_size,
+        )
+    };
+    if options.timeout_ms > 0 {
+        return extract_with_timeout(open, "opened file", output_dir, options, progress_callback, |_: &ExtractedPartition| {});
+    }
+
+    log::info!("=== PAYLOAD EXTRACTION START (file) ===");
+    log::info!("Output: {}", output_dir);
+
+    report_parsing_manifest(&mut progress_callback);
+    extract_opened(open()?, output_dir, options, progress_callback, |_: &ExtractedPartition| {})
+}
+
+/// Extract all partitions from the payload.bin inside an OTA package zip
+///
+/// The payload is read in place: STORE entries by offset, DEFLATE entries by
+/// decompressing on the fly. No temporary copy of payload.bin is written.
+///
+/// # Arguments
+/// * `zip_path` - Path to the OTA package (.zip)
+/// * `output_dir` - Directory where .img files will be written
+/// * `options` - Extraction options (source images, cancellation, ...)
+/// * `progress_callback` - Optional callback for progress updates (file, progress%, bytes_processed, total_bytes)
+///
+/// # Returns
+/// * `Ok(ExtractionResult)` - Information about extracted partitions
+/// * `Err(PayloadError)` - Error if extraction fails
+pub fn extract_payload_zip<F>(
+    zip_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    mut progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    if options.timeout_ms > 0 {
+        let path = zip_path.to_string();
+        let block_size = options.block_size;
+        let open = move || open_zip_for_extraction(&path, block_size);
+        return extract_with_timeout(open, zip_path, output_dir, options, progress_callback, |_: &ExtractedPartition| {});
+    }
+
+    log::info!("=== PAYLOAD EXTRACTION START (zip) ===");
+    log::info!("Package: {}", zip_path);
+    log::info!("Output: {}", output_dir);
+
+    report_parsing_manifest(&mut progress_callback);
+    let payload = open_zip_for_extraction(zip_path, options.block_size)?;
+    extract_opened(payload, output_dir, options, progress_callback, |_: &ExtractedPartition| {})
+}
+
+/// Extract partitions into files opened by the caller instead of a directory.
+///
+/// For destinations without a usable path, such as documents created through
+/// the Storage Access Framework. `open_output` is called with each partition
+/// name right before that partition is written and returns the file to write
+/// it to, plus the label reported as its `path`. The file must be readable,
+/// writable and seekable; any previous content is discarded.
+///
+/// Partitions are written one at a time on the calling thread, so
+/// `max_threads` is ignored. Options tied to an output directory
+/// (`on_existing`, `filename_template`, checkpoints, the free space check)
+/// don't apply, and `output_sparse`, `raw` and `timeout_ms` are rejected.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `options` - Extraction options (partition selection, cancellation, ...)
+/// * `open_output` - Opens the output of a partition; an error aborts the extraction
+/// * `progress_callback` - Optional callback for progress updates
+///
+/// # Returns
+/// * `Ok(ExtractionResult)` - Information about extracted partitions
+/// * `Err(PayloadError)` - Error if extraction fails
+pub fn extract_payload_to_files<F, O>(
+    payload_path: &str,
+    options: &ExtractOptions,
+    mut open_output: O,
+    mut progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    O: FnMut(&str) -> Result<(File, String), PayloadError>,
+{
+    log::info!("=== PAYLOAD EXTRACTION START (caller-provided outputs) ===");
+    log::info!("Payload: {}", payload_path);
+
+    if options.output_sparse {
+        return Err(PayloadError::InvalidInput(
+            "Sparse output is only available when extracting to a directory".to_string(),
+        ));
+    }
+    if options.raw {
+        return Err(PayloadError::InvalidInput(
+            "Raw blob output is only available when extracting to a directory".to_string(),
+        ));
+    }
+    if options.timeout_ms > 0 {
+        return Err(PayloadError::InvalidInput(
+            "A timeout is only available when extracting to a directory".to_string(),
+        ));
+    }
+
+    report_parsing_manifest(&mut progress_callback);
+    let mut payload = open_for_extraction(payload_path, options.block_size)?;
+    let manifest = std::mem::take(&mut payload.manifest);
+
+    let (selected, skipped) = select_partitions(&manifest.partitions, options.partitions.as_deref())?;
+    let (selected, excluded) = exclude_partitions(selected, &options.exclude)?;
+    check_operation_bounds(selected.iter().copied(), &manifest, &payload)?;
+    let (selected, empty) = split_empty_partitions(selected, payload.block_size, options)?;
+
+    if options.dry_run {
+        return Ok(plan_extraction(&selected, skipped, &HashMap::new(), payload.block_size, options)?
+            .with_empty_partitions(empty)
+            .with_excluded(excluded));
+    }
+    check_operations_supported(selected.iter().copied(), options)?;
+
+    if options.use_mmap {
+        if let Some(mapped) = payload.origin.map() {
+            payload.file = mapped.reopen()?;
+            payload.origin = mapped;
+        }
+    }
+
+    let total_bytes = options.progress_unit.total_bytes(selected.iter().copied())?;
+    let mut progress = ProgressTracker::new(progress_callback, total_bytes)
+        .throttled(Duration::from_millis(options.progress_interval_ms), options.progress_min_bytes)
+        .measured_in(options.progress_unit);
+
+    let mut extracted = Vec::with_capacity(selected.len());
+    for partition in selected {
+        let partition_name = &partition.partition_name;
+        log::info!("Extracting partition: {}", partition_name);
+
+        let (file, label) = open_output(partition_name)?;
+        progress.partition_started(partition_name);
+        let mut reported = 0;
+        let output = ImageOutput::File { file, label };
+        let extracted_partition = write_partition_image(&mut payload, partition, output, options, &mut |bytes| {
+            reported += bytes;
+            progress.advance(partition_name, bytes);
+        })?;
+        progress.partition_finished(partition, reported);
+        extracted.push(extracted_partition);
+
+        if options.is_cancelled() {
+            log::warn!("Extraction cancelled after partition {}", partition_name);
+            return Err(PayloadError::Cancelled);
+        }
+    }
+
+    log::info!("=== PAYLOAD EXTRACTION COMPLETE ===");
+    log::info!("Extracted {} partitions, skipped {}", extracted.len(), skipped.len());
+
+    Ok(ExtractionResult::new("success", extracted, skipped)
+        .with_empty_partitions(empty)
+        .with_excluded(excluded))
+}
+
+/// Extract the partitions selected by `options` from an opened payload,
+/// passing each partition to `on_partition` once its image is complete
+fn extract_opened<F, C>(
+    mut payload: OpenedPayload,
+    output_dir: &str,
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+    mut on_partition: C,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    C: FnMut(&ExtractedPartition) + Send,
+{
+    if options.raw && options.output_sparse {
+        return Err(PayloadError::InvalidInput(
+            "Raw blob output can't be written as sparse images".to_string(),
+        ));
+    }
+
+    let manifest = std::mem::take(&mut payload.manifest);
+
+    // Narrow down to the requested partitions, keeping manifest order
+    let (selected, mut skipped) = select_partitions(&manifest.partitions, options.partitions.as_deref())?;
+    let (selected, excluded) = exclude_partitions(selected, &options.exclude)?;
+
+    // Reject blobs outside the payload before anything is read or written
+    check_operation_bounds(selected.iter().copied(), &manifest, &payload)?;
+
+    let (selected, empty) = split_empty_partitions(selected, payload.block_size, options)?;
+    let image_paths = image_paths(&manifest.partitions, &selected, Path::new(output_dir), &options.filename_template)?;
+
+    if options.use_mmap && !options.dry_run {
+        if let Some(mapped) = payload.origin.map() {
+            payload.file = mapped.reopen()?;
+            payload.origin = mapped;
+        }
+    }
+
+    if options.dry_run {
+        return Ok(plan_extraction(&selected, skipped, &image_paths, payload.block_size, options)?
+            .with_empty_partitions(empty)
+            .with_excluded(excluded));
+    }
+
+    // Refuse delta operations we can't apply before any file is written,
+    // otherwise the output would look plausible but be corrupt
+    let mut failed = Vec::new();
+    let selected: Vec<&PartitionUpdate> = if options.continue_on_error {
+        selected
+            .into_iter()
+            .filter(|partition| match check_partition_supported(partition, options) {
+                Ok(()) => true,
+                Err(e) => {
+                    failed.push(partition_failed(partition, None, e));
+                    false
+                }
+            })
+            .collect()
+    } else {
+        check_operations_supported(selected.iter().copied(), options)?;
+        selected
+    };
+
+    // Create output directory if it doesn't exist
+    let output_path = ensure_output_dir(output_dir)?;
+
+    // Skip partitions completed by an earlier, interrupted extraction
+    let manifest_digest = to_hex(&Sha256::digest(manifest.encode_to_vec()));
+    let mut checkpoint = Checkpoint::load(output_path, &manifest_digest, options.output_sparse, options.raw, options.force);
+    let mut resumed = Vec::new();
+    let pending: Vec<&PartitionUpdate> = selected
+        .iter()
+        .copied()
+        .filter(|partition| match checkpoint.resume(&partition.partition_name) {
+            // Only if it was written under the name asked for this time
+            Some(done) if image_paths.get(&done.name).is_some_and(|path| *path == Path::new(&done.path)) => {
+                resumed.push(done);
+                false
+            }
+            _ => true,
+        })
+        .collect();
+
+    // Don't clobber images left by an earlier extraction unless asked to
+    let pending = handle_existing_outputs(pending, &image_paths, options.on_existing, &mut skipped)?;
+
+    // Fail now rather than halfway through with a full disk
+    check_free_space(output_path, pending.iter().copied())?;
+
+    // Resumed partitions are already complete
+    for done in &resumed {
+        on_partition(done);
+    }
+    let mut on_partition = |done: &ExtractedPartition| {
+        checkpoint.record(done);
+        on_partition(done);
+    };
+
+    // Calculate total bytes for progress tracking (partitions left to extract)
+    let total_bytes = options.progress_unit.total_bytes(pending.iter().copied())?;
+
+    let mut progress = ProgressTracker::new(progress_callback, total_bytes)
+        .throttled(Duration::from_millis(options.progress_interval_ms), options.progress_min_bytes)
+        .measured_in(options.progress_unit);
+
+    let threads = options.max_threads.min(pending.len());
+    let extracted = if threads > 1 {
+        let (extracted, failures) =
+            extract_parallel(&payload, &pending, &image_paths, options, threads, progress, &mut on_partition)?;
+        failed.extend(failures);
+        extracted
+    } else {
+        let mut extracted = Vec::new();
+
+        // Extract each partition
+        for partition in pending {
+            let partition_name = &partition.partition_name;
+            log::info!("Extracting partition: {}", partition_name);
+
+            progress.partition_started(partition_name);
+            let mut reported = 0;
+            let image_path = image_path(&image_paths, partition_name)?;
+            let output = ImageOutput::Path(image_path);
+            let written = write_partition_image(&mut payload, partition, output, options, &mut |bytes| {
+                reported += bytes;
+                progress.advance(partition_name, bytes);
+            });
+            let extracted_partition = match written {
+                Ok(extracted) => extracted,
+                Err(e) if continues_after(&e, options) => {
+                    progress.partition_finished(partition, reported);
+                    failed.push(partition_failed(partition, Some(image_path), e));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            progress.partition_finished(partition, reported);
+
+            on_partition(&extracted_partition);
+            extracted.push(extracted_partition);
+
+            if options.is_cancelled() {
+                log::warn!("Extraction cancelled after partition {}", partition_name);
+                return Err(PayloadError::Cancelled);
+            }
+        }
+
+        extracted
+    };
+
+    log::info!("=== PAYLOAD EXTRACTION COMPLETE ===");
+    log::info!(
+        "Extracted {} partitions, resumed {}, skipped {}, failed {}",
+        extracted.len(),
+        resumed.len(),
+        skipped.len(),
+        failed.len()
+    );
+
+    // Report resumed and new partitions together, in manifest order
+    let mut extracted: Vec<ExtractedPartition> = resumed.into_iter().chain(extracted).collect();
+    extracted.sort_by_key(|done| selected.iter().position(|p| p.partition_name == done.name));
+    failed.sort_by_key(|failure| {
+        manifest
+            .partitions
+            .iter()
+            .position(|p| p.partition_name == failure.name)
+    });
+
+    let linked = if options.link_duplicates {
+        link_duplicates(&extracted)
+    } else {
+        Vec::new()
+    };
+
+    Ok(ExtractionResult::new("success", extracted, skipped)
+        .with_failed(failed)
+        .with_empty_partitions(empty)
+        .with_excluded(excluded)
+        .with_linked(linked))
+}
+
+/// Replace each image identical to an earlier one of `extracted` (same size
+/// and hash) with a hard link to it, see `ExtractOptions::link_duplicates`
+fn link_duplicates(extracted: &[ExtractedPartition]) -> Vec<LinkedPartition> {
+    let mut originals: HashMap<(u64, &str), &ExtractedPartition> = HashMap::new();
+    let mut linked = Vec::new();
+
+    for partition in extracted {
+        if partition.actual_hash.is_empty() {
+            continue;
+        }
+        let Some(original) = originals.get(&(partition.size, partition.actual_hash.as_str())) else {
+            originals.insert((partition.size, &partition.actual_hash), partition);
+            continue;
+        };
+
+        let result = hard_link_over(Path::new(&original.path), Path::new(&partition.path));
+        match &result {
+            Ok(()) => log::info!("{} is identical to {}, hard linked", partition.name, original.name),
+            Err(e) => log::warn!(
+                "{} is identical to {} but could not be hard linked, keeping a copy: {}",
+                partition.name,
+                original.name,
+                e
+            ),
+        }
+        linked.push(LinkedPartition {
+            name: partition.name.clone(),
+            same_as: original.name.clone(),
+            hard_linked: result.is_ok(),
+            error: result.err().map(|e| e.to_string()),
+        });
+    }
+
+    linked
+}
+
+/// Make `path` a hard link to `original`. The link is created next to `path`
+/// and renamed over it, so `path` stays intact if linking fails.
+fn hard_link_over(original: &Path, path: &Path) -> std::io::Result<()> {
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".link");
+    let temp_path = path.with_file_name(temp_name);
+
+    let _ = std::fs::remove_file(&temp_path);
+    std::fs::hard_link(original, &temp_path)?;
+    std::fs::rename(&temp_path, path).inspect_err(|_| {
+        let _ = std::fs::remove_file(&temp_path);
+    })
+}
+
+/// Whether the extraction moves on to the next partition after `error`:
+/// only with `ExtractOptions::continue_on_error`, and never once cancelled
+fn continues_after(error: &PayloadError, options: &ExtractOptions) -> bool {
+    options.continue_on_error && !matches!(error, PayloadError::Cancelled) && !options.is_cancelled()
+}
+
+/// Record the failure of `partition`, removing the partial image at
+/// `image_path` so it can't be mistaken for a complete one
+fn partition_failed(partition: &PartitionUpdate, image_path: Option<&Path>, error: PayloadError) -> FailedPartition {
+    log::error!("Partition {} failed: {}", partition.partition_name, error);
+    if let Some(path) = image_path {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                log::warn!("  Failed to remove partial {}: {}", path.display(), e);
+            }
+            _ => {}
+        }
+    }
+
+    FailedPartition {
+        name: partition.partition_name.clone(),
+        error: error.to_string(),
+        code: error.code().to_string(),
+        code_number: error.code_number(),
+    }
+}
+
+/// Drop the selected partitions matching one of the `patterns` of
+/// `ExtractOptions::exclude`.
+///
+/// # Returns
+/// * `Ok((partitions, excluded))` - The partitions to extract, and the names
+///   of the excluded ones
+/// * `Err(PayloadError::InvalidInput)` - If nothing is left to extract
+fn exclude_partitions<'a>(
+    selected: Vec<&'a PartitionUpdate>,
+    patterns: &[String],
+) -> Result<(Vec<&'a PartitionUpdate>, Vec<String>), PayloadError> {
+    if patterns.is_empty() {
+        return Ok((selected, Vec::new()));
+    }
+
+    let (excluded, selected): (Vec<_>, Vec<_>) = selected.into_iter().partition(|partition| {
+        patterns
+            .iter()
+            .any(|pattern| matches_pattern(pattern, &partition.partition_name))
+    });
+    let excluded: Vec<String> = excluded
+        .into_iter()
+        .map(|partition| partition.partition_name.clone())
+        .collect();
+
+    if selected.is_empty() && !excluded.is_empty() {
+        return Err(PayloadError::InvalidInput(format!(
+            "Every selected partition is excluded by {}",
+            patterns.join(", ")
+        )));
+    }
+    if !excluded.is_empty() {
+        log::info!("Excluding partitions: {}", excluded.join(", "));
+    }
+
+    Ok((selected, excluded))
+}
+
+/// Whether `name` matches `pattern`, where `*` stands for any run of
+/// characters (including none) and `?` for exactly one
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Position after the last `*` seen, and the name position it matched up to
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                p += 1;
+                backtrack = Some((p, n));
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character
+                Some((star_p, star_n)) => {
+                    p = star_p;
+                    n = star_n + 1;
+                    backtrack = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Set aside the selected partitions whose image would be empty, unless
+/// `options.create_empty` asks for them to be written anyway.
+///
+/// # Returns
+/// The partitions to extract, and the names of the empty ones set aside
+fn split_empty_partitions<'a>(
+    selected: Vec<&'a PartitionUpdate>,
+    block_size: u64,
+    options: &ExtractOptions,
+) -> Result<(Vec<&'a PartitionUpdate>, Vec<String>), PayloadError> {
+    if options.create_empty {
+        return Ok((selected, Vec::new()));
+    }
+
+    let mut kept = Vec::with_capacity(selected.len());
+    let mut empty = Vec::new();
+    for partition in selected {
+        if planned_image_size(partition, block_size)? == 0 {
+            empty.push(partition.partition_name.clone());
+        } else {
+            kept.push(partition);
+        }
+    }
+    if !empty.is_empty() {
+        log::info!("Not writing empty partitions: {}", empty.join(", "));
+    }
+
+    Ok((kept, empty))
+}
+
+/// Dry run: report what extracting `selected` would produce, without
+/// touching the output directory.
+///
+/// Sizes are computed from the destination extents exactly as
+/// `write_partition_image` would lay out the image, and each partition is
+/// checked for operations that can't be applied.
+fn plan_extraction(
+    selected: &[&PartitionUpdate],
+    skipped: Vec<SkippedPartition>,
+    image_paths: &HashMap<String, PathBuf>,
+    block_size: u64,
+    options: &ExtractOptions,
+) -> Result<ExtractionResult, PayloadError> {
+    log::info!("=== DRY RUN: planning {} partitions ===", selected.len());
+
+    let planned = selected
+        .iter()
+        .map(|partition| {
+            let size = planned_image_size(partition, block_size)?;
+            let unsupported_reason = check_partition_supported(partition, options)
+                .err()
+                .map(|e| e.to_string());
+
+            log::info!(
+                "  {}: {} ({}){}",
+                partition.partition_name,
+                size,
+                format_size(size),
+                if unsupported_reason.is_some() { ", unsupported" } else { "" }
+            );
+
+            Ok(ExtractedPartition {
+                name: partition.partition_name.clone(),
+                size,
+                path: image_paths
+                    .get(&partition.partition_name)
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default(),
+                verified: false,
+                expected_hash: partition
+                    .new_partition_info
+                    .as_ref()
+                    .and_then(|info| info.hash.as_deref())
+                    .map(to_hex),
+                actual_hash: String::new(),
+                supported: unsupported_reason.is_none(),
+                unsupported_reason,
+                resumed: false,
+                warnings: operation_warnings(partition),
+            })
+        })
+        .collect::<Result<Vec<_>, PayloadError>>()?;
+
+    Ok(ExtractionResult::new("dry_run", planned, skipped))
+}
+
+/// Size of the image `write_partition_image` would produce for `partition`:
+/// the partition size, or the end of the furthest destination extent if the
+/// operations write past it. `InvalidInput` if that doesn't fit in a u64.
+fn planned_image_size(partition: &PartitionUpdate, block_size: u64) -> Result<u64, PayloadError> {
+    let partition_size = partition
+        .new_partition_info
+        .as_ref()
+        .and_then(|info| info.size)
+        .unwrap_or(0);
+
+    // Operations without extents are written sequentially after the previous
+    // one; only REPLACE has a known output length in that case
+    let byte_end = |extent: &Extent| {
+        extent_end(extent, block_size).ok_or_else(|| {
+            PayloadError::InvalidInput(format!(
+                "Partition {} has an out of range extent (start_block {}, num_blocks {}, block size {})",
+                partition.partition_name,
+                extent.start_block.unwrap_or(0),
+                extent.num_blocks.unwrap_or(0),
+                block_size
+            ))
+        })
+    };
+    let mut position = 0u64;
+    let mut end = 0u64;
+    for operation in &partition.operations {
+        if operation.dst_extents.is_empty() {
+            if operation.r#type() == OperationType::Replace {
+                position = checked_offset(position, operation.data_length.unwrap_or(0), "Image size")?;
+            }
+        } else if let Some(last) = operation.dst_extents.last() {
+            position = byte_end(last)?;
+            for extent in &operation.dst_extents {
+                end = end.max(byte_end(extent)?);
+            }
+        }
+        end = end.max(position);
+    }
+
+    Ok(partition_size.max(end))
+}
+
+/// Extract `selected` partitions on `threads` worker threads.
+///
+/// Workers take the next pending partition in manifest order, each reading the
+/// payload through its own handle. The first failure sets the cancellation
+/// token so the other workers stop at their next operation, and is returned;
+/// with `continue_on_error`, failures are collected and the workers go on.
+/// Results are returned in manifest order; `on_partition` sees them as they
+/// complete.
+fn extract_parallel<F, C>(
+    payload: &OpenedPayload,
+    selected: &[&PartitionUpdate],
+    image_paths: &HashMap<String, PathBuf>,
+    options: &ExtractOptions,
+    threads: usize,
+    progress: ProgressTracker<F>,
+    on_partition: &mut C,
+) -> Result<(Vec<ExtractedPartition>, Vec<FailedPartition>), PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    C: FnMut(&ExtractedPartition) + Send,
+{
+    log::info!("Extracting {} partitions on {} threads", selected.len(), threads);
+
+    // Workers share one token, so a failure in one of them stops the rest
+    let mut options = options.clone();
+    let cancel = options.cancel.get_or_insert_with(Default::default).clone();
+    let options = &options;
+
+    let (origin, data_offset, block_size, size) =
+        (&payload.origin, payload.data_offset, payload.block_size, payload.size);
+    let next_partition = AtomicUsize::new(0);
+    let progress = Mutex::new(progress);
+    let on_partition = Mutex::new(on_partition);
+    let results = Mutex::new(Vec::with_capacity(selected.len()));
+    let failures = Mutex::new(Vec::new());
+    let first_error: Mutex<Option<PayloadError>> = Mutex::new(None);
+
+    let fail = |error: PayloadError| {
+        cancel.store(true, Ordering::Relaxed);
+        if let Ok(mut first) = first_error.lock() {
+            first.get_or_insert(error);
+        }
+    };
+
+    std::thread::scope(|scope| {
+        for worker in 0..threads {
+            let (next_partition, progress, on_partition, results, failures, fail) =
+                (&next_partition, &progress, &on_partition, &results, &failures, &fail);
+            scope.spawn(move || {
+                // The manifest is not decoded again; workers only need the data layout
+                let mut handle = match origin.reopen() {
+                    Ok(file) => OpenedPayload {
+                        file,
+                        origin: origin.clone(),
+                        manifest: DeltaArchiveManifest::default(),
+                        data_offset,
+                        block_size,
+                        size,
+                    },
+                    Err(e) => return fail(e),
+                };
+
+                while !options.is_cancelled() {
+                    let index = next_partition.fetch_add(1, Ordering::Relaxed);
+                    let Some(partition) = selected.get(index) else {
+                        break;
+                    };
+                    log::info!("Worker {} extracting partition: {}", worker, partition.partition_name);
+
+                    if let Ok(mut progress) = progress.lock() {
+                        progress.partition_started(&partition.partition_name);
+                    }
+                    let mut reported = 0;
+                    let mut on_progress = |bytes| {
+                        reported += bytes;
+                        if let Ok(mut progress) = progress.lock() {
+                            progress.advance(&partition.partition_name, bytes);
+                        }
+                    };
+                    let written = image_path(image_paths, &partition.partition_name)
+                        .and_then(|path| {
+                            write_partition_image(&mut handle, partition, ImageOutput::Path(path), options, &mut on_progress)
+                        });
+                    match written {
+                        Ok(extracted) => {
+                            if let Ok(mut progress) = progress.lock() {
+                                progress.partition_finished(partition, reported);
+                            }
+                            if let Ok(mut on_partition) = on_partition.lock() {
+                                on_partition(&extracted);
+                            }
+                            if let Ok(mut results) = results.lock() {
+                                results.push((index, extracted));
+                            }
+                        }
+                        Err(e) if continues_after(&e, options) => {
+                            if let Ok(mut progress) = progress.lock() {
+                                progress.partition_finished(partition, reported);
+                            }
+                            let image_path = image_paths.get(&partition.partition_name).map(PathBuf::as_path);
+                            let failure = partition_failed(partition, image_path, e);
+                            if let Ok(mut failures) = failures.lock() {
+                                failures.push((index, failure));
+                            }
+                        }
+                        Err(e) => return fail(e),
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(error) = first_error.into_inner().ok().flatten() {
+        log::error!("Parallel extraction failed: {}", error);
+        return Err(error);
+    }
+    if options.is_cancelled() {
+        log::warn!("Extraction cancelled");
+        return Err(PayloadError::Cancelled);
+    }
+
+    let mut results = results.into_inner().unwrap_or_default();
+    results.sort_by_key(|(index, _)| *index);
+    let mut failures = failures.into_inner().unwrap_or_default();
+    failures.sort_by_key(|(index, _)| *index);
+    Ok((
+        results.into_iter().map(|(_, extracted)| extracted).collect(),
+        failures.into_iter().map(|(_, failure)| failure).collect(),
+    ))
+}
+
+/// Progress of an extraction, passed to the progress callback
+#[derive(Debug, Clone)]
+pub struct ProgressUpdate<'a> {
+    /// Partition currently being extracted
+    pub current_file: &'a str,
+    /// Overall progress percentage (0-100)
+    pub percent: i32,
+    pub bytes_processed: u64,
+    pub total_bytes: u64,
+    /// Throughput averaged over the last few seconds
+    pub bytes_per_second: u64,
+    /// Estimated seconds remaining, `None` until the throughput is known
+    pub eta_seconds: Option<u64>,
+}
+
+/// `current_file` of the progress update sent while the manifest is read and
+/// decoded, before the first partition starts. Not a valid partition name.
+pub const PARSING_MANIFEST: &str = "<manifest>";
+
+/// Tell the caller the manifest is being parsed, which can take a noticeable
+/// while for large manifests on slow storage
+fn report_parsing_manifest<F: FnMut(&ProgressUpdate)>(progress_callback: &mut Option<F>) {
+    if let Some(callback) = progress_callback {
+        callback(&ProgressUpdate {
+            current_file: PARSING_MANIFEST,
+            percent: 0,
+            bytes_processed: 0,
+            total_bytes: 0,
+            bytes_per_second: 0,
+            eta_seconds: None,
+        });
+    }
+}
+
+/// Window over which the throughput is averaged
+const THROUGHPUT_WINDOW: Duration = Duration::from_secs(5);
+
+/// Aggregates the bytes written across partitions and reports them, with the
+/// current throughput and ETA, through the progress callback.
+///
+/// Callbacks can be throttled to limit JNI crossings: updates arriving too
+/// soon after the previous callback are dropped, and the next callback
+/// carries the latest totals. The final update is always delivered.
+struct ProgressTracker<F> {
+    callback: Option<F>,
+    total_bytes: u64,
+    bytes_processed: u64,
+    min_interval: Duration,
+    min_bytes: u64,
+    /// What the byte counts measure
+    unit: ProgressUnit,
+    /// Time and byte count of the last delivered callback
+    last_reported: Option<(Instant, u64)>,
+    /// (time, bytes_processed) samples; the oldest one is the last sample
+    /// from before the throughput window
+    samples: std::collections::VecDeque<(Instant, u64)>,
+}
+
+impl<F: FnMut(&ProgressUpdate)> ProgressTracker<F> {
+    fn new(callback: Option<F>, total_bytes: u64) -> Self {
+        Self {
+            callback,
+            total_bytes,
+            bytes_processed: 0,
+            min_interval: Duration::ZERO,
+            min_bytes: 0,
+            unit: ProgressUnit::Output,
+            last_reported: None,
+            samples: std::collections::VecDeque::from([(Instant::now(), 0)]),
+        }
+    }
+
+    /// Deliver at most one callback per `min_interval`, unless `min_bytes`
+    /// (when non-zero) were processed since the last one
+    fn throttled(mut self, min_interval: Duration, min_bytes: u64) -> Self {
+        self.min_interval = min_interval;
+        self.min_bytes = min_bytes;
+        self
+    }
+
+    /// Count progress in `unit` (image bytes by default)
+    fn measured_in(mut self, unit: ProgressUnit) -> Self {
+        self.unit = unit;
+        self
+    }
+
+    /// Whether an update should be delivered now or coalesced into a later one
+    fn is_due(&self, now: Instant) -> bool {
+        let Some((time, bytes)) = self.last_reported else {
+            return true;
+        };
+
+        self.bytes_processed >= self.total_bytes
+            || now.duration_since(time) >= self.min_interval
+            || (self.min_bytes > 0 && self.bytes_processed - bytes >= self.min_bytes)
+    }
+
+    /// Report progress at the start of a partition
+    fn partition_started(&mut self, partition_name: &str) {
+        self.report(partition_name, 0);
+    }
+
+    /// Account for `bytes` written by an operation and report progress
+    fn advance(&mut self, partition_name: &str, bytes: u64) {
+        self.bytes_processed += bytes;
+        self.report(partition_name, 0);
+    }
+
+    /// Account for the rest of a completed partition, of which `reported`
+    /// bytes were already passed to `advance`, and report progress
+    fn partition_finished(&mut self, partition: &PartitionUpdate, reported: u64) {
+        let size = self.unit.partition_bytes(partition);
+        self.bytes_processed += size.saturating_sub(reported);
+        self.report(&partition.partition_name, 100);
+    }
+
+    /// `empty_percent` is reported when there is nothing to extract at all
+    fn report(&mut self, partition_name: &str, empty_percent: i32) {
+        let now = Instant::now();
+        if self.callback.is_none() || !self.is_due(now) {
+            return;
+        }
+        self.last_reported = Some((now, self.bytes_processed));
+
+        let bytes_per_second = self.sample_throughput(now);
+        let remaining = self.total_bytes.saturating_sub(self.bytes_processed);
+        let eta_seconds = match bytes_per_second {
+            0 if remaining > 0 => None,
+            0 => Some(0),
+            rate => Some(remaining.div_ceil(rate)),
+        };
+
+        let update = ProgressUpdate {
+            current_file: partition_name,
+            percent: if self.total_bytes > 0 {
+                ((self.bytes_processed as f64 / self.total_bytes as f64) * 100.0) as i32
+            } else {
+                empty_percent
+            },
+            bytes_processed: self.bytes_processed,
+            total_bytes: self.total_bytes,
+            bytes_per_second,
+            eta_seconds,
+        };
+
+        if let Some(ref mut callback) = self.callback {
+            callback(&update);
+        }
+    }
+
+    /// Record the current byte count and return the moving-average throughput
+    fn sample_throughput(&mut self, now: Instant) -> u64 {
+        self.samples.push_back((now, self.bytes_processed));
+
+        // Keep one sample older than the window as the baseline
+        while self.samples.len() > 2
+            && now.duration_since(self.samples[1].0) >= THROUGHPUT_WINDOW
+        {
+            self.samples.pop_front();
+        }
+
+        let (since, bytes_then) = self.samples[0];
+        let elapsed = now.duration_since(since).as_secs_f64();
+        if elapsed <= 0.0 {
+            return 0;
+        }
+        ((self.bytes_processed - bytes_then) as f64 / elapsed) as u64
+    }
+}
+
+/// Extract only the named partitions from a payload.bin file
+///
+/// Progress is computed over the selected partitions only. Requested names
+/// that don't exist in the payload are listed in `ExtractionResult::skipped`.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `output_dir` - Directory where .img files will be written
+/// * `names` - Partitions to extract (e.g. `["boot", "vendor_boot", "dtbo"]`)
+/// * `options` - Extraction options; `options.partitions` is replaced by `names`
+/// * `progress_callback` - Optional callback for progress updates (file, progress%, bytes_processed, total_bytes)
+pub fn extract_payload_selective<F>(
+    payload_path: &str,
+    output_dir: &str,
+    names: &[String],
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+) -> Result<ExtractionResult, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    let options = ExtractOptions {
+        partitions: Some(names.to_vec()),
+        ..options.clone()
+    };
+    extract_payload(payload_path, output_dir, &options, progress_callback)
+}
+
+/// Pick the partitions to extract, in manifest order.
+///
+/// Returns the selected partitions and a skip entry for every requested
+/// name that isn't in the manifest. If none of the requested names exist,
+/// fails with `InvalidInput` listing the available partitions.
+fn select_partitions<'a>(
+    partitions: &'a [PartitionUpdate],
+    names: Option<&[String]>,
+) -> Result<(Vec<&'a PartitionUpdate>, Vec<SkippedPartition>), PayloadError> {
+    let names = match names {
+        Some(names) => names,
+        None => return Ok((partitions.iter().collect(), Vec::new())),
+    };
+
+    let selected: Vec<&PartitionUpdate> = partitions
+        .iter()
+        .filter(|p| names.contains(&p.partition_name))
+        .collect();
+
+    let mut skipped: Vec<SkippedPartition> = Vec::new();
+    for name in names {
+        let found = partitions.iter().any(|p| &p.partition_name == name);
+        if !found && !skipped.iter().any(|s| &s.name == name) {
+            log::warn!("Requested partition {} not found in payload", name);
+            skipped.push(SkippedPartition {
+                name: name.clone(),
+                reason: "not found in payload".to_string(),
+            });
+        }
+    }
+
+    // Nothing requested exists: most likely a typo, so fail with the
+    // names that do
+    if selected.is_empty() && !names.is_empty() {
+        let missing: Vec<&str> = skipped.iter().map(|s| s.name.as_str()).collect();
+        return Err(partitions_not_found(&missing, partitions));
+    }
+
+    log::info!("Selected {} of {} partitions", selected.len(), partitions.len());
+
+    Ok((selected, skipped))
+}
+
+/// `InvalidInput` for requested partitions missing from the payload, listing
+/// the partitions it does have in sorted order
+fn partitions_not_found(missing: &[&str], partitions: &[PartitionUpdate]) -> PayloadError {
+    let mut available: Vec<&str> = partitions.iter().map(|p| p.partition_name.as_str()).collect();
+    available.sort_unstable();
+
+    let missing = missing
+        .iter()
+        .map(|name| format!("'{}'", name))
+        .collect::<Vec<_>>()
+        .join(", ");
+    PayloadError::InvalidInput(format!(
+        "Partition {} not found. Available partitions: {}",
+        missing,
+        available.join(", ")
+    ))
+}
+
+/// Apply `on_existing` to partitions whose output image already exists.
+///
+/// Returns the partitions still to extract. In `Skip` mode the others are
+/// added to `skipped`; in `Error` mode the first existing image is an error.
+fn handle_existing_outputs<'a>(
+    pending: Vec<&'a PartitionUpdate>,
+    image_paths: &HashMap<String, PathBuf>,
+    on_existing: OnExisting,
+    skipped: &mut Vec<SkippedPartition>,
+) -> Result<Vec<&'a PartitionUpdate>, PayloadError> {
+    if on_existing == OnExisting::Overwrite {
+        return Ok(pending);
+    }
+
+    let mut to_extract = Vec::with_capacity(pending.len());
+    for partition in pending {
+        let image_path = image_path(image_paths, &partition.partition_name)?;
+        if !image_path.exists() {
+            to_extract.push(partition);
+            continue;
+        }
+
+        if on_existing == OnExisting::Error {
+            log::error!("{} already exists, not overwriting", image_path.display());
+            return Err(PayloadError::OutputExists(image_path.to_string_lossy().to_string()));
+        }
+
+        log::info!("{} already exists, skipping", image_path.display());
+        skipped.push(SkippedPartition {
+            name: partition.partition_name.clone(),
+            reason: "output file already exists".to_string(),
+        });
+    }
+
+    Ok(to_extract)
+}
+
+/// Extract a single named partition from a payload.bin file
+///
+/// Only the operations of the requested partition are processed, seeking
+/// directly to its data, which is much faster than a full extraction when
+/// only e.g. boot.img or init_boot.img is needed.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `output_dir` - Directory where `<partition_name>.img` will be written
+/// * `partition_name` - Name of the partition to extract (e.g. "boot")
+///
+/// # Returns
+/// * `Ok(ExtractedPartition)` - The extracted image
+/// * `Err(PayloadError::InvalidInput)` - If the partition is not in the payload
+pub fn extract_partition(
+    payload_path: &str,
+    output_dir: &str,
+    partition_name: &str,
+) -> Result<ExtractedPartition, PayloadError> {
+    log::info!("=== PARTITION EXTRACTION START ===");
+    log::info!("Payload: {}", payload_path);
+    log::info!("Partition: {}", partition_name);
+    log::info!("Output: {}", output_dir);
+
+    let mut payload = open_for_extraction(payload_path, None)?;
+    let manifest = std::mem::take(&mut payload.manifest);
+
+    let partition = match manifest
+        .partitions
+        .iter()
+        .find(|p| p.partition_name == partition_name)
+    {
+        Some(p) => p,
+        None => {
+            log::error!("Partition {} not found in payload", partition_name);
+            return Err(partitions_not_found(&[partition_name], &manifest.partitions));
+        }
+    };
+
+    let options = ExtractOptions::default();
+    check_operation_bounds(std::iter::once(partition), &manifest, &payload)?;
+    check_operations_supported(std::slice::from_ref(partition), &options)?;
+
+    let image_paths = image_paths(&manifest.partitions, &[partition], Path::new(output_dir), &options.filename_template)?;
+    let output_path = ensure_output_dir(output_dir)?;
+    check_free_space(output_path, std::iter::once(partition))?;
+    let image_path = image_path(&image_paths, partition_name)?;
+    let extracted = write_partition_image(&mut payload, partition, ImageOutput::Path(image_path), &options, &mut |_| {})?;
+
+    log::info!("=== PARTITION EXTRACTION COMPLETE ===");
+
+    Ok(extracted)
+}
+
+/// Size limit of `extract_partition_to_vec` when the caller gives none.
+/// Comfortably fits boot, init_boot, vendor_boot and dtbo images.
+pub const DEFAULT_IN_MEMORY_LIMIT: u64 = 128 * 1024 * 1024;
+
+/// Extract a single named partition into memory
+///
+/// For small partitions that are only read, e.g. boot.img to look at its
+/// header and kernel version, without writing an image to storage. Applies
+/// the same operations as `extract_partition`; the image hash is checked
+/// against the manifest and a mismatch is logged.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `partition_name` - Name of the partition to extract (e.g. "boot")
+/// * `max_size` - Largest image accepted, `DEFAULT_IN_MEMORY_LIMIT` if `None`
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The partition image
+/// * `Err(PayloadError::InvalidInput)` - If the partition is not in the payload
+///   or its image is larger than `max_size`
+pub fn extract_partition_to_vec(
+    payload_path: &str,
+    partition_name: &str,
+    max_size: Option<u64>,
+) -> Result<Vec<u8>, PayloadError> {
+    let max_size = max_size.unwrap_or(DEFAULT_IN_MEMORY_LIMIT);
+    log::info!("=== IN-MEMORY PARTITION EXTRACTION START ===");
+    log::info!("Payload: {}", payload_path);
+    log::info!("Partition: {} (limit {})", partition_name, format_size(max_size));
+
+    let mut payload = open_for_extraction(payload_path, None)?;
+    let manifest = std::mem::take(&mut payload.manifest);
+
+    let partition = manifest
+        .partitions
+        .iter()
+        .find(|p| p.partition_name == partition_name)
+        .ok_or_else(|| partitions_not_found(&[partition_name], &manifest.partitions))?;
+
+    let options = ExtractOptions::default();
+    check_operation_bounds(std::iter::once(partition), &manifest, &payload)?;
+    check_operations_supported(std::slice::from_ref(partition), &options)?;
+
+    let too_large = |size: u64| {
+        PayloadError::InvalidInput(format!(
+            "Partition {} is {}, more than the in-memory limit of {}",
+            partition_name,
+            format_size(size),
+            format_size(max_size)
+        ))
+    };
+    let image_size = planned_image_size(partition, payload.block_size)?;
+    if image_size > max_size {
+        log::error!("Partition {} too large for memory: {} bytes", partition_name, image_size);
+        return Err(too_large(image_size));
+    }
+
+    let mut writer = ImageWriter::new(MemoryImage::new(image_size, max_size));
+    if let Err(e) = apply_operations(&mut payload, partition, &mut writer, &options, None, &mut |_| {}) {
+        // Operations without destination extents have no size known upfront
+        return Err(writer.inner.exceeded.map_or(e, too_large));
+    }
+
+    let image_size = image_size.max(writer.end());
+    let (image, streamed_hash) = writer.finish(image_size).map_err(|e| {
+        PayloadError::Io(format!("Flush failed for {}: {}", partition_name, e))
+    })?;
+    let mut image = image.data.into_inner();
+    image.resize(image_size as usize, 0);
+
+    let actual_hash = to_hex(&streamed_hash.unwrap_or_else(|| Sha256::digest(&image).to_vec()));
+    match partition.new_partition_info.as_ref().and_then(|info| info.hash.as_deref()) {
+        Some(expected) if to_hex(expected) != actual_hash => log::warn!(
+            "  ✗ Hash mismatch for {}: expected {}, got {}",
+            partition_name,
+            to_hex(expected),
+            actual_hash
+        ),
+        Some(_) => log::info!("  ✓ Hash verified: {}", actual_hash),
+        None => log::warn!("  No hash in manifest for {}, cannot verify", partition_name),
+    }
+
+    log::info!("=== IN-MEMORY PARTITION EXTRACTION COMPLETE: {} bytes ===", image.len());
+
+    Ok(image)
+}
+
+/// Extract the first `max_bytes` of a partition into memory
+///
+/// A cheap preview for reading magic numbers and version headers of large
+/// partitions: only the operations writing into the first `max_bytes` are
+/// decompressed, and output past that point is dropped. Operations are
+/// applied in manifest order, so the prefix is final once the last of them
+/// is done. There is no hash check, the manifest only covers whole images.
+///
+/// # Arguments
+/// * `payload_path` - Path to the payload.bin file
+/// * `partition_name` - Name of the partition to read (e.g. "system")
+/// * `max_bytes` - Number of bytes wanted from the start of the image
+///
+/// # Returns
+/// * `Ok(Vec<u8>)` - The first `max_bytes` of the image, or the whole image
+///   if it is smaller
+/// * `Err(PayloadError::InvalidInput)` - If the partition is not in the payload
+///   or `max_bytes` is larger than `DEFAULT_IN_MEMORY_LIMIT`
+pub fn extract_partition_prefix(payload_path: &str, partition_name: &str, max_bytes: u64) -> Result<Vec<u8>, PayloadError> {
+    if max_bytes > DEFAULT_IN_MEMORY_LIMIT {
+        return Err(PayloadError::InvalidInput(format!(
+            "Prefix of {} is more than the in-memory limit of {}",
+            format_size(max_bytes),
+            format_size(DEFAULT_IN_MEMORY_LIMIT)
+        )));
+    }
+    log::info!("Reading first {} of {} from {}", format_size(max_bytes), partition_name, payload_path);
+
+    let mut payload = open_for_extraction(payload_path, None)?;
+    let manifest = std::mem::take(&mut payload.manifest);
+
+    let partition = manifest
+        .partitions
+        .iter()
+        .find(|p| p.partition_name == partition_name)
+        .ok_or_else(|| partitions_not_found(&[partition_name], &manifest.partitions))?;
+
+    let options = ExtractOptions::default();
+    check_operation_bounds(std::iter::once(partition), &manifest, &payload)?;
+    check_operations_supported(std::slice::from_ref(partition), &options)?;
+
+    // Only sized once the extents are known to be in range
+    let prefix_len = max_bytes.min(planned_image_size(partition, payload.block_size)?);
+
+    let mut writer = ImageWriter::new(PrefixBuffer::new(prefix_len as usize));
+    let mut applied = 0;
+    apply_operations(&mut payload, partition, &mut writer, &options, Some(prefix_len), &mut |_| applied += 1)?;
+
+    log::info!(
+        "Read {} bytes of {} from {} of {} operations",
+        prefix_len,
+        partition_name,
+        applied,
+        partition.operations.len()
+    );
+
+    let (buffer, _) = writer
+        .finish(prefix_len)
+        .map_err(|e| PayloadError::Io(format!("Flush failed for {}: {}", partition_name, e)))?;
+    Ok(buffer.data)
+}
+
+/// In-memory image for `extract_partition_to_vec` that refuses writes
+/// past `limit`
+struct MemoryImage {
+    data: std::io::Cursor<Vec<u8>>,
+    limit: u64,
+    /// End of the first write refused for crossing `limit`
+    exceeded: Option<u64>,
+}
+
+impl MemoryImage {
+    fn new(capacity: u64, limit: u64) -> Self {
+        Self {
+            data: std::io::Cursor::new(Vec::with_capacity(capacity as usize)),
+            limit,
+            exceeded: None,
+        }
+    }
+}
+
+impl std::io::Write for MemoryImage {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let end = self.data.position().saturating_add(buf.len() as u64);
+        if end > self.limit {
+            self.exceeded = Some(end);
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::OutOfMemory,
+                "image exceeds the in-memory limit",
+            ));
+        }
+        self.data.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for MemoryImage {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.data.seek(pos)
+    }
+}
+
+/// Fixed-size in-memory image that keeps what is written below its length
+/// and drops the rest, for `extract_partition_prefix`
+struct PrefixBuffer {
+    data: Vec<u8>,
+    position: u64,
+}
+
+impl PrefixBuffer {
+    fn new(len: usize) -> Self {
+        Self {
+            data: vec![0u8; len],
+            position: 0,
+        }
+    }
+}
+
+impl std::io::Write for PrefixBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let len = self.data.len() as u64;
+        if self.position < len {
+            let start = self.position as usize;
+            let kept = buf.len().min(self.data.len() - start);
+            self.data[start..start + kept].copy_from_slice(&buf[..kept]);
+        }
+        self.position += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Seek for PrefixBuffer {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match pos {
+            SeekFrom::Start(offset) => self.position = offset,
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Unsupported,
+                    "prefix buffers only seek from the start",
+                ))
+            }
+        }
+        Ok(self.position)
+    }
+}
+
+/// Where `write_partition_image` writes an image
+enum ImageOutput<'a> {
+    /// A file at this path, created or truncated by the extractor
+    Path(&'a Path),
+    /// A file opened by the caller (e.g. from a file descriptor). `label` is
+    /// reported as the image path.
+    File { file: File, label: String },
+}
+
+impl ImageOutput<'_> {
+    /// Path reported in the result
+    fn label(&self) -> String {
+        match self {
+            ImageOutput::Path(path) => path.to_string_lossy().to_string(),
+            ImageOutput::File { label, .. } => label.clone(),
+        }
+    }
+
+    /// Open the output for reading and writing, emptied
+    fn open(self) -> std::io::Result<File> {
+        match self {
+            ImageOutput::Path(path) => create_fresh(path),
+            ImageOutput::File { file, .. } => {
+                file.set_len(0)?;
+                Ok(file)
+            }
+        }
+    }
+}
+
+/// Create an empty file at `path`, read- and writable. An existing file is
+/// unlinked first rather than truncated: it may be a hard link shared with
+/// another image (see `ExtractOptions::link_duplicates`), which truncating
+/// would destroy along with it.
+fn create_fresh(path: &Path) -> std::io::Result<File> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    std::fs::OpenOptions::new().read(true).write(true).create_new(true).open(path)
+}
+
+/// Apply all operations of `partition` and write the result to `output`
+///
+/// `on_progress` is called after each operation with the number of bytes it
+/// wrote to the image.
+fn write_partition_image(
+    payload: &mut OpenedPayload,
+    partition: &PartitionUpdate,
+    output: ImageOutput,
+    options: &ExtractOptions,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<ExtractedPartition, PayloadError> {
+    use std::io::BufWriter;
+
+    if options.raw {
+        return write_raw_blobs(payload, partition, output, options, on_progress);
+    }
+
+    let partition_name = &partition.partition_name;
+    let block_size = payload.block_size;
+
+    let output_label = output.label();
+    let output_file_path = match &output {
+        ImageOutput::Path(path) => Some(path.to_path_buf()),
+        ImageOutput::File { .. } => None,
+    };
+    log::info!("  Output: {}", output_label);
+
+    // Create output file; a second handle reads it back for hashing
+    let output_file = output.open().map_err(|e| {
+        PayloadError::Io(format!("Failed to create {}: {}", partition_name, e))
+    })?;
+    let mut image = output_file.try_clone().map_err(|e| {
+        PayloadError::Io(format!("Failed to reopen {}: {}", partition_name, e))
+    })?;
+    let mut writer = ImageWriter::new(BufWriter::with_capacity(options.write_buffer_size, output_file));
+
+    let partition_size = partition
+        .new_partition_info
+        .as_ref()
+        .and_then(|info| info.size)
+        .unwrap_or(0);
+
+    log::info!("  Size: {} ({})", partition_size, format_size(partition_size));
+    log::info!("  Operations: {}", partition.operations.len());
+
+    let warnings = match apply_operations(payload, partition, &mut writer, options, None, on_progress) {
+        Err(PayloadError::Cancelled) => {
+            drop(writer);
+            let removed = match &output_file_path {
+                Some(path) => std::fs::remove_file(path),
+                None => image.set_len(0),
+            };
+            if let Err(e) = removed {
+                log::warn!("  Failed to remove partial {}: {}", output_label, e);
+            }
+            return Err(PayloadError::Cancelled);
+        }
+        applied => applied?,
+    };
+
+    // Flush and sync
+    let image_size = partition_size.max(writer.end());
+    let (output_file, streamed_hash) = writer.finish(image_size).map_err(|e| {
+        PayloadError::Io(format!("Flush failed for {}: {}", partition_name, e))
+    })?;
+
+    // Blocks not covered by any operation are left as zeros up to the
+    // partition size, like update_engine's target partition
+    output_file.get_ref().set_len(image_size).map_err(|e| {
+        PayloadError::Io(format!("Failed to resize {}: {}", partition_name, e))
+    })?;
+    drop(output_file);
+
+    // Get final file size
+    let final_size = image.metadata().map(|m| m.len()).unwrap_or(0);
+
+    log::info!("  ✓ Extracted: {} bytes", final_size);
+
+    // Operations were written out of block order, so the streamed hash does
+    // not describe the image; read it back instead
+    let actual_hash = match streamed_hash {
+        Some(hash) => hash,
+        None => {
+            log::debug!("  Operations out of block order, re-reading image to hash it");
+            hash_region(&mut image, 0, final_size, &mut vec![0u8; STREAM_CHUNK_SIZE])?
+        }
+    };
+
+    // Compare the image hash with the one promised by the manifest
+    let actual_hash = to_hex(&actual_hash);
+    let expected_hash = partition
+        .new_partition_info
+        .as_ref()
+        .and_then(|info| info.hash.as_deref())
+        .map(to_hex);
+    let verified = expected_hash.as_deref() == Some(actual_hash.as_str());
+
+    match &expected_hash {
+        Some(expected) if !verified => log::warn!(
+            "  ✗ Hash mismatch for {}: expected {}, got {}",
+            partition_name,
+            expected,
+            actual_hash
+        ),
+        Some(_) => log::info!("  ✓ Hash verified: {}", actual_hash),
+        None => log::warn!("  No hash in manifest for {}, cannot verify", partition_name),
+    }
+
+    // The hash above describes the raw image; convert only afterwards
+    if let (true, Some(path)) = (options.output_sparse, &output_file_path) {
+        let sparse = sparse::sparsify_in_place(path, block_size as u32)?;
+        log::info!("  ✓ Converted to sparse image: {} bytes", sparse.sparse_size);
+    }
+
+    if options.drop_page_cache {
+        drop_page_cache(&image, &output_label);
+        if let (true, Some(path)) = (options.output_sparse, &output_file_path) {
+            match File::open(path) {
+                Ok(sparse) => drop_page_cache(&sparse, &output_label),
+                Err(e) => log::warn!("  Failed to reopen {} to drop it from the page cache: {}", output_label, e),
+            }
+        }
+    }
+
+    Ok(ExtractedPartition {
+        name: partition_name.clone(),
+        size: final_size,
+        path: output_label,
+        verified,
+        expected_hash,
+        actual_hash,
+        supported: true,
+        unsupported_reason: None,
+        resumed: false,
+        warnings,
+    })
+}
+
+/// Apply all operations of `partition` to `writer`, in manifest order
+///
+/// The loop shared by `write_partition_image` and the in-memory extractions:
+/// `on_progress` is called after each operation with the number of bytes it
+/// wrote to the image, and cancellation is checked between operations.
+///
+/// With `limit`, only the operations writing below that many bytes are
+/// applied and the loop stops after the last of them, for reading a prefix
+/// of the image. `writer` must then start out zero-filled: ZERO and DISCARD
+/// operations are skipped too.
+///
+/// # Returns
+/// * `Ok(Vec<OperationWarning>)` - Operations of unknown types that were
+///   written from their raw data
+/// * `Err(PayloadError)` - The first failure, `Cancelled` if cancelled
+fn apply_operations<W: std::io::Write + Seek>(
+    payload: &mut OpenedPayload,
+    partition: &PartitionUpdate,
+    writer: &mut ImageWriter<W>,
+    options: &ExtractOptions,
+    limit: Option<u64>,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<Vec<OperationWarning>, PayloadError> {
+    let partition_name = &partition.partition_name;
+    let data_offset = payload.data_offset;
+    let block_size = payload.block_size;
+    let payload_file = &mut payload.file;
+
+    // Open the source image only when this partition reads from it
+    let mut source_image = if partition
+        .operations
+        .iter()
+        .any(|op| applies_to_source(op.r#type()))
+    {
+        Some(open_source_image(options, partition_name)?)
+    } else {
+        None
+    };
+
+    // Reused for every operation of this partition
+    let mut chunk_buffer = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut warnings = Vec::new();
+    // REPLACE_XZ output decompressed ahead of time, by operation index
+    let mut decoded_ahead: std::collections::VecDeque<(usize, Vec<u8>)> = Default::default();
+
+    let last = match limit {
+        Some(limit) => partition
+            .operations
+            .iter()
+            .rposition(|operation| writes_below(operation, limit, block_size))
+            .map_or(0, |last| last + 1),
+        None => partition.operations.len(),
+    };
+
+    // Process each operation
+    for (op_idx, operation) in partition.operations.iter().enumerate().take(last) {
+        if options.is_cancelled() {
+            log::warn!("  Extraction cancelled at operation {} of {}", op_idx, partition_name);
+            return Err(PayloadError::Cancelled);
+        }
+
+        if let Some(limit) = limit {
+            if !writes_below(operation, limit, block_size)
+                || matches!(operation.r#type(), OperationType::Zero | OperationType::Discard)
+            {
+                continue;
+            }
+        }
+
+        // Progress made by this operation, in the requested unit
+        let op_bytes = options.progress_unit.operation_bytes(operation, block_size);
+
+        match operation.r#type() {
+            OperationType::SourceCopy => {
+                // Opened above whenever the partition reads from it
+                let source = source_image.as_mut().ok_or_else(|| {
+                    PayloadError::OperationFailed(format!("No source image for {}", partition_name))
+                })?;
+                copy_source_extents(
+                    source,
+                    &operation.src_extents,
+                    block_size,
+                    &mut ExtentWriter::new(&mut *writer, &operation.dst_extents, block_size),
+                )
+                .map_err(|e| {
+                    PayloadError::OperationFailed(format!(
+                        "SOURCE_COPY operation {} of {} failed: {}",
+                        op_idx, partition_name, e
+                    ))
+                })?;
+                on_progress(op_bytes);
+                continue;
+            }
+            op_type @ (OperationType::SourceBsdiff | OperationType::BrotliBsdiff) => {
+                let source = source_image.as_mut().ok_or_else(|| {
+                    PayloadError::OperationFailed(format!("No source image for {}", partition_name))
+                })?;
+                if operation.data_length.unwrap_or(0) == 0 || operation.data_offset.is_none() {
+                    return Err(missing_data_error(partition_name, op_idx, operation));
+                }
+                let patch = read_operation_blob(payload_file, data_offset, partition_name, op_idx, operation, options.verify_operations)?;
+                apply_bsdiff(source, &patch, operation, block_size, &mut ExtentWriter::new(&mut *writer, &operation.dst_extents, block_size))
+                    .map_err(|e| {
+                        PayloadError::OperationFailed(format!(
+                            "{} operation {} of {} failed: {}",
+                            op_type.as_str_name(),
+                            op_idx,
+                            partition_name,
+                            e
+                        ))
+                    })?;
+                on_progress(op_bytes);
+                continue;
+            }
+            OperationType::Zero | OperationType::Discard => {
+                // No blob: the destination blocks are zero (ZERO) or have
+                // unspecified content (DISCARD), which we also write as zeros
+                let length = extents_length(&operation.dst_extents, block_size);
+                let mut dst = ExtentWriter::new(&mut *writer, &operation.dst_extents, block_size);
+                std::io::copy(&mut std::io::repeat(0).take(length), &mut dst).map_err(|e| {
+                    PayloadError::Io(format!("Write failed for {}: {}", partition_name, e))
+                })?;
+                on_progress(op_bytes);
+                continue;
+            }
+            OperationType::ReplaceXz if options.decompress_threads > 1 && operation.data_length.unwrap_or(0) > 0 => {
+                if decoded_ahead.front().is_none_or(|(idx, _)| *idx != op_idx) {
+                    decoded_ahead = decompress_xz_ahead(
+                        payload_file,
+                        data_offset,
+                        partition,
+                        op_idx,
+                        block_size,
+                        options.decompress_threads,
+                        options.verify_operations,
+                    )?;
+                }
+                if let Some((_, data)) = decoded_ahead.pop_front() {
+                    let mut dst = ExtentWriter::new(&mut *writer, &operation.dst_extents, block_size);
+                    std::io::Write::write_all(&mut dst, &data).map_err(|e| {
+                        PayloadError::Io(format!("Write failed for {}: {}", partition_name, e))
+                    })?;
+                    on_progress(op_bytes);
+                    continue;
+                }
+            }
+            _ => {}
+        }
+
+        // Everything else writes its data blob to the destination extents
+        let op_type = operation.r#type();
+        let warning = operation_warning(partition_name, op_idx, operation);
+        if let Some(warning) = &warning {
+            log::warn!("  Operation {} type {} not fully supported, using raw data",
+                      op_idx, warning.operation_type);
+            warnings.push(warning.clone());
+        }
+
+        let (data_offset_in_blob, data_length) = match (operation.data_offset, operation.data_length) {
+            (Some(offset), Some(length)) if length > 0 => (offset, length),
+            // Nothing to write, or a type we don't know that came without data
+            _ if operation.dst_extents.is_empty() || warning.is_some() => {
+                on_progress(op_bytes);
+                continue;
+            }
+            // Rejected by check_operation_bounds before extraction starts;
+            // never leave the blocks silently unwritten
+            _ => return Err(missing_data_error(partition_name, op_idx, operation)),
+        };
+
+        // Verify the compressed blob before decompressing it
+        if options.verify_operations {
+            if let Some(expected) = operation.data_sha256_hash.as_deref() {
+                let actual = hash_region(
+                    payload_file,
+                    data_offset + data_offset_in_blob,
+                    data_length,
+                    &mut chunk_buffer,
+                )
+                .map_err(operation_io_error(partition_name, op_idx, "reading"))?;
+                if actual.as_slice() != expected {
+                    log::error!(
+                        "  ✗ Data hash mismatch in operation {} of {}: expected {}, got {}",
+                        op_idx,
+                        partition_name,
+                        to_hex(expected),
+                        to_hex(&actual)
+                    );
+                    return Err(PayloadError::OperationHashMismatch(
+                        partition_name.clone(),
+                        op_idx,
+                    ));
+                }
+            }
+        }
+
+        // Seek to the operation's data
+        payload_file
+            .seek(SeekFrom::Start(data_offset + data_offset_in_blob))
+            .map_err(operation_io_error(partition_name, op_idx, "seeking to"))?;
+
+        // Stream the blob through the matching decoder in fixed-size
+        // chunks, so memory stays bounded regardless of operation size
+        let blob_reader = (&mut *payload_file).take(data_length);
+        let mut decoder = decoder_for(op_type, blob_reader)?;
+        // Place the output at the operation's destination blocks
+        let mut dst = ExtentWriter::new(&mut *writer, &operation.dst_extents, block_size);
+        let written = copy_chunked(&mut decoder, &mut dst, &mut chunk_buffer, op_type, partition_name, op_idx)?;
+
+        if op_type == OperationType::Replace && written != data_length {
+            return Err(PayloadError::UnexpectedEof(format!(
+                "operation {} of {} ({} of {} bytes)",
+                op_idx, partition_name, written, data_length
+            )));
+        }
+
+        on_progress(op_bytes);
+    }
+
+    Ok(warnings)
+}
+
+/// An operation in the sidecar of a raw blob file, see `ExtractOptions::raw`
+#[derive(Debug, Clone, Serialize)]
+pub struct RawOperation {
+    /// Position of the operation in the partition
+    pub index: usize,
+    /// e.g. "REPLACE_XZ", or "UNKNOWN(42)" for a type this build doesn't know
+    pub operation_type: String,
+    /// Offset of the blob in the raw blob file
+    pub offset: u64,
+    /// Absolute offset of the blob in the payload (None without a blob)
+    pub payload_offset: Option<u64>,
+    /// Length of the blob in bytes (0 without a blob)
+    pub length: u64,
+    /// Hex SHA-256 of the blob from the manifest
+    pub data_sha256_hash: Option<String>,
+    pub src_extents: Vec<BlockRange>,
+    pub dst_extents: Vec<BlockRange>,
+}
+
+/// Sidecar of a raw blob file: the operations whose blobs it holds
+#[derive(Debug, Clone, Serialize)]
+pub struct RawBlobIndex {
+    pub partition: String,
+    pub block_size: u64,
+    /// Size of the partition image the operations produce
+    pub partition_size: u64,
+    pub operations: Vec<RawOperation>,
+}
+
+/// Raw mode: copy the operation blobs of `partition` back to back into
+/// `output`, unprocessed, and describe them in `<output>.ops.json`.
+///
+/// The result's `size` and `actual_hash` describe the blob file; `verified`
+/// is false since no image is produced.
+fn write_raw_blobs(
+    payload: &mut OpenedPayload,
+    partition: &PartitionUpdate,
+    output: ImageOutput,
+    options: &ExtractOptions,
+    on_progress: &mut dyn FnMut(u64),
+) -> Result<ExtractedPartition, PayloadError> {
+    let partition_name = &partition.partition_name;
+    let ImageOutput::Path(blob_path) = output else {
+        return Err(PayloadError::InvalidInput(
+            "Raw blob output is only available when extracting to a directory".to_string(),
+        ));
+    };
+    log::info!("  Raw blobs: {}", blob_path.display());
+
+    let blob_file = create_fresh(blob_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to create {}: {}", blob_path.display(), e)))?;
+    let mut writer = std::io::BufWriter::with_capacity(options.write_buffer_size, blob_file);
+    let write_error = |e: std::io::Error| PayloadError::Io(format!("Write failed for {}: {}", partition_name, e));
+
+    let mut chunk_buffer = vec![0u8; STREAM_CHUNK_SIZE];
+    let mut file_hasher = Sha256::new();
+    let mut written = 0u64;
+    let mut operations = Vec::with_capacity(partition.operations.len());
+
+    for (op_idx, operation) in partition.operations.iter().enumerate() {
+        if options.is_cancelled() {
+            log::warn!("  Extraction cancelled at operation {} of {}", op_idx, partition_name);
+            drop(writer);
+            if let Err(e) = std::fs::remove_file(blob_path) {
+                log::warn!("  Failed to remove partial {}: {}", blob_path.display(), e);
+            }
+            return Err(PayloadError::Cancelled);
+        }
+
+        let length = operation.data_length.unwrap_or(0);
+        let payload_offset = (length > 0).then(|| payload.data_offset + operation.data_offset.unwrap_or(0));
+
+        if let Some(start) = payload_offset {
+            payload
+                .file
+                .seek(SeekFrom::Start(start))
+                .map_err(operation_io_error(partition_name, op_idx, "seeking to"))?;
+            let mut blob = Read::by_ref(&mut payload.file).take(length);
+            let mut blob_hasher = Sha256::new();
+            let mut copied = 0u64;
+            loop {
+                let n = match blob.read(&mut chunk_buffer) {
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    read => read.map_err(operation_io_error(partition_name, op_idx, "reading"))?,
+                };
+                if n == 0 {
+                    break;
+                }
+                blob_hasher.update(&chunk_buffer[..n]);
+                file_hasher.update(&chunk_buffer[..n]);
+                std::io::Write::write_all(&mut writer, &chunk_buffer[..n]).map_err(write_error)?;
+                copied += n as u64;
+            }
+
+            if copied != length {
+                return Err(PayloadError::UnexpectedEof(format!(
+                    "operation {} of {} ({} of {} bytes)",
+                    op_idx, partition_name, copied, length
+                )));
+            }
+            if let (true, Some(expected)) = (options.verify_operations, operation.data_sha256_hash.as_deref()) {
+                if blob_hasher.finalize().as_slice() != expected {
+                    log::error!("  ✗ Data hash mismatch in operation {} of {}", op_idx, partition_name);
+                    return Err(PayloadError::OperationHashMismatch(partition_name.clone(), op_idx));
+                }
+            }
+        }
+
+        operations.push(RawOperation {
+            index: op_idx,
+            operation_type: operation_type_name(operation),
+            offset: written,
+            payload_offset,
+            length,
+            data_sha256_hash: operation.data_sha256_hash.as_deref().map(to_hex),
+            src_extents: operation.src_extents.iter().map(BlockRange::from).collect(),
+            dst_extents: operation.dst_extents.iter().map(BlockRange::from).collect(),
+        });
+        written += length;
+        on_progress(options.progress_unit.operation_bytes(operation, payload.block_size));
+    }
+
+    let blob_file = writer.into_inner().map_err(|e| write_error(e.into_error()))?;
+    blob_file.sync_all().map_err(write_error)?;
+    if options.drop_page_cache {
+        drop_page_cache(&blob_file, &blob_path.display().to_string());
+    }
+
+    let index = RawBlobIndex {
+        partition: partition_name.clone(),
+        block_size: payload.block_size,
+        partition_size: partition
+            .new_partition_info
+            .as_ref()
+            .and_then(|info| info.size)
+            .unwrap_or(0),
+        operations,
+    };
+    let mut index_path = blob_path.as_os_str().to_owned();
+    index_path.push(".ops.json");
+    std::fs::write(&index_path, serde_json::to_vec_pretty(&index)?)
+        .map_err(|e| PayloadError::Io(format!("Failed to write {}: {}", Path::new(&index_path).display(), e)))?;
+
+    log::info!("  ✓ Wrote {} bytes of raw blobs for {}", written, partition_name);
+
+    Ok(ExtractedPartition {
+        name: partition_name.clone(),
+        size: written,
+        path: blob_path.to_string_lossy().to_string(),
+        verified: false,
+        expected_hash: None,
+        actual_hash: to_hex(&file_hasher.finalize()),
+        supported: true,
+        unsupported_reason: None,
+        resumed: false,
+        warnings: Vec::new(),
+    })
+}
+
+/// Output image writer that computes the SHA-256 of the image while writing.
+///
+/// Writes that continue at the end of the hashed prefix extend the hash, and
+/// forward gaps are hashed as zeros since they read back as zeros. Once a
+/// write lands before the hashed prefix the streamed hash is abandoned and
+/// `finish` returns `None`.
+struct ImageWriter<W: std::io::Write + Seek> {
+    inner: W,
+    hasher: Sha256,
+    position: u64,
+    end: u64,
+    hashed_len: u64,
+    in_order: bool,
+}
+
+impl<W: std::io::Write + Seek> ImageWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            position: 0,
+            end: 0,
+            hashed_len: 0,
+            in_order: true,
+        }
+    }
+
+    /// Move the write position to `offset` bytes from the start of the image
+    fn seek_to(&mut self, offset: u64) -> std::io::Result<()> {
+        if offset != self.position {
+            self.inner.seek(SeekFrom::Start(offset))?;
+            self.position = offset;
+        }
+        Ok(())
+    }
+
+    /// Highest offset written so far
+    fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Flush the writer and return the inner writer with the digest of the
+    /// first `image_size` bytes, or `None` if writes were not in block order
+    fn finish(mut self, image_size: u64) -> std::io::Result<(W, Option<Vec<u8>>)> {
+        self.inner.flush()?;
+
+        if !self.in_order || self.hashed_len > image_size {
+            return Ok((self.inner, None));
+        }
+
+        self.hash_zeros(image_size - self.hashed_len);
+        Ok((self.inner, Some(self.hasher.finalize().to_vec())))
+    }
+
+    /// Feed `length` zero bytes into the hash
+    fn hash_zeros(&mut self, mut length: u64) {
+        let zeros = [0u8; 4096];
+        while length > 0 {
+            let n = length.min(zeros.len() as u64) as usize;
+            self.hasher.update(&zeros[..n]);
+            self.hashed_len += n as u64;
+            length -= n as u64;
+        }
+    }
+}
+
+impl<W: std::io::Write + Seek> std::io::Write for ImageWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.in_order {
+            if self.position < self.hashed_len {
+                self.in_order = false;
+            } else if self.position > self.hashed_len {
+                self.hash_zeros(self.position - self.hashed_len);
+            }
+        }
+
+        let written = self.inner.write(buf)?;
+        if self.in_order {
+            self.hasher.update(&buf[..written]);
+            self.hashed_len += written as u64;
+        }
+        self.position += written as u64;
+        self.end = self.end.max(self.position);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writer adapter that places a stream of operation output onto its
+/// destination extents, seeking to each extent's first block in turn.
+///
+/// Operations without destination extents are written at the current position.
+struct ExtentWriter<'a, W: std::io::Write + Seek> {
+    image: &'a mut ImageWriter<W>,
+    extents: &'a [Extent],
+    block_size: u64,
+    next_extent: usize,
+    remaining: u64,
+}
+
+impl<'a, W: std::io::Write + Seek> ExtentWriter<'a, W> {
+    fn new(image: &'a mut ImageWriter<W>, extents: &'a [Extent], block_size: u64) -> Self {
+        Self {
+            image,
+            extents,
+            block_size,
+            next_extent: 0,
+            remaining: 0,
+        }
+    }
+}
+
+impl<W: std::io::Write + Seek> std::io::Write for ExtentWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.extents.is_empty() {
+            return self.image.write(buf);
+        }
+
+        while self.remaining == 0 {
+            let extent = self.extents.get(self.next_extent).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::WriteZero,
+                    "operation data exceeds its destination extents",
+                )
+            })?;
+            self.next_extent += 1;
+            self.image
+                .seek_to(extent.start_block.unwrap_or(0) * self.block_size)?;
+            self.remaining = extent.num_blocks.unwrap_or(0) * self.block_size;
+        }
+
+        let want = self.remaining.min(buf.len() as u64) as usize;
+        let written = self.image.write(&buf[..want])?;
+        self.remaining -= written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.image.flush()
+    }
+}
+
+/// Compute the SHA-256 of `length` bytes of `file` starting at `offset`
+pub(crate) fn hash_region<R: Read + Seek + ?Sized>(
+    file: &mut R,
+    offset: u64,
+    length: u64,
+    buffer: &mut [u8],
+) -> std::io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut hasher = Sha256::new();
+    let mut remaining = length;
+    while remaining > 0 {
+        let want = remaining.min(buffer.len() as u64) as usize;
+        file.read_exact(&mut buffer[..want])?;
+        hasher.update(&buffer[..want]);
+        remaining -= want as u64;
+    }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+/// Encode bytes as a lowercase hex string
+pub(crate) fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// The warning for an operation whose data would be written unprocessed.
+///
+/// prost decodes unknown type values as REPLACE, so the raw value is checked
+/// to tell a real REPLACE from a type added after this build.
+fn operation_warning(partition_name: &str, op_idx: usize, operation: &InstallOperation) -> Option<OperationWarning> {
+    let operation_type = match OperationType::try_from(operation.r#type) {
+        Ok(op_type) if operation_support(op_type) != OperationSupport::Unsupported => return None,
+        _ => operation_type_name(operation),
+    };
+
+    Some(OperationWarning {
+        partition: partition_name.to_string(),
+        operation_index: op_idx,
+        operation_type,
+    })
+}
+
+/// Name of the operation's type, e.g. "REPLACE_XZ", or "UNKNOWN(42)" for a
+/// type this build doesn't know
+fn operation_type_name(operation: &InstallOperation) -> String {
+    match OperationType::try_from(operation.r#type) {
+        Ok(op_type) => op_type.as_str_name().to_string(),
+        Err(_) => format!("UNKNOWN({})", operation.r#type),
+    }
+}
+
+/// Number of operations of each type, keyed by `operation_type_name`
+fn operation_type_counts(operations: &[InstallOperation]) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for operation in operations {
+        *counts.entry(operation_type_name(operation)).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Names in an `operation_type_counts` histogram that this build can't
+/// apply, including types unknown to it
+fn unsupported_operation_types(counts: &BTreeMap<String, usize>) -> Vec<String> {
+    counts
+        .keys()
+        .filter(|name| {
+            all_operation_types()
+                .find(|op_type| op_type.as_str_name() == name.as_str())
+                .is_none_or(|op_type| operation_support(op_type) == OperationSupport::Unsupported)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Warnings for every operation of `partition` that would be written
+/// unprocessed
+fn operation_warnings(partition: &PartitionUpdate) -> Vec<OperationWarning> {
+    partition
+        .operations
+        .iter()
+        .enumerate()
+        .filter_map(|(op_idx, operation)| operation_warning(&partition.partition_name, op_idx, operation))
+        .collect()
+}
+
+/// Whether `partition` is a delta against a source image: it describes the
+/// old partition or reads from it
+fn is_incremental_partition(partition: &PartitionUpdate) -> bool {
+    let has_old_info = partition
+        .old_partition_info
+        .as_ref()
+        .is_some_and(|info| info.size.unwrap_or(0) > 0 || info.hash.is_some());
+    has_old_info
+        || partition
+            .operations
+            .iter()
+            .any(|operation| is_source_operation(operation.r#type()))
+}
+
+/// Whether an operation type reads from the source (old) partition image
+fn is_source_operation(op_type: OperationType) -> bool {
+    matches!(
+        op_type,
+        OperationType::SourceCopy
+            | OperationType::SourceBsdiff
+            | OperationType::BrotliBsdiff
+            | OperationType::Puffdiff
+            | OperationType::Zucchini
+            | OperationType::Lz4diffBsdiff
+            | OperationType::Lz4diffPuffdiff
+    )
+}
+
+/// Whether this build applies `op_type` to the source image: SOURCE_COPY and
+/// the bsdiff patches. Other source operations are rejected up front.
+fn applies_to_source(op_type: OperationType) -> bool {
+    operation_support(op_type) == OperationSupport::Source
+}
+
+/// How far this build supports an operation type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OperationSupport {
+    /// Applied from the payload data alone
+    Full,
+    /// Applied to the source image, given `ExtractOptions::source_dir`
+    Source,
+    /// Not implemented; partitions using it can't be extracted
+    Unsupported,
+}
+
+/// What this build can do with `op_type`. The one place that decides it:
+/// extraction, operation warnings, inspection and `capabilities` all ask here.
+fn operation_support(op_type: OperationType) -> OperationSupport {
+    match op_type {
+        OperationType::Replace
+        | OperationType::ReplaceXz
+        | OperationType::ReplaceBz
+        | OperationType::Zstd
+        | OperationType::ReplaceBrotli
+        | OperationType::Zero
+        | OperationType::Discard => OperationSupport::Full,
+        OperationType::SourceCopy | OperationType::SourceBsdiff | OperationType::BrotliBsdiff => {
+            OperationSupport::Source
+        }
+        OperationType::Move
+        | OperationType::Bsdiff
+        | OperationType::Puffdiff
+        | OperationType::Zucchini
+        | OperationType::Lz4diffBsdiff
+        | OperationType::Lz4diffPuffdiff => OperationSupport::Unsupported,
+    }
+}
+
+/// Every operation type of the manifest format, in numeric order
+fn all_operation_types() -> impl Iterator<Item = OperationType> {
+    (0..=i32::from(u8::MAX)).filter_map(|value| OperationType::try_from(value).ok())
+}
+
+/// Operation types and compression formats this build can apply, see
+/// `capabilities`
+#[derive(Debug, Clone, Serialize)]
+pub struct Capabilities {
+    /// Version of this library
+    pub version: &'static str,
+    /// Operation types applied from the payload data alone
+    pub operations: Vec<&'static str>,
+    /// Operation types of incremental payloads, applied to the source image
+    pub source_operations: Vec<&'static str>,
+    /// Operation types of the manifest format this build can't apply
+    pub unsupported_operations: Vec<&'static str>,
+    /// Compression formats of operation data, e.g. "xz"
+    pub compressions: Vec<String>,
+}
+
+/// List what this build supports, e.g. for a UI to warn about a payload
+/// before extracting it (see also `PayloadInspection::unsupported_operation_types`)
+pub fn capabilities() -> Capabilities {
+    let names = |support: OperationSupport| {
+        all_operation_types()
+            .filter(|op_type| operation_support(*op_type) == support)
+            .map(|op_type| op_type.as_str_name())
+            .collect()
+    };
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        operations: names(OperationSupport::Full),
+        source_operations: names(OperationSupport::Source),
+        unsupported_operations: names(OperationSupport::Unsupported),
+        compressions: all_operation_types()
+            .filter(|op_type| operation_support(*op_type) == OperationSupport::Full)
+            .map(compression_name)
+            .filter(|name| *name != "Raw")
+            .map(str::to_lowercase)
+            .collect(),
+    }
+}
+
+/// Verify that every operation of the given partitions can be applied.
+///
+/// SOURCE_COPY, SOURCE_BSDIFF and BROTLI_BSDIFF are supported when a source
+/// image directory is provided. The other diff-based operations (PUFFDIFF,
+/// ZUCCHINI, ...) and the deprecated in-place MOVE/BSDIFF are rejected with
+/// `OperationFailed`.
+fn check_operations_supported<'a>(
+    partitions: impl IntoIterator<Item = &'a PartitionUpdate>,
+    options: &ExtractOptions,
+) -> Result<(), PayloadError> {
+    partitions
+        .into_iter()
+        .try_for_each(|partition| check_partition_supported(partition, options))
+}
+
+/// Check that every operation's data blob lies within the payload and stays
+/// clear of the payload signature, and that the byte range of every extent
+/// is representable.
+///
+/// The manifest is untrusted input: a corrupt or crafted payload could point
+/// past the end of the file or declare an absurd blob length.
+fn check_operation_bounds<'a>(
+    partitions: impl IntoIterator<Item = &'a PartitionUpdate>,
+    manifest: &DeltaArchiveManifest,
+    payload: &OpenedPayload,
+) -> Result<(), PayloadError> {
+    // Signature blob at the end of the data section, relative to its start
+    let signature_region = match (manifest.signatures_offset, manifest.signatures_size) {
+        (Some(offset), Some(size)) if size > 0 => Some((offset, offset.saturating_add(size))),
+        _ => None,
+    };
+
+    for partition in partitions {
+        for (op_idx, operation) in partition.operations.iter().enumerate() {
+            let extents = operation.dst_extents.iter().chain(&operation.src_extents);
+            if let Some(extent) = extents.clone().find(|extent| extent_end(extent, payload.block_size).is_none()) {
+                log::error!(
+                    "Operation {} of {} has an extent past the u64 range",
+                    op_idx,
+                    partition.partition_name
+                );
+                return Err(PayloadError::InvalidInput(format!(
+                    "Operation {} of partition {} has an out of range extent \
+                     (start_block {}, num_blocks {}, block size {})",
+                    op_idx,
+                    partition.partition_name,
+                    extent.start_block.unwrap_or(0),
+                    extent.num_blocks.unwrap_or(0),
+                    payload.block_size
+                )));
+            }
+
+            let data_length = operation.data_length.unwrap_or(0);
+            let has_data = data_length > 0 && operation.data_offset.is_some();
+            if !has_data && writes_blob(operation) && !operation.dst_extents.is_empty() {
+                log::error!("Operation {} of {} has no data", op_idx, partition.partition_name);
+                return Err(missing_data_error(&partition.partition_name, op_idx, operation));
+            }
+            if data_length == 0 {
+                continue;
+            }
+
+            if data_length > MAX_OPERATION_DATA_LENGTH {
+                log::error!(
+                    "Operation {} of {} declares a {} byte blob",
+                    op_idx,
+                    partition.partition_name,
+                    data_length
+                );
+                return Err(PayloadError::InvalidInput(format!(
+                    "Operation {} of partition {} has data_length {} (max {})",
+                    op_idx, partition.partition_name, data_length, MAX_OPERATION_DATA_LENGTH
+                )));
+            }
+
+            let blob_offset = operation.data_offset.unwrap_or(0);
+            let blob_end = payload
+                .data_offset
+                .checked_add(blob_offset)
+                .and_then(|start| start.checked_add(data_length));
+            if blob_end.is_none_or(|end| end > payload.size) {
+                log::error!(
+                    "Operation {} of {} reads past the end of the payload",
+                    op_idx,
+                    partition.partition_name
+                );
+                return Err(PayloadError::InvalidInput(format!(
+                    "Operation {} of partition {} points outside the payload \
+                     (data_offset {}, data_length {}, payload size {})",
+                    op_idx, partition.partition_name, blob_offset, data_length, payload.size
+                )));
+            }
+
+            // Overflow was ruled out above
+            let relative_end = blob_offset + data_length;
+            if let Some((signature_start, signature_end)) = signature_region {
+                if blob_offset < signature_end && relative_end > signature_start {
+                    log::error!(
+                        "Operation {} of {} reads into the payload signature",
+                        op_idx,
+                        partition.partition_name
+                    );
+                    return Err(PayloadError::InvalidInput(format!(
+                        "Operation {} of partition {} overlaps the payload signature \
+                         (data_offset {}, data_length {}, signatures_offset {}, signatures_size {})",
+                        op_idx,
+                        partition.partition_name,
+                        blob_offset,
+                        data_length,
+                        signature_start,
+                        signature_end - signature_start
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether `operation` writes to a block starting below byte `offset` of the image
+fn writes_below(operation: &InstallOperation, offset: u64, block_size: u64) -> bool {
+    operation
+        .dst_extents
+        .iter()
+        .any(|extent| extent.start_block.unwrap_or(0).saturating_mul(block_size) < offset)
+}
+
+/// Whether `operation` is of a type that writes its data blob to the image
+/// (REPLACE and its compressed variants), and so can't do without one
+fn writes_blob(operation: &InstallOperation) -> bool {
+    matches!(
+        OperationType::try_from(operation.r#type),
+        Ok(OperationType::Replace
+            | OperationType::ReplaceXz
+            | OperationType::ReplaceBz
+            | OperationType::Zstd
+            | OperationType::ReplaceBrotli)
+    )
+}
+
+/// Error for an operation that should carry a data blob but doesn't say where
+/// it is; extracting it anyway would leave its blocks silently unwritten
+fn missing_data_error(partition_name: &str, op_idx: usize, operation: &InstallOperation) -> PayloadError {
+    PayloadError::InvalidInput(format!(
+        "{} operation {} of partition {} has no data (data_offset {:?}, data_length {:?})",
+        operation_type_name(operation),
+        op_idx,
+        partition_name,
+        operation.data_offset,
+        operation.data_length
+    ))
+}
+
+/// `a + b`, or `InvalidInput` if the sum doesn't fit in a u64.
+///
+/// Offsets and sizes come from the untrusted header and manifest, so adding
+/// them up must not be allowed to wrap.
+fn checked_offset(a: u64, b: u64, what: &str) -> Result<u64, PayloadError> {
+    a.checked_add(b).ok_or_else(|| {
+        log::error!("{} overflows: {} + {}", what, a, b);
+        PayloadError::InvalidInput(format!("{} overflows u64 ({} + {})", what, a, b))
+    })
+}
+
+/// Byte offset just past `extent`, `None` if it doesn't fit in a u64
+fn extent_end(extent: &Extent, block_size: u64) -> Option<u64> {
+    extent
+        .start_block
+        .unwrap_or(0)
+        .checked_add(extent.num_blocks.unwrap_or(0))?
+        .checked_mul(block_size)
+}
+
+/// Sum of the sizes of `partitions`, `InvalidInput` on overflow
+fn total_partition_size<'a>(partitions: impl IntoIterator<Item = &'a PartitionUpdate>) -> Result<u64, PayloadError> {
+    partitions
+        .into_iter()
+        .filter_map(|p| p.new_partition_info.as_ref().and_then(|info| info.size))
+        .try_fold(0, |total, size| checked_offset(total, size, "Total partition size"))
+}
+
+/// Check that every operation of `partition` can be applied
+fn check_partition_supported(partition: &PartitionUpdate, options: &ExtractOptions) -> Result<(), PayloadError> {
+    // Blobs are copied as they are, whatever the operation
+    if options.raw {
+        return Ok(());
+    }
+
+    for (op_idx, operation) in partition.operations.iter().enumerate() {
+        let op_type = operation.r#type();
+        match op_type {
+            t if applies_to_source(t) && options.source_dir.is_none() => {
+                log::error!(
+                    "{} in {} but no source image directory given",
+                    op_type.as_str_name(),
+                    partition.partition_name
+                );
+                return Err(PayloadError::OperationFailed(format!(
+                    "Partition {} is incremental ({} at operation {}); \
+                     a source image directory is required",
+                    partition.partition_name,
+                    op_type.as_str_name(),
+                    op_idx
+                )));
+            }
+            t if applies_to_source(t) => {}
+            t if operation_support(t) == OperationSupport::Unsupported => {
+                let kind = if is_source_operation(t) { "incremental operation" } else { "operation" };
+                return Err(PayloadError::OperationFailed(format!(
+                    "Unsupported {} {} at operation {} of partition {}",
+                    kind,
+                    op_type.as_str_name(),
+                    op_idx,
+                    partition.partition_name
+                )));
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Open `<source_dir>/<partition>.img` for the operations that read it
+fn open_source_image(options: &ExtractOptions, partition_name: &str) -> Result<File, PayloadError> {
+    let source_dir = options.source_dir.as_deref().ok_or_else(|| {
+        PayloadError::OperationFailed(format!(
+            "No source image directory given for incremental partition {}",
+            partition_name
+        ))
+    })?;
+
+    let source_path = Path::new(source_dir).join(format!("{}.img", partition_name));
+    log::info!("  Source image: {}", source_path.display());
+
+    File::open(&source_path).map_err(|e| {
+        PayloadError::OperationFailed(format!(
+            "Cannot open source image {}: {}",
+            source_path.display(),
+            e
+        ))
+    })
+}
+
+/// Total length in bytes of a list of extents, saturating at u64::MAX rather
+/// than wrapping to a small length for crafted extents
+fn extents_length(extents: &[Extent], block_size: u64) -> u64 {
+    extents
+        .iter()
+        .map(|extent| extent.num_blocks.unwrap_or(0).saturating_mul(block_size))
+        .fold(0, u64::saturating_add)
+}
+
+/// Copy the blocks described by `extents` from the source image to `writer`
+fn copy_source_extents<W: std::io::Write>(
+    source: &mut File,
+    extents: &[Extent],
+    block_size: u64,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    for extent in extents {
+        let start = extent.start_block.unwrap_or(0) * block_size;
+        let length = extent.num_blocks.unwrap_or(0) * block_size;
+
+        source.seek(SeekFrom::Start(start))?;
+        let copied = std::io::copy(&mut (&mut *source).take(length), writer)?;
+        if copied != length {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("source image ended after {} of {} bytes", copied, length),
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Apply the bsdiff `patch` of a SOURCE_BSDIFF or BROTLI_BSDIFF operation to
+/// its source extents and write the result to `writer`, which places it at
+/// the destination extents
+fn apply_bsdiff<W: std::io::Write>(
+    source: &mut File,
+    patch: &[u8],
+    operation: &InstallOperation,
+    block_size: u64,
+    writer: &mut W,
+) -> std::io::Result<()> {
+    // Both sides are held in memory; update_engine diffs in chunks of a few MiB
+    let old_length = extents_length(&operation.src_extents, block_size);
+    if old_length > MAX_OPERATION_DATA_LENGTH {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("source extents span {} bytes", old_length),
+        ));
+    }
+    let mut old = Vec::with_capacity(old_length as usize);
+    copy_source_extents(source, &operation.src_extents, block_size, &mut old)?;
+
+    let max_size = extents_length(&operation.dst_extents, block_size).min(MAX_OPERATION_DATA_LENGTH);
+    let new = bspatch::apply(&old, patch, max_size)?;
+    writer.write_all(&new)
+}
+
+/// Wrap `reader` in the decompressor for `op_type`.
+///
+/// REPLACE (and any unsupported type) passes the data through unchanged.
+fn decoder_for<'a, R: Read + 'a>(
+    op_type: OperationType,
+    reader: R,
+) -> Result<Box<dyn Read + 'a>, PayloadError> {
+    Ok(match op_type {
+        OperationType::ReplaceXz => Box::new(xz2::read::XzDecoder::new(reader)),
+        OperationType::ReplaceBz => Box::new(bzip2::read::BzDecoder::new(reader)),
+        OperationType::Zstd => Box::new(zstd::stream::read::Decoder::new(reader).map_err(|e| {
+            PayloadError::Io(format!("ZSTD decoder init failed: {}", e))
+        })?),
+        OperationType::ReplaceBrotli => Box::new(brotli::Decompressor::new(reader, STREAM_CHUNK_SIZE)),
+        _ => Box::new(reader),
+    })
+}
+
+/// Read the data blob of an operation into memory, checking it against its
+/// data_sha256_hash with `verify_operations`
+fn read_operation_blob(
+    payload_file: &mut Box<dyn PayloadStream>,
+    data_offset: u64,
+    partition_name: &str,
+    op_idx: usize,
+    operation: &InstallOperation,
+    verify_operations: bool,
+) -> Result<Vec<u8>, PayloadError> {
+    payload_file
+        .seek(SeekFrom::Start(data_offset + operation.data_offset.unwrap_or(0)))
+        .map_err(operation_io_error(partition_name, op_idx, "seeking to"))?;
+    let mut blob = vec![0u8; operation.data_length.unwrap_or(0) as usize];
+    payload_file
+        .read_exact(&mut blob)
+        .map_err(operation_io_error(partition_name, op_idx, "reading"))?;
+
+    if verify_operations {
+        if let Some(expected) = operation.data_sha256_hash.as_deref() {
+            let actual = Sha256::digest(&blob);
+            if actual.as_slice() != expected {
+                log::error!(
+                    "  ✗ Data hash mismatch in operation {} of {}: expected {}, got {}",
+                    op_idx,
+                    partition_name,
+                    to_hex(expected),
+                    to_hex(&actual)
+                );
+                return Err(PayloadError::OperationHashMismatch(partition_name.to_string(), op_idx));
+            }
+        }
+    }
+
+    Ok(blob)
+}
+
+/// Decompress the run of REPLACE_XZ operations starting at `first_op` of
+/// `partition`, up to `threads` of them, each on its own thread.
+///
+/// The blobs are read (and verified, with `verify_operations`) one after
+/// the other from `payload_file`; only decoding runs in parallel. Each
+/// output is bounded by the size of the operation's destination extents, so
+/// a blob that inflates past them fails instead of filling memory.
+///
+/// # Returns
+/// * `Ok(VecDeque)` - The output of each operation with its index, in order
+/// * `Err(PayloadError)` - The first read, hash or decompression failure
+fn decompress_xz_ahead(
+    payload_file: &mut Box<dyn PayloadStream>,
+    data_offset: u64,
+    partition: &PartitionUpdate,
+    first_op: usize,
+    block_size: u64,
+    threads: usize,
+    verify_operations: bool,
+) -> Result<std::collections::VecDeque<(usize, Vec<u8>)>, PayloadError> {
+    let partition_name = &partition.partition_name;
+
+    let mut blobs = Vec::with_capacity(threads);
+    for (op_idx, operation) in partition.operations.iter().enumerate().skip(first_op).take(threads) {
+        let data_length = operation.data_length.unwrap_or(0);
+        if operation.r#type() != OperationType::ReplaceXz || data_length == 0 || operation.dst_extents.is_empty() {
+            break;
+        }
+
+        let blob = read_operation_blob(payload_file, data_offset, partition_name, op_idx, operation, verify_operations)?;
+        blobs.push((op_idx, extents_length(&operation.dst_extents, block_size), blob));
+    }
+
+    let decoded: Vec<Result<(usize, Vec<u8>), PayloadError>> = std::thread::scope(|scope| {
+        let workers: Vec<_> = blobs
+            .iter()
+            .map(|(op_idx, limit, blob)| {
+                scope.spawn(move || {
+                    let mut output = Vec::new();
+                    xz2::read::XzDecoder::new(&blob[..])
+                        .take(limit + 1)
+                        .read_to_end(&mut output)
+                        .map_err(operation_io_error(partition_name, *op_idx, "decompressing XZ data of"))?;
+                    if output.len() as u64 > *limit {
+                        return Err(PayloadError::Io(format!(
+                            "Write failed for {}: operation data exceeds its destination extents",
+                            partition_name
+                        )));
+                    }
+                    Ok((*op_idx, output))
+                })
+            })
+            .collect();
+
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(PayloadError::OperationFailed("XZ decoder thread panicked".to_string())))
+            })
+            .collect()
+    });
+
+    decoded.into_iter().collect()
+}
+
+/// Human-readable name of the compression used by `op_type`, for error messages
+fn compression_name(op_type: OperationType) -> &'static str {
+    match op_type {
+        OperationType::ReplaceXz => "XZ",
+        OperationType::ReplaceBz => "Bzip2",
+        OperationType::Zstd => "ZSTD",
+        OperationType::ReplaceBrotli => "Brotli",
+        _ => "Raw",
+    }
+}
+
+/// Copy everything from `reader` to `writer` through `buffer`.
+///
+/// Returns the number of bytes written. Read failures are reported as
+/// decompression errors of operation `op_idx`, write failures as write errors
+/// for the partition.
+fn copy_chunked<R: Read + ?Sized, W: std::io::Write>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer: &mut [u8],
+    op_type: OperationType,
+    partition_name: &str,
+    op_idx: usize,
+) -> Result<u64, PayloadError> {
+    let mut written: u64 = 0;
+    let step = match compression_name(op_type) {
+        "Raw" => "reading".to_string(),
+        name => format!("decompressing {} data of", name),
+    };
+
+    loop {
+        let n = match reader.read(buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(operation_io_error(partition_name, op_idx, &step)(e)),
+        };
+
+        writer.write_all(&buffer[..n]).map_err(|e| {
+            PayloadError::Io(format!("Write failed for {}: {}", partition_name, e))
+        })?;
+        written += n as u64;
+    }
+
+    Ok(written)
+}
+
+/// `capabilities` as JSON
+pub fn capabilities_json() -> Result<String, PayloadError> {
+    to_json(&capabilities(), true)
+}
+
+/// List partition names and return JSON result: `{"partitions": [...]}`
+pub fn list_partition_names_json(path: &str) -> Result<String, PayloadError> {
+    let names = list_partition_names(path)?;
+
+    serde_json::to_string(&serde_json::json!({ "partitions": names }))
+        .map_err(PayloadError::from)
+}
+
+/// Estimate an extraction and return JSON, see `ExtractionEstimate`
+pub fn estimate_extraction_json(path: &str, bytes_per_second: Option<u64>) -> Result<String, PayloadError> {
+    let estimate = estimate_extraction(path, bytes_per_second)?;
+
+    to_json(&estimate, true)
+}
+
+/// Compare two payloads and return JSON, see `PayloadDiff`
+pub fn diff_payloads_json(path_a: &str, path_b: &str) -> Result<String, PayloadError> {
+    let diff = diff_payloads(path_a, path_b)?;
+
+    to_json(&diff, true)
+}
+
+/// Compute the layout of a payload and return JSON, see `PayloadLayout`
+pub fn inspect_payload_layout_json(path: &str) -> Result<String, PayloadError> {
+    let layout = inspect_payload_layout(path)?;
+
+    to_json(&layout, true)
+}
+
+/// Dump the manifest of a payload and return JSON, see `ManifestDump`
+pub fn dump_manifest_json(payload_path: &str, out_path: &str, decoded: bool) -> Result<String, PayloadError> {
+    let dump = dump_manifest(payload_path, out_path, decoded)?;
+
+    to_json(&dump, true)
+}
+
+/// Extract payload and return JSON result
+pub fn extract_payload_json<F>(
+    payload_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    progress_callback: Option<F>
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    log::info!("extract_payload_json called");
+
+    match extract_payload(payload_path, output_dir, options, progress_callback) {
+        Ok(result) => {
+            to_json(&result, false)
+        }
+        Err(e) => {
+            log::error!("Extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract payload, reporting each completed partition, and return JSON
+/// result
+///
+/// Same output as `extract_payload_json`; see `extract_payload_streaming`.
+pub fn extract_payload_streaming_json<F, C>(
+    payload_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+    on_partition: C,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    C: FnMut(&ExtractedPartition) + Send,
+{
+    log::info!("extract_payload_streaming_json called");
+
+    match extract_payload_streaming(payload_path, output_dir, options, progress_callback, on_partition) {
+        Ok(result) => to_json(&result, false),
+        Err(e) => {
+            log::error!("Extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract into caller-provided files and return JSON result
+///
+/// Same output as `extract_payload_json`.
+pub fn extract_payload_to_files_json<F, O>(
+    payload_path: &str,
+    options: &ExtractOptions,
+    open_output: O,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+    O: FnMut(&str) -> Result<(File, String), PayloadError>,
+{
+    log::info!("extract_payload_to_files_json called");
+
+    match extract_payload_to_files(payload_path, options, open_output, progress_callback) {
+        Ok(result) => to_json(&result, false),
+        Err(e) => {
+            log::error!("Extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract an already opened payload.bin and return JSON result
+pub fn extract_payload_file_json<F>(
+    file: File,
+    output_dir: &str,
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    log::info!("extract_payload_file_json called");
+
+    match extract_payload_file(file, output_dir, options, progress_callback) {
+        Ok(result) => to_json(&result, false),
+        Err(e) => {
+            log::error!("Extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract the payload.bin inside an OTA package zip and return JSON result
+pub fn extract_payload_zip_json<F>(
+    zip_path: &str,
+    output_dir: &str,
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    log::info!("extract_payload_zip_json called");
+
+    match extract_payload_zip(zip_path, output_dir, options, progress_callback) {
+        Ok(result) => to_json(&result, false),
+        Err(e) => {
+            log::error!("Extraction from zip failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract the named partitions and return JSON result
+pub fn extract_payload_selective_json<F>(
+    payload_path: &str,
+    output_dir: &str,
+    names: &[String],
+    options: &ExtractOptions,
+    progress_callback: Option<F>,
+) -> Result<String, PayloadError>
+where
+    F: FnMut(&ProgressUpdate) + Send,
+{
+    log::info!("extract_payload_selective_json called");
+
+    match extract_payload_selective(payload_path, output_dir, names, options, progress_callback) {
+        Ok(result) => {
+            to_json(&result, false)
+        }
+        Err(e) => {
+            log::error!("Selective extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Extract a single partition and return JSON result
+///
+/// The JSON has the same shape as `extract_payload_json`, with a single
+/// entry in `extracted`.
+pub fn extract_partition_json(
+    payload_path: &str,
+    output_dir: &str,
+    partition_name: &str,
+) -> Result<String, PayloadError> {
+    log::info!("extract_partition_json called");
+
+    match extract_partition(payload_path, output_dir, partition_name) {
+        Ok(partition) => {
+            let result = ExtractionResult::new("success", vec![partition], Vec::new());
+            to_json(&result, false)
+        }
+        Err(e) => {
+            log::error!("Partition extraction failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::InstallOperation;
+
+    /// Write a version 2 payload consisting of `manifest` followed by `blob`
+    fn write_test_payload(dir: &Path, manifest: &DeltaArchiveManifest, blob: &[u8]) -> String {
+        let manifest_bytes = manifest.encode_to_vec();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(PAYLOAD_MAGIC);
+        data.extend_from_slice(&2u64.to_be_bytes());
+        data.extend_from_slice(&(manifest_bytes.len() as u64).to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(&manifest_bytes);
+        data.extend_from_slice(blob);
+
+        let path = dir.join("payload.bin");
+        std::fs::write(&path, data).unwrap();
+        path.to_string_lossy().to_string()
+    }
+
+    /// Package a payload written by `write_test_payload` into an OTA zip
+    fn write_test_zip(dir: &Path, payload_path: &str, method: zip::CompressionMethod) -> String {
+        use std::io::Write;
+
+        let path = dir.join(format!("ota-{:?}.zip", method));
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(method);
+
+        zip.start_file("META-INF/com/android/metadata", options).unwrap();
+        zip.write_all(b"ota-type=AB\npost-build=google/oriole/oriole:14/UQ1A/1:user/release-keys\n").unwrap();
+        zip.start_file("payload_properties.txt", options).unwrap();
+        zip.write_all(b"FILE_SIZE=1234\nMETADATA_SIZE=56\n").unwrap();
+        zip.start_file("payload.bin", options).unwrap();
+        zip.write_all(&std::fs::read(payload_path).unwrap()).unwrap();
+        zip.finish().unwrap();
+
+        path.to_string_lossy().to_string()
+    }
+
+    fn extent(start_block: u64, num_blocks: u64) -> Extent {
+        Extent {
+            start_block: Some(start_block),
+            num_blocks: Some(num_blocks),
+        }
+    }
+
+    fn operation(op_type: OperationType, data: Option<(u64, u64)>, dst_extents: Vec<Extent>) -> InstallOperation {
+        InstallOperation {
+            r#type: op_type as i32,
+            data_offset: data.map(|(offset, _)| offset),
+            data_length: data.map(|(_, length)| length),
+            dst_extents,
+            ..Default::default()
+        }
+    }
+
+    fn partition(name: &str, size: u64, operations: Vec<InstallOperation>) -> PartitionUpdate {
+        PartitionUpdate {
+            partition_name: name.to_string(),
+            new_partition_info: Some(crate::proto::PartitionInfo {
+                size: Some(size),
+                hash: None,
+            }),
+            operations,
+            ..Default::default()
+        }
+    }
+
+    fn manifest(block_size: u32, partitions: Vec<PartitionUpdate>) -> DeltaArchiveManifest {
+        DeltaArchiveManifest {
+            block_size: Some(block_size),
+            partitions,
+            ..Default::default()
+        }
+    }
+
+    fn no_progress() -> Option<fn(&ProgressUpdate)> {
+        None
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1024), "1.00 KB");
+        assert_eq!(format_size(1536), "1.50 KB");
+        assert_eq!(format_size(1048576), "1.00 MB");
+        assert_eq!(format_size(1073741824), "1.00 GB");
+    }
+
+    #[test]
+    fn test_empty_path_error() {
+        let result = inspect_payload("");
+        assert!(result.is_err());
+        if let Err(PayloadError::EmptyPath) = result {
+            // Expected
+        } else {
+            panic!("Expected EmptyPath error");
+        }
+    }
+
+    #[test]
+    fn test_nonexistent_file_error() {
+        let result = inspect_payload("/nonexistent/path/to/file.bin");
+        assert!(result.is_err());
+        if let Err(PayloadError::FileNotFound(_)) = result {
+            // Expected
+        } else {
+            panic!("Expected FileNotFound error");
+        }
+    }
+
+    #[test]
+    fn test_big_endian_version() {
+        // Version 2 in big endian: 0x00 0x00 0x00 0x00 0x00 0x00 0x00 0x02
+        let version_bytes: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
+        let version = u64::from_be_bytes(version_bytes);
+        assert_eq!(version, 2);
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let original: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let compressed = zstd::encode_all(&original[..], 3).unwrap();
+        assert!(compressed.len() < original.len());
+
+        let mut decompressed = Vec::new();
+        decoder_for(OperationType::Zstd, &compressed[..])
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, original);
+    }
+
+    #[test]
+    fn test_brotli_round_trip() {
+        use std::io::Write;
+
+        let original: Vec<u8> = (0..64 * 1024).map(|i| (i % 251) as u8).collect();
+        let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 9, 22);
+        encoder.write_all(&original).unwrap();
+        let compressed = encoder.into_inner();
+        assert!(compressed.len() < original.len());
+
+        let mut decompressed = Vec::new();
+        decoder_for(OperationType::ReplaceBrotli, &compressed[..])
+            .unwrap()
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, original);
+
+        // Through a full extraction, as a REPLACE_BROTLI operation
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "odm",
+                    original.len() as u64,
+                    vec![operation(
+                        OperationType::ReplaceBrotli,
+                        Some((0, compressed.len() as u64)),
+                        vec![extent(0, 16)],
+                    )],
+                )],
+            ),
+            &compressed,
+        );
+        let out_dir = dir.path().join("out");
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert!(result.warnings.is_empty());
+        assert_eq!(std::fs::read(out_dir.join("odm.img")).unwrap(), original);
+
+        // Corrupt data is an error, not raw bytes in the image
+        assert!(decoder_for(OperationType::ReplaceBrotli, &b"not brotli at all"[..])
+            .unwrap()
+            .read_to_end(&mut Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_unsupported_delta_operation_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut op = operation(OperationType::Puffdiff, Some((0, 4)), vec![extent(0, 1)]);
+        op.src_extents = vec![extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("system", 4096, vec![op])]),
+            &[0u8; 4],
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &ExtractOptions::default(),
+            no_progress(),
+        );
+        match result {
+            Err(PayloadError::OperationFailed(msg)) => assert!(msg.contains("PUFFDIFF")),
+            other => panic!("Expected OperationFailed, got {:?}", other),
+        }
+        assert!(!out_dir.join("system.img").exists());
+    }
+
+    #[test]
+    fn test_source_copy_reads_source_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let block_size = 512u64;
+
+        // Source image: 4 blocks filled with the block index
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir(&source_dir).unwrap();
+        let source: Vec<u8> = (0..4u8).flat_map(|b| vec![b; block_size as usize]).collect();
+        std::fs::write(source_dir.join("boot.img"), &source).unwrap();
+
+        // Copy blocks 2..4 then block 0
+        let mut op = operation(OperationType::SourceCopy, None, vec![extent(0, 3)]);
+        op.src_extents = vec![extent(2, 2), extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(block_size as u32, vec![partition("boot", 3 * block_size, vec![op])]),
+            &[],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            source_dir: Some(source_dir.to_string_lossy().to_string()),
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 1);
+
+        let output = std::fs::read(out_dir.join("boot.img")).unwrap();
+        let expected: Vec<u8> = [2u8, 3, 0].iter().flat_map(|&b| vec![b; block_size as usize]).collect();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn test_extracted_hash_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let good: Vec<u8> = vec![0xAB; 4096];
+        let bad: Vec<u8> = vec![0xCD; 4096];
+        let blob = [good.clone(), bad.clone()].concat();
+
+        let mut good_partition = partition(
+            "boot",
+            4096,
+            vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+        );
+        good_partition.new_partition_info.as_mut().unwrap().hash = Some(Sha256::digest(&good).to_vec());
+
+        // Manifest claims the hash of `good` but the blob holds `bad`
+        let mut bad_partition = partition(
+            "dtbo",
+            4096,
+            vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+        );
+        bad_partition.new_partition_info.as_mut().unwrap().hash = Some(Sha256::digest(&good).to_vec());
+
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![good_partition, bad_partition]),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &ExtractOptions::default(),
+            no_progress(),
+        )
+        .unwrap();
+
+        assert_eq!(result.extracted.len(), 2);
+        assert!(result.extracted[0].verified);
+        assert_eq!(result.extracted[0].actual_hash, to_hex(&Sha256::digest(&good)));
+        assert!(!result.extracted[1].verified);
+        assert_eq!(result.extracted[1].actual_hash, to_hex(&Sha256::digest(&bad)));
+        assert_eq!(result.extracted[1].expected_hash, Some(to_hex(&Sha256::digest(&good))));
+    }
+
+    #[test]
+    fn test_cancelled_extraction_removes_partial_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[0u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            cancel: Some(Arc::new(AtomicBool::new(true))),
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress());
+
+        match result {
+            Err(PayloadError::Cancelled) => {}
+            other => panic!("Expected cancellation, got {:?}", other),
+        }
+        assert!(!out_dir.join("system.img").exists());
+    }
+
+    #[test]
+    fn test_extract_single_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        let extracted = extract_partition(&payload, out_dir.to_str().unwrap(), "boot").unwrap();
+        assert_eq!(extracted.name, "boot");
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![2u8; 4096]);
+        assert!(!out_dir.join("system.img").exists());
+
+        match extract_partition(&payload, out_dir.to_str().unwrap(), "vendor") {
+            Err(PayloadError::InvalidInput(msg)) => {
+                assert!(msg.contains("'vendor'") && msg.ends_with("Available partitions: boot, system"))
+            }
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
+
+        // Selecting only unknown names is an error too, not an empty success
+        let options = ExtractOptions {
+            partitions: Some(vec!["vendor".to_string(), "vendor_boot".to_string()]),
+            ..Default::default()
+        };
+        match extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()) {
+            Err(PayloadError::InvalidInput(msg)) => assert_eq!(
+                msg,
+                "Partition 'vendor', 'vendor_boot' not found. Available partitions: boot, system"
+            ),
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_selective_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 8192], vec![3u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "boot",
+                        8192,
+                        vec![operation(OperationType::Replace, Some((4096, 8192)), vec![extent(0, 2)])],
+                    ),
+                    partition(
+                        "dtbo",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((12288, 4096)), vec![extent(0, 1)])],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        let names = vec!["dtbo".to_string(), "boot".to_string(), "vendor_boot".to_string()];
+        let mut totals = Vec::new();
+        let result = extract_payload_selective(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &names,
+            &ExtractOptions::default(),
+            Some(|update: &ProgressUpdate| totals.push((update.current_file.to_string(), update.total_bytes))),
+        )
+        .unwrap();
+
+        let extracted: Vec<&str> = result.extracted.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(extracted, vec!["boot", "dtbo"]);
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(result.skipped[0].name, "vendor_boot");
+        assert_eq!(totals[0], (PARSING_MANIFEST.to_string(), 0));
+        assert!(totals[1..].iter().all(|(_, total)| *total == 12288));
+        assert!(!out_dir.join("system.img").exists());
+    }
+
+    #[test]
+    fn test_exclude_partitions_by_pattern() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = vec![7u8; 4096];
+        let parts = ["system", "vendor_boot", "userdata", "vendor_dlkm"]
+            .iter()
+            .map(|name| partition(name, 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]))
+            .collect();
+        let payload = write_test_payload(dir.path(), &manifest(4096, parts), &blob);
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            exclude: vec!["userdata".to_string(), "vendor_*".to_string()],
+            ..Default::default()
+        };
+        let mut totals = Vec::new();
+        let result = extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &options,
+            Some(|update: &ProgressUpdate| totals.push(update.total_bytes)),
+        )
+        .unwrap();
+
+        let extracted: Vec<&str> = result.extracted.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(extracted, vec!["system"]);
+        assert_eq!(result.excluded, vec!["vendor_boot", "userdata", "vendor_dlkm"]);
+        assert!(totals[1..].iter().all(|total| *total == 4096));
+        assert!(!out_dir.join("userdata.img").exists());
+
+        let options = ExtractOptions {
+            exclude: vec!["*".to_string()],
+            force: true,
+            ..Default::default()
+        };
+        assert!(matches!(
+            extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+
+        assert!(matches_pattern("vendor_*", "vendor_"));
+        assert!(matches_pattern("*_a", "system_ext_a"));
+        assert!(matches_pattern("s?stem*", "system_ext"));
+        assert!(matches_pattern("*dlkm*", "vendor_dlkm_a"));
+        assert!(!matches_pattern("vendor_*", "odm_vendor"));
+        assert!(!matches_pattern("boot", "boot_a"));
+        assert!(!matches_pattern("?boot", "boot"));
+    }
+
+    #[test]
+    fn test_streams_compressed_operations() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+        // Larger than one stream chunk so the copy loop runs more than once
+        let image: Vec<u8> = (0..(STREAM_CHUNK_SIZE + 8192)).map(|i| (i % 253) as u8).collect();
+
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&image).unwrap();
+        let xz_data = xz.finish().unwrap();
+        let mut bz = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bz.write_all(&image).unwrap();
+        let bz_data = bz.finish().unwrap();
+
+        let blocks = image.len() as u64 / 4096;
+        let blob = [xz_data.clone(), bz_data.clone()].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        image.len() as u64,
+                        vec![operation(
+                            OperationType::ReplaceXz,
+                            Some((0, xz_data.len() as u64)),
+                            vec![extent(0, blocks)],
+                        )],
+                    ),
+                    partition(
+                        "vendor",
+                        image.len() as u64,
+                        vec![operation(
+                            OperationType::ReplaceBz,
+                            Some((xz_data.len() as u64, bz_data.len() as u64)),
+                            vec![extent(0, blocks)],
+                        )],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), image);
+        assert_eq!(std::fs::read(out_dir.join("vendor.img")).unwrap(), image);
+    }
+
+    #[test]
+    fn test_extraction_opens_payload_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[7u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        PAYLOAD_OPENS.with(|opens| opens.set(0));
+        extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(PAYLOAD_OPENS.with(|opens| opens.get()), 1);
+    }
+
+    #[test]
+    fn test_operation_hash_verification() {
+        let dir = tempfile::tempdir().unwrap();
+        let data = vec![5u8; 4096];
+
+        let mut good_op = operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]);
+        good_op.data_sha256_hash = Some(Sha256::digest(&data).to_vec());
+        let mut bad_op = operation(OperationType::Replace, Some((4096, 4096)), vec![extent(1, 1)]);
+        bad_op.data_sha256_hash = Some(Sha256::digest(b"something else").to_vec());
+
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("vendor", 8192, vec![good_op, bad_op])]),
+            &[data.clone(), data].concat(),
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            verify_operations: true,
+            ..Default::default()
+        };
+        match extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()) {
+            Err(PayloadError::OperationHashMismatch(name, op_idx)) => {
+                assert_eq!(name, "vendor");
+                assert_eq!(op_idx, 1);
+            }
+            other => panic!("Expected OperationHashMismatch, got {:?}", other),
+        }
+
+        // Without verification the blob is written as-is
+        extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+    }
+
+    #[test]
+    fn test_zero_operation_writes_zeros() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "product",
+                    5 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Zero, None, vec![extent(1, 2)]),
+                        operation(OperationType::Discard, None, vec![extent(3, 1)]),
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(4, 1)]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(result.extracted[0].size, 5 * 4096);
+
+        let expected = [vec![1u8; 4096], vec![0u8; 3 * 4096], vec![2u8; 4096]].concat();
+        assert_eq!(std::fs::read(out_dir.join("product.img")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_operations_placed_at_dst_extents() {
+        let dir = tempfile::tempdir().unwrap();
+        // First operation covers blocks 2 and 0, second one covers block 1;
+        // block 3 is not written by any operation
+        let blob = [vec![3u8; 4096], vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let expected = [vec![1u8; 4096], vec![2u8; 4096], vec![3u8; 4096], vec![0u8; 4096]].concat();
+
+        let mut product = partition(
+            "product",
+            4 * 4096,
+            vec![
+                operation(OperationType::Replace, Some((0, 2 * 4096)), vec![extent(2, 1), extent(0, 1)]),
+                operation(OperationType::Replace, Some((2 * 4096, 4096)), vec![extent(1, 1)]),
+            ],
+        );
+        product.new_partition_info.as_mut().unwrap().hash = Some(Sha256::digest(&expected).to_vec());
+
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![product]), &blob);
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(result.extracted[0].size, 4 * 4096);
+        assert!(result.extracted[0].verified);
+        assert_eq!(std::fs::read(out_dir.join("product.img")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_inspection_reports_partition_hashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut boot = partition(
+            "boot",
+            3 * 4096,
+            vec![
+                operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                operation(OperationType::Zero, None, vec![extent(1, 2)]),
+            ],
+        );
+        boot.new_partition_info.as_mut().unwrap().hash = Some(vec![0xAB; 32]);
+        boot.old_partition_info = Some(crate::proto::PartitionInfo {
+            size: Some(2 * 4096),
+            hash: Some(vec![0xCD; 32]),
+        });
+        let system = partition("system", 4096, vec![]);
+        let empty = partition("empty", 0, vec![]);
+
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![boot, system, empty]), &[0u8; 4096]);
+        let inspection = inspect_payload(&payload).unwrap();
+
+        let boot = &inspection.partitions[0];
+        assert_eq!(boot.hash.as_deref(), Some("ab".repeat(32).as_str()));
+        assert_eq!(boot.old_size, Some(2 * 4096));
+        assert_eq!(boot.old_hash.as_deref(), Some("cd".repeat(32).as_str()));
+        assert_eq!(boot.total_blocks, 3);
+        assert_eq!(boot.compressed_size, 4096);
+        assert_eq!(boot.compression_ratio, Some(1.0 / 3.0));
+
+        // Sorted by name: boot, empty, system
+        assert_eq!(inspection.partitions[1].compression_ratio, None);
+
+        let system = &inspection.partitions[2];
+        assert_eq!(system.hash, None);
+        assert_eq!(system.old_size, None);
+        assert_eq!(system.total_blocks, 0);
+        assert_eq!(system.compressed_size, 0);
+        assert_eq!(system.compression_ratio, Some(0.0));
+        assert!(inspection.dynamic_partitions.is_none());
+        assert!(inspection.apex_packages.is_empty());
+    }
+
+    #[test]
+    fn test_inspection_reports_apex_packages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = manifest(4096, vec![]);
+        manifest.apex_info = vec![
+            crate::proto::ApexInfo {
+                package_name: Some("com.android.tzdata".to_string()),
+                version: Some(340090000),
+                is_compressed: Some(true),
+                decompressed_size: Some(1_048_576),
+            },
+            crate::proto::ApexInfo {
+                package_name: Some("com.android.art".to_string()),
+                version: Some(341311000),
+                ..Default::default()
+            },
+        ];
+
+        let payload = write_test_payload(dir.path(), &manifest, &[]);
+        let apex = inspect_payload(&payload).unwrap().apex_packages;
+
+        assert_eq!(apex.len(), 2);
+        assert_eq!(apex[0].name.as_deref(), Some("com.android.tzdata"));
+        assert_eq!(apex[0].version, Some(340090000));
+        assert!(apex[0].is_compressed);
+        assert_eq!(apex[0].decompressed_size, Some(1_048_576));
+        assert!(!apex[1].is_compressed);
+        assert_eq!(apex[1].decompressed_size, None);
+    }
+
+    #[test]
+    fn test_inspection_counts_operation_types() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut unknown = operation(OperationType::Replace, None, vec![extent(2, 1)]);
+        unknown.r#type = 42;
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        3 * 4096,
+                        vec![
+                            operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                            operation(OperationType::Zero, None, vec![extent(1, 1)]),
+                            unknown,
+                        ],
+                    ),
+                    partition("vendor", 4096, vec![operation(OperationType::Zero, None, vec![extent(0, 1)])]),
+                ],
+            ),
+            &[0u8; 4096],
+        );
+
+        let inspection = inspect_payload(&payload).unwrap();
+        let counts = |pairs: &[(&str, usize)]| -> BTreeMap<String, usize> {
+            pairs.iter().map(|(name, count)| (name.to_string(), *count)).collect()
+        };
+        assert_eq!(
+            inspection.partitions[0].operation_types,
+            counts(&[("REPLACE", 1), ("UNKNOWN(42)", 1), ("ZERO", 1)])
+        );
+        assert_eq!(inspection.partitions[1].operation_types, counts(&[("ZERO", 1)]));
+        assert_eq!(
+            inspection.operation_types,
+            counts(&[("REPLACE", 1), ("UNKNOWN(42)", 1), ("ZERO", 2)])
+        );
+        assert_eq!(inspection.unsupported_operation_types, ["UNKNOWN(42)"]);
+        assert_eq!(
+            unsupported_operation_types(&counts(&[("PUFFDIFF", 1), ("SOURCE_COPY", 2), ("ZSTD", 1)])),
+            ["PUFFDIFF"]
+        );
+    }
+
+    #[test]
+    fn test_inspection_reports_counts() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        3 * 4096,
+                        vec![
+                            operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                            operation(OperationType::Zero, None, vec![extent(1, 1)]),
+                            operation(OperationType::Discard, None, vec![extent(2, 1)]),
+                        ],
+                    ),
+                    partition("vendor", 4096, vec![operation(OperationType::Zero, None, vec![extent(0, 1)])]),
+                    partition("odm", 0, vec![]),
+                ],
+            ),
+            &[0u8; 4096],
+        );
+        let zip_path = write_test_zip(dir.path(), &payload, zip::CompressionMethod::Stored);
+
+        // The same counts whichever way the payload is read
+        for inspection in [
+            inspect_payload(&payload).unwrap(),
+            inspect_payload_bytes(&std::fs::read(&payload).unwrap()).unwrap(),
+            inspect_payload_zip(&zip_path).unwrap(),
+        ] {
+            assert_eq!(inspection.partition_count, 3);
+            assert_eq!(inspection.partition_count, inspection.partitions.len());
+            assert_eq!(inspection.total_operations, 4);
+            let per_partition: usize = inspection.partitions.iter().map(|p| p.operations_count).sum();
+            assert_eq!(inspection.total_operations, per_partition);
+        }
+
+        let json: serde_json::Value = serde_json::from_str(&inspect_payload_json(&payload).unwrap()).unwrap();
+        assert_eq!(json["partition_count"], 3);
+        assert_eq!(json["total_operations"], 4);
+
+        let empty = write_test_payload(dir.path(), &manifest(4096, vec![]), &[]);
+        let inspection = inspect_payload(&empty).unwrap();
+        assert_eq!((inspection.partition_count, inspection.total_operations), (0, 0));
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let capabilities = capabilities();
+        assert_eq!(
+            capabilities.operations,
+            ["REPLACE", "REPLACE_BZ", "ZERO", "DISCARD", "REPLACE_XZ", "ZSTD", "REPLACE_BROTLI"]
+        );
+        assert_eq!(capabilities.source_operations, ["SOURCE_COPY", "SOURCE_BSDIFF", "BROTLI_BSDIFF"]);
+        assert!(capabilities.unsupported_operations.contains(&"PUFFDIFF"));
+        assert_eq!(capabilities.compressions, ["bzip2", "xz", "zstd", "brotli"]);
+
+        // Every type of the format is listed exactly once
+        let listed = capabilities.operations.len()
+            + capabilities.source_operations.len()
+            + capabilities.unsupported_operations.len();
+        assert_eq!(listed, all_operation_types().count());
+        assert_eq!(all_operation_types().count(), 16);
+
+        let json: serde_json::Value = serde_json::from_str(&capabilities_json().unwrap()).unwrap();
+        assert_eq!(json["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn test_inspection_reports_verity() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut system = partition("system", 8 * 4096, vec![]);
+        system.hash_tree_data_extent = Some(extent(0, 6));
+        system.hash_tree_extent = Some(extent(6, 1));
+        system.hash_tree_algorithm = Some("sha256".to_string());
+        system.hash_tree_salt = Some(vec![0xab, 0xcd]);
+        system.fec_data_extent = Some(extent(0, 7));
+        system.fec_extent = Some(extent(7, 1));
+        system.fec_roots = Some(2);
+
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("boot", 4096, vec![]), system]),
+            &[],
+        );
+        let inspection = inspect_payload(&payload).unwrap();
+
+        assert!(inspection.partitions[0].verity.is_none());
+        let verity = inspection.partitions[1].verity.as_ref().unwrap();
+        assert_eq!(verity.hash_tree_algorithm.as_deref(), Some("sha256"));
+        assert_eq!(verity.hash_tree_salt.as_deref(), Some("abcd"));
+        assert_eq!(verity.hash_tree_extent.map(|r| (r.start_block, r.num_blocks)), Some((6, 1)));
+        assert!(verity.has_fec);
+        assert_eq!(verity.fec_roots, Some(2));
+
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert!(json["partitions"][0]["verity"].is_null());
+        assert_eq!(json["partitions"][1]["verity"]["fec_extent"]["start_block"], 7);
+    }
+
+    #[test]
+    fn test_inspection_reports_build_info() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut boot = partition("boot", 4096, vec![]);
+        boot.version = Some("1700000000".to_string());
+        let mut manifest = manifest(4096, vec![boot, partition("system", 4096, vec![])]);
+        manifest.max_timestamp = Some(1_700_000_000);
+        manifest.minor_version = Some(0);
+
+        let payload = write_test_payload(dir.path(), &manifest, &[]);
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!(inspection.max_timestamp, Some(1_700_000_000));
+        assert_eq!(inspection.max_timestamp_utc.as_deref(), Some("2023-11-14T22:13:20Z"));
+        assert_eq!(inspection.minor_version, Some(0));
+        assert_eq!(inspection.partitions[0].version.as_deref(), Some("1700000000"));
+        assert_eq!(inspection.partitions[1].version, None);
+        assert_eq!(inspection.build_fingerprint, None);
+        assert_eq!(inspection.ota_downgrade, None);
+        // Flashing it over a newer build is refused
+        assert_eq!(inspection.rollback_blocked(1_700_000_001), Some(true));
+        assert_eq!(inspection.rollback_blocked(1_700_000_000), Some(false));
+
+        // Unpacked OTA package: the fingerprint comes from its metadata
+        let metadata = dir.path().join("META-INF/com/android/metadata");
+        std::fs::create_dir_all(metadata.parent().unwrap()).unwrap();
+        std::fs::write(&metadata, "ota-type=AB\npre-build=old/fp\npost-build=new/fp\n").unwrap();
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!(inspection.build_fingerprint.as_deref(), Some("new/fp"));
+        assert_eq!(inspection.ota_downgrade, Some(false));
+
+        // Downgrade packages are let through
+        std::fs::write(&metadata, "ota-downgrade=yes\npost-build=old/fp\n").unwrap();
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!(inspection.ota_downgrade, Some(true));
+        assert_eq!(inspection.rollback_blocked(1_800_000_000), Some(false));
+
+        let json = serde_json::to_value(inspect_payload_bytes(&std::fs::read(&payload).unwrap()).unwrap()).unwrap();
+        assert!(json["ota_downgrade"].is_null());
+        assert_eq!(format_utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(format_utc_timestamp(-1), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_inspection_reports_rollback_info() {
+        use std::io::Write;
+
+        let dir = tempfile::tempdir().unwrap();
+
+        // No max_timestamp: nothing to report and no rollback check
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![]), &[]);
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!((inspection.max_timestamp, inspection.max_timestamp_utc.as_deref()), (None, None));
+        assert_eq!(inspection.rollback_blocked(i64::MAX), None);
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert!(json["max_timestamp"].is_null() && json["max_timestamp_utc"].is_null());
+        assert!(json["ota_downgrade"].is_null());
+
+        let payload = write_test_payload(
+            dir.path(),
+            &DeltaArchiveManifest {
+                max_timestamp: Some(1_704_067_200),
+                ..manifest(4096, vec![])
+            },
+            &[],
+        );
+
+        // OTA metadata read from the package zip
+        let zip_path = write_test_zip(dir.path(), &payload, zip::CompressionMethod::Stored);
+        let inspection = inspect_payload_zip(&zip_path).unwrap();
+        assert_eq!(inspection.max_timestamp_utc.as_deref(), Some("2024-01-01T00:00:00Z"));
+        assert_eq!(inspection.ota_downgrade, Some(false));
+        assert_eq!(inspection.rollback_blocked(1_704_067_201), Some(true));
+
+        let downgrade_zip = dir.path().join("downgrade.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&downgrade_zip).unwrap());
+        zip.start_file("META-INF/com/android/metadata", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"ota-type=AB\nota-downgrade = yes\n").unwrap();
+        zip.start_file("payload.bin", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(&std::fs::read(&payload).unwrap()).unwrap();
+        zip.finish().unwrap();
+        let inspection = inspect_payload_zip(downgrade_zip.to_str().unwrap()).unwrap();
+        assert_eq!(inspection.ota_downgrade, Some(true));
+        assert_eq!(inspection.rollback_blocked(1_704_067_201), Some(false));
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert_eq!(json["max_timestamp"], 1_704_067_200);
+        assert_eq!(json["max_timestamp_utc"], "2024-01-01T00:00:00Z");
+        assert_eq!(json["ota_downgrade"], true);
+
+        // Only an exact ota-downgrade=yes counts
+        assert!(!ota_zip::is_downgrade("ota-downgrade=no\n"));
+        assert!(!ota_zip::is_downgrade("pre-ota-downgrade=yes\n"));
+        assert!(!ota_zip::is_downgrade(""));
+        assert_eq!(format_utc_timestamp(253_402_300_799), "9999-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_inspect_payloads_reports_each_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 4096, vec![])]), &[]);
+        let missing = dir.path().join("missing.bin").to_str().unwrap().to_string();
+        let paths = vec![payload.clone(), missing.clone()];
+
+        let results = inspect_payloads(&paths);
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].as_ref().unwrap().partitions[0].name, "boot");
+        assert!(matches!(results[1], Err(PayloadError::FileNotFound(_))));
+
+        let json: serde_json::Value = serde_json::from_str(&inspect_payloads_json(&paths).unwrap()).unwrap();
+        assert_eq!(json[0]["path"], payload.as_str());
+        assert_eq!(json[0]["success"], true);
+        assert_eq!(json[0]["inspection"]["partitions"][0]["name"], "boot");
+        assert_eq!(json[1]["path"], missing.as_str());
+        assert_eq!(json[1]["success"], false);
+        assert_eq!(json[1]["code"], "FILE_NOT_FOUND");
+        assert!(json[1].get("inspection").is_none());
+    }
+
+    #[test]
+    fn test_inspection_reports_dynamic_partitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = manifest(4096, vec![partition("system", 4096, vec![]), partition("vendor", 4096, vec![])]);
+        manifest.dynamic_partition_metadata = Some(crate::proto::DynamicPartitionMetadata {
+            groups: vec![crate::proto::DynamicPartitionGroup {
+                name: "qti_dynamic_partitions".to_string(),
+                size: Some(8 * 1024 * 1024 * 1024),
+                partition_names: vec!["system".to_string(), "vendor".to_string()],
+            }],
+            snapshot_enabled: Some(true),
+            ..Default::default()
+        });
+
+        let payload = write_test_payload(dir.path(), &manifest, &[]);
+        let dynamic = inspect_payload(&payload).unwrap().dynamic_partitions.unwrap();
+
+        assert!(dynamic.snapshot_enabled);
+        assert!(!dynamic.vabc_enabled);
+        assert_eq!(dynamic.groups.len(), 1);
+        assert_eq!(dynamic.groups[0].name, "qti_dynamic_partitions");
+        assert_eq!(dynamic.groups[0].max_size, Some(8 * 1024 * 1024 * 1024));
+        assert_eq!(dynamic.groups[0].partitions, ["system", "vendor"]);
+    }
+
+    #[test]
+    fn test_list_partition_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("vendor", 4096, vec![]),
+                    partition("boot", 4096, vec![]),
+                ],
+            ),
+            &[],
+        );
+
+        assert_eq!(list_partition_names(&payload).unwrap(), vec!["vendor", "boot"]);
+        assert_eq!(
+            list_partition_names_json(&payload).unwrap(),
+            r#"{"partitions":["vendor","boot"]}"#
+        );
+    }
+
+    /// Inspect and extract a payload stored in an OTA zip with `method`
+    fn check_extract_from_ota_zip(method: zip::CompressionMethod) {
+        let dir = tempfile::tempdir().unwrap();
+        // Operations read the blob back to front, forcing backward seeks
+        let blob = [vec![2u8; 4096], vec![1u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    2 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(1, 1)]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+        let expected = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+
+        let zip_path = write_test_zip(dir.path(), &payload, method);
+
+        let inspection = inspect_payload_zip(&zip_path).unwrap();
+        assert_eq!(inspection.partitions[0].name, "boot");
+        assert_eq!(inspection.properties_source, Some(format!("{}!payload_properties.txt", zip_path)));
+        assert_eq!(inspection.properties.unwrap().file_size, Some(1234));
+        assert_eq!(
+            inspection.build_fingerprint.as_deref(),
+            Some("google/oriole/oriole:14/UQ1A/1:user/release-keys")
+        );
+
+        let out_dir = dir.path().join(format!("out-{:?}", method));
+        let result = extract_payload_zip(&zip_path, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 1);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_extract_from_stored_ota_zip() {
+        check_extract_from_ota_zip(zip::CompressionMethod::Stored);
+    }
+
+    #[test]
+    fn test_extract_from_deflated_ota_zip() {
+        check_extract_from_ota_zip(zip::CompressionMethod::Deflated);
+
+        // The decompressing stream seeks from the end like a plain file
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 4096, vec![])]), &[]);
+        let zip_path = write_test_zip(dir.path(), &payload, zip::CompressionMethod::Deflated);
+        let (mut stream, size) = ota_zip::open_payload_entry(File::open(&zip_path).unwrap()).unwrap();
+        let bytes = std::fs::read(&payload).unwrap();
+        assert_eq!(size, bytes.len() as u64);
+        assert_eq!(stream.seek(SeekFrom::End(-4)).unwrap(), size - 4);
+        let mut tail = Vec::new();
+        stream.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, bytes[bytes.len() - 4..]);
+    }
+
+    #[test]
+    fn test_properties_keep_unknown_keys() {
+        let text = "FILE_HASH=q1w2e3=\nFILE_SIZE=4096\n\n# comment\n=orphan\n\
+                    SWITCH_SLOT_ON_REBOOT=0\n RUNNING_IN_RECOVERY = 1 \nEXTRA_ARGS=a=b\nPOWERWASH=0\nPOWERWASH=1\n";
+        let props = parse_properties(std::io::Cursor::new(text));
+        assert_eq!(props.file_hash.as_deref(), Some("q1w2e3="));
+        assert_eq!(props.file_size, Some(4096));
+
+        // Unknown keys are kept trimmed, known ones and lines without a key are not
+        let extra: BTreeMap<_, _> = props.extra.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        assert_eq!(
+            extra,
+            BTreeMap::from([
+                ("EXTRA_ARGS", "a=b"),
+                ("POWERWASH", "1"),
+                ("RUNNING_IN_RECOVERY", "1"),
+                ("SWITCH_SLOT_ON_REBOOT", "0"),
+            ])
+        );
+
+        let json = serde_json::to_value(&props).unwrap();
+        assert_eq!(json["extra"]["RUNNING_IN_RECOVERY"], "1");
+        assert_eq!(json["extra"].as_object().unwrap().len(), 4);
+
+        // Without unknown keys the map is there but empty
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![]), &[]);
+        let zip_path = write_test_zip(dir.path(), &payload, zip::CompressionMethod::Stored);
+        let json: serde_json::Value = serde_json::from_str(&inspect_payload_zip_json(&zip_path).unwrap()).unwrap();
+        assert_eq!(json["properties"]["file_size"], 1234);
+        assert_eq!(json["properties"]["extra"], serde_json::json!({}));
+    }
+
+    #[test]
+    fn test_properties_from_elsewhere() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 4096, vec![])]), &[]);
+        let ota_dir = tempfile::tempdir().unwrap();
+        let zip_path = write_test_zip(ota_dir.path(), &payload, zip::CompressionMethod::Deflated);
+
+        let inspection = inspect_payload(&payload).unwrap();
+        assert!(inspection.properties.is_none());
+        assert!(inspection.properties_source.is_none());
+
+        // Entry of the original OTA package
+        let inspection = inspect_payload_with_properties(&payload, Some(&zip_path)).unwrap();
+        assert_eq!(inspection.properties.unwrap().file_size, Some(1234));
+        assert_eq!(
+            inspection.properties_source,
+            Some(format!("{}!payload_properties.txt", zip_path))
+        );
+
+        // Plain properties file
+        let properties = ota_dir.path().join("props.txt");
+        std::fs::write(&properties, "FILE_SIZE=99\nSWITCH_SLOT_ON_REBOOT = 0\nPOWERWASH=1\n").unwrap();
+        let properties = properties.to_str().unwrap();
+        let inspection = inspect_payload_with_properties(&payload, Some(properties)).unwrap();
+        let json = serde_json::to_value(&inspection).unwrap();
+        assert_eq!(json["properties"]["extra"]["SWITCH_SLOT_ON_REBOOT"], "0");
+        let parsed = inspection.properties.unwrap();
+        assert_eq!(parsed.file_size, Some(99));
+        assert_eq!(parsed.extra.len(), 2);
+        assert_eq!(parsed.extra["POWERWASH"], "1");
+        assert_eq!(inspection.properties_source.as_deref(), Some(properties));
+
+        // Missing properties path: the adjacent file is used
+        let adjacent = dir.path().join("payload_properties.txt");
+        std::fs::write(&adjacent, "FILE_SIZE=7\n").unwrap();
+        let inspection = inspect_payload_with_properties(&payload, Some("/nonexistent/props.txt")).unwrap();
+        assert_eq!(inspection.properties.unwrap().file_size, Some(7));
+        assert_eq!(inspection.properties_source.as_deref(), adjacent.to_str());
+    }
+
+    #[test]
+    fn test_zip_without_payload_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not-ota.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&path).unwrap());
+        zip.start_file("readme.txt", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.finish().unwrap();
+
+        let result = inspect_payload_zip(path.to_str().unwrap());
+        assert!(matches!(result, Err(PayloadError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_inspect_and_extract_opened_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[7u8; 4096],
+        );
+
+        let inspection = inspect_payload_file(File::open(&payload).unwrap(), "content://test/payload.bin").unwrap();
+        assert_eq!(inspection.file_path, "content://test/payload.bin");
+        assert_eq!(inspection.partitions[0].name, "boot");
+
+        let out_dir = dir.path().join("out");
+        let result = extract_payload_file(File::open(&payload).unwrap(), out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 1);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![7u8; 4096]);
+
+        // Directories (and pipes) are rejected up front
+        let not_a_file = File::open(dir.path()).unwrap();
+        assert!(matches!(inspect_payload_file(not_a_file, "dir"), Err(PayloadError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_insufficient_space_rejected_before_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("boot", 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                    partition("huge", u64::MAX / 2, vec![]),
+                ],
+            ),
+            &[0u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress());
+        assert!(matches!(result, Err(PayloadError::InsufficientSpace(needed, _)) if needed == 4096 + u64::MAX / 2));
+        assert!(!out_dir.join("boot.img").exists());
+
+        // Only the selected partitions count towards the estimate
+        let options = ExtractOptions {
+            partitions: Some(vec!["boot".to_string()]),
+            ..Default::default()
+        };
+        extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        assert!(out_dir.join("boot.img").exists());
+    }
+
+    #[test]
+    fn test_parallel_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["boot", "dtbo", "vendor_boot", "vbmeta", "init_boot"];
+        let blob: Vec<u8> = (0..names.len()).flat_map(|i| vec![i as u8 + 1; 4096]).collect();
+        let partitions = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                partition(
+                    name,
+                    4096,
+                    vec![operation(OperationType::Replace, Some((i as u64 * 4096, 4096)), vec![extent(0, 1)])],
+                )
+            })
+            .collect();
+        let payload = write_test_payload(dir.path(), &manifest(4096, partitions), &blob);
+        let out_dir = dir.path().join("out");
+
+        let options: ExtractOptions = serde_json::from_str(r#"{"max_threads": 3}"#).unwrap();
+        let mut last_reported = 0;
+        let result = extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &options,
+            Some(|update: &ProgressUpdate| last_reported = last_reported.max(update.bytes_processed)),
+        )
+        .unwrap();
+
+        // Results stay in manifest order and progress adds up across workers
+        let extracted: Vec<&str> = result.extracted.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(extracted, names);
+        assert_eq!(last_reported, 5 * 4096);
+        for (i, name) in names.iter().enumerate() {
+            let image = std::fs::read(out_dir.join(format!("{}.img", name))).unwrap();
+            assert_eq!(image, vec![i as u8 + 1; 4096]);
+        }
+    }
+
+    #[test]
+    fn test_streaming_reports_each_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let names = ["boot", "dtbo", "vendor_boot", "vbmeta"];
+        let blob: Vec<u8> = (0..names.len()).flat_map(|i| vec![i as u8 + 1; 4096]).collect();
+        let partitions = names
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                partition(
+                    name,
+                    4096,
+                    vec![operation(OperationType::Replace, Some((i as u64 * 4096, 4096)), vec![extent(0, 1)])],
+                )
+            })
+            .collect();
+        let payload = write_test_payload(dir.path(), &manifest(4096, partitions), &blob);
+
+        for max_threads in [1, 3] {
+            let out_dir = dir.path().join(format!("out-{}", max_threads));
+            let options = ExtractOptions {
+                max_threads,
+                ..Default::default()
+            };
+            let extract = || {
+                let mut reported = Vec::new();
+                let result = extract_payload_streaming(
+                    &payload,
+                    out_dir.to_str().unwrap(),
+                    &options,
+                    no_progress(),
+                    |done: &ExtractedPartition| {
+                        // The image is complete by the time it is reported
+                        assert_eq!(std::fs::metadata(&done.path).unwrap().len(), done.size);
+                        reported.push((done.name.clone(), done.resumed));
+                    },
+                )
+                .unwrap();
+                assert_eq!(result.extracted.len(), names.len());
+                reported.sort();
+                reported
+            };
+
+            let mut expected: Vec<(String, bool)> = names.iter().map(|name| (name.to_string(), false)).collect();
+            expected.sort();
+            assert_eq!(extract(), expected);
+
+            // Extracting again resumes every partition, still reporting each one
+            let resumed: Vec<(String, bool)> = expected.into_iter().map(|(name, _)| (name, true)).collect();
+            assert_eq!(extract(), resumed);
+        }
+    }
+
+    #[test]
+    fn test_parallel_extraction_stops_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        // "broken" holds data that is not valid XZ
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("broken", 4096, vec![operation(OperationType::ReplaceXz, Some((0, 4096)), vec![extent(0, 1)])]),
+                    partition("boot", 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                ],
+            ),
+            &[1u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            max_threads: 2,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress());
+        assert!(
+            matches!(result, Err(PayloadError::OperationIo(ref name, 0, ref step, _)) if name == "broken" && step.contains("XZ")),
+            "{:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_progress_throughput_and_eta() {
+        let mut updates = Vec::new();
+        let mut tracker = ProgressTracker::new(
+            Some(|update: &ProgressUpdate| {
+                updates.push((update.bytes_per_second, update.eta_seconds));
+            }),
+            300,
+        );
+
+        // Simulate 100 bytes/s: 100 bytes after 1s, 200 bytes after 2s
+        let start = tracker.samples[0].0;
+        tracker.samples[0].0 = start - Duration::from_secs(2);
+        tracker.samples.push_back((start - Duration::from_secs(1), 100));
+        tracker.bytes_processed = 200;
+        tracker.report("system", 0);
+
+        let (rate, eta) = updates[0];
+        assert!((95..=105).contains(&rate), "rate {}", rate);
+        assert!(matches!(eta, Some(1..=2)), "eta {:?}", eta);
+
+        // Samples that fall out of the window no longer count
+        let mut tracker: ProgressTracker<fn(&ProgressUpdate)> = ProgressTracker::new(None, 0);
+        let now = Instant::now();
+        tracker.samples[0].0 = now - Duration::from_secs(60);
+        tracker.samples.push_back((now - Duration::from_secs(10), 1000));
+        tracker.samples.push_back((now - Duration::from_secs(1), 2000));
+        tracker.bytes_processed = 2100;
+        assert_eq!(tracker.sample_throughput(now), 1100 / 10);
+        assert_eq!(tracker.samples.len(), 3);
+    }
+
+    #[test]
+    fn test_progress_throttling() {
+        let mut reported = Vec::new();
+        let mut tracker = ProgressTracker::new(
+            Some(|update: &ProgressUpdate| reported.push(update.bytes_processed)),
+            100,
+        )
+        .throttled(Duration::from_secs(3600), 50);
+
+        // The first update always goes through, then only every 50 bytes
+        for bytes in [0, 10, 20, 60, 70, 99] {
+            tracker.bytes_processed = bytes;
+            tracker.report("system", 0);
+        }
+        // The final update is delivered regardless of the throttle
+        tracker.bytes_processed = 100;
+        tracker.report("system", 100);
+
+        drop(tracker);
+        assert_eq!(reported, vec![0, 60, 100]);
+    }
+
+    #[test]
+    fn test_progress_within_partition() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Zero, None, vec![extent(1, 2)]),
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(3, 1)]),
+                    ],
+                )],
+            ),
+            &[1u8; 2 * 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            progress_interval_ms: 0,
+            ..Default::default()
+        };
+        let mut reported = Vec::new();
+        extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &options,
+            Some(|update: &ProgressUpdate| reported.push((update.bytes_processed, update.percent))),
+        )
+        .unwrap();
+
+        // Manifest parsing, start, one update per operation, then partition completion
+        assert_eq!(
+            reported,
+            vec![(0, 0), (0, 0), (4096, 25), (3 * 4096, 75), (4 * 4096, 100), (4 * 4096, 100)]
+        );
+    }
+
+    #[test]
+    fn test_version_1_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest_bytes = manifest(
+            4096,
+            vec![partition(
+                "boot",
+                4096,
+                vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+            )],
+        )
+        .encode_to_vec();
+
+        // Version 1: no metadata signature size, manifest starts at offset 20
+        let mut data = Vec::new();
+        data.extend_from_slice(PAYLOAD_MAGIC);
+        data.extend_from_slice(&1u64.to_be_bytes());
+        data.extend_from_slice(&(manifest_bytes.len() as u64).to_be_bytes());
+        data.extend_from_slice(&manifest_bytes);
+        data.extend_from_slice(&[7u8; 4096]);
+        let payload = dir.path().join("payload.bin");
+        std::fs::write(&payload, data).unwrap();
+        let payload = payload.to_str().unwrap();
+
+        let inspection = inspect_payload(payload).unwrap();
+        assert_eq!(inspection.header.version, 1);
+        assert_eq!(inspection.header.metadata_signature_size, 0);
+        assert_eq!(inspection.header.header_size(), 20);
+        assert_eq!(inspection.header.data_offset, 20 + manifest_bytes.len() as u64);
+        assert_eq!(inspection.partitions[0].name, "boot");
+
+        let out_dir = dir.path().join("out");
+        extract_partition(payload, out_dir.to_str().unwrap(), "boot").unwrap();
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![7u8; 4096]);
+    }
+
+    #[test]
+    fn test_dry_run_reports_sizes_without_writing() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    // Writes block 2, past the declared 4096-byte partition size
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(2, 1)])],
+                    ),
+                    partition(
+                        "boot",
+                        8192,
+                        vec![operation(OperationType::SourceCopy, None, vec![extent(0, 2)])],
+                    ),
+                ],
+            ),
+            &[1u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+
+        assert_eq!(result.status, "dry_run");
+        assert!(!out_dir.exists());
+
+        let system = &result.extracted[0];
+        assert_eq!((system.name.as_str(), system.size, system.supported), ("system", 3 * 4096, true));
+        assert!(system.unsupported_reason.is_none());
+
+        let boot = &result.extracted[1];
+        assert_eq!((boot.name.as_str(), boot.size, boot.supported), ("boot", 8192, false));
+        assert!(boot.unsupported_reason.as_deref().unwrap().contains("source image"));
+    }
+
+    #[test]
+    fn test_operation_outside_payload_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    // Blob ends 4096 bytes past the end of the file
+                    vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[1u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        match extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()) {
+            Err(PayloadError::InvalidInput(msg)) => {
+                assert!(msg.contains("Operation 0 of partition boot"), "{}", msg)
+            }
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
+        assert!(!out_dir.exists());
+
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, u64::MAX)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[1u8; 4096],
+        );
+        assert!(matches!(
+            extract_partition(&payload, out_dir.to_str().unwrap(), "boot"),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_verify_payload_integrity() {
+        use base64::Engine;
+
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![]), &[5u8; 3 * 1024 * 1024]);
+        let data = std::fs::read(&payload).unwrap();
+        let hash = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(&data));
+        let properties = dir.path().join("payload_properties.txt");
+
+        std::fs::write(&properties, format!("FILE_HASH={}\nFILE_SIZE={}\n", hash, data.len())).unwrap();
+        let mut last = 0;
+        let result = verify_payload_integrity(
+            &payload,
+            None,
+            Some(|update: &ProgressUpdate| last = update.bytes_processed),
+        )
+        .unwrap();
+        assert!(result.valid);
+        assert_eq!((result.size_matches, result.hash_matches), (Some(true), Some(true)));
+        assert_eq!(last, data.len() as u64);
+
+        // Cancelled before the first chunk is hashed
+        let mut last = 0;
+        let cancelled = verify_payload_integrity(
+            &payload,
+            Some(Arc::new(AtomicBool::new(true))),
+            Some(|update: &ProgressUpdate| last = update.bytes_processed),
+        )
+        .unwrap_err();
+        assert_eq!(cancelled.code(), "CANCELLED");
+        assert_eq!(last, 0);
+
+        // Truncated download: wrong size and hash
+        std::fs::write(&payload, &data[..data.len() - 1]).unwrap();
+        let result = verify_payload_integrity(&payload, None, no_progress()).unwrap();
+        assert!(!result.valid);
+        assert_eq!((result.size_matches, result.hash_matches), (Some(false), Some(false)));
+
+        // Only FILE_SIZE given: the hash is not checked
+        std::fs::write(&properties, format!("FILE_SIZE={}\n", data.len() - 1)).unwrap();
+        let result = verify_payload_integrity(&payload, None, no_progress()).unwrap();
+        assert!(result.valid);
+        assert_eq!(result.hash_matches, None);
+
+        std::fs::remove_file(&properties).unwrap();
+        assert!(matches!(
+            verify_payload_integrity(&payload, None, no_progress()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+
+        // CRC32 quick check against a checksum given by the caller
+        let crc = format!("{:08x}", crc32fast::hash(&data[..data.len() - 1]));
+        let quick_check = |expected: Option<&str>| {
+            verify_payload_integrity_with(&payload, IntegrityAlgorithm::Crc32, expected, None, no_progress())
+        };
+        let result = quick_check(Some(&crc.to_uppercase())).unwrap();
+        assert!(result.valid);
+        assert_eq!((result.algorithm, result.actual_hash.as_str()), (IntegrityAlgorithm::Crc32, crc.as_str()));
+        assert_eq!((result.size_matches, result.hash_matches), (None, Some(true)));
+        assert_eq!(serde_json::to_value(&result).unwrap()["algorithm"], "crc32");
+        assert!(!quick_check(Some("00000000")).unwrap().valid);
+        assert!(matches!(quick_check(None), Err(PayloadError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_cancel_integrity_check_midway() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![]), &vec![5u8; 8 * STREAM_CHUNK_SIZE]);
+
+        for algorithm in [IntegrityAlgorithm::Sha256, IntegrityAlgorithm::Crc32] {
+            // Cancelled at the first update once hashing is under way: no
+            // checksum comes back and nothing is reported afterwards
+            let cancel = Arc::new(AtomicBool::new(false));
+            let (mut hashed, mut updates_after_cancel) = (0, 0);
+            let result = verify_payload_integrity_with(
+                &payload,
+                algorithm,
+                Some("0"),
+                Some(cancel.clone()),
+                Some(|update: &ProgressUpdate| {
+                    if cancel.load(Ordering::Relaxed) {
+                        updates_after_cancel += 1;
+                    }
+                    hashed = update.bytes_processed;
+                    if hashed > 0 {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                }),
+            );
+
+            let error = result.unwrap_err();
+            assert!(matches!(error, PayloadError::Cancelled), "{:?}: {:?}", algorithm, error);
+            assert_eq!(error.code(), "CANCELLED");
+            assert!(hashed > 0, "{:?}", algorithm);
+            assert_eq!(updates_after_cancel, 0, "{:?}", algorithm);
+        }
+    }
+
+    #[test]
+    fn test_crc32_detects_corruption() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // The CRC-32 check value: the checksum is the standard one
+        let check = dir.path().join("check.bin");
+        std::fs::write(&check, b"123456789").unwrap();
+        let result =
+            verify_payload_integrity_with(check.to_str().unwrap(), IntegrityAlgorithm::Crc32, Some("CBF43926"), None, no_progress())
+                .unwrap();
+        assert_eq!(result.actual_hash, "cbf43926");
+        assert!(result.valid);
+
+        // A checksum recorded before a single byte got flipped no longer matches
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![]), &[5u8; 2 * 1024 * 1024]);
+        let recorded = verify_payload_integrity_with(&payload, IntegrityAlgorithm::Crc32, Some("0"), None, no_progress())
+            .unwrap()
+            .actual_hash;
+        let mut data = std::fs::read(&payload).unwrap();
+        let middle = data.len() / 2;
+        data[middle] ^= 1;
+        std::fs::write(&payload, &data).unwrap();
+
+        let result = verify_payload_integrity_with(&payload, IntegrityAlgorithm::Crc32, Some(&recorded), None, no_progress()).unwrap();
+        assert!(!result.valid);
+        assert_eq!(result.hash_matches, Some(false));
+        assert_eq!(result.actual_size, data.len() as u64);
+        assert_ne!(result.actual_hash, recorded);
+    }
+
+    #[test]
+    fn test_memory_mapped_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 8192]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "system",
+                        8192,
+                        vec![operation(OperationType::Replace, Some((4096, 8192)), vec![extent(0, 2)])],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+
+        for max_threads in [1, 2] {
+            let out_dir = dir.path().join(format!("out-{}", max_threads));
+            let options = ExtractOptions {
+                use_mmap: true,
+                max_threads,
+                ..Default::default()
+            };
+            let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+
+            assert_eq!(result.extracted.len(), 2);
+            assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![1u8; 4096]);
+            assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), vec![2u8; 8192]);
+        }
+    }
+
+    #[test]
+    fn test_extract_sparse_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    8 * 4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(2, 1)])],
+                )],
+            ),
+            &[7u8; 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            output_sparse: true,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        assert_eq!(result.extracted[0].size, 8 * 4096);
+
+        // DONT_CARE(2), RAW(1), DONT_CARE(5): header + 3 chunk headers + 1 block
+        let image = std::fs::read(out_dir.join("system.img")).unwrap();
+        assert_eq!(&image[..4], &0xED26_FF3Au32.to_le_bytes());
+        assert_eq!(image.len(), 28 + 3 * 12 + 4096);
+        assert!(!out_dir.join("system.img.sparse.tmp").exists());
+    }
+
+    #[test]
+    fn test_drop_page_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    2 * 4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(1, 1)])],
+                )],
+            ),
+            &[7u8; 4096],
+        );
+        let expected = [vec![0u8; 4096], vec![7u8; 4096]].concat();
+
+        // The hint doesn't change what is written, in any output mode
+        for (mode, output_sparse, raw) in [("plain", false, false), ("sparse", true, false), ("raw", false, true)] {
+            let out_dir = dir.path().join(mode);
+            let options = ExtractOptions {
+                drop_page_cache: true,
+                output_sparse,
+                raw,
+                ..Default::default()
+            };
+            let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+            let image = std::fs::read(out_dir.join("system.img")).unwrap();
+            match mode {
+                "plain" => {
+                    assert_eq!(result.extracted[0].actual_hash, to_hex(&Sha256::digest(&expected)));
+                    assert_eq!(image, expected);
+                }
+                "sparse" => assert_eq!(&image[..4], &0xED26_FF3Au32.to_le_bytes()),
+                _ => assert_eq!(image, vec![7u8; 4096]),
+            }
+        }
+    }
+
+    #[test]
+    fn test_link_duplicates() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let replace = |offset| vec![operation(OperationType::Replace, Some((offset, 4096)), vec![extent(0, 1)])];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("vbmeta_a", 4096, replace(0)),
+                    partition("boot", 4096, replace(4096)),
+                    partition("vbmeta_b", 4096, replace(0)),
+                    partition("dtbo", 4096, replace(0)),
+                ],
+            ),
+            &blob,
+        );
+
+        let out_dir = dir.path().join("out");
+        let options = ExtractOptions {
+            link_duplicates: true,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        let linked: Vec<_> = result
+            .linked
+            .iter()
+            .map(|l| (l.name.as_str(), l.same_as.as_str(), l.hard_linked))
+            .collect();
+        assert_eq!(linked, [("vbmeta_b", "vbmeta_a", true), ("dtbo", "vbmeta_a", true)]);
+        assert_eq!(result.extracted.len(), 4);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let inode = |name: &str| std::fs::metadata(out_dir.join(name)).unwrap().ino();
+            assert_eq!(inode("vbmeta_b.img"), inode("vbmeta_a.img"));
+            assert_eq!(inode("dtbo.img"), inode("vbmeta_a.img"));
+            assert_ne!(inode("boot.img"), inode("vbmeta_a.img"));
+        }
+        assert_eq!(std::fs::read(out_dir.join("dtbo.img")).unwrap(), vec![1u8; 4096]);
+        let leftovers = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension().is_some_and(|ext| ext == "link"))
+            .count();
+        assert_eq!(leftovers, 0);
+
+        // Nothing is linked by default
+        let plain_dir = dir.path().join("plain");
+        let result = extract_payload(&payload, plain_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress());
+        assert!(result.unwrap().linked.is_empty());
+
+        // Extracting one of the linked images again replaces it with a new
+        // file instead of writing through the shared inode
+        let other_dir = dir.path().join("other");
+        std::fs::create_dir(&other_dir).unwrap();
+        let other = write_test_payload(
+            &other_dir,
+            &manifest(4096, vec![partition("vbmeta_b", 4096, replace(0))]),
+            &[9u8; 4096],
+        );
+        extract_payload(&other, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(std::fs::read(out_dir.join("vbmeta_b.img")).unwrap(), vec![9u8; 4096]);
+        assert_eq!(std::fs::read(out_dir.join("vbmeta_a.img")).unwrap(), vec![1u8; 4096]);
+        assert_eq!(std::fs::read(out_dir.join("dtbo.img")).unwrap(), vec![1u8; 4096]);
+
+        // A failed link leaves the copy in place
+        let missing = dir.path().join("missing.img");
+        let copy = out_dir.join("boot.img");
+        assert!(hard_link_over(&missing, &copy).is_err());
+        assert_eq!(std::fs::read(&copy).unwrap(), vec![2u8; 4096]);
+    }
+
+    #[test]
+    fn test_resume_from_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+        let out = out_dir.to_str().unwrap();
+
+        let first = extract_payload(&payload, out, &ExtractOptions::default(), no_progress()).unwrap();
+        assert!(first.extracted.iter().all(|p| !p.resumed));
+        assert!(out_dir.join(crate::checkpoint::CHECKPOINT_FILE).exists());
+
+        // Simulate an interruption that corrupted system.img
+        std::fs::write(out_dir.join("system.img"), vec![9u8; 4096]).unwrap();
+
+        let second = extract_payload(&payload, out, &ExtractOptions::default(), no_progress()).unwrap();
+        let resumed: Vec<(&str, bool)> = second.extracted.iter().map(|p| (p.name.as_str(), p.resumed)).collect();
+        assert_eq!(resumed, [("boot", true), ("system", false)]);
+        assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), vec![2u8; 4096]);
+
+        let options = ExtractOptions {
+            force: true,
+            ..Default::default()
+        };
+        let forced = extract_payload(&payload, out, &options, no_progress()).unwrap();
+        assert!(forced.extracted.iter().all(|p| !p.resumed));
+    }
+
+    #[test]
+    fn test_existing_output_handling() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+        let out = out_dir.to_str().unwrap();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(out_dir.join("boot.img"), b"modified").unwrap();
+
+        let options = ExtractOptions {
+            on_existing: OnExisting::Error,
+            ..Default::default()
+        };
+        match extract_payload(&payload, out, &options, no_progress()) {
+            Err(PayloadError::OutputExists(path)) => assert!(path.ends_with("boot.img")),
+            other => panic!("Expected OutputExists, got {:?}", other),
+        }
+        assert!(!out_dir.join("system.img").exists());
+
+        let options = ExtractOptions {
+            on_existing: OnExisting::Skip,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out, &options, no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 1);
+        assert_eq!(result.extracted[0].name, "system");
+        assert_eq!(result.skipped[0].name, "boot");
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), b"modified");
+
+        let result = extract_payload(&payload, out, &ExtractOptions { force: true, ..Default::default() }, no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 2);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![1u8; 4096]);
+    }
+
+    #[test]
+    fn test_implausible_block_size_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        for block_size in [0u32, 100, 256, 3000, 131072] {
+            let payload = write_test_payload(dir.path(), &manifest(block_size, vec![partition("boot", 0, vec![])]), &[]);
+            assert!(
+                matches!(inspect_payload(&payload), Err(PayloadError::InvalidInput(_))),
+                "block size {} accepted by inspection",
+                block_size
+            );
+            let out = dir.path().join("out");
+            assert!(
+                matches!(
+                    extract_payload(&payload, out.to_str().unwrap(), &ExtractOptions::default(), no_progress()),
+                    Err(PayloadError::InvalidInput(_))
+                ),
+                "block size {} accepted by extraction",
+                block_size
+            );
+        }
+
+        let payload = write_test_payload(dir.path(), &manifest(65536, vec![partition("boot", 0, vec![])]), &[]);
+        assert_eq!(inspect_payload(&payload).unwrap().block_size, 65536);
+    }
+
+    #[test]
+    fn test_inspect_payload_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = vec![0u8; 4096];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &blob,
+        );
+        let data = std::fs::read(&payload).unwrap();
+
+        // The whole file and just its header + manifest inspect the same
+        let from_file = inspect_payload(&payload).unwrap();
+        let metadata_len = data.len() - blob.len();
+        for bytes in [&data[..], &data[..metadata_len]] {
+            let inspection = inspect_payload_bytes(bytes).unwrap();
+            assert_eq!(inspection.partitions.len(), 1);
+            assert_eq!(inspection.partitions[0].name, "boot");
+            assert_eq!(inspection.total_size, from_file.total_size);
+            assert_eq!(inspection.header.manifest_size, from_file.header.manifest_size);
+        }
+
+        let manifest_len = metadata_len as u64 - HEADER_SIZE;
+        assert!(matches!(
+            inspect_payload_bytes(&data[..metadata_len - 1]),
+            Err(PayloadError::TruncatedManifest(expected, available))
+                if expected == manifest_len && available == manifest_len - 1
+        ));
+        assert!(matches!(
+            inspect_payload_bytes(&data[..10]),
+            Err(PayloadError::FileTooSmall(10, _))
+        ));
+        assert!(matches!(
+            inspect_payload_bytes(b"PK\x03\x04 definitely not a payload"),
+            Err(PayloadError::InvalidMagic(..))
+        ));
+    }
+    #[test]
+    fn test_truncated_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &[1u8; 4096],
+        );
+        let manifest_size = inspect_payload(&payload).unwrap().header.manifest_size;
+
+        // Cut off halfway through the manifest, like an interrupted download
+        let data = std::fs::read(&payload).unwrap();
+        let present = manifest_size / 2;
+        std::fs::write(&payload, &data[..(HEADER_SIZE + present) as usize]).unwrap();
+
+        let error = inspect_payload(&payload).unwrap_err();
+        assert!(
+            matches!(error, PayloadError::TruncatedManifest(declared, found) if declared == manifest_size && found == present),
+            "{:?}",
+            error
+        );
+        assert_eq!(error.code(), "TRUNCATED_MANIFEST");
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Manifest truncated: the header declares {} bytes but only {} are present. The download is probably incomplete.",
+                manifest_size, present
+            )
+        );
+
+        let out_dir = dir.path().join("out");
+        assert!(matches!(
+            extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()),
+            Err(PayloadError::TruncatedManifest(declared, found)) if declared == manifest_size && found == present
+        ));
+    }
+
+    #[test]
+    fn test_invalid_magic_hints_at_file_type() {
+        let message = |data: &[u8]| inspect_payload_bytes(data).unwrap_err().to_string();
+
+        let zip = message(b"PK\x03\x04 an OTA package, not its payload");
+        assert!(zip.contains("ZIP archive"), "{}", zip);
+        assert!(zip.contains("inspectPayloadZip"), "{}", zip);
+        let gzip = message(b"\x1f\x8b\x08\x00 compressed payload.bin.gz");
+        assert!(gzip.contains("gzip"), "{}", gzip);
+        let other = message(b"ANDR!  a boot image, not a payload");
+        assert!(other.ends_with("(0x414E4452)"), "{}", other);
+    }
+
+    #[test]
+    fn test_operations_without_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("out");
+        let extract = |operations: Vec<InstallOperation>| {
+            let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 2 * 4096, operations)]), &[1u8; 4096]);
+            let options = ExtractOptions {
+                force: true,
+                ..Default::default()
+            };
+            extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress())
+        };
+
+        // A REPLACE_XZ without data would leave its blocks unwritten
+        let result = extract(vec![
+            operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+            operation(OperationType::ReplaceXz, None, vec![extent(1, 1)]),
+        ]);
+        match result {
+            Err(PayloadError::InvalidInput(msg)) => assert_eq!(
+                msg,
+                "REPLACE_XZ operation 1 of partition boot has no data (data_offset None, data_length None)"
+            ),
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
+        assert!(!out_dir.join("boot.img").exists());
+
+        // Data whose offset isn't given is not assumed to start the blob
+        let mut no_offset = operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]);
+        no_offset.data_offset = None;
+        assert!(matches!(extract(vec![no_offset]), Err(PayloadError::InvalidInput(_))));
+
+        // ZERO and unknown types without data are fine; the latter is reported
+        let mut unknown = operation(OperationType::Replace, None, vec![extent(1, 1)]);
+        unknown.r#type = 42;
+        let result = extract(vec![operation(OperationType::Zero, None, vec![extent(0, 1)]), unknown]).unwrap();
+        assert_eq!(result.warnings.len(), 1);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![0u8; 2 * 4096]);
+    }
+
+    #[test]
+    fn test_unknown_operation_type_reported_as_warning() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let mut unknown = operation(OperationType::Replace, Some((4096, 4096)), vec![extent(1, 1)]);
+        unknown.r#type = 42;
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    8192,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]), unknown],
+                )],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        let expected = OperationWarning {
+            partition: "boot".to_string(),
+            operation_index: 1,
+            operation_type: "UNKNOWN(42)".to_string(),
+        };
+        assert_eq!(result.warnings, vec![expected.clone()]);
+        assert_eq!(result.extracted[0].warnings, vec![expected]);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), blob);
+
+        let options = ExtractOptions {
+            dry_run: true,
+            ..Default::default()
+        };
+        let plan = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        assert_eq!(plan.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_metadata_hash_verification() {
+        use base64::Engine;
+
+        let dir = tempfile::tempdir().unwrap();
+        let blob = vec![7u8; 4096];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                )],
+            ),
+            &blob,
+        );
+        let data = std::fs::read(&payload).unwrap();
+        let metadata = &data[..data.len() - blob.len()];
+        let hash = base64::engine::general_purpose::STANDARD.encode(Sha256::digest(metadata));
+        let properties = dir.path().join("payload_properties.txt");
+
+        assert_eq!(inspect_payload(&payload).unwrap().metadata_verified, None);
+
+        std::fs::write(&properties, format!("METADATA_HASH={}\nMETADATA_SIZE={}\n", hash, metadata.len())).unwrap();
+        assert_eq!(inspect_payload(&payload).unwrap().metadata_verified, Some(true));
+
+        std::fs::write(&properties, format!("METADATA_HASH={}\nMETADATA_SIZE={}\n", hash, metadata.len() + 1)).unwrap();
+        assert_eq!(inspect_payload(&payload).unwrap().metadata_verified, Some(false));
+
+        // A changed manifest no longer matches
+        let mut tampered = data.clone();
+        let name_at = tampered.windows(4).position(|w| w == b"boot").unwrap();
+        tampered[name_at] = b'B';
+        std::fs::write(&payload, &tampered).unwrap();
+        std::fs::write(&properties, format!("METADATA_HASH={}\nMETADATA_SIZE={}\n", hash, metadata.len())).unwrap();
+        assert_eq!(inspect_payload(&payload).unwrap().metadata_verified, Some(false));
+
+        // Only METADATA_HASH given: nothing to check against
+        std::fs::write(&payload, &data).unwrap();
+        std::fs::write(&properties, format!("METADATA_HASH={}\n", hash)).unwrap();
+        assert_eq!(inspect_payload(&payload).unwrap().metadata_verified, None);
+    }
+
+    #[test]
+    fn test_filename_template() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let partitions = vec![
+            partition(
+                "boot",
+                4096,
+                vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+            ),
+            partition(
+                "dtbo",
+                4096,
+                vec![operation(OperationType::Replace, Some((4096, 4096)), vec![extent(0, 1)])],
+            ),
+        ];
+        let payload = write_test_payload(dir.path(), &manifest(4096, partitions.clone()), &blob);
+        let out_dir = dir.path().join("out");
+        let out = out_dir.to_str().unwrap();
+
+        let options = ExtractOptions {
+            filename_template: "{index}_{name}_a.img".to_string(),
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out, &options, no_progress()).unwrap();
+        assert!(result.extracted[1].path.ends_with("1_dtbo_a.img"));
+        assert_eq!(std::fs::read(out_dir.join("0_boot_a.img")).unwrap(), vec![1u8; 4096]);
+        assert_eq!(std::fs::read(out_dir.join("1_dtbo_a.img")).unwrap(), vec![2u8; 4096]);
+
+        for template in ["boot.img", "../{name}.img", "{name}/x.img", "{name}_{slot}.img"] {
+            let options = ExtractOptions {
+                filename_template: template.to_string(),
+                ..Default::default()
+            };
+            assert!(
+                matches!(extract_payload(&payload, out, &options, no_progress()), Err(PayloadError::InvalidInput(_))),
+                "template {} accepted",
+                template
+            );
+        }
+
+        // A crafted partition name can't escape the output directory
+        let mut evil = partitions;
+        evil[1].partition_name = "../../escaped".to_string();
+        let payload = write_test_payload(dir.path(), &manifest(4096, evil), &blob);
+        let nested = out_dir.join("a").join("b");
+        assert!(matches!(
+            extract_payload(&payload, nested.to_str().unwrap(), &ExtractOptions::default(), no_progress()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+        assert!(!out_dir.join("escaped.img").exists());
+        assert!(!nested.join("boot.img").exists());
+    }
 
-    let mut extracted = Vec::new();
+    #[test]
+    fn test_parallel_xz_decompression() {
+        use std::io::Write;
 
-    // Calculate total bytes for progress tracking
-    let total_bytes: u64 = manifest.partitions.iter()
-        .filter_map(|p| p.new_partition_info.as_ref().and_then(|info| info.size))
-        .sum();
+        let dir = tempfile::tempdir().unwrap();
+        // Nine 4-block XZ operations with a ZERO operation in the middle
+        let mut blob = Vec::new();
+        let mut operations = Vec::new();
+        let mut expected = Vec::new();
+        for i in 0..10u64 {
+            if i == 6 {
+                operations.push(operation(OperationType::Zero, None, vec![extent(i * 4, 4)]));
+                expected.extend(vec![0u8; 4 * 4096]);
+                continue;
+            }
+            let chunk: Vec<u8> = (0..4 * 4096u64).map(|b| ((b * (i + 1)) % 251) as u8).collect();
+            let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+            xz.write_all(&chunk).unwrap();
+            let data = xz.finish().unwrap();
+            let mut op = operation(OperationType::ReplaceXz, Some((blob.len() as u64, data.len() as u64)), vec![extent(i * 4, 4)]);
+            op.data_sha256_hash = Some(Sha256::digest(&data).to_vec());
+            operations.push(op);
+            blob.extend(data);
+            expected.extend(chunk);
+        }
+        let mut parts = vec![partition("system", expected.len() as u64, operations)];
+        let payload = write_test_payload(dir.path(), &manifest(4096, parts.clone()), &blob);
+        let out_dir = dir.path().join("out");
 
-    let mut bytes_processed: u64 = 0;
+        let options = ExtractOptions {
+            decompress_threads: 4,
+            verify_operations: true,
+            ..Default::default()
+        };
+        extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), expected);
 
-    // Extract each partition
-    for (_partition_idx, partition) in manifest.partitions.iter().enumerate() {
-        let partition_name = &partition.partition_name;
-        log::info!("Extracting partition: {}", partition_name);
+        // Blobs decoded ahead are still checked against their hashes
+        parts[0].operations[7].data_sha256_hash = Some(vec![0u8; 32]);
+        let payload = write_test_payload(dir.path(), &manifest(4096, parts), &blob);
+        assert!(matches!(
+            extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions { force: true, ..options.clone() }, no_progress()),
+            Err(PayloadError::OperationHashMismatch(name, 7)) if name == "system"
+        ));
 
-        // Report progress at start of partition
-        if let Some(ref mut callback) = progress_callback {
-            let progress_percent = if total_bytes > 0 {
-                ((bytes_processed as f64 / total_bytes as f64) * 100.0) as i32
-            } else {
-                0
+        // A blob that inflates past its destination extents fails the same
+        // way whether it is decoded ahead or streamed
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 6);
+        xz.write_all(&vec![7u8; 64 * 4096]).unwrap();
+        let data = xz.finish().unwrap();
+        let parts = vec![partition(
+            "system",
+            2 * 4096,
+            vec![
+                operation(OperationType::ReplaceXz, Some((0, data.len() as u64)), vec![extent(0, 1)]),
+                operation(OperationType::ReplaceXz, Some((0, data.len() as u64)), vec![extent(1, 1)]),
+            ],
+        )];
+        let payload = write_test_payload(dir.path(), &manifest(4096, parts), &data);
+        for threads in [1, 4] {
+            let options = ExtractOptions {
+                decompress_threads: threads,
+                force: true,
+                ..Default::default()
             };
-            callback(partition_name, progress_percent, bytes_processed as i64, total_bytes as i64);
+            let error = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "IO error reading file: Write failed for system: operation data exceeds its destination extents",
+                "{} thread(s)",
+                threads
+            );
         }
+    }
 
-        let output_file_path = output_path.join(format!("{}.img", partition_name));
-        log::info!("  Output: {}", output_file_path.display());
+    #[test]
+    fn test_operation_errors_name_partition_and_operation() {
+        let dir = tempfile::tempdir().unwrap();
+        // Operation 1 claims to be XZ but isn't
+        let blob = [vec![1u8; 4096], vec![0x5Au8; 64]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "vendor",
+                    2 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::ReplaceXz, Some((4096, 64)), vec![extent(1, 1)]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
 
-        // Create output file
-        let output_file = File::create(&output_file_path).map_err(|e| {
-            PayloadError::Io(format!("Failed to create {}: {}", partition_name, e))
-        })?;
-        let mut writer = BufWriter::new(output_file);
+        for threads in [1, 4] {
+            let options = ExtractOptions {
+                decompress_threads: threads,
+                force: true,
+                ..Default::default()
+            };
+            let error = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap_err();
+            assert!(
+                error.to_string().starts_with("I/O error decompressing XZ data of operation 1 of vendor: "),
+                "{} thread(s): {}",
+                threads,
+                error
+            );
+            assert_eq!(error.code(), "IO_ERROR");
+        }
 
-        let partition_size = partition
-            .new_partition_info
-            .as_ref()
-            .and_then(|info| info.size)
-            .unwrap_or(0);
+        let error = extract_partition_to_vec(&payload, "vendor", None).unwrap_err();
+        assert!(matches!(error, PayloadError::OperationIo(ref name, 1, _, _) if name == "vendor"), "{}", error);
+    }
 
-        log::info!("  Size: {} ({})", partition_size, format_size(partition_size));
-        log::info!("  Operations: {}", partition.operations.len());
+    #[test]
+    fn test_extract_to_caller_provided_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    // Written out of block order, so the image is read back to hash it
+                    partition(
+                        "dtbo",
+                        8192,
+                        vec![
+                            operation(OperationType::Replace, Some((4096, 4096)), vec![extent(1, 1)]),
+                            operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        ],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+        let docs = dir.path().join("documents");
+        std::fs::create_dir(&docs).unwrap();
+        // Stale, longer content must not survive
+        std::fs::write(docs.join("doc-dtbo"), vec![9u8; 20000]).unwrap();
 
-        // Process each operation
-        for (op_idx, operation) in partition.operations.iter().enumerate() {
-            if let Some(data_length) = operation.data_length {
-                if data_length > 0 {
-                    // Read compressed data from payload
-                    let data_offset_in_blob = operation.data_offset.unwrap_or(0);
-
-                    // Seek to the operation's data
-                    payload_file.seek(SeekFrom::Start(data_offset + data_offset_in_blob))?;
-
-                    // Read the compressed data
-                    let mut compressed_data = vec![0u8; data_length as usize];
-                    payload_file.read_exact(&mut compressed_data)?;
-
-                    // Decompress based on operation type
-                    let decompressed_data = match operation.r#type() {
-                        crate::proto::install_operation::Type::ReplaceXz => {
-                            decompress_xz(&compressed_data)?
-                        }
-                        crate::proto::install_operation::Type::ReplaceBz => {
-                            decompress_bz2(&compressed_data)?
-                        }
-                        crate::proto::install_operation::Type::Replace => {
-                            // No decompression needed
-                            compressed_data
-                        }
-                        _ => {
-                            log::warn!("  Operation {} type {:?} not fully supported, using raw data",
-                                      op_idx, operation.r#type());
-                            compressed_data
-                        }
-                    };
+        let mut requested = Vec::new();
+        let open_output = |name: &str| {
+            requested.push(name.to_string());
+            let path = docs.join(format!("doc-{}", name));
+            let file = std::fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+            Ok((file, format!("content://documents/{}", name)))
+        };
+        let result = extract_payload_to_files(&payload, &ExtractOptions::default(), open_output, no_progress()).unwrap();
 
-                    // Write decompressed data
-                    writer.write_all(&decompressed_data).map_err(|e| {
-                        PayloadError::Io(format!("Write failed for {}: {}", partition_name, e))
-                    })?;
+        assert_eq!(requested, vec!["boot", "dtbo"]);
+        assert_eq!(result.extracted[1].path, "content://documents/dtbo");
+        assert_eq!(std::fs::read(docs.join("doc-boot")).unwrap(), vec![1u8; 4096]);
+        let dtbo = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        assert_eq!(std::fs::read(docs.join("doc-dtbo")).unwrap(), dtbo);
+        assert_eq!(result.extracted[1].actual_hash, to_hex(&Sha256::digest(&dtbo)));
+
+        // A failing provider aborts the extraction
+        let failing = |_: &str| Err(PayloadError::Io("no document".to_string()));
+        assert!(matches!(
+            extract_payload_to_files(&payload, &ExtractOptions::default(), failing, no_progress()),
+            Err(PayloadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_update_type_detection() {
+        let dir = tempfile::tempdir().unwrap();
+        let replace = || operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]);
+        let full = vec![partition("boot", 4096, vec![replace()]), partition("dtbo", 4096, vec![replace()])];
+
+        let payload = write_test_payload(dir.path(), &manifest(4096, full.clone()), &[0u8; 4096]);
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!((inspection.update_type, inspection.is_incremental), (UpdateType::Full, false));
+
+        let mut partial = manifest(4096, full.clone());
+        partial.partial_update = Some(true);
+        let payload = write_test_payload(dir.path(), &partial, &[0u8; 4096]);
+        assert_eq!(inspect_payload(&payload).unwrap().update_type, UpdateType::Partial);
+
+        // A single SOURCE_COPY makes the payload incremental, even when partial
+        let mut delta = full.clone();
+        delta[1].operations.push(operation(OperationType::SourceCopy, None, vec![extent(1, 1)]));
+        let mut incremental = manifest(4096, delta);
+        incremental.partial_update = Some(true);
+        let payload = write_test_payload(dir.path(), &incremental, &[0u8; 4096]);
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!((inspection.update_type, inspection.is_incremental), (UpdateType::Incremental, true));
+
+        // So does a described source partition
+        let mut with_old = full;
+        with_old[0].old_partition_info = Some(crate::proto::PartitionInfo {
+            size: Some(4096),
+            hash: Some(vec![0u8; 32]),
+        });
+        let payload = write_test_payload(dir.path(), &manifest(4096, with_old), &[0u8; 4096]);
+        let json = inspect_payload_json(&payload).unwrap();
+        assert!(json.contains("\"update_type\": \"incremental\""));
+    }
+
+    #[test]
+    fn test_parse_header_computes_data_offset() {
+        let mut data = Vec::new();
+        data.extend_from_slice(PAYLOAD_MAGIC);
+        data.extend_from_slice(&2u64.to_be_bytes());
+        data.extend_from_slice(&100u64.to_be_bytes());
+        data.extend_from_slice(&256u32.to_be_bytes());
+
+        let header = parse_header(&mut &data[..], 1000).unwrap();
+        assert_eq!(header.version, 2);
+        assert_eq!(header.header_size(), 24);
+        assert_eq!(header.metadata_size(), 124);
+        assert_eq!(header.data_offset, 380);
+
+        // A v1 header is 20 bytes and has no metadata signature
+        data[4..12].copy_from_slice(&1u64.to_be_bytes());
+        let header = parse_header(&mut &data[..20], 1000).unwrap();
+        assert_eq!(header.metadata_signature_size, 0);
+        assert_eq!(header.data_offset, 120);
+
+        assert!(matches!(
+            parse_header(&mut &data[..], 10),
+            Err(PayloadError::FileTooSmall(10, _))
+        ));
+    }
+
+    #[test]
+    fn test_estimate_extraction() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(
+            4096,
+            vec![
+                partition(
+                    "boot",
+                    8192,
+                    vec![operation(OperationType::Replace, Some((0, 8192)), vec![extent(0, 2)])],
+                ),
+                partition(
+                    "vendor",
+                    4096,
+                    vec![operation(OperationType::Zero, None, vec![extent(0, 1)])],
+                ),
+            ],
+        );
+        let payload = write_test_payload(dir.path(), &manifest, &[0u8; 8192]);
+        let payload = payload.as_str();
+
+        let estimate = estimate_extraction(payload, Some(4096)).unwrap();
+        assert_eq!(estimate.compressed_bytes, 8192);
+        assert_eq!(estimate.uncompressed_bytes, 12288);
+        assert_eq!(estimate.estimated_seconds, 3.0);
+        assert_eq!(estimate.partitions[0].name, "boot");
+        assert_eq!(estimate.partitions[1].compressed_bytes, 0);
+        assert_eq!(estimate.partitions[1].estimated_seconds, 1.0);
+
+        let default = estimate_extraction(payload, None).unwrap();
+        assert_eq!(default.bytes_per_second, DEFAULT_ESTIMATE_THROUGHPUT);
+        assert!(matches!(
+            estimate_extraction(payload, Some(0)),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_dump_manifest() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(
+            4096,
+            vec![partition(
+                "boot",
+                4096,
+                vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+            )],
+        );
+        let payload = write_test_payload(dir.path(), &manifest, &[0u8; 4096]);
+        let out = dir.path().join("manifest.pb");
+        let out = out.to_str().unwrap();
+
+        let dump = dump_manifest(&payload, out, false).unwrap();
+        assert_eq!(std::fs::read(out).unwrap(), manifest.encode_to_vec());
+        assert_eq!(dump.size, manifest.encoded_len() as u64);
+        assert!(dump.decoded_path.is_none());
+
+        let dump = dump_manifest(&payload, out, true).unwrap();
+        let text = std::fs::read_to_string(dump.decoded_path.unwrap()).unwrap();
+        assert!(text.contains("partition_name: \"boot\""));
+    }
+
+    #[test]
+    fn test_empty_partitions_not_written_by_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(
+            4096,
+            vec![
+                partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                ),
+                partition("placeholder", 0, vec![]),
+            ],
+        );
+        let payload = write_test_payload(dir.path(), &manifest, &[1u8; 4096]);
+        let out_dir = dir.path().join("out");
+        let out = out_dir.to_str().unwrap();
+
+        let result = extract_payload(&payload, out, &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 1);
+        assert_eq!(result.empty_partitions, vec!["placeholder".to_string()]);
+        assert!(!out_dir.join("placeholder.img").exists());
+
+        let options = ExtractOptions {
+            create_empty: true,
+            force: true,
+            ..Default::default()
+        };
+        let result = extract_payload(&payload, out, &options, no_progress()).unwrap();
+        assert_eq!(result.extracted.len(), 2);
+        assert!(result.empty_partitions.is_empty());
+        assert_eq!(std::fs::metadata(out_dir.join("placeholder.img")).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_unbuffered_output_matches_buffered() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob: Vec<u8> = (0..3 * 4096).map(|i| (i % 253) as u8).collect();
+        let manifest = manifest(
+            4096,
+            vec![partition(
+                "boot",
+                4 * 4096,
+                vec![
+                    operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                    operation(OperationType::Zero, None, vec![extent(1, 1)]),
+                    operation(OperationType::Replace, Some((4096, 8192)), vec![extent(2, 2)]),
+                ],
+            )],
+        );
+        let payload = write_test_payload(dir.path(), &manifest, &blob);
+
+        let mut images = Vec::new();
+        for write_buffer_size in [0, DEFAULT_WRITE_BUFFER_SIZE] {
+            let out_dir = dir.path().join(format!("out{}", write_buffer_size));
+            let options = ExtractOptions {
+                write_buffer_size,
+                ..Default::default()
+            };
+            extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+            images.push(std::fs::read(out_dir.join("boot.img")).unwrap());
+        }
+        assert_eq!(images[0], images[1]);
+        assert_eq!(images[0].len(), 4 * 4096);
+    }
+
+    #[test]
+    fn test_payload_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob: Vec<u8> = (0..3 * 4096).map(|i| (i % 251) as u8).collect();
+        let manifest = manifest(
+            4096,
+            vec![
+                partition(
+                    "boot",
+                    8192,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(1, 1)]),
+                    ],
+                ),
+                partition(
+                    "vendor",
+                    8192,
+                    vec![
+                        operation(OperationType::Zero, None, vec![extent(0, 1)]),
+                        operation(OperationType::Replace, Some((8192, 4096)), vec![extent(1, 1)]),
+                    ],
+                ),
+                partition(
+                    "empty",
+                    4096,
+                    vec![operation(OperationType::Zero, None, vec![extent(0, 1)])],
+                ),
+            ],
+        );
+        let payload = write_test_payload(dir.path(), &manifest, &blob);
+
+        let layout = inspect_payload_layout(&payload).unwrap();
+        let data_offset = HEADER_SIZE + manifest.encoded_len() as u64;
+        assert_eq!(layout.data_offset, data_offset);
+        assert_eq!(layout.layout[0].offset, Some(data_offset));
+        assert_eq!((layout.layout[0].span, layout.layout[0].compressed_size), (8192, 8192));
+        assert_eq!(layout.layout[1].offset, Some(data_offset + 8192));
+        assert_eq!(layout.layout[1].span, 4096);
+        assert_eq!(layout.layout[2].offset, None);
+        assert_eq!(layout.layout[2].span, 0);
+
+        // The offset points at the partition's first blob in the file
+        let data = std::fs::read(&payload).unwrap();
+        let start = layout.layout[1].offset.unwrap() as usize;
+        assert_eq!(&data[start..start + 4096], &blob[8192..]);
+    }
+
+    #[test]
+    fn test_duplicate_partition_names_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let boot = || {
+            partition(
+                "boot",
+                4096,
+                vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+            )
+        };
+        let manifest = manifest(4096, vec![boot(), boot()]);
+        let payload = write_test_payload(dir.path(), &manifest, &[0u8; 4096]);
+        let out_dir = dir.path().join("out");
+
+        let error = inspect_payload(&payload).unwrap_err();
+        assert!(matches!(&error, PayloadError::InvalidInput(message) if message.contains("'boot'")));
+
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress());
+        assert!(matches!(result, Err(PayloadError::InvalidInput(_))));
+        assert!(!out_dir.join("boot.img").exists());
+    }
+
+    #[test]
+    fn test_output_dir_probe() {
+        let dir = tempfile::tempdir().unwrap();
+        let out_dir = dir.path().join("out");
+        ensure_output_dir(out_dir.to_str().unwrap()).unwrap();
+        assert_eq!(std::fs::read_dir(&out_dir).unwrap().count(), 0);
+
+        // A file in the way of the directory is reported before any image,
+        // as the I/O error it is rather than a permission problem
+        let blocked = dir.path().join("blocked");
+        std::fs::write(&blocked, b"").unwrap();
+        let error = ensure_output_dir(blocked.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.code(), "IO_ERROR", "{}", error);
+
+        // Root writes regardless of the mode
+        if unsafe { libc::geteuid() } != 0 {
+            use std::os::unix::fs::PermissionsExt;
+            let read_only = dir.path().join("read_only");
+            std::fs::create_dir(&read_only).unwrap();
+            std::fs::set_permissions(&read_only, std::fs::Permissions::from_mode(0o555)).unwrap();
+            let error = ensure_output_dir(read_only.to_str().unwrap()).unwrap_err();
+            assert_eq!(error.code(), "PERMISSION_DENIED", "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let dir = tempfile::tempdir().unwrap();
+        let missing = dir.path().join("missing.bin");
+        let error = inspect_payload_json(missing.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.code(), "FILE_NOT_FOUND");
+
+        let garbage = dir.path().join("garbage.bin");
+        std::fs::write(&garbage, [0u8; 64]).unwrap();
+        let error = inspect_payload_json(garbage.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.code(), "INVALID_MAGIC");
+
+        assert_eq!(PayloadError::Cancelled.code(), "CANCELLED");
+        assert_eq!(PayloadError::OperationFailed("cancelled".to_string()).code(), "OPERATION_FAILED");
+        assert_eq!(PayloadError::OperationFailed("other".to_string()).code(), "OPERATION_FAILED");
+
+        // Every code has its own number, and the documented ones don't move
+        let errors = [
+            PayloadError::FileNotFound(String::new()),
+            PayloadError::PermissionDenied(String::new()),
+            PayloadError::Io(String::new()),
+            PayloadError::InvalidMagic(String::new(), 0),
+            PayloadError::UnsupportedVersion(9),
+            PayloadError::ProtobufDecode(String::new()),
+            PayloadError::ManifestTooLarge(2, 1),
+            PayloadError::FileTooSmall(1, 2),
+            PayloadError::EmptyPath,
+            PayloadError::TruncatedManifest(2, 1),
+            PayloadError::UnexpectedEof(String::new()),
+            PayloadError::Cancelled,
+            PayloadError::Timeout,
+            PayloadError::OperationFailed(String::new()),
+            PayloadError::InvalidInput(String::new()),
+            PayloadError::OperationHashMismatch(String::new(), 0),
+            PayloadError::InsufficientSpace(2, 1),
+            PayloadError::OutputExists(String::new()),
+        ];
+        let numbers: Vec<i32> = errors.iter().map(PayloadError::code_number).collect();
+        assert_eq!(numbers, (1..=18).collect::<Vec<_>>());
+
+        // The table, JNI_ERROR included, has one non-zero number per code
+        let mut codes: Vec<&str> = ERROR_CODE_NUMBERS.iter().map(|(code, _)| *code).collect();
+        let mut table_numbers: Vec<i32> = ERROR_CODE_NUMBERS.iter().map(|(_, number)| *number).collect();
+        codes.sort_unstable();
+        codes.dedup();
+        table_numbers.sort_unstable();
+        table_numbers.dedup();
+        assert_eq!(codes.len(), ERROR_CODE_NUMBERS.len());
+        assert_eq!(table_numbers.len(), ERROR_CODE_NUMBERS.len());
+        assert!(table_numbers.iter().all(|number| *number > 0));
+        assert_eq!(PayloadError::OperationIo(String::new(), 0, String::new(), String::new()).code_number(), 3);
+        assert_eq!(error_code_number("JNI_ERROR"), 19);
+        assert_eq!(error_code_number("SOMETHING_NEW"), 0);
+    }
+
+    #[test]
+    fn test_offset_overflow_is_invalid_input() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Partition sizes summing past u64::MAX
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("system", u64::MAX, vec![]), partition("vendor", 4096, vec![])]),
+            &[],
+        );
+        assert!(matches!(inspect_payload(&payload), Err(PayloadError::InvalidInput(_))));
+        assert!(matches!(estimate_extraction(&payload, None), Err(PayloadError::InvalidInput(_))));
+
+        // Block counts and blob lengths summing past u64::MAX
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, u64::MAX)), vec![extent(0, u64::MAX)]),
+                        operation(OperationType::Replace, Some((0, 1)), vec![extent(0, 1)]),
+                    ],
+                )],
+            ),
+            &[],
+        );
+        assert!(matches!(inspect_payload(&payload), Err(PayloadError::InvalidInput(_))));
+        assert!(matches!(estimate_extraction(&payload, None), Err(PayloadError::InvalidInput(_))));
+
+        // Image sizes that don't fit in a u64
+        let past_range = partition(
+            "system",
+            4096,
+            vec![operation(OperationType::Zero, None, vec![extent(u64::MAX - 1, 2)])],
+        );
+        assert!(matches!(planned_image_size(&past_range, 4096), Err(PayloadError::InvalidInput(_))));
+        let past_range = partition(
+            "system",
+            4096,
+            vec![
+                operation(OperationType::Replace, Some((0, u64::MAX)), vec![]),
+                operation(OperationType::Replace, Some((0, 1)), vec![]),
+            ],
+        );
+        assert!(matches!(planned_image_size(&past_range, 4096), Err(PayloadError::InvalidInput(_))));
+        assert_eq!(extents_length(&[extent(0, u64::MAX), extent(0, 1)], 4096), u64::MAX);
+
+        // An extent whose byte offset doesn't fit in a u64
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![operation(OperationType::Zero, None, vec![extent(u64::MAX / 4096, 1)])],
+                )],
+            ),
+            &[],
+        );
+        let out_dir = dir.path().join("out");
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress());
+        assert!(matches!(result, Err(PayloadError::InvalidInput(_))));
+        assert!(!out_dir.exists());
+
+        // Also when the images are planned upfront for a timeout
+        let options = ExtractOptions {
+            timeout_ms: 30_000,
+            ..Default::default()
+        };
+        for last_block in [u64::MAX / 4096, u64::MAX] {
+            let payload = write_test_payload(
+                dir.path(),
+                &manifest(
+                    4096,
+                    vec![partition(
+                        "boot",
+                        4096,
+                        vec![operation(OperationType::Zero, None, vec![extent(last_block, 1)])],
+                    )],
+                ),
+                &[],
+            );
+            let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress());
+            match result {
+                Err(PayloadError::InvalidInput(message)) => {
+                    assert!(message.contains("out of range extent"), "{}", message)
                 }
+                other => panic!("expected InvalidInput, got {:?}", other),
             }
+            assert!(!out_dir.exists());
         }
+    }
 
-        // Flush and sync
-        writer.flush().map_err(|e| {
-            PayloadError::Io(format!("Flush failed for {}: {}", partition_name, e))
-        })?;
+    #[test]
+    fn test_raw_mode_writes_blobs_and_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blob = vec![1u8; 4096];
+        blob.extend_from_slice(&[2u8; 100]);
+        let mut bsdiff = operation(OperationType::SourceBsdiff, Some((4096, 100)), vec![extent(1, 1)]);
+        bsdiff.src_extents = vec![extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    3 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        bsdiff,
+                        operation(OperationType::Zero, None, vec![extent(2, 1)]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+        let options = ExtractOptions {
+            raw: true,
+            verify_operations: true,
+            ..Default::default()
+        };
 
-        // Get final file size
-        let final_size = std::fs::metadata(&output_file_path)
-            .map(|m| m.len())
-            .unwrap_or(0);
+        // Operations that can't be applied are still dumped
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+        let boot = &result.extracted[0];
+        assert_eq!(boot.size, 4196);
+        assert!(!boot.verified);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), blob);
+
+        let sidecar: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out_dir.join("boot.img.ops.json")).unwrap()).unwrap();
+        let operations = sidecar["operations"].as_array().unwrap();
+        assert_eq!(operations.len(), 3);
+        assert_eq!(operations[1]["operation_type"], "SOURCE_BSDIFF");
+        assert_eq!(operations[1]["offset"], 4096);
+        assert_eq!(operations[1]["length"], 100);
+        assert_eq!(operations[1]["src_extents"][0]["num_blocks"], 1);
+        assert!(operations[2]["payload_offset"].is_null());
+        assert_eq!(sidecar["partition_size"], 3 * 4096);
 
-        log::info!("  ✓ Extracted: {} bytes", final_size);
+        let sparse_raw = ExtractOptions {
+            output_sparse: true,
+            ..options
+        };
+        assert!(matches!(
+            extract_payload(&payload, out_dir.to_str().unwrap(), &sparse_raw, no_progress()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
 
-        // Update bytes processed
-        bytes_processed += partition_size;
+    #[test]
+    fn test_json_carries_exact_sizes() {
+        let dir = tempfile::tempdir().unwrap();
+        let huge = (1u64 << 53) + 1;
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("system", huge, vec![]), partition("boot", 4096, vec![])]),
+            &[],
+        );
 
-        // Report progress after partition completion
-        if let Some(ref mut callback) = progress_callback {
-            let progress_percent = if total_bytes > 0 {
-                ((bytes_processed as f64 / total_bytes as f64) * 100.0) as i32
-            } else {
-                100
+        let json: serde_json::Value = serde_json::from_str(&inspect_payload_json(&payload).unwrap()).unwrap();
+        assert_eq!(json["total_size_str"], (huge + 4096).to_string());
+        assert_eq!(json["header"]["data_offset_str"], json["header"]["data_offset"].to_string());
+        assert_eq!(json["partitions"][1]["size_str"], huge.to_string());
+        assert_eq!(json["partitions"][1]["size"].as_u64(), Some(huge));
+        assert!(json.get("block_size_str").is_some());
+        assert!(json.get("max_timestamp_str").is_none());
+
+        // Fields keep their declaration order
+        let keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        assert_eq!(keys[..2], ["header", "block_size"]);
+    }
+
+    #[test]
+    fn test_block_size_override() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob: Vec<u8> = (0..8192).map(|i| (i % 251) as u8).collect();
+        // Extents are in 4096-byte blocks, but the manifest claims 3000
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                3000,
+                vec![partition(
+                    "boot",
+                    8192,
+                    vec![operation(OperationType::Replace, Some((0, 8192)), vec![extent(0, 2)])],
+                )],
+            ),
+            &blob,
+        );
+        let out_dir = dir.path().join("out");
+        let extract = |block_size| {
+            let options = ExtractOptions {
+                block_size,
+                ..Default::default()
             };
-            callback(partition_name, progress_percent, bytes_processed as i64, total_bytes as i64);
+            extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress())
+        };
+
+        assert!(matches!(extract(None), Err(PayloadError::InvalidInput(_))));
+        assert!(matches!(extract(Some(1000)), Err(PayloadError::InvalidInput(_))));
+
+        let result = extract(Some(4096)).unwrap();
+        assert_eq!(result.extracted[0].size, 8192);
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), blob);
+    }
+
+    #[test]
+    fn test_progress_in_compressed_bytes() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Zero, None, vec![extent(1, 2)]),
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(3, 1)]),
+                    ],
+                )],
+            ),
+            &[1u8; 2 * 4096],
+        );
+        let out_dir = dir.path().join("out");
+
+        let options = ExtractOptions {
+            progress_interval_ms: 0,
+            progress_unit: ProgressUnit::Compressed,
+            ..Default::default()
+        };
+        let mut reported = Vec::new();
+        extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &options,
+            Some(|update: &ProgressUpdate| {
+                reported.push((update.bytes_processed, update.total_bytes, update.percent))
+            }),
+        )
+        .unwrap();
+
+        // The zero operation reads nothing from the payload, so it makes no progress
+        let total = 2 * 4096;
+        assert_eq!(
+            reported,
+            vec![
+                (0, 0, 0),
+                (0, total, 0),
+                (4096, total, 50),
+                (4096, total, 50),
+                (total, total, 100),
+                (total, total, 100),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_extract_partition_to_vec() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition(
+                        "system",
+                        4096,
+                        vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])],
+                    ),
+                    partition(
+                        "boot",
+                        3 * 4096,
+                        vec![
+                            operation(OperationType::Replace, Some((4096, 4096)), vec![extent(2, 1)]),
+                            operation(OperationType::Zero, None, vec![extent(0, 1)]),
+                        ],
+                    ),
+                ],
+            ),
+            &blob,
+        );
+
+        // Blocks no operation writes are zeros up to the partition size
+        let image = extract_partition_to_vec(&payload, "boot", None).unwrap();
+        assert_eq!(image, [vec![0u8; 2 * 4096], vec![2u8; 4096]].concat());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        match extract_partition_to_vec(&payload, "boot", Some(2 * 4096)) {
+            Err(PayloadError::InvalidInput(msg)) => assert_eq!(
+                msg,
+                "Partition boot is 12.00 KB, more than the in-memory limit of 8.00 KB"
+            ),
+            other => panic!("Expected InvalidInput, got {:?}", other),
         }
+        assert!(matches!(
+            extract_partition_to_vec(&payload, "vendor", None),
+            Err(PayloadError::InvalidInput(_))
+        ));
 
-        extracted.push(ExtractedPartition {
-            name: partition_name.clone(),
-            size: final_size,
-            path: output_file_path.to_string_lossy().to_string(),
-        });
+        // Output of an operation without destination extents counts too
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "boot",
+                    4096,
+                    vec![
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+        assert_eq!(extract_partition_to_vec(&payload, "boot", None).unwrap(), blob);
+        match extract_partition_to_vec(&payload, "boot", Some(4096)) {
+            Err(PayloadError::InvalidInput(msg)) => assert_eq!(
+                msg,
+                "Partition boot is 8.00 KB, more than the in-memory limit of 4.00 KB"
+            ),
+            other => panic!("Expected InvalidInput, got {:?}", other),
+        }
     }
 
-    log::info!("=== PAYLOAD EXTRACTION COMPLETE ===");
-    log::info!("Extracted {} partitions", extracted.len());
+    #[test]
+    fn test_inspection_summary() {
+        let dir = tempfile::tempdir().unwrap();
+        let blob = vec![1u8; 4096];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("system", 1 << 30, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                    partition("boot", 1 << 26, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                ],
+            ),
+            &blob,
+        );
 
-    Ok(ExtractionResult {
-        status: "success".to_string(),
-        extracted,
-    })
-}
+        assert_eq!(inspect_payload_summary(&payload).unwrap(), "2 partitions, 1.06 GB total, full update");
 
-/// Decompress XZ/LZMA compressed data
-fn decompress_xz(data: &[u8]) -> Result<Vec<u8>, PayloadError> {
-    use std::io::Read;
+        let mut inspection = inspect_payload(&payload).unwrap();
+        assert_eq!((inspection.partition_count, inspection.total_operations), (2, 2));
+        inspection.build_fingerprint = Some("google/oriole/oriole:14/UQ1A.240105.004/11206848:user/release-keys".to_string());
+        inspection.security_patch_level = Some("2024-03-05".to_string());
+        inspection.partitions.truncate(1);
+        assert_eq!(
+            inspection.summary_lines(),
+            vec!["Android 14", "1 partition", "1.06 GB total", "full update", "security patch 2024-03-05"]
+        );
 
-    let mut decompressor = xz2::read::XzDecoder::new(data);
-    let mut decompressed = Vec::new();
+        assert_eq!(android_release("google/raven/raven:15/AP4A.250105.002/12701944:user/release-keys"), Some("15"));
+        assert_eq!(android_release("garbage"), None);
+    }
 
-    decompressor.read_to_end(&mut decompressed).map_err(|e| {
-        PayloadError::Io(format!("XZ decompression failed: {}", e))
-    })?;
+    #[test]
+    fn test_timeout_removes_unfinished_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut copy = operation(OperationType::SourceCopy, None, vec![extent(0, 1)]);
+        copy.src_extents = vec![extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("boot", 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                    partition("vendor", 4096, vec![copy]),
+                ],
+            ),
+            &[7u8; 4096],
+        );
+        assert!(inspect_payload_with_timeout(&payload, Duration::from_secs(30)).is_ok());
 
-    Ok(decompressed)
-}
+        // Opening a FIFO blocks until a writer shows up: a read that hangs
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir(&source_dir).unwrap();
+        let fifo = source_dir.join("vendor.img");
+        let c_fifo = std::ffi::CString::new(fifo.to_str().unwrap()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_fifo.as_ptr(), 0o600) }, 0);
 
-/// Decompress bzip2 compressed data
-fn decompress_bz2(data: &[u8]) -> Result<Vec<u8>, PayloadError> {
-    use std::io::Read;
+        let out_dir = dir.path().join("out");
+        let options = ExtractOptions {
+            source_dir: Some(source_dir.to_string_lossy().to_string()),
+            timeout_ms: 300,
+            force: true,
+            ..Default::default()
+        };
+        let zip_path = write_test_zip(dir.path(), &payload, zip::CompressionMethod::Stored);
+        let out = out_dir.to_str().unwrap();
 
-    let mut decompressor = bzip2::read::BzDecoder::new(data);
-    let mut decompressed = Vec::new();
+        // Every entry point writing to a directory honours the timeout
+        for entry_point in ["path", "file", "zip"] {
+            let _ = std::fs::remove_dir_all(&out_dir);
+            let mut updates = 0;
+            let progress = Some(|_: &ProgressUpdate| updates += 1);
+            let result = match entry_point {
+                "path" => extract_payload(&payload, out, &options, progress),
+                "file" => extract_payload_file(File::open(&payload).unwrap(), out, &options, progress),
+                _ => extract_payload_zip(&zip_path, out, &options, progress),
+            };
 
-    decompressor.read_to_end(&mut decompressed).map_err(|e| {
-        PayloadError::Io(format!("Bzip2 decompression failed: {}", e))
-    })?;
+            let error = result.unwrap_err();
+            assert_eq!(error.code(), "TIMEOUT", "{}", entry_point);
+            assert!(updates > 0, "{}", entry_point);
+            assert!(out_dir.join("boot.img").exists(), "{}", entry_point);
+            assert!(!out_dir.join("vendor.img").exists(), "{}", entry_point);
 
-    Ok(decompressed)
-}
+            // Let the blocked thread go
+            drop(std::fs::OpenOptions::new().write(true).open(&fifo).unwrap());
+        }
 
-/// Extract payload and return JSON result
-pub fn extract_payload_json<F>(
-    payload_path: &str,
-    output_dir: &str,
-    progress_callback: Option<F>
-) -> Result<String, String>
-where
-    F: FnMut(&str, i32, i64, i64) + Send,
-{
-    log::info!("extract_payload_json called");
+        // Caller-provided files can't be cleaned up after a timeout
+        let open_output = |name: &str| -> Result<(File, String), PayloadError> {
+            Ok((File::create(dir.path().join(name))?, name.to_string()))
+        };
+        assert!(matches!(
+            extract_payload_to_files(&payload, &options, open_output, no_progress()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
 
-    match extract_payload(payload_path, output_dir, progress_callback) {
-        Ok(result) => {
-            match serde_json::to_string(&result) {
-                Ok(json) => Ok(json),
-                Err(e) => Err(format!("JSON serialization error: {}", e)),
-            }
+    #[test]
+    fn test_operations_stay_clear_of_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let replace = operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]);
+        let mut signed = manifest(4096, vec![partition("boot", 4096, vec![replace])]);
+        signed.signatures_offset = Some(4096);
+        signed.signatures_size = Some(16);
+        let mut blob = vec![3u8; 4096];
+        blob.extend_from_slice(&[0xAA; 16]);
+        let payload = write_test_payload(dir.path(), &signed, &blob);
+
+        let inspection = inspect_payload(&payload).unwrap();
+        assert_eq!((inspection.signatures_offset, inspection.signatures_size), (Some(4096), Some(16)));
+        let out_dir = dir.path().join("out");
+        extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).unwrap();
+        assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), vec![3u8; 4096]);
+
+        // A signature recorded inside the operation data
+        signed.signatures_offset = Some(4080);
+        let payload = write_test_payload(dir.path(), &signed, &blob);
+        let out_dir = dir.path().join("out2");
+        let result = extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress());
+        match result {
+            Err(PayloadError::InvalidInput(message)) => assert!(message.contains("overlaps the payload signature")),
+            other => panic!("expected InvalidInput, got {:?}", other.map(|r| r.status)),
         }
-        Err(e) => {
-            log::error!("Extraction failed: {}", e);
-            Err(e.to_string())
+    }
+
+    #[test]
+    fn test_manifest_limits() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let partitions = (0..=MAX_PARTITIONS).map(|i| partition(&format!("p{}", i), 0, Vec::new())).collect();
+        let payload = write_test_payload(dir.path(), &manifest(4096, partitions), &[]);
+        match inspect_payload(&payload) {
+            Err(PayloadError::InvalidInput(message)) => assert!(message.contains("1025 partitions")),
+            other => panic!("expected InvalidInput, got {:?}", other.map(|i| i.partition_count)),
+        }
+
+        let op = operation(OperationType::Zero, None, vec![extent(0, 1); MAX_EXTENTS_PER_OPERATION + 1]);
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 4096, vec![op])]), &[]);
+        match inspect_payload(&payload) {
+            Err(PayloadError::InvalidInput(message)) => assert!(message.contains("extents")),
+            other => panic!("expected InvalidInput, got {:?}", other.map(|i| i.partition_count)),
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_apply_incremental() {
+        let dir = tempfile::tempdir().unwrap();
+        let source_dir = dir.path().join("source");
+        std::fs::create_dir(&source_dir).unwrap();
+        std::fs::write(source_dir.join("system.img"), [vec![1u8; 4096], vec![2u8; 4096]].concat()).unwrap();
+
+        // Uncompressed BSDF2 patch adding 4 to each byte of the source block
+        let mut patch = b"BSDF2\0\0\0".to_vec();
+        for value in [24u64, 4096, 4096, 4096, 0, 0] {
+            patch.extend_from_slice(&value.to_le_bytes());
+        }
+        patch.extend_from_slice(&[4u8; 4096]);
+
+        let mut copy = operation(OperationType::SourceCopy, None, vec![extent(0, 1)]);
+        copy.src_extents = vec![extent(1, 1)];
+        let mut bsdiff = operation(OperationType::SourceBsdiff, Some((0, patch.len() as u64)), vec![extent(1, 1)]);
+        bsdiff.src_extents = vec![extent(0, 1)];
+        let replace = operation(OperationType::Replace, Some((patch.len() as u64, 4096)), vec![extent(2, 1)]);
+
+        let expected = [vec![2u8; 4096], vec![5u8; 4096], vec![9u8; 4096]].concat();
+        let mut system = partition("system", 3 * 4096, vec![copy, bsdiff, replace]);
+        system.new_partition_info.as_mut().unwrap().hash = Some(Sha256::digest(&expected).to_vec());
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![system]),
+            &[patch, vec![9u8; 4096]].concat(),
+        );
+
+        let out_dir = dir.path().join("out");
+        let result = apply_incremental(&payload, source_dir.to_str().unwrap(), out_dir.to_str().unwrap(), no_progress()).unwrap();
+        assert!(result.extracted[0].verified);
+        assert!(result.warnings.is_empty());
+        assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), expected);
+
+        let missing = dir.path().join("missing");
+        assert!(matches!(
+            apply_incremental(&payload, missing.to_str().unwrap(), out_dir.to_str().unwrap(), no_progress()),
+            Err(PayloadError::FileNotFound(_))
+        ));
+    }
 
     #[test]
-    fn test_format_size() {
-        assert_eq!(format_size(0), "0 B");
-        assert_eq!(format_size(512), "512 B");
-        assert_eq!(format_size(1024), "1.00 KB");
-        assert_eq!(format_size(1536), "1.50 KB");
-        assert_eq!(format_size(1048576), "1.00 MB");
-        assert_eq!(format_size(1073741824), "1.00 GB");
+    fn test_read_payload_header() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(4096, vec![partition("boot", 4096, Vec::new())]);
+        let payload = write_test_payload(dir.path(), &manifest, &[0u8; 100]);
+
+        let info = read_payload_header(&payload).unwrap();
+        let manifest_size = manifest.encode_to_vec().len() as u64;
+        assert_eq!(info.header.data_offset, HEADER_SIZE + manifest_size);
+        assert_eq!((info.manifest_offset, info.metadata_signature_offset), (24, 24 + manifest_size));
+        assert_eq!(info.data_size, 100);
+        assert!(info.header_bytes.starts_with("437241550000000000000002"));
+
+        let json: serde_json::Value = serde_json::from_str(&read_payload_header_json(&payload).unwrap()).unwrap();
+        assert_eq!(json["data_offset"], info.header.data_offset);
+        assert_eq!(json["data_offset_str"], info.header.data_offset.to_string());
     }
 
     #[test]
-    fn test_empty_path_error() {
-        let result = inspect_payload("");
-        assert!(result.is_err());
-        if let Err(PayloadError::EmptyPath) = result {
-            // Expected
-        } else {
-            panic!("Expected EmptyPath error");
+    fn test_continue_on_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blob = vec![0xAB; 4096];
+        blob.extend_from_slice(b"not an xz stream");
+        let mut unsupported = operation(OperationType::Puffdiff, Some((0, 4)), vec![extent(0, 1)]);
+        unsupported.src_extents = vec![extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![
+                    partition("boot", 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                    partition("system", 4096, vec![unsupported]),
+                    partition("vendor", 4096, vec![operation(OperationType::ReplaceXz, Some((4096, 16)), vec![extent(0, 1)])]),
+                    partition("odm", 4096, vec![operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)])]),
+                ],
+            ),
+            &blob,
+        );
+
+        // Without it, the first failure aborts the extraction
+        let out_dir = dir.path().join("out");
+        assert!(extract_payload(&payload, out_dir.to_str().unwrap(), &ExtractOptions::default(), no_progress()).is_err());
+
+        for max_threads in [1, 2] {
+            let out_dir = dir.path().join(format!("out{}", max_threads));
+            let options = ExtractOptions {
+                continue_on_error: true,
+                max_threads,
+                ..Default::default()
+            };
+            let result = extract_payload(&payload, out_dir.to_str().unwrap(), &options, no_progress()).unwrap();
+
+            assert_eq!(result.status, "partial");
+            let extracted: Vec<&str> = result.extracted.iter().map(|p| p.name.as_str()).collect();
+            assert_eq!(extracted, ["boot", "odm"]);
+            let failed: Vec<(&str, &str)> = result.failed.iter().map(|p| (p.name.as_str(), p.code.as_str())).collect();
+            assert_eq!(failed, [("system", "OPERATION_FAILED"), ("vendor", "IO_ERROR")]);
+            assert!(result.failed[0].error.contains("PUFFDIFF"));
+
+            assert_eq!(std::fs::read(out_dir.join("odm.img")).unwrap(), vec![0xAB; 4096]);
+            assert!(!out_dir.join("system.img").exists());
+            assert!(!out_dir.join("vendor.img").exists());
         }
     }
 
     #[test]
-    fn test_nonexistent_file_error() {
-        let result = inspect_payload("/nonexistent/path/to/file.bin");
-        assert!(result.is_err());
-        if let Err(PayloadError::FileNotFound(_)) = result {
-            // Expected
-        } else {
-            panic!("Expected FileNotFound error");
+    fn test_diff_payloads() {
+        let dir = tempfile::tempdir().unwrap();
+        let with_hash = |name: &str, size: u64, hash: u8| {
+            let mut partition = partition(name, size, vec![]);
+            if let Some(info) = partition.new_partition_info.as_mut() {
+                info.hash = Some(vec![hash; 32]);
+            }
+            partition
+        };
+        let dir_a = dir.path().join("a");
+        let dir_b = dir.path().join("b");
+        std::fs::create_dir(&dir_a).unwrap();
+        std::fs::create_dir(&dir_b).unwrap();
+        let a = write_test_payload(
+            &dir_a,
+            &manifest(
+                4096,
+                vec![
+                    with_hash("boot", 8192, 1),
+                    with_hash("system", 16384, 2),
+                    with_hash("odm", 4096, 3),
+                    partition("vbmeta", 4096, vec![]),
+                ],
+            ),
+            &[],
+        );
+        let b = write_test_payload(
+            &dir_b,
+            &manifest(
+                4096,
+                vec![
+                    with_hash("boot", 8192, 1),
+                    with_hash("system", 12288, 4),
+                    partition("vbmeta", 4096, vec![]),
+                    with_hash("vendor_dlkm", 4096, 5),
+                ],
+            ),
+            &[],
+        );
+
+        let diff = diff_payloads(&a, &b).unwrap();
+        let names = |partitions: &[PartitionInfo]| partitions.iter().map(|p| p.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&diff.added), ["vendor_dlkm"]);
+        assert_eq!(names(&diff.removed), ["odm"]);
+
+        let common: Vec<(&str, i64, Option<bool>, bool)> = diff
+            .partitions
+            .iter()
+            .map(|p| (p.name.as_str(), p.size_delta, p.hash_changed, p.changed))
+            .collect();
+        assert_eq!(
+            common,
+            [
+                ("boot", 0, Some(false), false),
+                ("system", -4096, Some(true), true),
+                ("vbmeta", 0, None, false),
+            ]
+        );
+        assert_eq!(diff.changed_count, 1);
+        assert_eq!((diff.total_size_a, diff.total_size_b, diff.total_size_delta), (32768, 28672, -4096));
+
+        // Sizes and deltas have exact string copies like every other size
+        let json: serde_json::Value = serde_json::from_str(&diff_payloads_json(&a, &b).unwrap()).unwrap();
+        assert_eq!(json["total_size_a_str"], "32768");
+        assert_eq!(json["total_size_b_str"], "28672");
+        assert_eq!(json["total_size_delta_str"], "-4096");
+        let system = &json["partitions"][1];
+        assert_eq!(system["name"], "system");
+        assert_eq!((&system["size_a_str"], &system["size_b_str"]), (&"16384".into(), &"12288".into()));
+        assert_eq!(system["size_delta_str"], "-4096");
+        assert_eq!(json["added"][0]["size_str"], "4096");
+        assert!(system.get("hash_a_str").is_none());
+
+        assert!(diff_payloads(&a, "/nonexistent/payload.bin").is_err());
+    }
+
+    #[test]
+    fn test_manifest_decode_error_location() {
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = manifest(4096, vec![partition("boot", 4096, vec![]), partition("vendor", 4096, vec![])]);
+        let payload = write_test_payload(dir.path(), &manifest, &[]);
+
+        // Make the second partition name invalid UTF-8
+        let mut data = std::fs::read(&payload).unwrap();
+        let name_at = data.windows(6).position(|window| window == b"vendor").unwrap();
+        data[name_at] = 0xFF;
+        std::fs::write(&payload, &data).unwrap();
+
+        // The key of partitions[1] precedes its length and name
+        let manifest_bytes = &data[24..];
+        let (position, field) = locate_decode_failure(manifest_bytes);
+        assert_eq!(field, "partitions[1]");
+        assert_eq!(manifest_bytes[position], 13 << 3 | 2);
+        assert!(position < name_at - 24);
+
+        match inspect_payload(&payload) {
+            Err(PayloadError::ProtobufDecode(message)) => {
+                assert!(message.contains("partitions[1]"), "{}", message);
+                assert!(message.contains(&format!("byte {} of the manifest (file offset {})", position, position + 24)));
+                assert!(message.contains(&hex_prefix(manifest_bytes, 16)));
+            }
+            other => panic!("Expected ProtobufDecode, got {:?}", other),
         }
     }
 
     #[test]
-    fn test_big_endian_version() {
-        // Version 2 in big endian: 0x00 0x00 0x00 0x00 0x00 0x00 0x00 0x02
-        let version_bytes: [u8; 8] = [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02];
-        let version = u64::from_be_bytes(version_bytes);
-        assert_eq!(version, 2);
+    fn test_manifest_size_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = write_test_payload(dir.path(), &manifest(4096, vec![partition("boot", 4096, vec![])]), &[]);
+        let manifest_size = inspect_payload(&payload).unwrap().header.manifest_size;
+
+        assert!(inspect_payload_with_manifest_limit(&payload, manifest_size).is_ok());
+        let error = inspect_payload_with_manifest_limit(&payload, manifest_size - 1).unwrap_err();
+        assert!(matches!(error, PayloadError::ManifestTooLarge(size, limit) if size == manifest_size && limit == manifest_size - 1));
+        assert!(error.to_string().contains(&format!("max {} bytes", manifest_size - 1)));
+
+        // Larger than the default limit, but within a raised one
+        let mut data = std::fs::read(&payload).unwrap();
+        data[12..20].copy_from_slice(&(DEFAULT_MAX_MANIFEST_SIZE + 1).to_be_bytes());
+        std::fs::write(&payload, &data).unwrap();
+        assert!(matches!(inspect_payload(&payload), Err(PayloadError::ManifestTooLarge(..))));
+        assert!(matches!(
+            inspect_payload_with_manifest_limit(&payload, 2 * DEFAULT_MAX_MANIFEST_SIZE),
+            Err(PayloadError::TruncatedManifest(..))
+        ));
+
+        // A forged header claiming a huge manifest, under a limit raised as
+        // far as it goes, is rejected before anything is allocated
+        let mut forged = data[..HEADER_SIZE as usize].to_vec();
+        forged[12..20].copy_from_slice(&(1u64 << 63).to_be_bytes());
+        std::fs::write(&payload, &forged).unwrap();
+        assert!(matches!(
+            inspect_payload_with_manifest_limit(&payload, u64::MAX),
+            Err(PayloadError::TruncatedManifest(size, 0)) if size == 1 << 63
+        ));
+        assert!(matches!(
+            parse_header_with_limit(&mut &forged[..], 1 << 20, u64::MAX),
+            Err(PayloadError::TruncatedManifest(size, present)) if size == 1 << 63 && present == (1 << 20) - HEADER_SIZE
+        ));
+    }
+
+    #[test]
+    fn test_extract_partition_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut blob = [vec![1u8; 4096], vec![2u8; 4096]].concat();
+        // Garbage for the last operation: reading it would fail
+        blob.extend_from_slice(&[0xFFu8; 64]);
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4 * 4096,
+                    vec![
+                        operation(OperationType::Replace, Some((4096, 4096)), vec![extent(1, 1)]),
+                        operation(OperationType::Zero, None, vec![extent(2, 1)]),
+                        operation(OperationType::Replace, Some((0, 4096)), vec![extent(0, 1)]),
+                        operation(OperationType::ReplaceXz, Some((8192, 64)), vec![extent(3, 1)]),
+                    ],
+                )],
+            ),
+            &blob,
+        );
+
+        // Output past the prefix is dropped, operations beyond it are skipped
+        let prefix = extract_partition_prefix(&payload, "system", 4100).unwrap();
+        assert_eq!(prefix, [vec![1u8; 4096], vec![2u8; 4]].concat());
+        let prefix = extract_partition_prefix(&payload, "system", 3 * 4096).unwrap();
+        assert_eq!(prefix, [vec![1u8; 4096], vec![2u8; 4096], vec![0u8; 4096]].concat());
+        assert!(extract_partition_prefix(&payload, "system", 0).unwrap().is_empty());
+
+        // Reading into the corrupt operation fails like a full extraction
+        assert!(extract_partition_prefix(&payload, "system", 4 * 4096).is_err());
+        assert!(matches!(
+            extract_partition_prefix(&payload, "vendor", 16),
+            Err(PayloadError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            extract_partition_prefix(&payload, "system", DEFAULT_IN_MEMORY_LIMIT + 1),
+            Err(PayloadError::InvalidInput(_))
+        ));
+
+        // An extent ending past the u64 range is rejected, not sized
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(
+                4096,
+                vec![partition(
+                    "system",
+                    4096,
+                    vec![operation(OperationType::Zero, None, vec![extent(u64::MAX - 1, 2)])],
+                )],
+            ),
+            &[],
+        );
+        match extract_partition_prefix(&payload, "system", 16) {
+            Err(PayloadError::InvalidInput(message)) => assert!(message.contains("out of range extent"), "{}", message),
+            other => panic!("expected InvalidInput, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_source_copy_requires_source_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut op = operation(OperationType::SourceCopy, None, vec![extent(0, 1)]);
+        op.src_extents = vec![extent(0, 1)];
+        let payload = write_test_payload(
+            dir.path(),
+            &manifest(4096, vec![partition("vendor", 4096, vec![op])]),
+            &[],
+        );
+        let out_dir = dir.path().join("out");
+
+        let result = extract_payload(
+            &payload,
+            out_dir.to_str().unwrap(),
+            &ExtractOptions::default(),
+            no_progress(),
+        );
+        assert!(matches!(result, Err(PayloadError::OperationFailed(_))));
     }
 }
@@ -106,6 +106,12 @@ pub mod install_operation {
         Zucchini = 11,
         Lz4diffBsdiff = 12,
         Lz4diffPuffdiff = 13,
+        /// Zstandard-compressed replacement data
+        Zstd = 14,
+        /// Brotli-compressed replacement data. Not in upstream AOSP's
+        /// update_metadata.proto, whose last type is ZSTD = 14: a local extension
+        /// on the next free number. If upstream ever assigns 15, this must move.
+        ReplaceBrotli = 15,
     }
 
     impl Type {
@@ -125,6 +131,8 @@ pub mod install_operation {
                 Type::Zucchini => "ZUCCHINI",
                 Type::Lz4diffBsdiff => "LZ4DIFF_BSDIFF",
                 Type::Lz4diffPuffdiff => "LZ4DIFF_PUFFDIFF",
+                Type::Zstd => "ZSTD",
+                Type::ReplaceBrotli => "REPLACE_BROTLI",
             }
         }
     }
@@ -0,0 +1,388 @@
+//! Remote Payload Module
+//!
+//! Inspects a payload.bin hosted on an HTTP(S) server without downloading it.
+//! Only the header and manifest are fetched, using HTTP range requests:
+//!
+//! - The first request asks for the first `PROBE_SIZE` bytes, which holds the
+//!   header and usually part of the manifest.
+//! - Further reads fetch exactly the missing range (at least `MIN_FETCH_SIZE`).
+//! - If the server ignores the Range header and sends the whole file (200 OK),
+//!   the body is read sequentially and the connection is dropped as soon as
+//!   the manifest is decoded, so the rest of the file is never downloaded.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::time::Duration;
+
+use crate::ota_zip;
+use crate::payload::{self, PayloadError, PayloadInspection};
+use crate::signature;
+
+/// Bytes requested up front: the header plus the start of the manifest
+const PROBE_SIZE: u64 = 64 * 1024;
+
+/// Smallest range fetched when a read falls outside the buffered bytes
+const MIN_FETCH_SIZE: u64 = 64 * 1024;
+
+/// Timeout for connecting and for each read from the server
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Inspect a payload.bin served over HTTP(S).
+///
+/// Produces the same result as `inspect_payload`, except that
+/// payload_properties.txt is not looked up.
+///
+/// # Arguments
+/// * `url` - http:// or https:// URL of the payload.bin
+///
+/// # Returns
+/// * `Ok(PayloadInspection)` - Parsed payload information
+/// * `Err(PayloadError)` - Invalid URL, HTTP failure or invalid payload
+pub fn inspect_payload_url(url: &str) -> Result<PayloadInspection, PayloadError> {
+    log::info!("=== REMOTE PAYLOAD INSPECTION START ===");
+    log::info!("URL: {}", url);
+
+    if url.trim().is_empty() {
+        return Err(PayloadError::EmptyPath);
+    }
+    if !(url.starts_with("http://") || url.starts_with("https://")) {
+        return Err(PayloadError::InvalidInput(format!(
+            "Not an http(s) URL: {}",
+            url
+        )));
+    }
+
+    let mut reader = HttpRangeReader::open(url)?;
+    // Without a known length the size check in the header parser is moot
+    let size = reader.size.unwrap_or(u64::MAX);
+    let (header, manifest) = payload::read_header_and_manifest(&mut reader, size)?;
+
+    // The metadata signature follows the manifest; the payload signature at
+    // the end would cost another request, or the whole download without ranges
+    let signatures = signature::read_signatures(&mut reader, size, &header, &manifest, false);
+
+    log::info!("Fetched {} bytes in {} requests", reader.fetched, reader.requests);
+
+    let mut inspection = payload::build_inspection(url, header, &manifest)?;
+    inspection.signatures = signatures;
+    Ok(inspection)
+}
+
+/// Inspect a remote payload and return JSON string
+///
+/// Same output as `inspect_payload_json`.
+pub fn inspect_payload_url_json(url: &str) -> Result<String, PayloadError> {
+    log::info!("inspect_payload_url_json called with url: {}", url);
+
+    match inspect_payload_url(url) {
+        Ok(inspection) => payload::to_json(&inspection, true),
+        Err(e) => {
+            log::error!("Remote payload inspection failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Seekable reader over a remote file, backed by HTTP range requests
+struct HttpRangeReader {
+    agent: ureq::Agent,
+    url: String,
+    /// Total size of the remote file, if the server reported it
+    size: Option<u64>,
+    pos: u64,
+    /// Bytes fetched most recently and their offset in the file
+    buffer: Vec<u8>,
+    buffer_start: u64,
+    /// Response body of a server that ignored the Range header, and the
+    /// file offset it has been read up to
+    stream: Option<(Box<dyn Read + Send + Sync>, u64)>,
+    /// Statistics for the log
+    requests: usize,
+    fetched: u64,
+}
+
+impl HttpRangeReader {
+    /// Probe the server with a range request for the start of the file
+    fn open(url: &str) -> Result<Self, PayloadError> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(HTTP_TIMEOUT)
+            .timeout_read(HTTP_TIMEOUT)
+            .build();
+
+        let mut reader = Self {
+            agent,
+            url: url.to_string(),
+            size: None,
+            pos: 0,
+            buffer: Vec::new(),
+            buffer_start: 0,
+            stream: None,
+            requests: 0,
+            fetched: 0,
+        };
+
+        let response = reader.request(0, PROBE_SIZE)?;
+        match response.status() {
+            206 => {
+                reader.size = response
+                    .header("Content-Range")
+                    .and_then(total_from_content_range);
+                log::info!("Server supports range requests, file size {:?}", reader.size);
+                reader.buffer = read_body(response, PROBE_SIZE)?;
+                reader.fetched += reader.buffer.len() as u64;
+            }
+            200 => {
+                reader.size = response
+                    .header("Content-Length")
+                    .and_then(|value| value.trim().parse().ok());
+                log::warn!(
+                    "Server ignored the Range header, reading {:?} bytes sequentially",
+                    reader.size
+                );
+                reader.stream = Some((response.into_reader(), 0));
+            }
+            status => {
+                return Err(PayloadError::Io(format!(
+                    "Unexpected HTTP status {} for {}",
+                    status, url
+                )))
+            }
+        }
+
+        Ok(reader)
+    }
+
+    /// Send a GET for `len` bytes starting at `start`
+    fn request(&mut self, start: u64, len: u64) -> Result<ureq::Response, PayloadError> {
+        let range = format!("bytes={}-{}", start, start + len - 1);
+        log::debug!("GET {} Range: {}", self.url, range);
+        self.requests += 1;
+
+        self.agent
+            .get(&self.url)
+            .set("Range", &range)
+            .call()
+            .map_err(|e| PayloadError::Io(format!("HTTP request failed: {}", e)))
+    }
+
+    /// Fetch at least `want` bytes at the current position into the buffer
+    fn fill_buffer(&mut self, want: u64) -> std::io::Result<()> {
+        let mut len = want.max(MIN_FETCH_SIZE);
+        if let Some(size) = self.size {
+            len = len.min(size.saturating_sub(self.pos));
+        }
+        if len == 0 {
+            self.buffer.clear();
+            self.buffer_start = self.pos;
+            return Ok(());
+        }
+
+        let response = self.request(self.pos, len).map_err(std::io::Error::other)?;
+        if response.status() != 206 {
+            return Err(std::io::Error::other(format!(
+                "Expected 206 Partial Content, got HTTP {}",
+                response.status()
+            )));
+        }
+
+        self.buffer = read_body(response, len).map_err(std::io::Error::other)?;
+        self.buffer_start = self.pos;
+        self.fetched += self.buffer.len() as u64;
+        Ok(())
+    }
+
+    /// Read from a server that doesn't support ranges: skip forward to the
+    /// current position, then read straight from the body
+    fn read_stream(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some((body, offset)) = self.stream.as_mut() else {
+            return Ok(0);
+        };
+
+        if self.pos < *offset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "backward seek on a server without range support",
+            ));
+        }
+        if self.pos > *offset {
+            let skip = self.pos - *offset;
+            let skipped = std::io::copy(&mut body.take(skip), &mut std::io::sink())?;
+            *offset += skipped;
+            if skipped < skip {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+
+        let n = body.read(buf)?;
+        *offset += n as u64;
+        self.pos += n as u64;
+        self.fetched += n as u64;
+        Ok(n)
+    }
+}
+
+impl Read for HttpRangeReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() || self.size.is_some_and(|size| self.pos >= size) {
+            return Ok(0);
+        }
+        if self.stream.is_some() {
+            return self.read_stream(buf);
+        }
+
+        let buffer_end = self.buffer_start + self.buffer.len() as u64;
+        if self.pos < self.buffer_start || self.pos >= buffer_end {
+            self.fill_buffer(buf.len() as u64)?;
+        }
+
+        let offset = (self.pos - self.buffer_start) as usize;
+        let available = &self.buffer[offset.min(self.buffer.len())..];
+        let n = available.len().min(buf.len());
+        buf[..n].copy_from_slice(&available[..n]);
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl Seek for HttpRangeReader {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.pos = ota_zip::resolve_seek(pos, self.pos, self.size.unwrap_or(u64::MAX))?;
+        Ok(self.pos)
+    }
+}
+
+/// Read a response body of at most `limit` bytes
+fn read_body(response: ureq::Response, limit: u64) -> Result<Vec<u8>, PayloadError> {
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(limit)
+        .read_to_end(&mut body)
+        .map_err(|e| PayloadError::Io(format!("Failed to read HTTP response: {}", e)))?;
+    Ok(body)
+}
+
+/// Total length from a `Content-Range: bytes 0-65535/123456` header
+fn total_from_content_range(value: &str) -> Option<u64> {
+    value.rsplit_once('/')?.1.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Serve `data` over HTTP on a local port, honouring Range headers if
+    /// `ranges` is set. Returns the URL and the number of bytes sent so far.
+    fn serve(data: Vec<u8>, ranges: bool) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/payload.bin", listener.local_addr().unwrap());
+        let sent = Arc::new(AtomicUsize::new(0));
+        let sent_by_server = sent.clone();
+
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { return };
+                let mut range = None;
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                        let (start, end) = value.trim().split_once('-').unwrap();
+                        range = Some((start.parse::<usize>().unwrap(), end.parse::<usize>().unwrap()));
+                    }
+                }
+
+                let (status, start, end) = match range {
+                    Some((start, end)) if ranges => ("206 Partial Content", start, end.min(data.len() - 1)),
+                    _ => ("200 OK", 0, data.len() - 1),
+                };
+                let body = &data[start..=end];
+                let header = format!(
+                    "HTTP/1.1 {}\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len(),
+                    start,
+                    end,
+                    data.len()
+                );
+                let _ = stream.write_all(header.as_bytes());
+                if let Ok(()) = stream.write_all(body) {
+                    sent_by_server.fetch_add(body.len(), Ordering::Relaxed);
+                }
+            }
+        });
+
+        (url, sent)
+    }
+
+    /// A payload whose manifest spans past the probe, followed by a big blob
+    fn test_payload() -> Vec<u8> {
+        use crate::proto::{DeltaArchiveManifest, PartitionInfo, PartitionUpdate};
+        use prost::Message;
+
+        let partitions = (0..1000)
+            .map(|i| PartitionUpdate {
+                partition_name: format!("partition_{:04}", i),
+                new_partition_info: Some(PartitionInfo {
+                    size: Some(4096),
+                    hash: Some(vec![i as u8; 32]),
+                }),
+                old_partition_info: Some(PartitionInfo {
+                    size: Some(4096),
+                    hash: Some(vec![!i as u8; 32]),
+                }),
+                ..Default::default()
+            })
+            .collect();
+        let manifest = DeltaArchiveManifest {
+            block_size: Some(4096),
+            partitions,
+            ..Default::default()
+        }
+        .encode_to_vec();
+        assert!(manifest.len() as u64 > PROBE_SIZE);
+
+        let mut data = Vec::new();
+        data.extend_from_slice(b"CrAU");
+        data.extend_from_slice(&2u64.to_be_bytes());
+        data.extend_from_slice(&(manifest.len() as u64).to_be_bytes());
+        data.extend_from_slice(&0u32.to_be_bytes());
+        data.extend_from_slice(&manifest);
+        data.extend_from_slice(&vec![0u8; 4 * 1024 * 1024]);
+        data
+    }
+
+    #[test]
+    fn test_inspect_payload_url_with_ranges() {
+        let data = test_payload();
+        let total = data.len();
+        let (url, sent) = serve(data, true);
+
+        let inspection = inspect_payload_url(&url).unwrap();
+        assert_eq!(inspection.partitions.len(), 1000);
+        assert_eq!(inspection.file_path, url);
+        // Only the header and manifest were transferred
+        assert!(sent.load(Ordering::Relaxed) < total / 4);
+    }
+
+    #[test]
+    fn test_inspect_payload_url_without_ranges() {
+        let (url, _) = serve(test_payload(), false);
+
+        let inspection = inspect_payload_url(&url).unwrap();
+        assert_eq!(inspection.partitions.len(), 1000);
+
+        assert!(matches!(
+            inspect_payload_url("ftp://example.com/payload.bin"),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
+}
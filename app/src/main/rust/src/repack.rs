@@ -0,0 +1,384 @@
+//! Repack Module
+//!
+//! Builds a full payload.bin from partition images, e.g. to flash an
+//! extracted image again after modifying it.
+//!
+//! Payload Layout (version 2, as read by `payload::parse_header`):
+//! - Header: "CrAU", version (u64 BE), manifest size (u64 BE), metadata
+//!   signature size (u32 BE, always 0 here)
+//! - Manifest (DeltaArchiveManifest protobuf) with one PartitionUpdate per
+//!   image, each split into operations of `OPERATION_SIZE` bytes
+//! - Operation data blobs, in manifest order
+//!
+//! Each operation is REPLACE, REPLACE_XZ (when asked for and smaller than the
+//! raw data) or ZERO for blocks that are all zeros, like update_engine's
+//! full payload generator.
+//!
+//! The result is UNSIGNED: it has neither a metadata nor a payload signature,
+//! so recovery and update_engine on a locked device reject it. It can be
+//! inspected and extracted, and flashed by tools that don't check signatures.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::payload::{self, PayloadError};
+use crate::proto::install_operation::Type as OperationType;
+use crate::proto::{DeltaArchiveManifest, Extent, InstallOperation, PartitionInfo, PartitionUpdate};
+
+/// Block size of the payloads written
+const BLOCK_SIZE: u64 = 4096;
+
+/// Bytes of image covered by each operation (update_engine's default chunk size)
+const OPERATION_SIZE: u64 = 2 * 1024 * 1024;
+
+/// XZ preset used for REPLACE_XZ data
+const XZ_PRESET: u32 = 6;
+
+/// How operation data is stored in a repacked payload
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepackCompression {
+    /// REPLACE: data stored as is. Fast, and the payload is as large as the images.
+    #[default]
+    None,
+    /// REPLACE_XZ where it makes the data smaller. Much slower to build.
+    Xz,
+}
+
+impl RepackCompression {
+    /// Parse a compression name ("none" or "xz", case-insensitive)
+    pub fn from_name(name: &str) -> Result<Self, PayloadError> {
+        match name.to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(RepackCompression::None),
+            "xz" => Ok(RepackCompression::Xz),
+            _ => Err(PayloadError::InvalidInput(format!(
+                "Unknown compression '{}', expected \"none\" or \"xz\"",
+                name
+            ))),
+        }
+    }
+}
+
+/// Summary of a repacked payload
+#[derive(Debug, Clone, Serialize)]
+pub struct RepackedPayload {
+    /// Path of the payload written
+    pub path: String,
+    /// Size of the payload in bytes
+    pub size: u64,
+    pub manifest_size: u64,
+    /// Always false: repacked payloads carry no signature
+    pub signed: bool,
+    pub partitions: Vec<RepackedPartition>,
+}
+
+/// A partition of a repacked payload
+#[derive(Debug, Clone, Serialize)]
+pub struct RepackedPartition {
+    pub name: String,
+    /// Size of the image in bytes
+    pub size: u64,
+    /// Bytes of operation data stored for it in the payload
+    pub data_size: u64,
+    pub operations_count: usize,
+    /// SHA-256 of the image (hex), as recorded in the manifest
+    pub hash: String,
+}
+
+/// Build an unsigned full payload.bin from partition images.
+///
+/// Operation data is first written to `<out_path>.data` (the manifest, which
+/// precedes it, is only known once all images have been read), then appended
+/// to the header and manifest; the temporary file is removed.
+///
+/// # Arguments
+/// * `images` - (partition name, image path) of each partition, in manifest order
+/// * `out_path` - Where to write the payload (overwritten)
+/// * `compression` - How to store the operation data
+///
+/// # Returns
+/// * `Ok(RepackedPayload)` - Size and contents of the payload
+/// * `Err(PayloadError)` - If a name is invalid or repeated, an image can't be
+///   read or isn't a whole number of 4096-byte blocks, or the output can't be
+///   written
+pub fn create_payload(
+    images: &[(String, String)],
+    out_path: &str,
+    compression: RepackCompression,
+) -> Result<RepackedPayload, PayloadError> {
+    log::info!("=== PAYLOAD REPACK START ===");
+    log::info!("Output: {}", out_path);
+    log::info!("Compression: {:?}", compression);
+
+    if images.is_empty() {
+        return Err(PayloadError::InvalidInput("No partition images given".to_string()));
+    }
+    for (i, (name, _)) in images.iter().enumerate() {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return Err(PayloadError::InvalidInput(format!("Invalid partition name '{}'", name)));
+        }
+        if images[..i].iter().any(|(other, _)| other == name) {
+            return Err(PayloadError::InvalidInput(format!("Partition {} given more than once", name)));
+        }
+    }
+
+    if let Some(parent) = Path::new(out_path).parent().filter(|p| !p.as_os_str().is_empty()) {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| PayloadError::Io(format!("Failed to create {}: {}", parent.display(), e)))?;
+    }
+
+    // === STEP 1: Write the operation data of every image ===
+    let data_path = format!("{}.data", out_path);
+    let result = write_operation_data(images, &data_path, compression);
+    let (partitions, summaries) = match result {
+        Ok(written) => written,
+        Err(e) => {
+            let _ = std::fs::remove_file(&data_path);
+            return Err(e);
+        }
+    };
+
+    // === STEP 2: Header and manifest, then the data ===
+    let manifest = DeltaArchiveManifest {
+        block_size: Some(BLOCK_SIZE as u32),
+        minor_version: Some(0),
+        partitions,
+        ..Default::default()
+    }
+    .encode_to_vec();
+    let result = write_payload(out_path, &manifest, &data_path);
+    let _ = std::fs::remove_file(&data_path);
+    let size = result?;
+
+    log::info!("=== PAYLOAD REPACK COMPLETE: {} ({}) ===", out_path, payload::format_size(size));
+
+    Ok(RepackedPayload {
+        path: out_path.to_string(),
+        size,
+        manifest_size: manifest.len() as u64,
+        signed: false,
+        partitions: summaries,
+    })
+}
+
+/// Split each image into operations, writing their data to `data_path`
+fn write_operation_data(
+    images: &[(String, String)],
+    data_path: &str,
+    compression: RepackCompression,
+) -> Result<(Vec<PartitionUpdate>, Vec<RepackedPartition>), PayloadError> {
+    let data_file = File::create(data_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to create {}: {}", data_path, e)))?;
+    let mut data = BufWriter::new(data_file);
+    let mut data_offset = 0u64;
+
+    let mut partitions = Vec::with_capacity(images.len());
+    let mut summaries = Vec::with_capacity(images.len());
+    let mut chunk = vec![0u8; OPERATION_SIZE as usize];
+
+    for (name, image_path) in images {
+        let mut image = File::open(image_path)
+            .map_err(|e| PayloadError::Io(format!("Failed to open {}: {}", image_path, e)))?;
+        let size = image.metadata()?.len();
+        if size % BLOCK_SIZE != 0 {
+            return Err(PayloadError::InvalidInput(format!(
+                "Image of partition {} is {} bytes, not a multiple of the {} byte block size",
+                name, size, BLOCK_SIZE
+            )));
+        }
+        log::info!("Partition {}: {} ({})", name, image_path, payload::format_size(size));
+
+        let mut image_hasher = Sha256::new();
+        let mut operations = Vec::new();
+        let partition_start = data_offset;
+        let mut position = 0u64;
+
+        while position < size {
+            let length = OPERATION_SIZE.min(size - position) as usize;
+            image.read_exact(&mut chunk[..length]).map_err(|e| {
+                PayloadError::Io(format!("Failed to read image of partition {}: {}", name, e))
+            })?;
+            let raw = &chunk[..length];
+            image_hasher.update(raw);
+
+            let dst_extents = vec![Extent {
+                start_block: Some(position / BLOCK_SIZE),
+                num_blocks: Some(length as u64 / BLOCK_SIZE),
+            }];
+            position += length as u64;
+
+            if raw.iter().all(|&b| b == 0) {
+                operations.push(InstallOperation {
+                    r#type: OperationType::Zero as i32,
+                    dst_extents,
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            // REPLACE_XZ only when it saves space, as update_engine does
+            let compressed = match compression {
+                RepackCompression::Xz => Some(compress_xz(raw)?).filter(|xz| xz.len() < raw.len()),
+                RepackCompression::None => None,
+            };
+            let (op_type, blob) = match &compressed {
+                Some(xz) => (OperationType::ReplaceXz, xz.as_slice()),
+                None => (OperationType::Replace, raw),
+            };
+
+            data.write_all(blob)
+                .map_err(|e| PayloadError::Io(format!("Failed to write {}: {}", data_path, e)))?;
+            operations.push(InstallOperation {
+                r#type: op_type as i32,
+                data_offset: Some(data_offset),
+                data_length: Some(blob.len() as u64),
+                data_sha256_hash: Some(Sha256::digest(blob).to_vec()),
+                dst_extents,
+                ..Default::default()
+            });
+            data_offset += blob.len() as u64;
+        }
+
+        let hash = image_hasher.finalize().to_vec();
+        summaries.push(RepackedPartition {
+            name: name.clone(),
+            size,
+            data_size: data_offset - partition_start,
+            operations_count: operations.len(),
+            hash: payload::to_hex(&hash),
+        });
+        partitions.push(PartitionUpdate {
+            partition_name: name.clone(),
+            new_partition_info: Some(PartitionInfo {
+                size: Some(size),
+                hash: Some(hash),
+            }),
+            operations,
+            ..Default::default()
+        });
+    }
+
+    data.flush()
+        .map_err(|e| PayloadError::Io(format!("Failed to write {}: {}", data_path, e)))?;
+    Ok((partitions, summaries))
+}
+
+/// Compress one operation's data with XZ
+fn compress_xz(raw: &[u8]) -> Result<Vec<u8>, PayloadError> {
+    let mut encoder = xz2::write::XzEncoder::new(Vec::with_capacity(raw.len() / 2), XZ_PRESET);
+    encoder
+        .write_all(raw)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| PayloadError::Io(format!("XZ compression failed: {}", e)))
+}
+
+/// Write the header and manifest to `out_path`, followed by the data in
+/// `data_path`. Returns the size of the payload.
+fn write_payload(out_path: &str, manifest: &[u8], data_path: &str) -> Result<u64, PayloadError> {
+    let write_error = |e: std::io::Error| PayloadError::Io(format!("Failed to write {}: {}", out_path, e));
+    let mut out = BufWriter::new(File::create(out_path).map_err(write_error)?);
+
+    out.write_all(b"CrAU").map_err(write_error)?;
+    out.write_all(&2u64.to_be_bytes()).map_err(write_error)?;
+    out.write_all(&(manifest.len() as u64).to_be_bytes()).map_err(write_error)?;
+    out.write_all(&0u32.to_be_bytes()).map_err(write_error)?;
+    out.write_all(manifest).map_err(write_error)?;
+
+    let mut data = File::open(data_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to reopen {}: {}", data_path, e)))?;
+    std::io::copy(&mut data, &mut out).map_err(write_error)?;
+
+    let out = out.into_inner().map_err(|e| write_error(e.into_error()))?;
+    out.sync_all().map_err(write_error)?;
+    Ok(out.metadata().map_err(write_error)?.len())
+}
+
+/// Build an unsigned payload from partition images and return JSON result
+pub fn create_payload_json(
+    images: &[(String, String)],
+    out_path: &str,
+    compression: &str,
+) -> Result<String, PayloadError> {
+    log::info!("create_payload_json called");
+
+    let result = RepackCompression::from_name(compression)
+        .and_then(|compression| create_payload(images, out_path, compression));
+    match result {
+        Ok(repacked) => payload::to_json(&repacked, false),
+        Err(e) => {
+            log::error!("Repacking payload failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_payload_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        // Compressible data spanning two operations, noise, and zeros only
+        let system: Vec<u8> = (0..OPERATION_SIZE + 2 * BLOCK_SIZE).map(|i| (i % 7) as u8).collect();
+        let boot: Vec<u8> = (0..2 * BLOCK_SIZE).map(|i| (i * 31 % 251) as u8).collect();
+        let misc = vec![0u8; 2 * BLOCK_SIZE as usize];
+        let images_dir = dir.path().join("images");
+        std::fs::create_dir(&images_dir).unwrap();
+        let mut images = Vec::new();
+        for (name, data) in [("system", &system), ("boot", &boot), ("misc", &misc)] {
+            let path = images_dir.join(format!("{}.img", name));
+            std::fs::write(&path, data).unwrap();
+            images.push((name.to_string(), path.to_string_lossy().to_string()));
+        }
+
+        for compression in ["none", "xz"] {
+            let out_path = dir.path().join(compression).join("payload.bin");
+            let out_path = out_path.to_str().unwrap();
+            let json = create_payload_json(&images, out_path, compression).unwrap();
+            let repacked: serde_json::Value = serde_json::from_str(&json).unwrap();
+            assert_eq!(repacked["signed"], false);
+            assert!(!Path::new(&format!("{}.data", out_path)).exists());
+
+            let inspection = payload::inspect_payload(out_path).unwrap();
+            assert!(inspection.signatures.is_empty());
+            let types = &inspection.operation_types;
+            match compression {
+                "xz" => assert_eq!((types.get("REPLACE_XZ"), types.get("ZERO")), (Some(&3), Some(&1))),
+                _ => assert_eq!((types.get("REPLACE"), types.get("ZERO")), (Some(&3), Some(&1))),
+            }
+
+            let out_dir = dir.path().join(format!("{}-out", compression));
+            let options = payload::ExtractOptions::default();
+            let result = payload::extract_payload(
+                out_path,
+                out_dir.to_str().unwrap(),
+                &options,
+                None::<fn(&payload::ProgressUpdate)>,
+            )
+            .unwrap();
+            assert!(result.extracted.iter().all(|p| p.verified));
+            assert_eq!(std::fs::read(out_dir.join("system.img")).unwrap(), system);
+            assert_eq!(std::fs::read(out_dir.join("boot.img")).unwrap(), boot);
+            assert_eq!(std::fs::read(out_dir.join("misc.img")).unwrap(), misc);
+        }
+
+        // Images must be whole blocks, names unique
+        let odd = images_dir.join("odd.img");
+        std::fs::write(&odd, [1u8; 100]).unwrap();
+        let odd = vec![("odd".to_string(), odd.to_string_lossy().to_string())];
+        let out_path = dir.path().join("bad.bin");
+        let out_path = out_path.to_str().unwrap();
+        assert!(matches!(create_payload(&odd, out_path, RepackCompression::None), Err(PayloadError::InvalidInput(_))));
+        assert!(!Path::new(&format!("{}.data", out_path)).exists());
+        let twice = [images[1].clone(), images[1].clone()];
+        assert!(matches!(create_payload(&twice, out_path, RepackCompression::None), Err(PayloadError::InvalidInput(_))));
+        assert!(RepackCompression::from_name("lz4").is_err());
+    }
+}
@@ -0,0 +1,363 @@
+//! Android Sparse Image Module
+//!
+//! Converts raw partition images into the Android sparse format accepted by
+//! `fastboot flash`, which also keeps zero-filled regions out of the file.
+//!
+//! Sparse Image Format (little endian):
+//! - File header (28 bytes): magic 0xED26FF3A, version 1.0, header sizes,
+//!   block size, total blocks, total chunks, image checksum (0 = none)
+//! - Chunks, each with a 12 byte header: type, reserved, size in blocks,
+//!   total size in bytes including the header
+//!   - RAW (0xCAC1): followed by the block data
+//!   - DONT_CARE (0xCAC3): no data; used here for all-zero blocks
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use serde::Serialize;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::payload::{self, PayloadError};
+
+/// Sparse image magic number
+const SPARSE_MAGIC: u32 = 0xED26_FF3A;
+
+/// Size of the sparse file header
+const FILE_HEADER_SIZE: u16 = 28;
+
+/// Size of each chunk header
+const CHUNK_HEADER_SIZE: u16 = 12;
+
+/// Chunk holding raw block data
+const CHUNK_TYPE_RAW: u16 = 0xCAC1;
+
+/// Chunk for blocks whose content doesn't matter (zeros here)
+const CHUNK_TYPE_DONT_CARE: u16 = 0xCAC3;
+
+/// Block size used for sparse images of extracted partitions
+pub(crate) const DEFAULT_BLOCK_SIZE: u32 = 4096;
+
+/// Longest RAW chunk written, in blocks. Keeps chunks well within the u32
+/// byte count of the chunk header and fastboot's download buffer.
+const MAX_RAW_CHUNK_BLOCKS: u32 = 16 * 1024;
+
+/// Summary of a sparse conversion
+#[derive(Debug, Clone, Serialize)]
+pub struct SparseImageInfo {
+    /// Path of the sparse image
+    pub path: String,
+    /// Size of the raw image in bytes (rounded up to whole blocks)
+    pub raw_size: u64,
+    /// Size of the sparse image in bytes
+    pub sparse_size: u64,
+    pub block_size: u32,
+    pub total_blocks: u32,
+    pub total_chunks: u32,
+    /// Blocks stored as data (the rest are zero)
+    pub data_blocks: u32,
+}
+
+/// Convert a raw image to an Android sparse image.
+///
+/// # Arguments
+/// * `raw_img_path` - Path of the raw image, e.g. an extracted `system.img`
+/// * `sparse_out_path` - Where to write the sparse image (overwritten)
+///
+/// # Returns
+/// * `Ok(SparseImageInfo)` - Sizes and chunk counts of the result
+/// * `Err(PayloadError)` - If the image can't be read or written, or is too
+///   large for the sparse format
+pub fn convert_to_sparse(raw_img_path: &str, sparse_out_path: &str) -> Result<SparseImageInfo, PayloadError> {
+    log::info!("=== SPARSE CONVERSION ===");
+    log::info!("Raw: {}", raw_img_path);
+    log::info!("Sparse: {}", sparse_out_path);
+
+    if raw_img_path.trim().is_empty() || sparse_out_path.trim().is_empty() {
+        return Err(PayloadError::EmptyPath);
+    }
+    if Path::new(raw_img_path) == Path::new(sparse_out_path) {
+        return Err(PayloadError::InvalidInput(
+            "Sparse output must not overwrite the raw image".to_string(),
+        ));
+    }
+
+    write_sparse_image(Path::new(raw_img_path), Path::new(sparse_out_path), DEFAULT_BLOCK_SIZE)
+}
+
+/// Convert a raw image to sparse and return JSON result
+pub fn convert_to_sparse_json(raw_img_path: &str, sparse_out_path: &str) -> Result<String, PayloadError> {
+    log::info!("convert_to_sparse_json called");
+
+    match convert_to_sparse(raw_img_path, sparse_out_path) {
+        Ok(info) => payload::to_json(&info, false),
+        Err(e) => {
+            log::error!("Sparse conversion failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Replace a raw image with its sparse version, for extraction with
+/// `output_sparse`
+pub(crate) fn sparsify_in_place(image_path: &Path, block_size: u32) -> Result<SparseImageInfo, PayloadError> {
+    let mut temp_name = image_path.as_os_str().to_owned();
+    temp_name.push(".sparse.tmp");
+    let temp_path = Path::new(&temp_name);
+
+    let mut info = match write_sparse_image(image_path, temp_path, block_size) {
+        Ok(info) => info,
+        Err(e) => {
+            let _ = std::fs::remove_file(temp_path);
+            return Err(e);
+        }
+    };
+
+    std::fs::rename(temp_path, image_path).map_err(|e| {
+        PayloadError::Io(format!("Failed to replace {} with sparse image: {}", image_path.display(), e))
+    })?;
+    info.path = image_path.to_string_lossy().to_string();
+    Ok(info)
+}
+
+/// Write the sparse version of `raw_path` to `sparse_path`.
+///
+/// Blocks are read once: runs of zero blocks become DONT_CARE chunks, runs
+/// of data blocks RAW chunks. Chunk headers of RAW runs are written once the
+/// run length is known, by seeking back; the file header likewise.
+fn write_sparse_image(raw_path: &Path, sparse_path: &Path, block_size: u32) -> Result<SparseImageInfo, PayloadError> {
+    let mut raw = File::open(raw_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to open {}: {}", raw_path.display(), e)))?;
+    let raw_len = raw
+        .metadata()
+        .map_err(|e| PayloadError::Io(format!("Cannot stat {}: {}", raw_path.display(), e)))?
+        .len();
+
+    let total_blocks = u32::try_from(raw_len.div_ceil(block_size as u64)).map_err(|_| {
+        PayloadError::InvalidInput(format!(
+            "{} is too large for a sparse image ({} bytes)",
+            raw_path.display(),
+            raw_len
+        ))
+    })?;
+    log::info!("{} bytes, {} blocks of {}", raw_len, total_blocks, block_size);
+
+    let out = File::create(sparse_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to create {}: {}", sparse_path.display(), e)))?;
+    let mut writer = SparseWriter {
+        out: BufWriter::new(out),
+        block_size,
+        chunks: 0,
+        data_blocks: 0,
+        run: None,
+    };
+    let io_error = |e: std::io::Error| PayloadError::Io(format!("Sparse conversion failed: {}", e));
+
+    // Header placeholder, rewritten once the chunk count is known
+    writer.write_file_header(total_blocks).map_err(io_error)?;
+
+    let mut block = vec![0u8; block_size as usize];
+    for _ in 0..total_blocks {
+        // The last block of an unaligned image is zero padded
+        block.fill(0);
+        read_block(&mut raw, &mut block).map_err(io_error)?;
+        writer.push_block(&block).map_err(io_error)?;
+    }
+    writer.finish_run().map_err(io_error)?;
+
+    writer.out.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    writer.write_file_header(total_blocks).map_err(io_error)?;
+    let out = writer.out.into_inner().map_err(|e| io_error(e.into_error()))?;
+    out.sync_all().map_err(io_error)?;
+    let sparse_size = out.metadata().map_err(io_error)?.len();
+
+    log::info!(
+        "✓ Sparse image: {} chunks, {} data blocks, {} bytes",
+        writer.chunks,
+        writer.data_blocks,
+        sparse_size
+    );
+
+    Ok(SparseImageInfo {
+        path: sparse_path.to_string_lossy().to_string(),
+        raw_size: total_blocks as u64 * block_size as u64,
+        sparse_size,
+        block_size,
+        total_blocks,
+        total_chunks: writer.chunks,
+        data_blocks: writer.data_blocks,
+    })
+}
+
+/// Fill `block` from `reader`, leaving the rest untouched at end of file
+fn read_block<R: Read>(reader: &mut R, block: &mut [u8]) -> std::io::Result<()> {
+    let mut filled = 0;
+    while filled < block.len() {
+        match reader.read(&mut block[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Chunk run being accumulated
+#[derive(Clone, Copy)]
+enum Run {
+    /// Zero blocks; nothing written yet
+    Zero { blocks: u32 },
+    /// Data blocks already written after a placeholder header at `header_pos`
+    Raw { blocks: u32, header_pos: u64 },
+}
+
+/// Streams blocks into sparse chunks
+struct SparseWriter {
+    out: BufWriter<File>,
+    block_size: u32,
+    chunks: u32,
+    data_blocks: u32,
+    run: Option<Run>,
+}
+
+impl SparseWriter {
+    fn write_file_header(&mut self, total_blocks: u32) -> std::io::Result<()> {
+        self.out.write_all(&SPARSE_MAGIC.to_le_bytes())?;
+        self.out.write_all(&1u16.to_le_bytes())?; // major version
+        self.out.write_all(&0u16.to_le_bytes())?; // minor version
+        self.out.write_all(&FILE_HEADER_SIZE.to_le_bytes())?;
+        self.out.write_all(&CHUNK_HEADER_SIZE.to_le_bytes())?;
+        self.out.write_all(&self.block_size.to_le_bytes())?;
+        self.out.write_all(&total_blocks.to_le_bytes())?;
+        self.out.write_all(&self.chunks.to_le_bytes())?;
+        self.out.write_all(&0u32.to_le_bytes()) // no image checksum
+    }
+
+    fn write_chunk_header(&mut self, chunk_type: u16, blocks: u32, data_len: u64) -> std::io::Result<()> {
+        let total_size = CHUNK_HEADER_SIZE as u64 + data_len;
+        self.out.write_all(&chunk_type.to_le_bytes())?;
+        self.out.write_all(&0u16.to_le_bytes())?;
+        self.out.write_all(&blocks.to_le_bytes())?;
+        self.out.write_all(&(total_size as u32).to_le_bytes())
+    }
+
+    /// Add the next block of the image
+    fn push_block(&mut self, block: &[u8]) -> std::io::Result<()> {
+        let is_zero = block.iter().all(|&b| b == 0);
+
+        self.run = Some(match (self.run, is_zero) {
+            (Some(Run::Zero { blocks }), true) => Run::Zero { blocks: blocks + 1 },
+            (Some(Run::Raw { blocks, header_pos }), false) if blocks < MAX_RAW_CHUNK_BLOCKS => {
+                self.out.write_all(block)?;
+                Run::Raw { blocks: blocks + 1, header_pos }
+            }
+            (_, true) => {
+                self.finish_run()?;
+                Run::Zero { blocks: 1 }
+            }
+            (_, false) => {
+                self.finish_run()?;
+                let header_pos = self.out.stream_position()?;
+                self.write_chunk_header(CHUNK_TYPE_RAW, 0, 0)?;
+                self.out.write_all(block)?;
+                Run::Raw { blocks: 1, header_pos }
+            }
+        });
+
+        if !is_zero {
+            self.data_blocks += 1;
+        }
+        Ok(())
+    }
+
+    /// Write out the chunk for the current run
+    fn finish_run(&mut self) -> std::io::Result<()> {
+        match self.run.take() {
+            None => return Ok(()),
+            Some(Run::Zero { blocks }) => {
+                self.write_chunk_header(CHUNK_TYPE_DONT_CARE, blocks, 0)?;
+            }
+            Some(Run::Raw { blocks, header_pos }) => {
+                let end = self.out.stream_position()?;
+                self.out.seek(SeekFrom::Start(header_pos))?;
+                self.write_chunk_header(CHUNK_TYPE_RAW, blocks, blocks as u64 * self.block_size as u64)?;
+                self.out.seek(SeekFrom::Start(end))?;
+            }
+        }
+        self.chunks += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Expand a sparse image back to raw bytes (DONT_CARE as zeros)
+    fn unsparse(data: &[u8]) -> Vec<u8> {
+        let u16_at = |pos: usize| u16::from_le_bytes([data[pos], data[pos + 1]]);
+        let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+
+        assert_eq!(u32_at(0), SPARSE_MAGIC);
+        assert_eq!((u16_at(8), u16_at(10)), (FILE_HEADER_SIZE, CHUNK_HEADER_SIZE));
+        let block_size = u32_at(12) as usize;
+        let total_blocks = u32_at(16) as usize;
+        let total_chunks = u32_at(20);
+
+        let mut raw = Vec::new();
+        let mut pos = FILE_HEADER_SIZE as usize;
+        for _ in 0..total_chunks {
+            let blocks = u32_at(pos + 4) as usize;
+            let total_size = u32_at(pos + 8) as usize;
+            match u16_at(pos) {
+                CHUNK_TYPE_RAW => {
+                    assert_eq!(total_size, 12 + blocks * block_size);
+                    raw.extend_from_slice(&data[pos + 12..pos + total_size]);
+                }
+                CHUNK_TYPE_DONT_CARE => {
+                    assert_eq!(total_size, 12);
+                    raw.resize(raw.len() + blocks * block_size, 0);
+                }
+                other => panic!("unexpected chunk type {:#x}", other),
+            }
+            pos += total_size;
+        }
+        assert_eq!(pos, data.len());
+        assert_eq!(raw.len(), total_blocks * block_size);
+        raw
+    }
+
+    #[test]
+    fn test_convert_to_sparse_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let raw_path = dir.path().join("system.img");
+        let sparse_path = dir.path().join("system.sparse.img");
+
+        // data, zeros, data, zeros, and a partial last block
+        let mut raw = Vec::new();
+        raw.extend_from_slice(&[1u8; 4096 * 2]);
+        raw.extend_from_slice(&[0u8; 4096 * 3]);
+        raw.extend_from_slice(&[2u8; 4096]);
+        raw.extend_from_slice(&[0u8; 4096 * 4]);
+        raw.extend_from_slice(&[3u8; 100]);
+        std::fs::write(&raw_path, &raw).unwrap();
+
+        let info = convert_to_sparse(raw_path.to_str().unwrap(), sparse_path.to_str().unwrap()).unwrap();
+        assert_eq!(info.total_blocks, 11);
+        assert_eq!(info.total_chunks, 5);
+        assert_eq!(info.data_blocks, 4);
+        assert!(info.sparse_size < raw.len() as u64);
+
+        let sparse = std::fs::read(&sparse_path).unwrap();
+        assert_eq!(info.sparse_size, sparse.len() as u64);
+        let mut expected = raw.clone();
+        expected.resize(11 * 4096, 0);
+        assert_eq!(unsparse(&sparse), expected);
+
+        assert!(matches!(
+            convert_to_sparse(raw_path.to_str().unwrap(), raw_path.to_str().unwrap()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
+}
@@ -0,0 +1,570 @@
+//! Super Image Module
+//!
+//! Assembles a `super.img` from extracted dynamic partition images, writing
+//! the LP (logical partition) metadata `lpmake` would, so the result can be
+//! read by lpdump/lpunpack or flashed with `fastboot flash super`.
+//!
+//! Super Image Layout (little endian, as in liblp's metadata_format.h):
+//! - 4096 reserved bytes (zeros)
+//! - Geometry (52 bytes, padded to 4096): magic 0x616C4467, struct size,
+//!   SHA-256 of itself, metadata max size, slot count, logical block size;
+//!   followed by a backup copy
+//! - Metadata slots of `metadata_max_size` bytes each, then their backups.
+//!   Each holds a 128 byte header (format 10.0) with SHA-256 checksums of
+//!   itself and of the tables that follow: partitions, extents, groups and
+//!   block devices
+//! - Partition data from the first logical sector, each partition one linear
+//!   extent aligned to 1 MiB
+//!
+//! This is a basic layout: names are written as given (no slot suffixes are
+//! added) and every metadata slot holds the same metadata.
+//!
+//! IMPORTANT: This module is called from JNI and must NEVER panic.
+
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::payload::{self, PayloadError};
+use crate::proto::DynamicPartitionMetadata;
+
+/// Geometry magic ("gDla")
+const GEOMETRY_MAGIC: u32 = 0x616C_4467;
+
+/// Metadata header magic ("0PLA")
+const HEADER_MAGIC: u32 = 0x414C_5030;
+
+/// Metadata format version written (10.0, the oldest liblp reads)
+const METADATA_MAJOR_VERSION: u16 = 10;
+const METADATA_MINOR_VERSION: u16 = 0;
+
+/// Bytes left empty at the start of the super partition
+const RESERVED_BYTES: u64 = 4096;
+
+/// Space taken by each copy of the geometry
+const GEOMETRY_SIZE: u64 = 4096;
+
+/// Size of the geometry struct
+const GEOMETRY_STRUCT_SIZE: u32 = 52;
+
+/// Size of a version 10.0 metadata header
+const HEADER_SIZE: u32 = 128;
+
+/// Sizes of the metadata table entries
+const PARTITION_ENTRY_SIZE: u32 = 52;
+const EXTENT_ENTRY_SIZE: u32 = 24;
+const GROUP_ENTRY_SIZE: u32 = 48;
+const BLOCK_DEVICE_ENTRY_SIZE: u32 = 64;
+
+/// Room for partition and group names, including the NUL terminator
+const NAME_SIZE: usize = 36;
+
+/// Unit of extent offsets and lengths
+const SECTOR_SIZE: u64 = 512;
+
+/// Logical block size recorded in the geometry
+const LOGICAL_BLOCK_SIZE: u32 = 4096;
+
+/// Alignment of the partition data (lpmake's default)
+const PARTITION_ALIGNMENT: u64 = 1024 * 1024;
+
+/// Number of metadata slots (one per A/B slot)
+const METADATA_SLOT_COUNT: u32 = 2;
+
+/// Smallest metadata slot written (lpmake's default)
+const MIN_METADATA_MAX_SIZE: u32 = 65536;
+
+/// Partition attribute: mapped read-only
+const PARTITION_ATTR_READONLY: u32 = 1;
+
+/// Extent target type: a range of the super partition
+const TARGET_TYPE_LINEAR: u32 = 0;
+
+/// Group partitions land in when the metadata doesn't place them elsewhere
+const DEFAULT_GROUP: &str = "default";
+
+/// Name of the block device holding the super partition
+const SUPER_DEVICE: &str = "super";
+
+/// Summary of a built super image
+#[derive(Debug, Clone, Serialize)]
+pub struct SuperImageInfo {
+    /// Path of the super image
+    pub path: String,
+    /// Size of the super partition (and the image) in bytes
+    pub size: u64,
+    /// Size reserved for each metadata slot
+    pub metadata_max_size: u32,
+    pub metadata_slot_count: u32,
+    /// Partitions in the order they were laid out
+    pub partitions: Vec<SuperPartitionInfo>,
+}
+
+/// Where a partition was placed in the super image
+#[derive(Debug, Clone, Serialize)]
+pub struct SuperPartitionInfo {
+    pub name: String,
+    pub group: String,
+    /// Offset of the partition data in the image, in bytes
+    pub offset: u64,
+    /// Size of the partition in bytes (the image size rounded up to sectors)
+    pub size: u64,
+}
+
+/// A partition group as written to the metadata
+struct Group {
+    name: String,
+    /// 0 means unlimited
+    maximum_size: u64,
+}
+
+/// Build a super image from partition images.
+///
+/// Partitions listed in a group of `metadata` are placed in that group, the
+/// others in the "default" group. Each group's partitions must fit in the
+/// group's maximum size. The super partition is sized to hold all groups at
+/// their maximum size; space not taken by a partition is left as a hole.
+///
+/// # Arguments
+/// * `partitions` - (name, image path) of each partition, in layout order
+/// * `metadata` - Dynamic partition metadata from the payload manifest
+/// * `out_path` - Where to write the super image (overwritten)
+///
+/// # Returns
+/// * `Ok(SuperImageInfo)` - Size and layout of the image
+/// * `Err(PayloadError)` - If an image can't be read, a name is too long or
+///   repeated, a group overflows, or the output can't be written
+pub fn build_super_image(
+    partitions: &[(String, String)],
+    metadata: &DynamicPartitionMetadata,
+    out_path: &str,
+) -> Result<SuperImageInfo, PayloadError> {
+    log::info!("=== SUPER IMAGE ===");
+    log::info!("Output: {}", out_path);
+
+    if out_path.trim().is_empty() {
+        return Err(PayloadError::EmptyPath);
+    }
+
+    let mut groups = vec![Group {
+        name: DEFAULT_GROUP.to_string(),
+        maximum_size: 0,
+    }];
+    groups.extend(
+        metadata
+            .groups
+            .iter()
+            .filter(|group| group.name != DEFAULT_GROUP)
+            .map(|group| Group {
+                name: group.name.clone(),
+                maximum_size: group.size.unwrap_or(0),
+            }),
+    );
+    for group in &groups {
+        check_name(&group.name, "Group")?;
+    }
+
+    // Place each partition in its group and measure its image
+    let mut placed = Vec::with_capacity(partitions.len());
+    for (index, (name, image_path)) in partitions.iter().enumerate() {
+        check_name(name, "Partition")?;
+        if partitions[..index].iter().any(|(other, _)| other == name) {
+            return Err(PayloadError::InvalidInput(format!(
+                "Partition '{}' is listed more than once",
+                name
+            )));
+        }
+
+        let image_size = std::fs::metadata(image_path)?.len();
+        let group_index = metadata
+            .groups
+            .iter()
+            .find(|group| group.partition_names.contains(name))
+            .and_then(|group| groups.iter().position(|g| g.name == group.name))
+            .unwrap_or(0);
+        log::info!("{}: {} bytes, group {}", name, image_size, groups[group_index].name);
+
+        let size = image_size
+            .checked_next_multiple_of(SECTOR_SIZE)
+            .ok_or_else(|| too_large("Partition", name))?;
+        placed.push((name, group_index, size));
+    }
+
+    for (index, group) in groups.iter().enumerate() {
+        let used = placed
+            .iter()
+            .filter(|(_, group_index, _)| *group_index == index)
+            .try_fold(0u64, |used, (_, _, size)| used.checked_add(*size))
+            .ok_or_else(|| too_large("Group", &group.name))?;
+        if group.maximum_size > 0 && used > group.maximum_size {
+            log::error!("Group {} needs {} bytes, max {}", group.name, used, group.maximum_size);
+            return Err(PayloadError::InvalidInput(format!(
+                "Partitions of group {} need {} bytes, more than its maximum size of {}",
+                group.name, used, group.maximum_size
+            )));
+        }
+    }
+
+    // =========================================================================
+    // Layout: metadata first, then each partition at the next aligned offset
+    // =========================================================================
+    let tables_size = PARTITION_ENTRY_SIZE as u64 * placed.len() as u64
+        + EXTENT_ENTRY_SIZE as u64 * placed.len() as u64
+        + GROUP_ENTRY_SIZE as u64 * groups.len() as u64
+        + BLOCK_DEVICE_ENTRY_SIZE as u64;
+    let metadata_max_size = u32::try_from((HEADER_SIZE as u64 + tables_size).next_multiple_of(4096))
+        .map_err(|_| PayloadError::InvalidInput("Too many partitions for LP metadata".to_string()))?
+        .max(MIN_METADATA_MAX_SIZE);
+
+    let metadata_end = RESERVED_BYTES
+        + 2 * GEOMETRY_SIZE
+        + 2 * METADATA_SLOT_COUNT as u64 * metadata_max_size as u64;
+    let first_logical_offset = metadata_end.next_multiple_of(PARTITION_ALIGNMENT);
+
+    let mut layout = Vec::with_capacity(placed.len());
+    let mut offset = first_logical_offset;
+    for &(name, group_index, size) in &placed {
+        layout.push(SuperPartitionInfo {
+            name: name.clone(),
+            group: groups[group_index].name.clone(),
+            offset,
+            size,
+        });
+        offset = offset
+            .checked_add(size)
+            .map(|end| end.next_multiple_of(PARTITION_ALIGNMENT))
+            .ok_or_else(|| PayloadError::InvalidInput("Partitions too large for a super image".to_string()))?;
+    }
+    // Group maximum sizes come from the manifest, so they may add up to
+    // anything
+    let groups_end = groups
+        .iter()
+        .try_fold(0u64, |sum, group| sum.checked_add(group.maximum_size))
+        .and_then(|size| size.checked_next_multiple_of(PARTITION_ALIGNMENT))
+        .and_then(|size| size.checked_add(first_logical_offset))
+        .ok_or_else(|| PayloadError::InvalidInput("Group maximum sizes too large for a super image".to_string()))?;
+    let device_size = offset.max(groups_end);
+    log::info!(
+        "Super partition: {} ({} metadata slots of {} bytes)",
+        payload::format_size(device_size),
+        METADATA_SLOT_COUNT,
+        metadata_max_size
+    );
+
+    let geometry = encode_geometry(metadata_max_size);
+    let metadata_blob = encode_metadata(&layout, &groups, first_logical_offset, device_size);
+
+    // =========================================================================
+    // Write the image: geometry and metadata copies, then partition data
+    // =========================================================================
+    let io_error = |e: std::io::Error| PayloadError::Io(format!("Failed to write super image: {}", e));
+    let mut out = File::create(out_path)
+        .map_err(|e| PayloadError::Io(format!("Failed to create {}: {}", out_path, e)))?;
+    out.set_len(device_size).map_err(io_error)?;
+
+    for copy in 0..2 {
+        out.seek(SeekFrom::Start(RESERVED_BYTES + copy * GEOMETRY_SIZE)).map_err(io_error)?;
+        out.write_all(&geometry).map_err(io_error)?;
+    }
+    let slots_start = RESERVED_BYTES + 2 * GEOMETRY_SIZE;
+    for slot in 0..2 * METADATA_SLOT_COUNT as u64 {
+        out.seek(SeekFrom::Start(slots_start + slot * metadata_max_size as u64)).map_err(io_error)?;
+        out.write_all(&metadata_blob).map_err(io_error)?;
+    }
+
+    for (partition, (_, image_path)) in layout.iter().zip(partitions) {
+        let mut image = File::open(image_path)
+            .map_err(|e| PayloadError::Io(format!("Failed to open {}: {}", image_path, e)))?;
+        out.seek(SeekFrom::Start(partition.offset)).map_err(io_error)?;
+        let copied = std::io::copy(&mut image, &mut out).map_err(io_error)?;
+        log::info!("✓ {} written at {} ({} bytes)", partition.name, partition.offset, copied);
+    }
+    out.sync_all().map_err(io_error)?;
+
+    Ok(SuperImageInfo {
+        path: out_path.to_string(),
+        size: device_size,
+        metadata_max_size,
+        metadata_slot_count: METADATA_SLOT_COUNT,
+        partitions: layout,
+    })
+}
+
+/// Build a super image from the images extracted from a payload.
+///
+/// Takes the partitions of the manifest's dynamic partition groups, in
+/// manifest order, from `<images_dir>/<name>.img`.
+pub fn build_super_image_from_payload(
+    payload_path: &str,
+    images_dir: &str,
+    out_path: &str,
+) -> Result<SuperImageInfo, PayloadError> {
+    let (mut file, file_size) = payload::open_payload_file(payload_path)?;
+    let (_, manifest) = payload::read_header_and_manifest(&mut file, file_size)?;
+    let metadata = manifest.dynamic_partition_metadata.ok_or_else(|| {
+        PayloadError::InvalidInput("Payload has no dynamic partition metadata".to_string())
+    })?;
+
+    let partitions: Vec<(String, String)> = metadata
+        .groups
+        .iter()
+        .flat_map(|group| &group.partition_names)
+        .map(|name| {
+            let image_path = Path::new(images_dir).join(format!("{}.img", name));
+            (name.clone(), image_path.to_string_lossy().to_string())
+        })
+        .collect();
+    if let Some((name, image_path)) = partitions.iter().find(|(_, path)| !Path::new(path).exists()) {
+        return Err(PayloadError::FileNotFound(format!(
+            "Image of partition {} not found: {}",
+            name, image_path
+        )));
+    }
+
+    build_super_image(&partitions, &metadata, out_path)
+}
+
+/// Build a super image from a payload's extracted images and return JSON result
+pub fn build_super_image_json(payload_path: &str, images_dir: &str, out_path: &str) -> Result<String, PayloadError> {
+    log::info!("build_super_image_json called");
+
+    match build_super_image_from_payload(payload_path, images_dir, out_path) {
+        Ok(info) => payload::to_json(&info, false),
+        Err(e) => {
+            log::error!("Building super image failed: {}", e);
+            Err(e)
+        }
+    }
+}
+
+/// Check that `name` fits the fixed-size name fields of the metadata
+fn check_name(name: &str, what: &str) -> Result<(), PayloadError> {
+    if name.is_empty() || name.len() >= NAME_SIZE {
+        return Err(PayloadError::InvalidInput(format!(
+            "{} name '{}' must be 1 to {} bytes long",
+            what,
+            name,
+            NAME_SIZE - 1
+        )));
+    }
+    Ok(())
+}
+
+/// Error for a partition or group whose size doesn't fit in a super image
+fn too_large(what: &str, name: &str) -> PayloadError {
+    PayloadError::InvalidInput(format!("{} {} is too large for a super image", what, name))
+}
+
+/// `name` as a NUL-padded name field
+fn name_field(name: &str) -> [u8; NAME_SIZE] {
+    let mut field = [0u8; NAME_SIZE];
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    field
+}
+
+/// The geometry block, padded to `GEOMETRY_SIZE`
+fn encode_geometry(metadata_max_size: u32) -> Vec<u8> {
+    let mut geometry = Vec::with_capacity(GEOMETRY_SIZE as usize);
+    geometry.extend_from_slice(&GEOMETRY_MAGIC.to_le_bytes());
+    geometry.extend_from_slice(&GEOMETRY_STRUCT_SIZE.to_le_bytes());
+    geometry.extend_from_slice(&[0u8; 32]);
+    geometry.extend_from_slice(&metadata_max_size.to_le_bytes());
+    geometry.extend_from_slice(&METADATA_SLOT_COUNT.to_le_bytes());
+    geometry.extend_from_slice(&LOGICAL_BLOCK_SIZE.to_le_bytes());
+
+    // The checksum covers the struct with the checksum field zeroed
+    let checksum = Sha256::digest(&geometry);
+    geometry[8..40].copy_from_slice(&checksum);
+    geometry.resize(GEOMETRY_SIZE as usize, 0);
+    geometry
+}
+
+/// The metadata header followed by its tables
+fn encode_metadata(layout: &[SuperPartitionInfo], groups: &[Group], first_logical_offset: u64, device_size: u64) -> Vec<u8> {
+    let mut partitions = Vec::new();
+    let mut extents = Vec::new();
+    let mut extent_count = 0u32;
+    for partition in layout {
+        let num_extents = u32::from(partition.size > 0);
+        let group_index = groups.iter().position(|group| group.name == partition.group).unwrap_or(0);
+
+        partitions.extend_from_slice(&name_field(&partition.name));
+        partitions.extend_from_slice(&PARTITION_ATTR_READONLY.to_le_bytes());
+        partitions.extend_from_slice(&extent_count.to_le_bytes());
+        partitions.extend_from_slice(&num_extents.to_le_bytes());
+        partitions.extend_from_slice(&(group_index as u32).to_le_bytes());
+
+        if num_extents > 0 {
+            extents.extend_from_slice(&(partition.size / SECTOR_SIZE).to_le_bytes());
+            extents.extend_from_slice(&TARGET_TYPE_LINEAR.to_le_bytes());
+            extents.extend_from_slice(&(partition.offset / SECTOR_SIZE).to_le_bytes());
+            // Block device 0, the super partition itself
+            extents.extend_from_slice(&0u32.to_le_bytes());
+            extent_count += 1;
+        }
+    }
+
+    let mut group_table = Vec::new();
+    for group in groups {
+        group_table.extend_from_slice(&name_field(&group.name));
+        group_table.extend_from_slice(&0u32.to_le_bytes());
+        group_table.extend_from_slice(&group.maximum_size.to_le_bytes());
+    }
+
+    let mut block_devices = Vec::new();
+    block_devices.extend_from_slice(&(first_logical_offset / SECTOR_SIZE).to_le_bytes());
+    block_devices.extend_from_slice(&(PARTITION_ALIGNMENT as u32).to_le_bytes());
+    block_devices.extend_from_slice(&0u32.to_le_bytes());
+    block_devices.extend_from_slice(&device_size.to_le_bytes());
+    block_devices.extend_from_slice(&name_field(SUPER_DEVICE));
+    block_devices.extend_from_slice(&0u32.to_le_bytes());
+
+    // Table descriptors: offset from the end of the header, count, entry size
+    let mut descriptors = Vec::new();
+    let mut table_offset = 0u32;
+    for (table, entry_size) in [
+        (&partitions, PARTITION_ENTRY_SIZE),
+        (&extents, EXTENT_ENTRY_SIZE),
+        (&group_table, GROUP_ENTRY_SIZE),
+        (&block_devices, BLOCK_DEVICE_ENTRY_SIZE),
+    ] {
+        descriptors.extend_from_slice(&table_offset.to_le_bytes());
+        descriptors.extend_from_slice(&(table.len() as u32 / entry_size).to_le_bytes());
+        descriptors.extend_from_slice(&entry_size.to_le_bytes());
+        table_offset += table.len() as u32;
+    }
+    let tables = [partitions, extents, group_table, block_devices].concat();
+
+    let mut header = Vec::with_capacity(HEADER_SIZE as usize + tables.len());
+    header.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+    header.extend_from_slice(&METADATA_MAJOR_VERSION.to_le_bytes());
+    header.extend_from_slice(&METADATA_MINOR_VERSION.to_le_bytes());
+    header.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    header.extend_from_slice(&[0u8; 32]);
+    header.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+    header.extend_from_slice(&Sha256::digest(&tables));
+    header.extend_from_slice(&descriptors);
+
+    // The header checksum covers the header with that field zeroed
+    let checksum = Sha256::digest(&header);
+    header[12..44].copy_from_slice(&checksum);
+
+    header.extend_from_slice(&tables);
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::DynamicPartitionGroup;
+
+    fn u32_at(data: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn u64_at(data: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap())
+    }
+
+    fn metadata(group_size: u64) -> DynamicPartitionMetadata {
+        DynamicPartitionMetadata {
+            groups: vec![DynamicPartitionGroup {
+                name: "qti_dynamic_partitions".to_string(),
+                size: Some(group_size),
+                partition_names: vec!["system".to_string(), "vendor".to_string()],
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_build_super_image_layout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut partitions = Vec::new();
+        for (name, fill, len) in [("system", 1u8, 8192), ("vendor", 2, 1000), ("odm", 3, 4096)] {
+            let path = dir.path().join(format!("{}.img", name));
+            std::fs::write(&path, vec![fill; len]).unwrap();
+            partitions.push((name.to_string(), path.to_string_lossy().to_string()));
+        }
+        let out = dir.path().join("super.img");
+
+        let info = build_super_image(&partitions, &metadata(4 << 20), out.to_str().unwrap()).unwrap();
+        let data = std::fs::read(&out).unwrap();
+        assert_eq!(data.len() as u64, info.size);
+
+        // Geometry and its backup, checksummed over the zeroed-checksum struct
+        let geometry = &data[4096..4096 + 52];
+        assert_eq!(u32_at(geometry, 0), GEOMETRY_MAGIC);
+        let mut zeroed = geometry.to_vec();
+        zeroed[8..40].fill(0);
+        assert_eq!(&geometry[8..40], Sha256::digest(&zeroed).as_slice());
+        assert_eq!(&data[8192..8192 + 52], geometry);
+        assert_eq!(u32_at(geometry, 40), info.metadata_max_size);
+
+        // Primary metadata: header, then the partition table
+        let header = &data[12288..];
+        assert_eq!(u32_at(header, 0), HEADER_MAGIC);
+        let tables = &header[128..128 + u32_at(header, 44) as usize];
+        assert_eq!(&header[48..80], Sha256::digest(tables).as_slice());
+        assert_eq!((u32_at(header, 84), u32_at(header, 108)), (3, 2));
+        assert_eq!(&tables[..6], b"system");
+        assert_eq!(u32_at(tables, 48), 1);
+        assert_eq!(u32_at(tables, 2 * 52 + 48), 0, "odm is in the default group");
+
+        // Partition data, aligned and padded to whole sectors
+        assert_eq!(info.partitions[0].offset % PARTITION_ALIGNMENT, 0);
+        assert_eq!(info.partitions[1].size, 1024);
+        let vendor = &info.partitions[1];
+        assert_eq!(vendor.group, "qti_dynamic_partitions");
+        assert!(data[vendor.offset as usize..][..1000].iter().all(|&b| b == 2));
+        let extents = &tables[3 * 52..];
+        assert_eq!(u64_at(extents, 24 + 12), vendor.offset / 512);
+
+        // Room for the whole group
+        assert!(info.size >= info.partitions[0].offset + (4 << 20));
+    }
+
+    #[test]
+    fn test_build_super_image_rejects_full_group() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system.img");
+        std::fs::write(&path, vec![1u8; 8192]).unwrap();
+        let partitions = [("system".to_string(), path.to_string_lossy().to_string())];
+        let out = dir.path().join("super.img");
+
+        assert!(matches!(
+            build_super_image(&partitions, &metadata(4096), out.to_str().unwrap()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            build_super_image(&[partitions[0].clone(), partitions[0].clone()], &metadata(1 << 20), out.to_str().unwrap()),
+            Err(PayloadError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn test_build_super_image_rejects_oversized_groups() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("system.img");
+        std::fs::write(&path, vec![1u8; 8192]).unwrap();
+        let partitions = [("system".to_string(), path.to_string_lossy().to_string())];
+        let out = dir.path().join("super.img");
+
+        // Group sizes from a crafted manifest summing or rounding past u64::MAX
+        let mut two_groups = metadata(u64::MAX / 2 + 1);
+        two_groups.groups.push(DynamicPartitionGroup {
+            name: "other".to_string(),
+            size: Some(u64::MAX / 2 + 1),
+            partition_names: vec![],
+        });
+        for metadata in [metadata(u64::MAX), two_groups] {
+            match build_super_image(&partitions, &metadata, out.to_str().unwrap()) {
+                Err(PayloadError::InvalidInput(message)) => assert!(message.contains("too large"), "{}", message),
+                other => panic!("expected InvalidInput, got {:?}", other),
+            }
+        }
+        assert!(!out.exists());
+    }
+}